@@ -0,0 +1,95 @@
+//! Updates of persistent collections compared with copy-on-write collections, where every
+//! version is kept, so each update of a copy-on-write collection has to copy all of it.
+//!
+//! Run with `cargo bench --bench collections`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use yapl::persistent::{Dict, Vector};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const UPDATES: usize = 1_000;
+const ITERATIONS: u32 = 5;
+
+/// Best time over `ITERATIONS` runs of `updates` on a fresh collection from `build`, per update
+fn time<C>(build: impl Fn() -> C, updates: impl Fn(C) -> Vec<C>) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let collection = build();
+        let start = Instant::now();
+        let versions = black_box(updates(collection));
+        best = best.min(start.elapsed());
+        drop(versions);
+    }
+    best / UPDATES as u32
+}
+
+/// `UPDATES` versions, each made by `update` from the one before
+fn versions<C: Clone>(first: C, update: impl Fn(&mut C, usize)) -> Vec<C> {
+    let mut versions = vec![first];
+    for i in 0..UPDATES {
+        let mut next = versions[i].clone();
+        update(&mut next, i);
+        versions.push(next);
+    }
+    versions
+}
+
+fn report(name: &str, size: usize, persistent: Duration, copy_on_write: Duration) {
+    println!(
+        "{:<7} {:>7}: persistent {:>9.2?}, copy-on-write {:>9.2?} per update ({:.0}x)",
+        name,
+        size,
+        persistent,
+        copy_on_write,
+        copy_on_write.as_secs_f64() / persistent.as_secs_f64(),
+    );
+}
+
+fn main() {
+    for size in SIZES {
+        // Spread the updates over the whole collection
+        let index = move |i: usize| i * 7919 % size;
+
+        let persistent = time(
+            || (0..size).collect::<Vector<usize>>(),
+            |v| versions(v, |v, i| *v.get_mut(index(i)).unwrap() = i),
+        );
+        let copy_on_write = time(
+            || Rc::new((0..size).collect::<Vec<usize>>()),
+            |v| versions(v, |v, i| Rc::make_mut(v)[index(i)] = i),
+        );
+        report("set", size, persistent, copy_on_write);
+
+        let persistent = time(
+            || (0..size).collect::<Vector<usize>>(),
+            |v| versions(v, |v, i| v.push(i)),
+        );
+        let copy_on_write = time(
+            || Rc::new((0..size).collect::<Vec<usize>>()),
+            |v| versions(v, |v, i| Rc::make_mut(v).push(i)),
+        );
+        report("push", size, persistent, copy_on_write);
+
+        let persistent = time(
+            || (0..size).map(|i| (i, i)).collect::<Dict<usize, usize>>(),
+            |d| {
+                versions(d, |d, i| {
+                    d.insert(index(i) * 2, i);
+                })
+            },
+        );
+        let copy_on_write = time(
+            || Rc::new((0..size).map(|i| (i, i)).collect::<HashMap<usize, usize>>()),
+            |d| {
+                versions(d, |d, i| {
+                    Rc::make_mut(d).insert(index(i) * 2, i);
+                })
+            },
+        );
+        report("insert", size, persistent, copy_on_write);
+    }
+}
@@ -0,0 +1,73 @@
+//! Lexer throughput over a generated multi-megabyte source file.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use yapl::lexer::Lexer;
+
+const TARGET_SIZE: usize = 8 * 1024 * 1024;
+const ITERATIONS: u32 = 10;
+
+/// Builds a source file of at least `size` bytes out of numbered copies of a factorial function,
+/// so that it contains a realistic mix of keywords, identifiers, literals and comments.
+fn generate_source(size: usize) -> String {
+    let mut source = String::with_capacity(size + 1024);
+    let mut n = 0;
+    while source.len() < size {
+        write!(
+            source,
+            r#"fun factorial_{n}(num_{n}) {{
+    var result_{n} = 1
+    val unused_{n} = 14.0
+    val message_{n} = "computing factorial number {n}"
+
+    // check if the number is negative, positive or zero
+    if num_{n} < 0 {{
+        print("Sorry, factorial does not exist for negative numbers")
+    }} else {{
+        var i = 1
+        loop {{
+            if i >= num_{n} + 1 {{
+                break
+            }} else {{
+                i = i + 1
+                result_{n} = result_{n} * i
+            }}
+        }}
+        return result_{n}
+    }}
+}}
+
+"#,
+            n = n
+        )
+        .unwrap();
+        n += 1;
+    }
+    source
+}
+
+fn main() {
+    let source = generate_source(TARGET_SIZE);
+
+    let mut tokens = 0;
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        tokens = black_box(Lexer::new(black_box(&source))).count();
+        best = best.min(start.elapsed());
+    }
+
+    let secs = best.as_secs_f64();
+    println!(
+        "lexed {:.1} MiB ({} tokens) in {:.2?}: {:.1} MiB/s, {:.1} Mtokens/s",
+        source.len() as f64 / (1024.0 * 1024.0),
+        tokens,
+        best,
+        source.len() as f64 / (1024.0 * 1024.0) / secs,
+        tokens as f64 / 1_000_000.0 / secs,
+    );
+}
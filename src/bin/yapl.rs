@@ -1,15 +1,62 @@
 use std::env;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use yapl::compile;
+use yapl::modules::ModuleGraph;
+use yapl::{load, print_ast, run_graph};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let path = env::args().nth(1).unwrap_or_else(|| {
-        println!("must include filepath as single arg");
+    let mut args = env::args().skip(1);
+    let first = args.next().unwrap_or_else(|| {
+        println!("usage: yapl [run [--path <dir>]...] <filepath>");
         exit(1);
     });
 
-    compile(path.as_str())?;
+    if first == "run" {
+        // Directories to look for imported modules in, after the directory of the importing file
+        let mut search_path = vec![];
+        let path = loop {
+            match args.next() {
+                Some(arg) if arg == "--path" => {
+                    let dir = args.next().unwrap_or_else(|| {
+                        println!("--path must be followed by a directory");
+                        exit(1);
+                    });
+                    search_path.push(PathBuf::from(dir));
+                }
+                Some(arg) => break arg,
+                None => {
+                    println!("must include filepath to run");
+                    exit(1);
+                }
+            }
+        };
+        let result = load(Path::new(&path), search_path).and_then(|graph| {
+            print_lints(&graph);
+            run_graph(&graph, &mut std::io::stdout())
+        });
+        // Errors are reported with their stack trace instead of their debug representation
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    } else {
+        let graph = load(Path::new(&first), vec![])?;
+        print_lints(&graph);
+        print_ast(&graph);
+    }
 
     Ok(())
 }
+
+/// Prints the lints of every module of `graph` to stderr
+fn print_lints(graph: &ModuleGraph) {
+    for module in &graph.modules {
+        for lint in &module.lints {
+            match graph.file_name(module) {
+                Some(file) => eprintln!("{} in {}", lint, file),
+                None => eprintln!("{}", lint),
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::rc::Rc;
+
+use crate::token::Position;
+use crate::value::Value;
+
+/// A single VM instruction.
+///
+/// Jump targets are absolute indexes into the chunk's code. Local slots are relative to the
+/// base of the current call frame, global slots index the VM's global table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u32),
+    Unit,
+    True,
+    False,
+    /// Pops the top of the stack, closing any upvalue that still refers to it
+    Pop,
+
+    GetLocal(u32),
+    SetLocal(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    DefineGlobal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    Negate,
+    Not,
+
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+
+    /// Unconditional jump
+    Jump(usize),
+    /// Jumps if the top of the stack is false, leaving it on the stack
+    JumpIfFalse(usize),
+    /// Jumps if the top of the stack is true, leaving it on the stack
+    JumpIfTrue(usize),
+    /// Jumps if the top of the stack is `none`, leaving it on the stack
+    JumpIfNone(usize),
+
+    /// Pushes a closure over the function at the given index of the chunk's functions
+    Closure(u32),
+    /// Replaces the top values of the stack with an instance of the struct at the given index of
+    /// the program's structs. The values are in the order the fields were declared.
+    Construct(u32),
+    /// Replaces the struct on top of the stack with its field named by the given constant
+    GetField(u32),
+    /// Pops a value and a struct, sets the field named by the given constant and pushes the value
+    SetField(u32),
+    /// Calls the method named by the constant `name` on the value below the top `args` values
+    Invoke {
+        name: u32,
+        args: u32,
+    },
+    Call(u32),
+    /// Calls like `Call`, the last of the `args` values being passed by the names held by the
+    /// tuple constant `names`
+    CallNamed {
+        args: u32,
+        names: u32,
+    },
+    /// Invokes like `Invoke`, the last of the `args` values being passed by the names held by
+    /// the tuple constant `names`
+    InvokeNamed {
+        name: u32,
+        args: u32,
+        names: u32,
+    },
+    /// Replaces the top `n` values of the stack with a list of them
+    List(u32),
+    /// Replaces the top `n` key value pairs of the stack with a map of them
+    Map(u32),
+    Index,
+    /// Pops a value, index and target, stores the value at the index and pushes the value
+    SetIndex,
+    /// Pops the end, start and target, either bound may be unit
+    Slice,
+    /// Replaces the top `n` values of the stack with a tuple of them
+    Tuple(u32),
+    /// Replaces the value on top of the stack with its value at the given index, which is an
+    /// element of a tuple or list, a value held by an enum variant or a struct field in
+    /// declaration order
+    Element(u32),
+    /// Replaces the top of the stack with whether it is a tuple with the given number of values
+    IsTuple(u32),
+    /// Replaces the top of the stack with whether it is a list of `len` values, or at least
+    /// `len` values with `rest`
+    IsList {
+        len: u32,
+        rest: bool,
+    },
+    /// Replaces the top of the stack with whether it is an instance of the struct at the given
+    /// index of the program's structs
+    IsStruct(u32),
+    /// Fails because a value does not match the pattern it is destructured with
+    NoMatch,
+    /// Replaces the top of the stack with whether it is the variant `variant` of the enum at
+    /// index `def` of the program's enums
+    IsVariant {
+        def: u32,
+        variant: u32,
+    },
+    /// Duplicates the top value of the stack
+    Duplicate,
+    /// Duplicates the top two values of the stack
+    DupPair,
+
+    /// Replaces the top of the stack with an iterator over it
+    Iter,
+    /// Pushes the next value of the iterator in local `slot`, or jumps to `exit` when it is done
+    IterNext {
+        slot: u32,
+        exit: usize,
+    },
+
+    Print,
+    Return,
+    /// Suspends the generator running in the current frame, handing the value on top of the
+    /// stack to the code stepping it
+    Yield,
+    /// Replaces an `ok` on top of the stack with its value, or returns an `err` from the function
+    Try,
+    /// Fails with the value of an `err` on top of the stack, leaving any other value. It is what
+    /// `main` does with an `err` instead of returning it.
+    CheckErr,
+    /// Starts a task that calls the function below the given number of arguments with them,
+    /// taking both off the stack
+    Spawn(u32),
+    /// Starts a task like `Spawn`, the last of the `args` values being passed by the names held
+    /// by the tuple constant `names`
+    SpawnNamed {
+        args: u32,
+        names: u32,
+    },
+    /// Jumps to `target` if the parameter at `param` was passed to the current function, which
+    /// is given by the hidden local that follows the parameters. Otherwise its default value is
+    /// evaluated.
+    JumpIfPassed {
+        param: u32,
+        target: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// Source position of each instruction that can fail in a way worth pointing at
+    pub positions: Vec<Option<Position>>,
+    /// Name of the file the positions are in, `None` for source that was not read from a file
+    pub file: Option<Rc<str>>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Rc<Function>>,
+}
+
+impl Chunk {
+    /// Appends `op` and returns its index
+    pub fn push(&mut self, op: Op, position: Option<Position>) -> usize {
+        self.code.push(op);
+        self.positions.push(position);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    pub fn add_function(&mut self, function: Function) -> u32 {
+        self.functions.push(Rc::new(function));
+        (self.functions.len() - 1) as u32
+    }
+}
+
+/// A compiled function
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Params,
+    /// Whether a call gives an iterator that runs the function as it is stepped
+    pub generator: bool,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+/// The parameters of a function as its calls see them
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    /// Name of each parameter, empty for a destructured one, which can't be passed by name
+    pub names: Vec<Rc<str>>,
+    /// Number of parameters without a default value, which come before those with one
+    pub required: usize,
+    /// Whether a trailing parameter takes the positional arguments left over as a list
+    pub variadic: bool,
+}
+
+impl Params {
+    /// Whether some parameters have a default value
+    pub fn has_defaults(&self) -> bool {
+        self.required < self.names.len()
+    }
+
+    /// Number of stack slots the parameters take in the function's frame. A variadic
+    /// parameter takes one after the others, and when there are defaults a hidden local after
+    /// that records which parameters were passed.
+    pub fn slots(&self) -> usize {
+        self.names.len() + self.variadic as usize + self.has_defaults() as usize
+    }
+}
+
+/// Where a closure finds a captured variable when it is created
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// A local slot of the enclosing function
+    Local(u32),
+    /// An upvalue of the enclosing function
+    Upvalue(u32),
+}
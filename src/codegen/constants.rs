@@ -0,0 +1,649 @@
+//! Evaluation of constants at compile time.
+//!
+//! The evaluator walks the expression of a constant and the bodies of the `const fun`s it calls,
+//! using the operations of the VM so that a constant has the value its expression would have at
+//! run time. Constants of the module being compiled are evaluated when they are first needed, so
+//! they can refer to each other regardless of declaration order.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{Codegen, CodegenError, ModuleScope};
+use crate::modules::ModuleGraph;
+use crate::parser::ast::*;
+use crate::value::Value;
+use crate::vm::ops::{self, Bound};
+use crate::vm::RuntimeError;
+
+/// Nesting of `const fun` calls at which evaluation gives up, which stops runaway recursion
+/// before the recursion of the evaluator itself overflows the stack
+const MAX_DEPTH: usize = 48;
+
+/// Why evaluation of an expression stopped before producing a value
+enum Exit {
+    /// A `return` out of the `const fun` being called
+    Return(Value),
+    /// Boxed to keep the frames of the deeply recursive evaluation small
+    Error(Box<CodegenError>),
+}
+
+impl From<CodegenError> for Exit {
+    fn from(err: CodegenError) -> Self {
+        Exit::Error(Box::new(err))
+    }
+}
+
+type Eval<T> = Result<T, Exit>;
+
+/// The parameters and `val`s of a `const fun` call, or of the expression of a constant
+struct Frame {
+    /// Graph index of the module the code is declared in, which its names refer to
+    module: usize,
+    locals: HashMap<String, Value>,
+}
+
+pub(super) struct Evaluator<'a> {
+    codegen: &'a Codegen,
+    graph: &'a ModuleGraph,
+    /// Graph index of the module being compiled
+    current: usize,
+    /// Constants declared by the module being compiled
+    declared: HashMap<&'a str, &'a Expr>,
+    /// Values of the constants of the module being compiled evaluated so far
+    values: HashMap<String, Value>,
+    /// Constants whose evaluation is in progress, innermost last
+    evaluating: Vec<String>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// An evaluator for the constants in `declarations`, those of the module being compiled
+    pub(super) fn new(
+        codegen: &'a Codegen,
+        graph: &'a ModuleGraph,
+        declarations: &'a [Declaration],
+    ) -> Self {
+        let declared = declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Const(constant) => Some((constant.ident.0.as_str(), &constant.value)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            codegen,
+            graph,
+            current: codegen.modules.len(),
+            declared,
+            values: HashMap::new(),
+            evaluating: vec![],
+            frames: vec![],
+        }
+    }
+
+    /// The value of the constant `name` of the module being compiled
+    pub(super) fn constant(&mut self, name: &str) -> Result<Value, CodegenError> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(value.clone());
+        }
+        if self.evaluating.iter().any(|n| n == name) {
+            return Err(CodegenError::CyclicConstant {
+                name: name.to_string(),
+            });
+        }
+        // Only called for declared constants
+        let expr = self.declared[name];
+
+        // The expression doesn't see the locals of a `const fun` that refers to the constant
+        let frames = std::mem::replace(
+            &mut self.frames,
+            vec![Frame {
+                module: self.current,
+                locals: HashMap::new(),
+            }],
+        );
+        self.evaluating.push(name.to_string());
+        let result = self.expr(expr);
+        self.evaluating.pop();
+        self.frames = frames;
+
+        let value = match result {
+            Ok(value) => value,
+            Err(Exit::Error(err)) => return Err(*err),
+            Err(Exit::Return(_)) => return Err(not_constant("`return` outside of a function")),
+        };
+        self.values.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    // Helpers
+
+    fn frame(&mut self) -> &mut Frame {
+        // A frame is pushed before anything is evaluated
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Names defined in or imported into the module at graph index `module`
+    fn scope(&self, module: usize) -> &'a ModuleScope {
+        if module == self.current {
+            &self.codegen.scope
+        } else {
+            &self.codegen.modules[module]
+        }
+    }
+
+    /// Reports an operation that failed while evaluating the constant
+    fn fail(&self, err: RuntimeError) -> Exit {
+        Exit::from(CodegenError::ConstantFailed {
+            // Nothing is evaluated outside of a constant
+            name: self.evaluating.last().unwrap().clone(),
+            err,
+        })
+    }
+
+    fn op(&self, result: Result<Value, RuntimeError>) -> Eval<Value> {
+        result.map_err(|err| self.fail(err))
+    }
+
+    fn bool(&self, value: Value) -> Eval<bool> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(self.fail(RuntimeError::ExpectedBool {
+                found: other.type_name(),
+            })),
+        }
+    }
+
+    /// The graph index of the module `ident` names in the module at `module`, if it is the name
+    /// of a module imported as a whole that isn't shadowed
+    fn imported_module(&self, module: usize, ident: &Identifier) -> Option<usize> {
+        if self.frames.last()?.locals.contains_key(&ident.0) {
+            return None;
+        }
+        self.scope(module).module_slots.get(&ident.0).copied()
+    }
+
+    /// Checks that `name` is an item other modules can use from the module at `module`
+    fn check_public(&self, module: usize, name: &Identifier) -> Result<(), CodegenError> {
+        let scope = self.scope(module);
+        if scope.exports.contains(&name.0) {
+            return Ok(());
+        }
+        let defined =
+            scope.const_slots.contains_key(&name.0) || scope.global_slots.contains_key(&name.0);
+        Err(if defined {
+            CodegenError::PrivateItem {
+                module: scope.name.clone(),
+                name: name.0.clone(),
+                position: name.1,
+            }
+        } else {
+            CodegenError::UnknownItem {
+                module: scope.name.clone(),
+                name: name.0.clone(),
+                position: name.1,
+            }
+        })
+    }
+
+    /// The value of the constant `ident` in the module at `module`
+    fn named_constant(&mut self, module: usize, ident: &Identifier) -> Eval<Value> {
+        if module == self.current && self.declared.contains_key(ident.0.as_str()) {
+            return Ok(self.constant(&ident.0)?);
+        }
+        match self.scope(module).const_slots.get(&ident.0) {
+            Some(index) => Ok(self.codegen.constants[*index as usize].clone()),
+            None => Err(unsupported(&format!("`{}`", ident.0))),
+        }
+    }
+
+    // Expressions
+
+    fn expr(&mut self, expr: &Expr) -> Eval<Value> {
+        let Expr::Assignment(assignment) = expr;
+        match assignment {
+            Assignment::AssignedVal(_) | Assignment::Compound(_) => {
+                Err(unsupported("an assignment"))
+            }
+            Assignment::Coalesce(_) => Err(unsupported("`??`")),
+            Assignment::LogicOr(logic_or) => self.logic_or(logic_or),
+        }
+    }
+
+    fn logic_or(&mut self, logic_or: &LogicOr) -> Eval<Value> {
+        let left = match &logic_or.left {
+            LogicOrLeft::LogicAnd(left) => self.logic_and(left)?,
+            LogicOrLeft::LogicOr(left) => self.logic_or(left)?,
+        };
+        let Some(right) = &logic_or.right else {
+            return Ok(left);
+        };
+        if self.bool(left)? {
+            return Ok(Value::Bool(true));
+        }
+        let right = self.logic_and(right)?;
+        Ok(Value::Bool(self.bool(right)?))
+    }
+
+    fn logic_and(&mut self, logic_and: &LogicAnd) -> Eval<Value> {
+        let left = match &logic_and.left {
+            LogicAndLeft::Equality(left) => self.equality(left)?,
+            LogicAndLeft::LogicAnd(left) => self.logic_and(left)?,
+        };
+        let Some(right) = &logic_and.right else {
+            return Ok(left);
+        };
+        if !self.bool(left)? {
+            return Ok(Value::Bool(false));
+        }
+        let right = self.equality(right)?;
+        Ok(Value::Bool(self.bool(right)?))
+    }
+
+    fn equality(&mut self, equality: &Equality) -> Eval<Value> {
+        let left = match &equality.left {
+            EqualityLeft::Comparison(left) => self.comparison(left)?,
+            EqualityLeft::Equality(left) => self.equality(left)?,
+        };
+        let Some(right) = &equality.right else {
+            return Ok(left);
+        };
+        let equal = left.equals(&self.comparison(&right.right)?);
+        Ok(Value::Bool(match right.op {
+            EqualityOp::Equal => equal,
+            EqualityOp::NotEqual => !equal,
+        }))
+    }
+
+    fn comparison(&mut self, comparison: &Comparison) -> Eval<Value> {
+        let left = match &comparison.left {
+            ComparisonLeft::BitOr(left) => self.bit_or(left)?,
+            ComparisonLeft::Comparison(left) => self.comparison(left)?,
+        };
+        let Some(right) = &comparison.right else {
+            return Ok(left);
+        };
+        let value = self.bit_or(&right.right)?;
+        let (op, test): (_, fn(Ordering) -> bool) = match right.op {
+            ComparisonOp::Greater => (">", Ordering::is_gt),
+            ComparisonOp::GreaterEqual => (">=", Ordering::is_ge),
+            ComparisonOp::Less => ("<", Ordering::is_lt),
+            ComparisonOp::LessEqual => ("<=", Ordering::is_le),
+        };
+        let ordering = ops::compare(op, &left, &value).map_err(|err| self.fail(err))?;
+        Ok(Value::Bool(ordering.is_some_and(test)))
+    }
+
+    fn bit_or(&mut self, bit_or: &BitOr) -> Eval<Value> {
+        let left = match &bit_or.left {
+            BitOrLeft::BitXor(left) => self.bit_xor(left)?,
+            BitOrLeft::BitOr(left) => self.bit_or(left)?,
+        };
+        match &bit_or.right {
+            Some(right) => {
+                let right = self.bit_xor(&right.right)?;
+                self.op(ops::bit_or(left, right))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn bit_xor(&mut self, bit_xor: &BitXor) -> Eval<Value> {
+        let left = match &bit_xor.left {
+            BitXorLeft::BitAnd(left) => self.bit_and(left)?,
+            BitXorLeft::BitXor(left) => self.bit_xor(left)?,
+        };
+        match &bit_xor.right {
+            Some(right) => {
+                let right = self.bit_and(&right.right)?;
+                self.op(ops::bit_xor(left, right))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn bit_and(&mut self, bit_and: &BitAnd) -> Eval<Value> {
+        let left = match &bit_and.left {
+            BitAndLeft::Shift(left) => self.shift(left)?,
+            BitAndLeft::BitAnd(left) => self.bit_and(left)?,
+        };
+        match &bit_and.right {
+            Some(right) => {
+                let right = self.shift(&right.right)?;
+                self.op(ops::bit_and(left, right))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn shift(&mut self, shift: &Shift) -> Eval<Value> {
+        let left = match &shift.left {
+            ShiftLeft::Term(left) => self.term(left)?,
+            ShiftLeft::Shift(left) => self.shift(left)?,
+        };
+        let Some(right) = &shift.right else {
+            return Ok(left);
+        };
+        let value = self.term(&right.right)?;
+        self.op(match right.op {
+            ShiftOp::Left => ops::shift_left(left, value),
+            ShiftOp::Right => ops::shift_right(left, value),
+        })
+    }
+
+    fn term(&mut self, term: &Term) -> Eval<Value> {
+        let left = match &term.left {
+            TermLeft::Factor(left) => self.factor(left)?,
+            TermLeft::Term(left) => self.term(left)?,
+        };
+        let Some(right) = &term.right else {
+            return Ok(left);
+        };
+        let value = self.factor(&right.right)?;
+        self.op(match right.op {
+            TermOp::Plus => ops::add(left, value),
+            TermOp::Minus => ops::subtract(left, value),
+        })
+    }
+
+    fn factor(&mut self, factor: &Factor) -> Eval<Value> {
+        let left = match &factor.left {
+            FactorLeft::Unary(left) => self.unary(left)?,
+            FactorLeft::Factor(left) => self.factor(left)?,
+        };
+        let Some(right) = &factor.right else {
+            return Ok(left);
+        };
+        let value = self.unary(&right.right)?;
+        self.op(match right.op {
+            FactorOp::Mult => ops::multiply(left, value),
+            FactorOp::Div => ops::divide(left, value),
+            FactorOp::Mod => ops::modulo(left, value),
+        })
+    }
+
+    fn unary(&mut self, unary: &Unary) -> Eval<Value> {
+        let value = match unary.right.as_ref() {
+            UnaryRight::Unary(right) => self.unary(right)?,
+            UnaryRight::Power(power) => self.power(power)?,
+        };
+        match unary.op {
+            Some(UnaryOp::Minus) => self.op(ops::negate(value)),
+            Some(UnaryOp::Not) => self.op(ops::not(value)),
+            Some(UnaryOp::BitNot) => self.op(ops::bit_not(value)),
+            None => Ok(value),
+        }
+    }
+
+    fn power(&mut self, power: &Power) -> Eval<Value> {
+        let left = self.call(&power.left)?;
+        match &power.right {
+            Some(right) => {
+                let right = self.unary(&right.right)?;
+                self.op(ops::power(left, right))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn call(&mut self, call: &Call) -> Eval<Value> {
+        let Some(right) = &call.right else {
+            return self.call_left(&call.left);
+        };
+        let module = self.frame().module;
+        match right {
+            CallRight::Args { args, .. } => match &call.left {
+                CallLeft::Primary(Primary::Identifier(ident))
+                    if !self.frame().locals.contains_key(&ident.0) =>
+                {
+                    self.call_function(module, ident, args)
+                }
+                // `module.function(args)`
+                CallLeft::Call(inner) => match (&inner.left, &inner.right) {
+                    (
+                        CallLeft::Primary(Primary::Identifier(name)),
+                        Some(CallRight::Field { ident, .. }),
+                    ) => match self.imported_module(module, name) {
+                        Some(index) => {
+                            self.check_public(index, ident)?;
+                            self.call_function(index, ident, args)
+                        }
+                        None => Err(unsupported("a method call")),
+                    },
+                    _ => Err(unsupported("a call of a computed function")),
+                },
+                _ => Err(unsupported("a call of a computed function")),
+            },
+            CallRight::Index { index, .. } => {
+                let target = self.call_left(&call.left)?;
+                let index = self.expr(index)?;
+                self.op(ops::index(target, index))
+            }
+            // `module.CONSTANT`
+            CallRight::Field { ident, .. } => match &call.left {
+                CallLeft::Primary(Primary::Identifier(name)) => {
+                    match self.imported_module(module, name) {
+                        Some(index) => {
+                            self.check_public(index, ident)?;
+                            self.named_constant(index, ident)
+                        }
+                        None => Err(unsupported("a field")),
+                    }
+                }
+                _ => Err(unsupported("a field")),
+            },
+            CallRight::Slice { .. } => Err(unsupported("a slice")),
+            CallRight::SafeField { .. } => Err(unsupported("`?.`")),
+            CallRight::Element { .. } => Err(unsupported("a tuple element")),
+            CallRight::Try { .. } => Err(unsupported("`?`")),
+        }
+    }
+
+    fn call_left(&mut self, left: &CallLeft) -> Eval<Value> {
+        match left {
+            CallLeft::Primary(primary) => self.primary(primary),
+            CallLeft::Call(call) => self.call(call),
+        }
+    }
+
+    /// Calls the `const fun` called `ident` in the module at `module`
+    fn call_function(&mut self, module: usize, ident: &Identifier, args: &Args) -> Eval<Value> {
+        let Some((index, declaration)) = self.scope(module).const_functions.get(&ident.0) else {
+            return Err(unsupported(&format!("a call of `{}`", ident.0)));
+        };
+        let Declaration::Function(function) =
+            &self.graph.modules[*index].ast.declarations[*declaration]
+        else {
+            unreachable!("`const_functions` only holds functions")
+        };
+
+        let decl = function.args.as_ref();
+        let params = decl.map_or(&[][..], |a| &a.args[..]);
+        let defaults = decl.map_or(&[][..], |a| &a.defaults[..]);
+        let variadic = decl.and_then(|a| a.variadic.as_ref());
+        if self.frames.len() > MAX_DEPTH {
+            return Err(self.fail(RuntimeError::StackOverflow));
+        }
+
+        let mut positional = vec![];
+        for arg in &args.args {
+            positional.push(self.expr(arg)?);
+        }
+        let mut named = vec![];
+        for arg in &args.named {
+            named.push((arg.ident.0.as_str(), self.expr(&arg.value)?));
+        }
+        let signature = super::function_params(&ident.0, params, defaults, variadic.is_some())?;
+        let bound =
+            ops::bind(&ident.0, &signature, positional, named).map_err(|err| self.fail(err))?;
+
+        // Defaults are evaluated in the frame of the call, after the parameters before them
+        self.frames.push(Frame {
+            module: *index,
+            locals: HashMap::new(),
+        });
+        let result = self
+            .bind_params(params, defaults, variadic, bound)
+            .and_then(|()| self.block(&function.block));
+        self.frames.pop();
+        match result {
+            Ok(value) | Err(Exit::Return(value)) => Ok(value),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Binds the arguments `bound` to the parameters of a `const fun` in the frame on top,
+    /// evaluating the defaults of those left out
+    fn bind_params(
+        &mut self,
+        params: &[BindingPattern],
+        defaults: &[Option<Expr>],
+        variadic: Option<&BindingPattern>,
+        bound: Bound<Value>,
+    ) -> Eval<()> {
+        let args = params.iter().zip(defaults).zip(bound.params);
+        for ((param, default), arg) in args {
+            let value = match (arg, default) {
+                (Some(arg), _) => arg,
+                (None, Some(default)) => self.expr(default)?,
+                (None, None) => unreachable!("a required parameter was left out"),
+            };
+            self.bind_param(param, value)?;
+        }
+        if let Some(variadic) = variadic {
+            let rest = Value::List(Rc::new(RefCell::new(bound.rest)));
+            self.bind_param(variadic, rest)?;
+        }
+        Ok(())
+    }
+
+    fn bind_param(&mut self, param: &BindingPattern, value: Value) -> Eval<()> {
+        let Some(name) = param.ident() else {
+            return Err(unsupported("a destructured parameter"));
+        };
+        self.frame().locals.insert(name.0.clone(), value);
+        Ok(())
+    }
+
+    fn primary(&mut self, primary: &Primary) -> Eval<Value> {
+        Ok(match primary {
+            Primary::Int(literal) => {
+                Value::parse_int(literal).ok_or_else(|| CodegenError::InvalidLiteral {
+                    literal: literal.clone(),
+                })?
+            }
+            Primary::Float(literal) => Value::Float(parse(literal)?),
+            Primary::Decimal(literal) => {
+                Value::parse_decimal(literal).ok_or_else(|| CodegenError::InvalidLiteral {
+                    literal: literal.clone(),
+                })?
+            }
+            Primary::String(literal) => Value::String(literal.as_str().into()),
+            Primary::Char(c) => Value::Char(*c),
+            Primary::True => Value::Bool(true),
+            Primary::False => Value::Bool(false),
+            Primary::Identifier(ident) => {
+                if let Some(value) = self.frame().locals.get(&ident.0) {
+                    return Ok(value.clone());
+                }
+                let module = self.frame().module;
+                return self.named_constant(module, ident);
+            }
+            Primary::Grouping(expr) => return self.expr(expr),
+            Primary::If(if_expr) => return self.if_expr(if_expr),
+            Primary::Block(block) => return self.block(block),
+            Primary::None(_) => return Err(unsupported("`none`")),
+            Primary::List(_) => return Err(unsupported("a list")),
+            Primary::Map(_) => return Err(unsupported("a map")),
+            Primary::Tuple(_) => return Err(unsupported("a tuple")),
+            Primary::Struct(_) => return Err(unsupported("a struct literal")),
+            Primary::Lambda(_) => return Err(unsupported("a lambda")),
+            Primary::Match(_) => return Err(unsupported("a `match`")),
+            Primary::Loop(_) => return Err(unsupported("a `loop`")),
+        })
+    }
+
+    fn if_expr(&mut self, if_expr: &If) -> Eval<Value> {
+        if if_expr.binding.is_some() {
+            return Err(unsupported("`if val`"));
+        }
+        let condition = self.expr(&if_expr.expr)?;
+        if self.bool(condition)? {
+            return self.block(&if_expr.block);
+        }
+        match &if_expr.else_branch {
+            Some(Else::Block(block)) => self.block(block),
+            Some(Else::If(if_expr)) => self.if_expr(if_expr),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    /// The value of a block, which is that of its trailing expression statement
+    fn block(&mut self, block: &Block) -> Eval<Value> {
+        // `val`s go out of scope at the end of the block
+        let locals = self.frame().locals.clone();
+        let result = self.declarations(&block.declarations);
+        self.frame().locals = locals;
+        result
+    }
+
+    fn declarations(&mut self, declarations: &[Declaration]) -> Eval<Value> {
+        let mut value = Value::Unit;
+        for declaration in declarations {
+            value = Value::Unit;
+            match declaration {
+                Declaration::Variable(Variable {
+                    v_type: VariableType::Val,
+                    target,
+                    value: Some(expr),
+                    ..
+                }) => {
+                    let Some(ident) = target.ident() else {
+                        return Err(unsupported("a destructuring `val`"));
+                    };
+                    let value = self.expr(expr)?;
+                    self.frame().locals.insert(ident.0.clone(), value);
+                }
+                Declaration::Variable(_) => return Err(unsupported("a `var`")),
+                Declaration::Statement(Statement::Expression(expr)) => value = self.expr(expr)?,
+                Declaration::Statement(Statement::Return(ret)) => {
+                    let value = match &ret.expr {
+                        Some(expr) => self.expr(expr)?,
+                        None => Value::Unit,
+                    };
+                    return Err(Exit::Return(value));
+                }
+                Declaration::Statement(Statement::For(_)) => {
+                    return Err(unsupported("a `for` loop"))
+                }
+                Declaration::Statement(Statement::Print(_)) => return Err(unsupported("`print`")),
+                Declaration::Statement(Statement::Yield(_)) => return Err(unsupported("`yield`")),
+                Declaration::Statement(Statement::Spawn(_)) => return Err(unsupported("`spawn`")),
+                Declaration::Statement(Statement::Break(_) | Statement::Continue) => {
+                    return Err(unsupported("a `break` or `continue`"))
+                }
+                _ => return Err(unsupported("a nested declaration")),
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// The reason evaluation stops at `what`, which can't be used in a constant expression
+fn unsupported(what: &str) -> Exit {
+    not_constant(what).into()
+}
+
+fn not_constant(what: &str) -> CodegenError {
+    CodegenError::NotConstant {
+        what: what.to_string(),
+    }
+}
+
+fn parse<T: std::str::FromStr>(literal: &str) -> Result<T, CodegenError> {
+    literal.parse().map_err(|_| CodegenError::InvalidLiteral {
+        literal: literal.to_string(),
+    })
+}
@@ -0,0 +1,3568 @@
+pub mod bytecode;
+mod constants;
+mod patterns;
+mod types;
+
+use snafu::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::modules::{Module, ModuleGraph};
+use crate::parser::ast::*;
+use crate::token::Position;
+use crate::value::{
+    Builtin, Closure, EnumDef, EnumValue, StructDef, Value, VariantDef, ERR, INDEX, NEG, NEXT, OK,
+};
+use crate::vm::RuntimeError;
+use bytecode::{Capture, Chunk, Function, Op, Params};
+use constants::Evaluator;
+use types::{EnumTypes, Overload, Ty, TypeParams};
+
+/// Name of the function that is called after the top level declarations have run
+const ENTRY_POINT: &str = "main";
+
+/// Name given to functions created by lambda expressions
+const LAMBDA_NAME: &str = "<lambda>";
+
+/// Index of the prelude `Result` enum in the enums of every program
+pub const RESULT: usize = 0;
+
+/// Index of the prelude `Rounding` enum in the enums of every program
+pub const ROUNDING: usize = 1;
+
+#[derive(Debug, Snafu)]
+pub enum CodegenError {
+    #[snafu(display("codegen error - undefined variable `{name}` at {position}"))]
+    UndefinedVariable { name: String, position: Position },
+
+    #[snafu(display("codegen error - cannot assign to val `{name}` at {position}"))]
+    AssignToImmutable { name: String, position: Position },
+
+    #[snafu(display("codegen error - `{name}` is already defined at {position}"))]
+    DuplicateDefinition { name: String, position: Position },
+
+    #[snafu(display("codegen error - cannot apply `{op}` to {left} and {right}"))]
+    UnsupportedOperands {
+        op: &'static str,
+        left: String,
+        right: String,
+    },
+
+    #[snafu(display(
+        "codegen error - cannot apply `{op}` to {ty}, which has no method `{method}`"
+    ))]
+    NoOperatorMethod {
+        op: &'static str,
+        ty: String,
+        method: &'static str,
+    },
+
+    #[snafu(display("codegen error - invalid literal `{literal}`"))]
+    InvalidLiteral { literal: String },
+
+    #[snafu(display("codegen error - {what} outside of a function"))]
+    ReturnOutsideFunction { what: &'static str },
+
+    #[snafu(display("codegen error - {what} can't return a value from generator `{name}`"))]
+    GeneratorReturn { what: &'static str, name: String },
+
+    #[snafu(display("codegen error - `spawn` needs a call of a function, found {what}"))]
+    NotSpawnable { what: String },
+
+    #[snafu(display("codegen error - `{keyword}` outside of a loop"))]
+    OutsideLoop { keyword: &'static str },
+
+    #[snafu(display("codegen error - only `break` out of a `loop` can have a value"))]
+    BreakValue,
+
+    #[snafu(display("codegen error - `{keyword}` is only allowed at the top level"))]
+    NotTopLevel { keyword: &'static str },
+
+    #[snafu(display("codegen error - undefined struct `{name}` at {position}"))]
+    UndefinedStruct { name: String, position: Position },
+
+    #[snafu(display("codegen error - struct `{name}` has no field `{field}` at {position}"))]
+    UnknownField {
+        name: String,
+        field: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - struct `{name}` has no method `{method}` at {position}"))]
+    UnknownMethod {
+        name: String,
+        method: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - no struct has a field `{field}` at {position}"))]
+    UndefinedField { field: String, position: Position },
+
+    #[snafu(display("codegen error - no struct has a method `{method}` at {position}"))]
+    UndefinedMethod { method: String, position: Position },
+
+    #[snafu(display("codegen error - `{name}` is missing field `{field}` at {position}"))]
+    MissingField {
+        name: String,
+        field: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - enum `{name}` has no variant `{variant}` at {position}"))]
+    UnknownVariant {
+        name: String,
+        variant: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - undefined enum `{name}` at {position}"))]
+    UndefinedEnum { name: String, position: Position },
+
+    #[snafu(display(
+        "codegen error - `{name}` holds {expected} values, pattern has {found} at {position}"
+    ))]
+    PatternArity {
+        name: String,
+        expected: usize,
+        found: usize,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - `{name}` is bound twice in the same pattern at {position}"))]
+    DuplicateBinding { name: String, position: Position },
+
+    #[snafu(display("codegen error - `{name}` has no parameter `{argument}` at {position}"))]
+    UnknownArgument {
+        name: String,
+        argument: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - argument `{argument}` of `{name}` is passed more than once at {position}"
+    ))]
+    DuplicateArgument {
+        name: String,
+        argument: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - `{name}` expects {expected} arguments, found {found} at {position}"
+    ))]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - `{name}` expects at most {expected} arguments, found {found} at \
+         {position}"
+    ))]
+    TooManyArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - call of `{name}` is missing {argument} at {position}"))]
+    MissingArgument {
+        name: String,
+        argument: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - {param} of `{name}` needs a default value, as one before it has one"
+    ))]
+    MissingDefault { name: String, param: String },
+
+    #[snafu(display(
+        "codegen error - non-exhaustive match at {position}, `{missing}` is not covered"
+    ))]
+    NonExhaustiveMatch { missing: String, position: Position },
+
+    #[snafu(display("codegen error - unreachable match arm at {position}"))]
+    UnreachableArm { position: Position },
+
+    #[snafu(display("codegen error - module `{module}` has no item `{name}` at {position}"))]
+    UnknownItem {
+        module: String,
+        name: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - `{name}` is private to module `{module}` at {position}"))]
+    PrivateItem {
+        module: String,
+        name: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - {what} at {position} may be none, handle it with `?.`, `??` or `if val` \
+         first"
+    ))]
+    MaybeNone { what: String, position: Position },
+
+    #[snafu(display("codegen error - {what} is not optional and can't be none at {position}"))]
+    NotOptional { what: String, position: Position },
+
+    #[snafu(display(
+        "codegen error - top level `{name}` may be none, declare it with an optional type like \
+         `{name}: T?` at {position}"
+    ))]
+    OptionalGlobal { name: String, position: Position },
+
+    #[snafu(display("codegen error - undefined type `{name}` at {position}"))]
+    UndefinedType { name: String, position: Position },
+
+    #[snafu(display("codegen error - `{name}` takes {expected} type arguments, found {found}"))]
+    TypeArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[snafu(display(
+        "codegen error - expected {expected} for {what}, found {found} at {position}"
+    ))]
+    TypeMismatch {
+        what: String,
+        expected: String,
+        found: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - type parameter `{param}` of `{owner}` can't be both {first} and {second} \
+         at {position}"
+    ))]
+    ConflictingTypes {
+        param: String,
+        owner: String,
+        first: String,
+        second: String,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - undefined trait `{name}` at {position}"))]
+    UndefinedTrait { name: String, position: Position },
+
+    #[snafu(display("codegen error - `{ty}` does not implement trait `{name}` at {position}"))]
+    NotImplemented {
+        ty: String,
+        name: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - trait `{trait_name}` is already implemented for `{name}` at {position}"
+    ))]
+    DuplicateImpl {
+        name: String,
+        trait_name: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - `{name}` is missing method `{method}` of trait `{trait_name}` at \
+         {position}"
+    ))]
+    MissingMethod {
+        name: String,
+        method: String,
+        trait_name: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - `{method}` is not a method of trait `{trait_name}` at {position}"
+    ))]
+    NotTraitMethod {
+        method: String,
+        trait_name: String,
+        position: Position,
+    },
+
+    #[snafu(display(
+        "codegen error - `{method}` of `{name}` takes {found} parameters, trait `{trait_name}` \
+         declares {expected} at {position}"
+    ))]
+    TraitMethodArity {
+        name: String,
+        method: String,
+        trait_name: String,
+        expected: usize,
+        found: usize,
+        position: Position,
+    },
+
+    #[snafu(display("codegen error - `{ty}` has no method `{method}`"))]
+    NoTraitMethod { ty: String, method: String },
+
+    #[snafu(display("codegen error - {what} can't be used in a constant expression"))]
+    NotConstant { what: String },
+
+    #[snafu(display("codegen error - evaluating constant `{name}` failed with `{err}`"))]
+    ConstantFailed { name: String, err: RuntimeError },
+
+    #[snafu(display("codegen error - constant `{name}` depends on itself"))]
+    CyclicConstant { name: String },
+
+    #[snafu(display("codegen error - cannot assign to constant `{name}` at {position}"))]
+    AssignToConstant { name: String, position: Position },
+
+    /// An error in a module read from a file
+    #[snafu(display("{err} in {file}"))]
+    InFile {
+        err: Box<CodegenError>,
+        file: String,
+    },
+}
+
+/// The output of code generation, ready to be run by the VM
+#[derive(Debug)]
+pub struct Program {
+    pub script: Rc<Function>,
+    pub globals: Vec<String>,
+    pub structs: Vec<Rc<StructDef>>,
+    pub enums: Vec<Rc<EnumDef>>,
+}
+
+/// What is known at compile time about the value of a variable
+#[derive(Default, Clone)]
+struct Known {
+    /// Index of the struct the variable is known to hold
+    struct_type: Option<u32>,
+    /// Whether the variable may hold `none`
+    optional: bool,
+    /// Signature of the function the variable is known to hold
+    signature: Option<Signature>,
+    /// Type of the variable's value
+    ty: Ty,
+}
+
+impl Known {
+    /// Whether the variable may hold `none`, as declared or as its type says
+    fn may_be_none(&self) -> bool {
+        self.optional || self.ty.is_optional()
+    }
+}
+
+/// Which parameters of a function are optional and whether it may return `none`, along with
+/// their declared types
+#[derive(Debug, Clone, Default)]
+struct Signature {
+    params: Vec<bool>,
+    returns_optional: bool,
+    /// Type parameters inferred at each call
+    type_params: Vec<String>,
+    /// Traits the types inferred for the type parameters must implement
+    bounds: Vec<(String, u32)>,
+    /// Declared type of each parameter, unknown when it has none
+    types: Vec<Ty>,
+    ret: Ty,
+    /// Name of each parameter that can be passed by name, when they are known
+    names: Option<Vec<String>>,
+    /// Whether the last of `params` and `types` is a variadic parameter, whose type is that of
+    /// each argument it takes
+    variadic: bool,
+    /// Number of parameters without a default value, which come before those with one, when
+    /// the number of arguments calls must pass is known
+    required: Option<usize>,
+}
+
+impl Signature {
+    /// Index in `params` and `types` of the parameter taking the positional argument at `i`,
+    /// counting the receiver of a method call
+    fn positional(&self, i: usize) -> Option<usize> {
+        let fixed = self.params.len() - self.variadic as usize;
+        if i < fixed {
+            Some(i)
+        } else {
+            self.variadic.then_some(fixed)
+        }
+    }
+
+    /// Index in `params` and `types` of the parameter named `name`
+    fn named(&self, name: &str) -> Option<usize> {
+        self.names.as_ref()?.iter().position(|n| n == name)
+    }
+
+    /// Describes the parameter at `index` in errors
+    fn describe(&self, index: usize) -> String {
+        match self.names.as_ref().and_then(|names| names.get(index)) {
+            Some(name) if !name.is_empty() => format!("argument `{}`", name),
+            _ => format!("argument {}", index + 1),
+        }
+    }
+}
+
+struct Global {
+    name: String,
+    mutable: bool,
+    known: Known,
+}
+
+struct Local {
+    name: String,
+    /// Stack slot relative to the frame, which can be above the local count when a block
+    /// expression declares locals on top of temporaries
+    slot: u32,
+    depth: usize,
+    mutable: bool,
+    known: Known,
+}
+
+struct StructState {
+    name: String,
+    type_params: Vec<String>,
+    bounds: Vec<(String, u32)>,
+    fields: Vec<String>,
+    /// Whether each field is declared optional
+    optional: Vec<bool>,
+    /// Declared type of each field, resolved once every type of the module is declared
+    field_types: Vec<Ty>,
+    /// Declared before any method is compiled, so methods can call each other
+    method_names: Vec<String>,
+    signatures: HashMap<String, Signature>,
+    methods: HashMap<String, Rc<Closure>>,
+    /// Traits the struct implements
+    traits: Vec<u32>,
+}
+
+struct TraitState {
+    name: String,
+    /// Methods an implementation must have, in declaration order
+    method_names: Vec<String>,
+    signatures: HashMap<String, Signature>,
+}
+
+struct LoopState {
+    start: usize,
+    /// Stack depth at the start of each iteration, a `break` or `continue` pops down to it
+    stack_depth: usize,
+    breaks: Vec<usize>,
+    /// Slot holding the value of a `loop` expression, `for` loops have no value
+    result: Option<u32>,
+    /// Description and position of a value given to `break` that may be `none`
+    optional: Option<(String, Position)>,
+}
+
+/// Per function compilation state
+struct FunctionState {
+    function: Function,
+    locals: Vec<Local>,
+    upvalues: Vec<Capture>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+    /// Number of values the code emitted so far leaves on the stack of the frame
+    stack_depth: usize,
+    /// Stack depth at each forward jump, which is the depth at its target
+    jump_depths: HashMap<usize, usize>,
+    /// Whether this is the top level code of a module rather than a function
+    top_level: bool,
+    /// Whether the function may return `none`
+    returns_optional: bool,
+    /// Whether any value returned so far may be `none`
+    returned_none: bool,
+    /// Position of the name of the function if it is the `main` of the root module, an `err`
+    /// it returns fails the program there
+    entry_point: Option<Position>,
+    /// Type parameters in scope, those of the function and the declarations enclosing it
+    type_params: Vec<String>,
+    bounds: Vec<(String, u32)>,
+}
+
+impl FunctionState {
+    fn new(name: &str, params: Params, scope_depth: usize, file: Option<Rc<str>>) -> Self {
+        let slots = params.slots();
+        Self {
+            function: Function {
+                name: name.to_string(),
+                params,
+                generator: false,
+                chunk: Chunk {
+                    file,
+                    ..Chunk::default()
+                },
+                captures: vec![],
+            },
+            locals: vec![],
+            upvalues: vec![],
+            scope_depth,
+            loops: vec![],
+            stack_depth: slots,
+            jump_depths: HashMap::new(),
+            // Functions start in the scope of their parameters
+            top_level: scope_depth == 0,
+            returns_optional: false,
+            returned_none: false,
+            entry_point: None,
+            type_params: vec![],
+            bounds: vec![],
+        }
+    }
+}
+
+/// Names defined in or imported into a module
+#[derive(Default)]
+struct ModuleScope {
+    name: String,
+    global_slots: HashMap<String, u32>,
+    struct_slots: HashMap<String, u32>,
+    enum_slots: HashMap<String, u32>,
+    trait_slots: HashMap<String, u32>,
+    /// Index of each constant in the program's constants
+    const_slots: HashMap<String, u32>,
+    /// Graph index of the module declaring each `const fun` and the index of its declaration
+    const_functions: HashMap<String, (usize, usize)>,
+    /// Graph index of each module imported as a whole, by the name it is imported as
+    module_slots: HashMap<String, usize>,
+    /// Names other modules can import
+    exports: HashSet<String>,
+}
+
+/// An item of another module used as `module.name`
+enum Member {
+    Global(u32),
+    /// A constant, by index in the program's constants
+    Constant(u32),
+}
+
+enum Resolved {
+    Local(u32, bool),
+    Upvalue(u32, bool),
+    Global(u32, bool),
+    Builtin(Builtin),
+    /// A constant, by index in the program's constants
+    Constant(u32),
+    /// `ok` or `err`, the constructor of a variant of the prelude `Result`
+    Constructor(usize),
+}
+
+/// Compiles an ast into bytecode
+pub struct Codegen {
+    globals: Vec<Global>,
+    structs: Vec<StructState>,
+    enums: Vec<Rc<EnumDef>>,
+    /// Declared types of the enums, by the same index
+    enum_types: Vec<EnumTypes>,
+    traits: Vec<TraitState>,
+    /// Values of the constants of every module, which are inlined where they are used
+    constants: Vec<Value>,
+    functions: Vec<FunctionState>,
+    /// The module being compiled
+    scope: ModuleScope,
+    /// Modules that have been compiled, by graph index
+    modules: Vec<ModuleScope>,
+    /// Name of the file of the module being compiled
+    file: Option<Rc<str>>,
+    /// Whether the module being compiled is the root module, whose `main` is called once the top
+    /// level code of every module has run
+    root: bool,
+    /// Description and position of the value of the expression compiled last if it may be
+    /// `none`. Each expression sets it and whatever uses the value takes it.
+    optional: Option<(String, Position)>,
+    /// Enum, variant and position of each enum value declared optional
+    optional_payloads: HashSet<(u32, usize, usize)>,
+    /// Type of each lambda compiled so far, by its address in the ast. Lambdas declare no
+    /// result, so whether they may return `none` is only known from their body.
+    lambda_types: HashMap<*const Lambda, Ty>,
+}
+
+impl Default for Codegen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Self {
+            globals: vec![],
+            structs: vec![],
+            enums: vec![Rc::new(EnumDef::result()), Rc::new(EnumDef::rounding())],
+            enum_types: vec![EnumTypes::result(), EnumTypes::rounding()],
+            traits: vec![],
+            constants: vec![],
+            functions: vec![],
+            scope: ModuleScope::default(),
+            modules: vec![],
+            file: None,
+            root: false,
+            optional: None,
+            optional_payloads: HashSet::new(),
+            lambda_types: HashMap::new(),
+        }
+    }
+
+    /// Compiles the modules of `graph` into a program that runs the top level code of each
+    /// module in order, then the `main` function of the root module
+    pub fn generate(mut self, graph: &ModuleGraph) -> Result<Program, CodegenError> {
+        self.functions
+            .push(FunctionState::new("<script>", Params::default(), 0, None));
+
+        for (i, module) in graph.modules.iter().enumerate() {
+            self.root = i + 1 == graph.modules.len();
+            let file = graph.file_name(module);
+            self.file = file.clone().map(Rc::from);
+            self.module(graph, module).map_err(|err| match file {
+                Some(file) => CodegenError::InFile {
+                    err: Box::new(err),
+                    file,
+                },
+                None => err,
+            })?;
+        }
+
+        // The root module is last
+        if let (Some(root), Some(scope)) = (graph.modules.last(), self.modules.last()) {
+            if let Some(slot) = scope.global_slots.get(ENTRY_POINT).copied() {
+                let is_function = root
+                    .ast
+                    .declarations
+                    .iter()
+                    .any(|d| matches!(d, Declaration::Function(f) if f.ident.0 == ENTRY_POINT));
+                if is_function {
+                    self.emit(Op::GetGlobal(slot));
+                    self.emit(Op::Call(0));
+                    self.emit(Op::Pop);
+                }
+            }
+        }
+        self.emit(Op::Unit);
+        self.emit(Op::Return);
+
+        // This unwrap is safe because the script state was pushed above
+        let script = self.functions.pop().unwrap().function;
+        let structs = self
+            .structs
+            .into_iter()
+            .map(|s| {
+                Rc::new(StructDef {
+                    name: s.name,
+                    fields: s.fields,
+                    methods: s.methods,
+                })
+            })
+            .collect();
+        Ok(Program {
+            script: Rc::new(script),
+            globals: self.globals.into_iter().map(|g| g.name).collect(),
+            structs,
+            enums: self.enums,
+        })
+    }
+
+    /// Compiles a module into a function that runs its top level code and calls it
+    fn module(&mut self, graph: &ModuleGraph, module: &Module) -> Result<(), CodegenError> {
+        self.scope = ModuleScope {
+            name: module.name.clone(),
+            ..ModuleScope::default()
+        };
+        let declarations = &module.ast.declarations;
+
+        // The loader resolves every import of the module
+        let mut imports = module.imports.iter();
+        for declaration in declarations {
+            if let Declaration::Import(import) = declaration {
+                let index = *imports.next().unwrap();
+                self.import(import, index)?;
+            }
+        }
+
+        // Top level names are visible everywhere, and functions are defined before any top level
+        // code runs so that they can be called regardless of declaration order.
+        for declaration in declarations {
+            if let Declaration::Trait(decl) = declaration {
+                self.declare_trait(decl)?;
+            }
+        }
+        for declaration in declarations {
+            match declaration {
+                Declaration::Struct(decl) => self.declare_struct(decl)?,
+                Declaration::Enum(decl) => self.declare_enum(decl)?,
+                _ => {}
+            }
+        }
+        // The prelude enums can be shadowed by any type or module
+        for prelude in [RESULT, ROUNDING] {
+            let name = &self.enums[prelude].name;
+            if !self.scope.struct_slots.contains_key(name)
+                && !self.scope.trait_slots.contains_key(name)
+                && !self.scope.module_slots.contains_key(name)
+            {
+                self.scope
+                    .enum_slots
+                    .entry(name.clone())
+                    .or_insert(prelude as u32);
+            }
+        }
+        // Types can refer to each other regardless of declaration order
+        for declaration in declarations {
+            match declaration {
+                Declaration::Struct(decl) => self.field_types(decl)?,
+                Declaration::Enum(decl) => self.variant_types(decl)?,
+                Declaration::Trait(decl) => self.trait_methods(decl)?,
+                _ => {}
+            }
+        }
+        for declaration in declarations {
+            if let Declaration::Impl(decl) = declaration {
+                self.declare_methods(decl)?;
+            }
+        }
+        self.constants(graph, declarations)?;
+        for declaration in declarations {
+            match declaration {
+                Declaration::Function(function) => {
+                    let slot = self.declare_global(&function.ident, false)?;
+                    let signature = self.signature(function, &[], &TypeParams::default())?;
+                    self.globals[slot as usize].known.signature = Some(signature);
+                    self.export(&function.ident, function.public);
+                }
+                Declaration::Variable(variable) => {
+                    let mutable = is_mutable(&variable.v_type);
+                    let optional = self.optional_bindings(&variable.target.pattern)?;
+                    for name in self.binding_names(&variable.target.pattern)? {
+                        let slot = self.declare_global(&name, mutable)?;
+                        // Types without a declaration are inferred when the variable is defined
+                        let ty = match variable.target.ident() {
+                            Some(_) => self.annotation(variable.target.ty.as_ref())?,
+                            None => Ty::Unknown,
+                        };
+                        let known = Known {
+                            struct_type: self.known_struct(variable),
+                            optional: declared_optional(variable) || optional.contains(&name.0),
+                            signature: None,
+                            ty,
+                        };
+                        self.globals[slot as usize].known = known;
+                        self.export(&name, variable.public);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for declaration in declarations {
+            if let Declaration::Impl(decl) = declaration {
+                self.methods(decl)?;
+            }
+        }
+
+        let name = format!("<module {}>", module.name);
+        self.functions.push(FunctionState::new(
+            &name,
+            Params::default(),
+            0,
+            self.file.clone(),
+        ));
+        for declaration in declarations {
+            if let Declaration::Function(function) = declaration {
+                self.function(function)?;
+            }
+        }
+        for declaration in declarations {
+            match declaration {
+                Declaration::Function(_)
+                | Declaration::Struct(_)
+                | Declaration::Impl(_)
+                | Declaration::Enum(_)
+                | Declaration::Trait(_)
+                | Declaration::Const(_)
+                | Declaration::Import(_) => {}
+                _ => self.declaration(declaration)?,
+            }
+        }
+        self.emit(Op::Unit);
+        self.emit(Op::Return);
+
+        // This unwrap is safe because the state was pushed above
+        let function = self.functions.pop().unwrap().function;
+        let index = self.chunk().add_function(function);
+        self.emit(Op::Closure(index));
+        self.emit(Op::Call(0));
+        self.emit(Op::Pop);
+
+        let scope = std::mem::take(&mut self.scope);
+        self.modules.push(scope);
+        Ok(())
+    }
+
+    /// Brings the module at graph index `index`, or the items listed by `import`, into scope
+    fn import(&mut self, import: &Import, index: usize) -> Result<(), CodegenError> {
+        let module = &self.modules[index];
+        let Some(items) = &import.items else {
+            // The path has at least one segment
+            let ident = import.path.last().unwrap();
+            let name = &ident.0;
+            if self.scope.module_slots.contains_key(name) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: name.clone(),
+                    position: ident.1,
+                });
+            }
+            self.scope.module_slots.insert(name.clone(), index);
+            return Ok(());
+        };
+
+        for item in items {
+            let name = &item.0;
+            let defined = module.global_slots.contains_key(name)
+                || module.struct_slots.contains_key(name)
+                || module.enum_slots.contains_key(name)
+                || module.trait_slots.contains_key(name)
+                || module.const_slots.contains_key(name);
+            if !module.exports.contains(name) {
+                return Err(if defined {
+                    CodegenError::PrivateItem {
+                        module: module.name.clone(),
+                        name: name.clone(),
+                        position: item.1,
+                    }
+                } else {
+                    CodegenError::UnknownItem {
+                        module: module.name.clone(),
+                        name: name.clone(),
+                        position: item.1,
+                    }
+                });
+            }
+
+            if let Some(function) = module.const_functions.get(name) {
+                self.scope.const_functions.insert(name.clone(), *function);
+            }
+            let (slots, source) = if let Some(slot) = module.global_slots.get(name) {
+                (&mut self.scope.global_slots, *slot)
+            } else if let Some(slot) = module.struct_slots.get(name) {
+                (&mut self.scope.struct_slots, *slot)
+            } else if let Some(slot) = module.trait_slots.get(name) {
+                (&mut self.scope.trait_slots, *slot)
+            } else if let Some(index) = module.const_slots.get(name) {
+                (&mut self.scope.const_slots, *index)
+            } else {
+                (&mut self.scope.enum_slots, module.enum_slots[name])
+            };
+            if slots.insert(name.clone(), source).is_some()
+                || self.scope.module_slots.contains_key(name)
+            {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: name.clone(),
+                    position: item.1,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets other modules import `ident` if it is declared `pub`
+    fn export(&mut self, ident: &Identifier, public: bool) {
+        if public {
+            self.scope.exports.insert(ident.0.clone());
+        }
+    }
+
+    /// The item `module.name` if `left` names a module imported as a whole
+    fn module_member(
+        &self,
+        left: &CallLeft,
+        ident: &Identifier,
+    ) -> Result<Option<Member>, CodegenError> {
+        let CallLeft::Primary(Primary::Identifier(name)) = left else {
+            return Ok(None);
+        };
+        let Some(index) = self.scope.module_slots.get(&name.0).copied() else {
+            return Ok(None);
+        };
+        let is_local = self
+            .functions
+            .iter()
+            .any(|state| state.locals.iter().any(|l| l.name == name.0));
+        if is_local {
+            return Ok(None);
+        }
+
+        let module = &self.modules[index];
+        let member = match module.global_slots.get(&ident.0) {
+            Some(slot) => Some(Member::Global(*slot)),
+            None => module
+                .const_slots
+                .get(&ident.0)
+                .map(|i| Member::Constant(*i)),
+        };
+        match member {
+            Some(member) if module.exports.contains(&ident.0) => Ok(Some(member)),
+            Some(_) => Err(CodegenError::PrivateItem {
+                module: module.name.clone(),
+                name: ident.0.clone(),
+                position: ident.1,
+            }),
+            None => Err(CodegenError::UnknownItem {
+                module: module.name.clone(),
+                name: ident.0.clone(),
+                position: ident.1,
+            }),
+        }
+    }
+
+    // Helpers
+
+    fn state(&mut self) -> &mut FunctionState {
+        // There is always at least the script state while generating
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.push(op, None)
+    }
+
+    /// Emits an instruction whose runtime errors are reported at `position`
+    fn emit_at(&mut self, op: Op, position: Position) -> usize {
+        self.push(op, Some(position))
+    }
+
+    fn push(&mut self, op: Op, position: Option<Position>) -> usize {
+        let effect = self.stack_effect(&op);
+        let state = self.state();
+        let index = state.function.chunk.push(op, position);
+        if matches!(
+            op,
+            Op::Jump(_)
+                | Op::JumpIfFalse(_)
+                | Op::JumpIfTrue(_)
+                | Op::JumpIfNone(_)
+                | Op::JumpIfPassed { .. }
+                | Op::IterNext { .. }
+        ) {
+            state.jump_depths.insert(index, state.stack_depth);
+        }
+        state.stack_depth = (state.stack_depth as isize + effect) as usize;
+        index
+    }
+
+    /// Change in the stack depth when `op` falls through to the next instruction
+    fn stack_effect(&self, op: &Op) -> isize {
+        match *op {
+            Op::Constant(_)
+            | Op::Unit
+            | Op::True
+            | Op::False
+            | Op::GetLocal(_)
+            | Op::GetGlobal(_)
+            | Op::GetUpvalue(_)
+            | Op::Closure(_)
+            | Op::Duplicate
+            | Op::IterNext { .. } => 1,
+            Op::DupPair => 2,
+            Op::SetLocal(_)
+            | Op::SetGlobal(_)
+            | Op::SetUpvalue(_)
+            | Op::Negate
+            | Op::Not
+            | Op::BitNot
+            | Op::Jump(_)
+            | Op::JumpIfFalse(_)
+            | Op::JumpIfTrue(_)
+            | Op::JumpIfNone(_)
+            | Op::JumpIfPassed { .. }
+            | Op::GetField(_)
+            | Op::Element(_)
+            | Op::IsTuple(_)
+            | Op::IsList { .. }
+            | Op::IsStruct(_)
+            | Op::IsVariant { .. }
+            | Op::NoMatch
+            | Op::Iter
+            | Op::Try
+            | Op::CheckErr => 0,
+            Op::Pop
+            | Op::DefineGlobal(_)
+            | Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Modulo
+            | Op::Power
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::ShiftLeft
+            | Op::ShiftRight
+            | Op::Equal
+            | Op::NotEqual
+            | Op::Greater
+            | Op::GreaterEqual
+            | Op::Less
+            | Op::LessEqual
+            | Op::SetField(_)
+            | Op::Index
+            | Op::Print
+            | Op::Return
+            | Op::Yield => -1,
+            Op::SetIndex | Op::Slice => -2,
+            Op::Construct(index) => 1 - self.structs[index as usize].fields.len() as isize,
+            Op::Invoke { args, .. }
+            | Op::InvokeNamed { args, .. }
+            | Op::Call(args)
+            | Op::CallNamed { args, .. } => -(args as isize),
+            Op::Spawn(args) | Op::SpawnNamed { args, .. } => -(args as isize) - 1,
+            Op::List(count) | Op::Tuple(count) => 1 - count as isize,
+            Op::Map(count) => 1 - 2 * count as isize,
+        }
+    }
+
+    /// Emits the instruction of an operator, or a call of the method overloading it for the struct
+    /// that is its left operand, which takes the other `args` operands
+    fn emit_operator(&mut self, op: Op, overload: Option<Overload>, args: u32, position: Position) {
+        let Some(overload) = overload else {
+            self.emit_at(op, position);
+            return;
+        };
+        let name = self
+            .chunk()
+            .add_constant(Value::String(overload.method.into()));
+        self.emit_at(Op::Invoke { name, args }, position);
+        match op {
+            Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual => {
+                // `cmp` orders the struct against the operand as its result is against zero
+                self.emit_constant(Value::Int(0));
+                self.emit_at(op, position);
+            }
+            _ => {
+                self.optional = overload
+                    .returns_optional
+                    .then(|| (format!("the result of `{}`", overload.method), position));
+            }
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let index = self.chunk().add_constant(value);
+        self.emit(Op::Constant(index));
+    }
+
+    /// Points the jump at `index` to the next instruction to be emitted
+    fn patch(&mut self, index: usize) {
+        let target = self.chunk().code.len();
+        match &mut self.chunk().code[index] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::JumpIfTrue(t) | Op::JumpIfNone(t) => *t = target,
+            Op::IterNext { exit, .. } | Op::JumpIfPassed { target: exit, .. } => *exit = target,
+            op => unreachable!("patching non jump instruction {:?}", op),
+        }
+
+        // The instruction before the target may not fall through, the jump always arrives
+        let state = self.state();
+        state.stack_depth = state.jump_depths[&index];
+    }
+
+    fn declare_global(&mut self, ident: &Identifier, mutable: bool) -> Result<u32, CodegenError> {
+        if self.scope.global_slots.contains_key(&ident.0)
+            || self.scope.const_slots.contains_key(&ident.0)
+            || self.scope.module_slots.contains_key(&ident.0)
+        {
+            return Err(CodegenError::DuplicateDefinition {
+                name: ident.0.clone(),
+                position: ident.1,
+            });
+        }
+
+        let slot = self.globals.len() as u32;
+        self.globals.push(Global {
+            name: ident.0.clone(),
+            mutable,
+            known: Known::default(),
+        });
+        self.scope.global_slots.insert(ident.0.clone(), slot);
+        Ok(slot)
+    }
+
+    /// Adds a local for the value on top of the stack and returns its slot
+    fn add_local(&mut self, name: &str, mutable: bool) -> u32 {
+        let slot = self.state().stack_depth as u32 - 1;
+        self.declare_local(name, mutable, slot);
+        slot
+    }
+
+    fn declare_local(&mut self, name: &str, mutable: bool, slot: u32) {
+        let state = self.state();
+        state.locals.push(Local {
+            name: name.to_string(),
+            slot,
+            depth: state.scope_depth,
+            mutable,
+            known: Known::default(),
+        });
+    }
+
+    /// Marks the local added last as one that may hold `none`
+    fn mark_optional(&mut self) {
+        // Callers always add a local first
+        self.state().locals.last_mut().unwrap().known.optional = true;
+    }
+
+    fn resolve(&mut self, ident: &Identifier) -> Result<Resolved, CodegenError> {
+        let state = self.state();
+        if let Some(local) = state.locals.iter().rev().find(|l| l.name == ident.0) {
+            return Ok(Resolved::Local(local.slot, local.mutable));
+        }
+
+        let current = self.functions.len() - 1;
+        if let Some((index, mutable)) = self.resolve_upvalue(current, &ident.0) {
+            return Ok(Resolved::Upvalue(index, mutable));
+        }
+
+        if let Some(index) = self.scope.const_slots.get(&ident.0) {
+            return Ok(Resolved::Constant(*index));
+        }
+        if let Some(slot) = self.scope.global_slots.get(&ident.0) {
+            return Ok(Resolved::Global(
+                *slot,
+                self.globals[*slot as usize].mutable,
+            ));
+        }
+
+        // Builtins can be shadowed by any definition
+        if let Some(variant) = result_constructor(&ident.0) {
+            return Ok(Resolved::Constructor(variant));
+        }
+        match Builtin::lookup(&ident.0) {
+            Some(builtin) => Ok(Resolved::Builtin(builtin)),
+            None => Err(CodegenError::UndefinedVariable {
+                name: ident.0.clone(),
+                position: ident.1,
+            }),
+        }
+    }
+
+    /// Finds `name` in the functions enclosing the function at `function`, capturing it in every
+    /// function in between. Returns the upvalue index and whether the variable is mutable.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<(u32, bool)> {
+        if function == 0 {
+            return None;
+        }
+
+        let enclosing = &self.functions[function - 1];
+        let (capture, mutable) = match enclosing.locals.iter().rev().find(|l| l.name == name) {
+            Some(local) => (Capture::Local(local.slot), local.mutable),
+            None => {
+                let (index, mutable) = self.resolve_upvalue(function - 1, name)?;
+                (Capture::Upvalue(index), mutable)
+            }
+        };
+
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|u| *u == capture) {
+            return Some((index as u32, mutable));
+        }
+        upvalues.push(capture);
+        Some(((upvalues.len() - 1) as u32, mutable))
+    }
+
+    /// The struct a `val` is known to hold because it is initialized with a struct literal
+    fn known_struct(&self, variable: &Variable) -> Option<u32> {
+        variable.target.ident()?;
+        let value = variable.value.as_ref()?;
+        match (&variable.v_type, value.as_primary()) {
+            (VariableType::Val, Some(Primary::Struct(literal))) => {
+                self.scope.struct_slots.get(&literal.ident.0).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// What is known about the variable `ident` refers to
+    fn known(&self, ident: &Identifier) -> Option<&Known> {
+        // Captured variables are the same binding, so what is known carries over
+        for state in self.functions.iter().rev() {
+            if let Some(local) = state.locals.iter().rev().find(|l| l.name == ident.0) {
+                return Some(&local.known);
+            }
+        }
+        self.scope
+            .global_slots
+            .get(&ident.0)
+            .map(|slot| &self.globals[*slot as usize].known)
+    }
+
+    /// The value of the constant `ident` refers to, if it isn't shadowed by a variable
+    fn constant(&self, ident: &Identifier) -> Option<&Value> {
+        let is_local = self
+            .functions
+            .iter()
+            .any(|state| state.locals.iter().any(|l| l.name == ident.0));
+        if is_local {
+            return None;
+        }
+        let index = self.scope.const_slots.get(&ident.0)?;
+        Some(&self.constants[*index as usize])
+    }
+
+    /// The struct the value of `left` is known to hold, only tracked for variables
+    fn static_struct(&self, left: &CallLeft) -> Option<u32> {
+        let CallLeft::Primary(Primary::Identifier(ident)) = left else {
+            return None;
+        };
+        self.known(ident)?.struct_type
+    }
+
+    /// The type of the value of `left` along with the traits it implements, when it is a trait
+    /// object or a type parameter with bounds. Only their methods can be called on it.
+    fn receiver_traits(&self, left: &CallLeft) -> Option<(Ty, Vec<u32>)> {
+        let ty = self.left_type(left).plain();
+        let traits = match &ty {
+            Ty::Dyn(index) => vec![*index],
+            Ty::Param(param) => self.visible_bounds(param),
+            _ => return None,
+        };
+        (!traits.is_empty()).then_some((ty, traits))
+    }
+
+    /// Whether the field `field` of the value of `left` is declared optional. When the struct
+    /// isn't known, it is if it is in any struct with such a field.
+    fn field_optional(&self, left: &CallLeft, field: &Identifier) -> bool {
+        let optional = |state: &StructState| {
+            let index = state.fields.iter().position(|f| *f == field.0);
+            index.is_some_and(|index| state.optional[index])
+        };
+        match self.static_struct(left) {
+            Some(index) => optional(&self.structs[index as usize]),
+            None => self.structs.iter().any(optional),
+        }
+    }
+
+    /// The signature of the function called by a call of `left`, if it is known
+    fn callee_signature(&self, left: &CallLeft) -> Option<Signature> {
+        let CallLeft::Primary(Primary::Identifier(ident)) = left else {
+            return None;
+        };
+        match self.known(ident) {
+            Some(known) => known.signature.clone(),
+            None => match result_constructor(&ident.0) {
+                // The value of an `ok` or `err` may be anything
+                Some(variant) => Some(Signature {
+                    params: vec![true],
+                    ..self.variant_signature(RESULT as u32, variant)
+                }),
+                None => Builtin::lookup(&ident.0).map(builtin_signature),
+            },
+        }
+    }
+
+    /// The signature of the method `method` of the value of `left`. When the struct isn't known,
+    /// it combines the methods of that name of every struct.
+    fn method_signature(&self, left: &CallLeft, method: &Identifier) -> Option<Signature> {
+        if let Some((_, traits)) = self.receiver_traits(left) {
+            return traits
+                .iter()
+                .find_map(|index| self.traits[*index as usize].signatures.get(&method.0))
+                .cloned();
+        }
+        if let Some(index) = self.static_struct(left) {
+            return self.structs[index as usize]
+                .signatures
+                .get(&method.0)
+                .cloned();
+        }
+        if let Ty::Iterator(element) = self.left_type(left).plain() {
+            return (method.0 == NEXT).then(|| next_signature(*element));
+        }
+
+        // Any value might be an iterator
+        let iterator = (method.0 == NEXT).then(|| next_signature(Ty::Unknown));
+        let mut signatures = self
+            .structs
+            .iter()
+            .filter_map(|s| s.signatures.get(&method.0).cloned())
+            .chain(iterator);
+        let first = signatures.next()?;
+        Some(signatures.fold(first, |combined, signature| {
+            Signature {
+                params: combined
+                    .params
+                    .iter()
+                    .zip(&signature.params)
+                    .map(|(a, b)| *a && *b)
+                    .collect(),
+                returns_optional: combined.returns_optional || signature.returns_optional,
+                ..Signature::default()
+            }
+        }))
+    }
+
+    /// Takes the value just compiled, which is about to be used where `none` is not allowed
+    fn plain(&mut self) -> Result<(), CodegenError> {
+        match self.optional.take() {
+            Some((what, position)) => Err(CodegenError::MaybeNone { what, position }),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes the value just compiled, which is about to be stored in `what`
+    fn store(&mut self, optional: bool, what: impl FnOnce() -> String) -> Result<(), CodegenError> {
+        match self.optional.take() {
+            Some((_, position)) if !optional => Err(CodegenError::NotOptional {
+                what: what(),
+                position,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the value of `left` can have a field called `field`
+    fn check_field(&self, left: &CallLeft, field: &Identifier) -> Result<(), CodegenError> {
+        match self.static_struct(left) {
+            Some(index) => {
+                let state = &self.structs[index as usize];
+                if !state.fields.contains(&field.0) {
+                    return Err(CodegenError::UnknownField {
+                        name: state.name.clone(),
+                        field: field.0.clone(),
+                        position: field.1,
+                    });
+                }
+            }
+            None => {
+                if !self.structs.iter().any(|s| s.fields.contains(&field.0)) {
+                    return Err(CodegenError::UndefinedField {
+                        field: field.0.clone(),
+                        position: field.1,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the value of `left` can have a method called `method`. Fields holding
+    /// functions can be called like methods.
+    fn check_method(&self, left: &CallLeft, method: &Identifier) -> Result<(), CodegenError> {
+        if let Some((ty, traits)) = self.receiver_traits(left) {
+            let has = |index: &u32| {
+                let state = &self.traits[*index as usize];
+                state.method_names.contains(&method.0)
+            };
+            if !traits.iter().any(has) {
+                return Err(CodegenError::NoTraitMethod {
+                    ty: self.type_name(&ty),
+                    method: method.0.clone(),
+                });
+            }
+            return Ok(());
+        }
+
+        let receiver = self.left_type(left).plain();
+        if matches!(receiver, Ty::Iterator(_)) && method.0 != NEXT {
+            return Err(CodegenError::NoTraitMethod {
+                ty: self.type_name(&receiver),
+                method: method.0.clone(),
+            });
+        }
+
+        let has =
+            |s: &StructState| s.method_names.contains(&method.0) || s.fields.contains(&method.0);
+        match self.static_struct(left) {
+            Some(index) => {
+                let state = &self.structs[index as usize];
+                if !has(state) {
+                    return Err(CodegenError::UnknownMethod {
+                        name: state.name.clone(),
+                        method: method.0.clone(),
+                        position: method.1,
+                    });
+                }
+            }
+            None => {
+                if !self.structs.iter().any(has) && method.0 != NEXT {
+                    return Err(CodegenError::UndefinedMethod {
+                        method: method.0.clone(),
+                        position: method.1,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name_constant(&mut self, ident: &Identifier) -> u32 {
+        self.chunk()
+            .add_constant(Value::String(ident.0.as_str().into()))
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let depth = self.state().scope_depth - 1;
+        self.pop_locals(depth);
+        self.discard_scope();
+    }
+
+    /// Ends a scope whose locals have already been popped
+    fn discard_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while matches!(state.locals.last(), Some(l) if l.depth > depth) {
+            state.locals.pop();
+        }
+    }
+
+    /// Emits pops for every local deeper than `depth` without forgetting them
+    fn pop_locals(&mut self, depth: usize) {
+        let count = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|l| l.depth > depth)
+            .count();
+        for _ in 0..count {
+            self.emit(Op::Pop);
+        }
+    }
+
+    // Declarations
+
+    fn declaration(&mut self, declaration: &Declaration) -> Result<(), CodegenError> {
+        let public = match declaration {
+            Declaration::Variable(variable) => variable.public,
+            Declaration::Function(function) => function.public,
+            Declaration::Struct(decl) => decl.public,
+            Declaration::Enum(decl) => decl.public,
+            Declaration::Trait(decl) => decl.public,
+            Declaration::Const(decl) => decl.public,
+            _ => false,
+        };
+        if public && self.state().scope_depth > 0 {
+            return Err(CodegenError::NotTopLevel { keyword: "pub" });
+        }
+
+        match declaration {
+            Declaration::Variable(variable) => self.variable(variable),
+            Declaration::Statement(statement) => self.statement(statement),
+            Declaration::Function(function) if function.constant => {
+                Err(CodegenError::NotTopLevel { keyword: "const" })
+            }
+            Declaration::Function(function) => self.function(function),
+            // Top level types, traits and impls are handled before any code is generated
+            Declaration::Struct(_) => Err(CodegenError::NotTopLevel { keyword: "struct" }),
+            Declaration::Impl(_) => Err(CodegenError::NotTopLevel { keyword: "impl" }),
+            Declaration::Enum(_) => Err(CodegenError::NotTopLevel { keyword: "enum" }),
+            Declaration::Trait(_) => Err(CodegenError::NotTopLevel { keyword: "trait" }),
+            Declaration::Const(_) => Err(CodegenError::NotTopLevel { keyword: "const" }),
+            Declaration::Import(_) => Err(CodegenError::NotTopLevel { keyword: "import" }),
+        }
+    }
+
+    /// Evaluates the constants of a module and makes them and its `const fun`s visible
+    fn constants(
+        &mut self,
+        graph: &ModuleGraph,
+        declarations: &[Declaration],
+    ) -> Result<(), CodegenError> {
+        let module = self.modules.len();
+        for (index, declaration) in declarations.iter().enumerate() {
+            if let Declaration::Function(function) = declaration {
+                if function.constant {
+                    let name = function.ident.0.clone();
+                    self.scope.const_functions.insert(name, (module, index));
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut evaluator = Evaluator::new(self, graph, declarations);
+        let mut values = vec![];
+        for declaration in declarations {
+            if let Declaration::Const(constant) = declaration {
+                let name = &constant.ident.0;
+                if !names.insert(name)
+                    || self.scope.const_slots.contains_key(name)
+                    || self.scope.module_slots.contains_key(name)
+                {
+                    return Err(CodegenError::DuplicateDefinition {
+                        name: name.clone(),
+                        position: constant.ident.1,
+                    });
+                }
+                values.push((constant, evaluator.constant(name)?));
+            }
+        }
+
+        for (constant, value) in values {
+            let declared = self.annotation(constant.ty.as_ref())?;
+            let what = || format!("`{}`", constant.ident.0);
+            self.check_type(what, &declared, types::value_type(&value), constant.ident.1)?;
+            self.export(&constant.ident, constant.public);
+            self.scope
+                .const_slots
+                .insert(constant.ident.0.clone(), self.constants.len() as u32);
+            self.constants.push(value);
+        }
+        Ok(())
+    }
+
+    /// Checks that no type, trait or imported module of the module is called `ident` yet
+    fn check_type_name(&self, ident: &Identifier) -> Result<(), CodegenError> {
+        let name = &ident.0;
+        if self.scope.struct_slots.contains_key(name)
+            || self.scope.enum_slots.contains_key(name)
+            || self.scope.trait_slots.contains_key(name)
+            || self.scope.module_slots.contains_key(name)
+        {
+            return Err(CodegenError::DuplicateDefinition {
+                name: name.clone(),
+                position: ident.1,
+            });
+        }
+        Ok(())
+    }
+
+    fn declare_trait(&mut self, decl: &Trait) -> Result<(), CodegenError> {
+        self.check_type_name(&decl.ident)?;
+        let mut method_names: Vec<String> = vec![];
+        for method in &decl.methods {
+            if method_names.contains(&method.ident.0) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: method.ident.0.clone(),
+                    position: method.ident.1,
+                });
+            }
+            method_names.push(method.ident.0.clone());
+        }
+
+        self.export(&decl.ident, decl.public);
+        self.scope
+            .trait_slots
+            .insert(decl.ident.0.clone(), self.traits.len() as u32);
+        self.traits.push(TraitState {
+            name: decl.ident.0.clone(),
+            method_names,
+            signatures: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn trait_methods(&mut self, decl: &Trait) -> Result<(), CodegenError> {
+        let index = self.scope.trait_slots[&decl.ident.0] as usize;
+        for method in &decl.methods {
+            let signature = self.declared_signature(
+                &method.type_params,
+                method.args.as_ref(),
+                method.ret.as_ref(),
+                &[],
+                &TypeParams::default(),
+            )?;
+            self.traits[index]
+                .signatures
+                .insert(method.ident.0.clone(), signature);
+        }
+        Ok(())
+    }
+
+    fn trait_index(&self, ident: &Identifier) -> Result<u32, CodegenError> {
+        self.scope
+            .trait_slots
+            .get(&ident.0)
+            .copied()
+            .ok_or_else(|| CodegenError::UndefinedTrait {
+                name: ident.0.clone(),
+                position: ident.1,
+            })
+    }
+
+    fn declare_struct(&mut self, decl: &Struct) -> Result<(), CodegenError> {
+        self.check_type_name(&decl.ident)?;
+
+        let mut fields: Vec<String> = vec![];
+        for field in &decl.fields {
+            if fields.contains(&field.ident.0) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: field.ident.0.clone(),
+                    position: field.ident.1,
+                });
+            }
+            fields.push(field.ident.0.clone());
+        }
+        let optional = decl.fields.iter().map(|f| f.ty.is_optional()).collect();
+        let TypeParams { names, bounds } = self.type_params(&decl.type_params)?;
+
+        self.export(&decl.ident, decl.public);
+        self.scope
+            .struct_slots
+            .insert(decl.ident.0.clone(), self.structs.len() as u32);
+        self.structs.push(StructState {
+            name: decl.ident.0.clone(),
+            type_params: names,
+            bounds,
+            fields,
+            optional,
+            field_types: vec![],
+            method_names: vec![],
+            signatures: HashMap::new(),
+            methods: HashMap::new(),
+            traits: vec![],
+        });
+        Ok(())
+    }
+
+    fn declare_enum(&mut self, decl: &Enum) -> Result<(), CodegenError> {
+        self.check_type_name(&decl.ident)?;
+
+        let index = self.enums.len() as u32;
+        let mut variants: Vec<VariantDef> = vec![];
+        for (i, variant) in decl.variants.iter().enumerate() {
+            if variants.iter().any(|v| v.name == variant.ident.0) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: variant.ident.0.clone(),
+                    position: variant.ident.1,
+                });
+            }
+            variants.push(VariantDef {
+                name: variant.ident.0.clone(),
+                arity: variant.fields.len(),
+            });
+            for (j, ty) in variant.fields.iter().enumerate() {
+                if ty.is_optional() {
+                    self.optional_payloads.insert((index, i, j));
+                }
+            }
+        }
+
+        self.export(&decl.ident, decl.public);
+        self.scope.enum_slots.insert(decl.ident.0.clone(), index);
+        self.enums.push(Rc::new(EnumDef {
+            name: decl.ident.0.clone(),
+            variants,
+        }));
+        let TypeParams { names, bounds } = self.type_params(&decl.type_params)?;
+        self.enum_types.push(EnumTypes {
+            params: names,
+            bounds,
+            variants: vec![],
+        });
+        Ok(())
+    }
+
+    fn field_types(&mut self, decl: &Struct) -> Result<(), CodegenError> {
+        let index = self.scope.struct_slots[&decl.ident.0] as usize;
+        let params = self.structs[index].type_params.clone();
+        let types = decl
+            .fields
+            .iter()
+            .map(|field| self.resolve_type(&field.ty, &params))
+            .collect::<Result<_, _>>()?;
+        self.structs[index].field_types = types;
+        Ok(())
+    }
+
+    fn variant_types(&mut self, decl: &Enum) -> Result<(), CodegenError> {
+        let index = self.scope.enum_slots[&decl.ident.0] as usize;
+        let params = self.enum_types[index].params.clone();
+        let mut variants = vec![];
+        for variant in &decl.variants {
+            let types = variant
+                .fields
+                .iter()
+                .map(|ty| self.resolve_type(ty, &params))
+                .collect::<Result<_, _>>()?;
+            variants.push(types);
+        }
+        self.enum_types[index].variants = variants;
+        Ok(())
+    }
+
+    /// The enum named by `ident` when it is not shadowed by a variable
+    fn enum_index(&self, ident: &Identifier) -> Option<u32> {
+        let index = self.scope.enum_slots.get(&ident.0).copied()?;
+        let is_variable = self.scope.global_slots.contains_key(&ident.0)
+            || self
+                .functions
+                .iter()
+                .any(|state| state.locals.iter().any(|l| l.name == ident.0));
+        if is_variable {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    /// The name and signature of the constructor of `Enum.Variant` if `left` names an enum
+    fn constructor_signature(
+        &self,
+        left: &CallLeft,
+        variant: &Identifier,
+    ) -> Option<(String, Signature)> {
+        let CallLeft::Primary(Primary::Identifier(ident)) = left else {
+            return None;
+        };
+        let def = self.enum_index(ident)?;
+        let variant = self.variant_index(def, variant).ok()?;
+        let name = self.enums[def as usize].variant_name(variant);
+        Some((name, self.variant_signature(def, variant)))
+    }
+
+    /// Index of the variant of an enum called `variant`
+    fn variant_index(&self, def: u32, variant: &Identifier) -> Result<usize, CodegenError> {
+        let def = &self.enums[def as usize];
+        def.variants
+            .iter()
+            .position(|v| v.name == variant.0)
+            .ok_or_else(|| CodegenError::UnknownVariant {
+                name: def.name.clone(),
+                variant: variant.0.clone(),
+                position: variant.1,
+            })
+    }
+
+    /// The value of `Enum.Variant` if `left` names an enum. Variants holding values evaluate to
+    /// their constructor.
+    fn enum_variant(
+        &self,
+        left: &CallLeft,
+        variant: &Identifier,
+    ) -> Result<Option<Value>, CodegenError> {
+        let CallLeft::Primary(Primary::Identifier(ident)) = left else {
+            return Ok(None);
+        };
+        let Some(index) = self.enum_index(ident) else {
+            return Ok(None);
+        };
+
+        let variant = self.variant_index(index, variant)?;
+        let def = self.enums[index as usize].clone();
+        Ok(Some(if def.variants[variant].arity == 0 {
+            Value::Enum(Rc::new(EnumValue {
+                def,
+                variant,
+                values: vec![],
+            }))
+        } else {
+            Value::Constructor(def, variant)
+        }))
+    }
+
+    fn struct_index(&self, ident: &Identifier) -> Result<u32, CodegenError> {
+        self.scope
+            .struct_slots
+            .get(&ident.0)
+            .copied()
+            .ok_or_else(|| CodegenError::UndefinedStruct {
+                name: ident.0.clone(),
+                position: ident.1,
+            })
+    }
+
+    fn declare_methods(&mut self, decl: &Impl) -> Result<(), CodegenError> {
+        let index = self.struct_index(&decl.ident)?;
+        if let Some(trait_ident) = &decl.trait_ident {
+            self.check_impl(index, trait_ident, &decl.methods)?;
+        }
+        // Methods use the type parameters of the struct, inferred from the receiver
+        let state = &self.structs[index as usize];
+        let params = TypeParams {
+            names: state.type_params.clone(),
+            bounds: state.bounds.clone(),
+        };
+        for method in &decl.methods {
+            let signature = self.signature(method, &params.names, &params)?;
+            let state = &mut self.structs[index as usize];
+            let name = &method.ident.0;
+            if state.method_names.contains(name) || state.fields.contains(name) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: name.clone(),
+                    position: method.ident.1,
+                });
+            }
+            state.method_names.push(name.clone());
+            state.signatures.insert(name.clone(), signature);
+        }
+        Ok(())
+    }
+
+    /// Checks that the struct at `index` implements the trait `trait_ident` once, with exactly
+    /// the methods of the trait
+    fn check_impl(
+        &mut self,
+        index: u32,
+        trait_ident: &Identifier,
+        methods: &[crate::parser::ast::Function],
+    ) -> Result<(), CodegenError> {
+        let trait_index = self.trait_index(trait_ident)?;
+        let state = &self.structs[index as usize];
+        let required = &self.traits[trait_index as usize];
+        if state.traits.contains(&trait_index) {
+            return Err(CodegenError::DuplicateImpl {
+                name: state.name.clone(),
+                trait_name: required.name.clone(),
+                position: trait_ident.1,
+            });
+        }
+
+        for method in methods {
+            let Some(signature) = required.signatures.get(&method.ident.0) else {
+                return Err(CodegenError::NotTraitMethod {
+                    method: method.ident.0.clone(),
+                    trait_name: required.name.clone(),
+                    position: method.ident.1,
+                });
+            };
+            let found = method
+                .args
+                .as_ref()
+                .map_or(0, |a| a.args.len() + a.variadic.is_some() as usize);
+            if found != signature.params.len() {
+                return Err(CodegenError::TraitMethodArity {
+                    name: state.name.clone(),
+                    method: method.ident.0.clone(),
+                    trait_name: required.name.clone(),
+                    expected: signature.params.len(),
+                    found,
+                    position: method.ident.1,
+                });
+            }
+        }
+        for name in &required.method_names {
+            if !methods.iter().any(|m| m.ident.0 == *name) {
+                return Err(CodegenError::MissingMethod {
+                    name: state.name.clone(),
+                    method: name.clone(),
+                    trait_name: required.name.clone(),
+                    position: trait_ident.1,
+                });
+            }
+        }
+
+        self.structs[index as usize].traits.push(trait_index);
+        Ok(())
+    }
+
+    fn methods(&mut self, decl: &Impl) -> Result<(), CodegenError> {
+        let index = self.struct_index(&decl.ident)?;
+        let state = &self.structs[index as usize];
+        let struct_params = TypeParams {
+            names: state.type_params.clone(),
+            bounds: state.bounds.clone(),
+        };
+        for method in &decl.methods {
+            let returns_optional = method.ret.as_ref().is_some_and(Type::is_optional);
+            let mut type_params = struct_params.clone();
+            let own = self.type_params(&method.type_params)?;
+            type_params.names.extend(own.names);
+            type_params.bounds.extend(own.bounds);
+            let receiver_type = Ty::Struct(
+                index,
+                struct_params.names.iter().cloned().map(Ty::Param).collect(),
+            );
+            let function = self.compile_function(
+                &method.ident.0,
+                method.args.as_ref(),
+                returns_optional,
+                type_params,
+                |this| {
+                    // The receiver is known to be an instance of the struct
+                    if let Some(receiver) = this.state().locals.first_mut() {
+                        if receiver.name == "self" {
+                            receiver.known.struct_type = Some(index);
+                            receiver.known.ty = receiver_type;
+                        }
+                    }
+                    this.function_block(&method.block, method.generator)
+                },
+            )?;
+
+            let closure = Closure {
+                function: Rc::new(function),
+                upvalues: vec![],
+            };
+            self.structs[index as usize]
+                .methods
+                .insert(method.ident.0.clone(), Rc::new(closure));
+        }
+        Ok(())
+    }
+
+    fn variable(&mut self, variable: &Variable) -> Result<(), CodegenError> {
+        let declared = self.annotation(variable.target.ty.as_ref())?;
+        match &variable.value {
+            Some(value) => self.expr(value)?,
+            None => {
+                self.emit_constant(Value::None);
+                self.optional = Some(("`none`".to_string(), variable.target.position));
+            }
+        }
+        // The type is taken once the value is compiled, which is when that of a lambda is known
+        let ty = match &variable.value {
+            Some(value) => {
+                let found = self.static_type(value);
+                let what = || match variable.target.ident() {
+                    Some(ident) => format!("`{}`", ident.0),
+                    None => "the destructured value".to_string(),
+                };
+                self.check_type(what, &declared, found.clone(), variable.target.position)?;
+                // A `var` may be assigned values of other types later
+                match (&variable.target.ty, &variable.v_type) {
+                    (Some(_), _) => declared,
+                    (None, VariableType::Val) => found,
+                    (None, VariableType::Var) => Ty::Unknown,
+                }
+            }
+            None => declared,
+        };
+
+        let mutable = is_mutable(&variable.v_type);
+        let Some(ident) = variable.target.ident() else {
+            // The value is destructured from a hidden local, which at the top level is only
+            // needed until the globals are defined
+            self.plain()?;
+            let slot = self.add_local("", false);
+            self.bind_pattern(&variable.target, slot, mutable)?;
+            if self.state().scope_depth == 0 {
+                self.emit(Op::Pop);
+                self.state().locals.pop();
+            }
+            return Ok(());
+        };
+
+        if self.state().scope_depth == 0 {
+            // Top level variables were declared up front
+            let slot = self.scope.global_slots[&ident.0];
+            let optional = self.globals[slot as usize].known.optional;
+            // Functions may read the variable before it is defined, so whether it is optional
+            // can't be inferred from its value
+            if variable.target.ty.is_none() && self.optional.is_some() && !optional {
+                return Err(CodegenError::OptionalGlobal {
+                    name: ident.0.clone(),
+                    position: ident.1,
+                });
+            }
+            self.store(optional, || format!("`{}`", ident.0))?;
+            self.emit(Op::DefineGlobal(slot));
+            self.globals[slot as usize].known.ty = ty;
+        } else {
+            // A local without a type may hold `none` if its value may
+            let optional = match &variable.target.ty {
+                Some(ty) => ty.is_optional(),
+                None => self.optional.is_some(),
+            };
+            self.store(optional, || format!("`{}`", ident.0))?;
+            self.add_local(&ident.0, mutable);
+            let known = Known {
+                struct_type: self.known_struct(variable),
+                optional,
+                signature: None,
+                ty,
+            };
+            // This unwrap is safe because the local was just added
+            self.state().locals.last_mut().unwrap().known = known;
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &crate::parser::ast::Function) -> Result<(), CodegenError> {
+        let visible = self.visible_type_params();
+        let signature = self.signature(function, visible, &TypeParams::default())?;
+        let returns_optional = signature.returns_optional;
+        let type_params = self.type_params(&function.type_params)?;
+        if self.state().scope_depth == 0 {
+            let entry_point = self.root && function.ident.0 == ENTRY_POINT;
+            self.closure(
+                &function.ident.0,
+                function.args.as_ref(),
+                returns_optional,
+                type_params,
+                |this| {
+                    if entry_point {
+                        this.state().entry_point = Some(function.ident.1);
+                    }
+                    this.function_block(&function.block, function.generator)
+                },
+            )?;
+            let slot = self.scope.global_slots[&function.ident.0];
+            self.emit(Op::DefineGlobal(slot));
+        } else {
+            // The local is added first so that the function can call itself, the closure is
+            // pushed into its slot
+            let slot = self.state().stack_depth as u32;
+            self.declare_local(&function.ident.0, false, slot);
+            // This unwrap is safe because the local was just added
+            self.state().locals.last_mut().unwrap().known.signature = Some(signature);
+            self.closure(
+                &function.ident.0,
+                function.args.as_ref(),
+                returns_optional,
+                type_params,
+                |this| this.function_block(&function.block, function.generator),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Compiles a function whose body is generated by `body` and pushes a closure over it
+    fn closure(
+        &mut self,
+        name: &str,
+        args: Option<&ArgsDecl>,
+        returns_optional: bool,
+        type_params: TypeParams,
+        body: impl FnOnce(&mut Self) -> Result<(), CodegenError>,
+    ) -> Result<(), CodegenError> {
+        let compiled = self.compile_function(name, args, returns_optional, type_params, body)?;
+        let index = self.chunk().add_function(compiled);
+        self.emit(Op::Closure(index));
+        self.optional = None;
+        Ok(())
+    }
+
+    /// Compiles a function whose body is generated by `body`. The function has the type
+    /// parameters `type_params` on top of those of the code it is declared in.
+    fn compile_function(
+        &mut self,
+        name: &str,
+        args: Option<&ArgsDecl>,
+        returns_optional: bool,
+        type_params: TypeParams,
+        body: impl FnOnce(&mut Self) -> Result<(), CodegenError>,
+    ) -> Result<Function, CodegenError> {
+        let variadic = args.and_then(|a| a.variadic.as_ref());
+        let defaults = args.map_or(&[][..], |a| &a.defaults[..]);
+        let args = args.map_or(&[][..], |a| &a.args[..]);
+        let params = function_params(name, args, defaults, variadic.is_some())?;
+
+        // Parameters must have distinct names, including those bound by destructuring
+        let mut bound: Vec<String> = vec![];
+        for arg in args.iter().chain(variadic) {
+            for ident in self.bound_names(&arg.pattern)? {
+                if bound.contains(&ident.0) {
+                    return Err(CodegenError::DuplicateDefinition {
+                        name: ident.0,
+                        position: ident.1,
+                    });
+                }
+                bound.push(ident.0);
+            }
+        }
+
+        let mut state = FunctionState::new(name, params, 1, self.file.clone());
+        state.returns_optional = returns_optional;
+        // This unwrap is safe because there is always at least the script state
+        let enclosing = self.functions.last().unwrap();
+        state.type_params = enclosing.type_params.clone();
+        state.type_params.extend(type_params.names);
+        state.bounds = enclosing.bounds.clone();
+        state.bounds.extend(type_params.bounds);
+        for (slot, arg) in args.iter().chain(variadic).enumerate() {
+            let mut ty = match &arg.ty {
+                Some(ty) => self.resolve_type(ty, &state.type_params)?,
+                None => Ty::Unknown,
+            };
+            // The variadic parameter holds a list of the arguments it takes
+            let is_variadic = slot == args.len();
+            if is_variadic {
+                ty = Ty::List(Box::new(ty));
+            }
+            // Destructured parameters are bound from a hidden local before the body runs, and
+            // named ones are only in scope once the defaults before them are compiled
+            state.locals.push(Local {
+                name: String::new(),
+                slot: slot as u32,
+                depth: 1,
+                mutable: false,
+                known: Known {
+                    optional: arg.is_optional() && !is_variadic,
+                    ty,
+                    ..Known::default()
+                },
+            });
+        }
+        self.functions.push(state);
+
+        // A default sees the parameters before it, but not its own or later ones
+        for (slot, (arg, default)) in args.iter().zip(defaults).enumerate() {
+            if let Some(default) = default {
+                self.default_value(slot as u32, arg, default)?;
+            }
+            match arg.ident() {
+                Some(ident) => self.state().locals[slot].name = ident.0.clone(),
+                None => self.bind_pattern(arg, slot as u32, false)?,
+            }
+        }
+        if let Some(variadic) = variadic {
+            match variadic.ident() {
+                Some(ident) => self.state().locals[args.len()].name = ident.0.clone(),
+                None => self.bind_pattern(variadic, args.len() as u32, false)?,
+            }
+        }
+        body(self)?;
+
+        // This unwrap is safe because the state was pushed above
+        let state = self.functions.pop().unwrap();
+        let mut compiled = state.function;
+        compiled.captures = state.upvalues;
+        Ok(compiled)
+    }
+
+    /// Compiles the default value of the parameter `param`, which is evaluated into its slot
+    /// when a call leaves it out
+    fn default_value(
+        &mut self,
+        param: u32,
+        arg: &BindingPattern,
+        default: &Expr,
+    ) -> Result<(), CodegenError> {
+        let what = || match arg.ident() {
+            Some(ident) => format!("parameter `{}`", ident.0),
+            None => format!("parameter {}", param + 1),
+        };
+        let expected = self.state().locals[param as usize].known.ty.clone();
+        self.check_type(what, &expected, self.static_type(default), arg.position)?;
+
+        let skip = self.emit(Op::JumpIfPassed { param, target: 0 });
+        self.expr(default)?;
+        self.store(arg.is_optional(), what)?;
+        self.emit(Op::SetLocal(param));
+        self.emit(Op::Pop);
+        self.patch(skip);
+        Ok(())
+    }
+
+    /// Compiles the block of a function. A trailing expression is the function's return value,
+    /// otherwise it returns unit. A generator's block only runs for the values it yields.
+    fn function_block(&mut self, block: &Block, generator: bool) -> Result<(), CodegenError> {
+        if generator {
+            self.state().function.generator = true;
+            for declaration in &block.declarations {
+                self.declaration(declaration)?;
+            }
+            self.emit(Op::Unit);
+            return self.emit_return();
+        }
+        match block.declarations.split_last() {
+            Some((Declaration::Statement(Statement::Expression(expr)), rest)) => {
+                for declaration in rest {
+                    self.declaration(declaration)?;
+                }
+                self.expr(expr)?;
+            }
+            _ => {
+                for declaration in &block.declarations {
+                    self.declaration(declaration)?;
+                }
+                self.emit(Op::Unit);
+            }
+        }
+        self.emit_return()
+    }
+
+    /// The error for `what` returning a value from the generator being compiled
+    fn generator_return(&mut self, what: &'static str) -> CodegenError {
+        CodegenError::GeneratorReturn {
+            what,
+            name: self.state().function.name.clone(),
+        }
+    }
+
+    /// Compiles `spawn expr`, which is compiled as the call it must be before the call is
+    /// turned into starting a task
+    fn spawn(&mut self, expr: &Expr) -> Result<(), CodegenError> {
+        let is_call = match expr.as_call() {
+            Some(Call {
+                left: CallLeft::Call(call),
+                right: None,
+            }) => matches!(call.right, Some(CallRight::Args { .. })),
+            _ => false,
+        };
+        if !is_call {
+            return Err(CodegenError::NotSpawnable {
+                what: "an expression that isn't a call".to_string(),
+            });
+        }
+        self.expr(expr)?;
+        self.optional = None;
+
+        // The call is always the last instruction, the task takes the result off the stack
+        let state = self.state();
+        // This unwrap is safe because a call was just emitted
+        let op = state.function.chunk.code.last_mut().unwrap();
+        match *op {
+            Op::Call(args) => *op = Op::Spawn(args),
+            Op::CallNamed { args, names } => *op = Op::SpawnNamed { args, names },
+            Op::Invoke { name, .. } | Op::InvokeNamed { name, .. } => {
+                let method = match &state.function.chunk.constants[name as usize] {
+                    Value::String(name) => name.to_string(),
+                    other => unreachable!("name constant {} is not a string", other),
+                };
+                return Err(CodegenError::NotSpawnable {
+                    what: format!("a call of method `{}`", method),
+                });
+            }
+            op => unreachable!("call compiled to {:?}", op),
+        }
+        state.stack_depth -= 1;
+        Ok(())
+    }
+
+    /// Returns the value just compiled, which may only be `none` if the function is declared to
+    /// return an optional
+    fn emit_return(&mut self) -> Result<(), CodegenError> {
+        let returned_none = self.optional.is_some();
+        let state = self.state();
+        state.returned_none |= returned_none;
+        let (optional, name) = (state.returns_optional, state.function.name.clone());
+        self.store(optional, || format!("the result of `{}`", name))?;
+        if let Some(position) = self.state().entry_point {
+            self.emit_at(Op::CheckErr, position);
+        }
+        self.emit(Op::Return);
+        Ok(())
+    }
+
+    // Statements
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CodegenError> {
+        match statement {
+            Statement::Expression(expr) => {
+                self.expr(expr)?;
+                self.optional = None;
+                self.emit(Op::Pop);
+            }
+            Statement::For(for_stmt) => self.for_stmt(for_stmt)?,
+            Statement::Print(print) => {
+                self.expr(&print.expr)?;
+                self.optional = None;
+                self.emit(Op::Print);
+            }
+            Statement::Return(ret) => {
+                if self.state().top_level {
+                    return Err(CodegenError::ReturnOutsideFunction { what: "return" });
+                }
+                match &ret.expr {
+                    Some(_) if self.state().function.generator => {
+                        return Err(self.generator_return("`return`"))
+                    }
+                    Some(expr) => self.expr(expr)?,
+                    None => {
+                        self.emit(Op::Unit);
+                    }
+                }
+                self.emit_return()?;
+            }
+            Statement::Yield(expr) => {
+                if self.state().top_level {
+                    return Err(CodegenError::ReturnOutsideFunction { what: "yield" });
+                }
+                self.expr(expr)?;
+                // A step of the iterator gives `none` once it has run out
+                self.plain()?;
+                self.emit(Op::Yield);
+            }
+            Statement::Spawn(expr) => self.spawn(expr)?,
+            Statement::Break(value) => {
+                let (depth, result) = match self.state().loops.last() {
+                    Some(l) => (l.stack_depth, l.result),
+                    None => return Err(CodegenError::OutsideLoop { keyword: "break" }),
+                };
+                if let Some(value) = value {
+                    let result = result.ok_or(CodegenError::BreakValue)?;
+                    self.expr(value)?;
+                    if let Some(optional) = self.optional.take() {
+                        // This unwrap is safe because of the check above
+                        self.state().loops.last_mut().unwrap().optional = Some(optional);
+                    }
+                    self.emit(Op::SetLocal(result));
+                    self.emit(Op::Pop);
+                }
+
+                let jump = self.jump_out(depth, Op::Jump(0));
+                // This unwrap is safe because of the check above
+                self.state().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            Statement::Continue => {
+                let (depth, start) = match self.state().loops.last() {
+                    Some(l) => (l.stack_depth, l.start),
+                    None => {
+                        return Err(CodegenError::OutsideLoop {
+                            keyword: "continue",
+                        })
+                    }
+                };
+                self.jump_out(depth, Op::Jump(start));
+            }
+        }
+        Ok(())
+    }
+
+    /// Pops everything above `depth`, including temporaries of the enclosing expressions, then
+    /// emits `jump`
+    fn jump_out(&mut self, depth: usize, jump: Op) -> usize {
+        // Code after the jump continues with the values still on the stack
+        let stack_depth = self.state().stack_depth;
+        for _ in depth..stack_depth {
+            self.emit(Op::Pop);
+        }
+        let index = self.emit(jump);
+        self.state().stack_depth = stack_depth;
+        index
+    }
+
+    fn loop_expr(&mut self, loop_expr: &crate::parser::ast::Loop) -> Result<(), CodegenError> {
+        self.emit(Op::Unit);
+        let result = self.add_local("", false);
+
+        let start = self.chunk().code.len();
+        let stack_depth = self.state().stack_depth;
+        self.state().loops.push(LoopState {
+            start,
+            stack_depth,
+            breaks: vec![],
+            result: Some(result),
+            optional: None,
+        });
+
+        self.block(&loop_expr.block)?;
+        self.emit(Op::Jump(start));
+
+        self.optional = self.end_loop();
+        // The result stays on the stack as the value of the loop
+        self.state().locals.pop();
+        Ok(())
+    }
+
+    fn for_stmt(&mut self, for_stmt: &For) -> Result<(), CodegenError> {
+        let element = match self.static_type(&for_stmt.expr) {
+            Ty::List(element) | Ty::Vector(element) | Ty::Iterator(element) => *element,
+            _ => Ty::Unknown,
+        };
+        let declared = self.annotation(for_stmt.target.ty.as_ref())?;
+        if let Some(ident) = for_stmt.target.ident() {
+            let what = || format!("`{}`", ident.0);
+            self.check_type(what, &declared, element.clone(), ident.1)?;
+            if element.is_optional()
+                && for_stmt.target.ty.is_some()
+                && !for_stmt.target.is_optional()
+            {
+                return Err(CodegenError::NotOptional {
+                    what: format!("`{}`", ident.0),
+                    position: ident.1,
+                });
+            }
+        }
+
+        self.begin_scope();
+        self.expr(&for_stmt.expr)?;
+        self.plain()?;
+        self.emit(Op::Iter);
+        let slot = self.add_local("", false);
+
+        let start = self.chunk().code.len();
+        let stack_depth = self.state().stack_depth;
+        let next = self.emit(Op::IterNext { slot, exit: 0 });
+        self.state().loops.push(LoopState {
+            start,
+            stack_depth,
+            breaks: vec![next],
+            result: None,
+            optional: None,
+        });
+
+        self.begin_scope();
+        match for_stmt.target.ident() {
+            Some(ident) => {
+                self.add_local(&ident.0, false);
+                if for_stmt.target.is_optional() {
+                    self.mark_optional();
+                }
+                let ty = match for_stmt.target.ty {
+                    Some(_) => declared,
+                    None => element,
+                };
+                // This unwrap is safe because the local was just added
+                self.state().locals.last_mut().unwrap().known.ty = ty;
+            }
+            None => {
+                let slot = self.add_local("", false);
+                self.bind_pattern(&for_stmt.target, slot, false)?;
+            }
+        }
+        for declaration in &for_stmt.block.declarations {
+            self.declaration(declaration)?;
+        }
+        self.end_scope();
+        self.emit(Op::Jump(start));
+
+        self.end_loop();
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Patches the breaks of the innermost loop to jump to the next instruction. Returns the
+    /// description of a value given to `break` that may be `none`.
+    fn end_loop(&mut self) -> Option<(String, Position)> {
+        // This unwrap is safe because callers always push a loop first
+        let loop_state = self.state().loops.pop().unwrap();
+        for jump in loop_state.breaks {
+            self.patch(jump);
+        }
+        loop_state.optional
+    }
+
+    fn if_expr(&mut self, if_expr: &If) -> Result<(), CodegenError> {
+        if let Some(binding) = &if_expr.binding {
+            return self.if_val(if_expr, binding);
+        }
+
+        self.expr(&if_expr.expr)?;
+        self.plain()?;
+        let then_jump = self.emit(Op::JumpIfFalse(0));
+        self.emit(Op::Pop);
+        self.block_value(&if_expr.block)?;
+        let then_optional = self.optional.take();
+        let else_jump = self.emit(Op::Jump(0));
+
+        self.patch(then_jump);
+        self.emit(Op::Pop);
+        self.else_branch(&if_expr.else_branch)?;
+        self.patch(else_jump);
+        self.optional = self.optional.take().or(then_optional);
+        Ok(())
+    }
+
+    /// Compiles `if val pattern = expr`. The value of `expr` is bound to the pattern in the
+    /// block unless it is `none`, the value of the whole `if` is kept in a hidden local below it.
+    fn if_val(&mut self, if_expr: &If, binding: &BindingPattern) -> Result<(), CodegenError> {
+        self.emit(Op::Unit);
+        let result = self.add_local("", false);
+
+        let ty = self.static_type(&if_expr.expr).plain();
+        self.expr(&if_expr.expr)?;
+        self.optional = None;
+        let else_jump = self.emit(Op::JumpIfNone(0));
+
+        self.begin_scope();
+        match binding.ident() {
+            Some(ident) => {
+                self.add_local(&ident.0, false);
+                // This unwrap is safe because the local was just added
+                self.state().locals.last_mut().unwrap().known.ty = ty;
+            }
+            None => {
+                let slot = self.add_local("", false);
+                self.bind_pattern(binding, slot, false)?;
+            }
+        }
+        self.block_value(&if_expr.block)?;
+        let then_optional = self.optional.take();
+        self.emit(Op::SetLocal(result));
+        self.emit(Op::Pop);
+        self.end_scope();
+        let end = self.emit(Op::Jump(0));
+
+        self.patch(else_jump);
+        self.emit(Op::Pop);
+        self.else_branch(&if_expr.else_branch)?;
+        self.emit(Op::SetLocal(result));
+        self.emit(Op::Pop);
+        self.patch(end);
+        self.optional = self.optional.take().or(then_optional);
+
+        // The result stays on the stack as the value of the `if`
+        self.state().locals.pop();
+        Ok(())
+    }
+
+    /// Compiles the `else` of an `if`, which is unit when there is none
+    fn else_branch(&mut self, else_branch: &Option<Else>) -> Result<(), CodegenError> {
+        match else_branch {
+            Some(Else::Block(block)) => self.block_value(block)?,
+            Some(Else::If(if_expr)) => self.if_expr(if_expr)?,
+            None => {
+                self.emit(Op::Unit);
+            }
+        }
+        Ok(())
+    }
+
+    // Misc
+
+    fn block(&mut self, block: &Block) -> Result<(), CodegenError> {
+        self.begin_scope();
+        for declaration in &block.declarations {
+            self.declaration(declaration)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles a block that pushes its value
+    fn block_value(&mut self, block: &Block) -> Result<(), CodegenError> {
+        // The value is computed above the block's locals, so when there are any it is moved into
+        // a slot below them before they are popped
+        let has_locals = block
+            .declarations
+            .iter()
+            .any(|d| matches!(d, Declaration::Variable(_) | Declaration::Function(_)));
+        let result = if has_locals {
+            self.emit(Op::Unit);
+            Some(self.add_local("", false))
+        } else {
+            None
+        };
+
+        self.begin_scope();
+        match block.declarations.split_last() {
+            Some((Declaration::Statement(Statement::Expression(expr)), rest)) => {
+                for declaration in rest {
+                    self.declaration(declaration)?;
+                }
+                self.expr(expr)?;
+            }
+            _ => {
+                for declaration in &block.declarations {
+                    self.declaration(declaration)?;
+                }
+                self.emit(Op::Unit);
+                self.optional = None;
+            }
+        }
+        if let Some(result) = result {
+            self.emit(Op::SetLocal(result));
+            self.emit(Op::Pop);
+        }
+        self.end_scope();
+
+        if result.is_some() {
+            // The result stays on the stack as the value of the block
+            self.state().locals.pop();
+        }
+        Ok(())
+    }
+
+    // Expressions
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CodegenError> {
+        match expr {
+            Expr::Assignment(assignment) => self.assignment(assignment),
+        }
+    }
+
+    fn assignment(&mut self, assignment: &Assignment) -> Result<(), CodegenError> {
+        match assignment {
+            Assignment::AssignedVal(assigned) => match &assigned.target {
+                AssignTarget::Identifier(ident) => {
+                    let (_, set) = self.assignable(ident)?;
+                    let optional = self.known(ident).is_some_and(|known| known.optional);
+                    self.expr(&assigned.expr)?;
+                    self.store(optional, || format!("`{}`", ident.0))?;
+                    self.emit(set);
+                }
+                AssignTarget::Index(target) => {
+                    self.call_left(&target.target)?;
+                    self.plain()?;
+                    self.expr(&target.index)?;
+                    self.plain()?;
+                    self.expr(&assigned.expr)?;
+                    self.optional = None;
+                    self.emit_at(Op::SetIndex, target.position);
+                }
+                AssignTarget::Field(target) => {
+                    let name = self.field_target(target)?;
+                    let optional = self.field_optional(&target.target, &target.field);
+                    self.call_left(&target.target)?;
+                    self.plain()?;
+                    self.expr(&assigned.expr)?;
+                    self.store(optional, || format!("field `{}`", target.field.0))?;
+                    self.emit_at(Op::SetField(name), target.position);
+                }
+            },
+            Assignment::Compound(compound) => match &compound.target {
+                AssignTarget::Identifier(ident) => {
+                    let (get, set) = self.assignable(ident)?;
+                    if self.known(ident).is_some_and(Known::may_be_none) {
+                        return Err(CodegenError::MaybeNone {
+                            what: format!("`{}`", ident.0),
+                            position: ident.1,
+                        });
+                    }
+                    self.emit(get);
+                    self.expr(&compound.expr)?;
+                    self.plain()?;
+                    self.emit_at(assign_op(&compound.op), compound.position);
+                    self.emit(set);
+                }
+                AssignTarget::Index(target) => {
+                    self.call_left(&target.target)?;
+                    self.plain()?;
+                    self.expr(&target.index)?;
+                    self.plain()?;
+                    self.emit(Op::DupPair);
+                    self.emit_at(Op::Index, target.position);
+                    self.expr(&compound.expr)?;
+                    self.plain()?;
+                    self.emit_at(assign_op(&compound.op), compound.position);
+                    self.emit_at(Op::SetIndex, target.position);
+                }
+                AssignTarget::Field(target) => {
+                    let name = self.field_target(target)?;
+                    if self.field_optional(&target.target, &target.field) {
+                        return Err(CodegenError::MaybeNone {
+                            what: format!("field `{}`", target.field.0),
+                            position: target.field.1,
+                        });
+                    }
+                    self.call_left(&target.target)?;
+                    self.plain()?;
+                    self.emit(Op::Duplicate);
+                    self.emit_at(Op::GetField(name), target.position);
+                    self.expr(&compound.expr)?;
+                    self.plain()?;
+                    self.emit_at(assign_op(&compound.op), compound.position);
+                    self.emit_at(Op::SetField(name), target.position);
+                }
+            },
+            Assignment::Coalesce(coalesce) => self.coalesce(coalesce)?,
+            Assignment::LogicOr(logic_or) => self.logic_or(logic_or)?,
+        }
+        Ok(())
+    }
+
+    fn coalesce(&mut self, coalesce: &Coalesce) -> Result<(), CodegenError> {
+        self.logic_or(&coalesce.left)?;
+
+        if let Some(right) = &coalesce.right {
+            // The value is only kept when it isn't `none`, so the result is optional only if the
+            // default is
+            self.optional = None;
+            let default = self.emit(Op::JumpIfNone(0));
+            let end = self.emit(Op::Jump(0));
+            self.patch(default);
+            self.emit(Op::Pop);
+            self.coalesce(right)?;
+            self.patch(end);
+        }
+        Ok(())
+    }
+
+    /// Checks that the field of `target` may be assigned to and returns the constant holding its
+    /// name. Fields of a struct held in a `val` are immutable, except through `self` in methods.
+    fn field_target(&mut self, target: &FieldTarget) -> Result<u32, CodegenError> {
+        self.check_field(&target.target, &target.field)?;
+
+        if let CallLeft::Primary(Primary::Identifier(ident)) = &target.target {
+            let mutable = match self.resolve(ident)? {
+                Resolved::Local(_, mutable)
+                | Resolved::Upvalue(_, mutable)
+                | Resolved::Global(_, mutable) => mutable,
+                Resolved::Builtin(_) | Resolved::Constant(_) | Resolved::Constructor(_) => false,
+            };
+            if !mutable && ident.0 != "self" {
+                return Err(CodegenError::AssignToImmutable {
+                    name: ident.0.clone(),
+                    position: ident.1,
+                });
+            }
+        }
+
+        Ok(self.name_constant(&target.field))
+    }
+
+    /// Get and set instructions for a variable that may be assigned to
+    fn assignable(&mut self, ident: &Identifier) -> Result<(Op, Op), CodegenError> {
+        match self.resolve(ident)? {
+            Resolved::Local(slot, true) => Ok((Op::GetLocal(slot), Op::SetLocal(slot))),
+            Resolved::Upvalue(index, true) => Ok((Op::GetUpvalue(index), Op::SetUpvalue(index))),
+            Resolved::Global(slot, true) => Ok((Op::GetGlobal(slot), Op::SetGlobal(slot))),
+            Resolved::Constant(_) => Err(CodegenError::AssignToConstant {
+                name: ident.0.clone(),
+                position: ident.1,
+            }),
+            Resolved::Local(_, false)
+            | Resolved::Upvalue(_, false)
+            | Resolved::Global(_, false)
+            | Resolved::Builtin(_)
+            | Resolved::Constructor(_) => Err(CodegenError::AssignToImmutable {
+                name: ident.0.clone(),
+                position: ident.1,
+            }),
+        }
+    }
+
+    fn logic_or(&mut self, logic_or: &LogicOr) -> Result<(), CodegenError> {
+        match &logic_or.left {
+            LogicOrLeft::LogicAnd(left) => self.logic_and(left)?,
+            LogicOrLeft::LogicOr(left) => self.logic_or(left)?,
+        }
+
+        if let Some(right) = &logic_or.right {
+            self.plain()?;
+            let jump = self.emit(Op::JumpIfTrue(0));
+            self.emit(Op::Pop);
+            self.logic_and(right)?;
+            self.plain()?;
+            self.patch(jump);
+        }
+        Ok(())
+    }
+
+    fn logic_and(&mut self, logic_and: &LogicAnd) -> Result<(), CodegenError> {
+        match &logic_and.left {
+            LogicAndLeft::Equality(left) => self.equality(left)?,
+            LogicAndLeft::LogicAnd(left) => self.logic_and(left)?,
+        }
+
+        if let Some(right) = &logic_and.right {
+            self.plain()?;
+            let jump = self.emit(Op::JumpIfFalse(0));
+            self.emit(Op::Pop);
+            self.equality(right)?;
+            self.plain()?;
+            self.patch(jump);
+        }
+        Ok(())
+    }
+
+    fn equality(&mut self, equality: &Equality) -> Result<(), CodegenError> {
+        // `eq` is called when the comparison runs, since any value may be compared with `none`
+        self.check_equality(self.equality_operands(equality))?;
+        match &equality.left {
+            EqualityLeft::Comparison(left) => self.comparison(left)?,
+            EqualityLeft::Equality(left) => self.equality(left)?,
+        }
+
+        if let Some(right) = &equality.right {
+            // Anything can be compared with `none`
+            self.optional = None;
+            self.comparison(&right.right)?;
+            self.optional = None;
+            let op = match right.op {
+                EqualityOp::Equal => Op::Equal,
+                EqualityOp::NotEqual => Op::NotEqual,
+            };
+            self.emit_at(op, right.position);
+        }
+        Ok(())
+    }
+
+    fn comparison(&mut self, comparison: &Comparison) -> Result<(), CodegenError> {
+        let overload = self.check_operands(self.comparison_operands(comparison))?;
+        match &comparison.left {
+            ComparisonLeft::BitOr(left) => self.bit_or(left)?,
+            ComparisonLeft::Comparison(left) => self.comparison(left)?,
+        }
+
+        if let Some(right) = &comparison.right {
+            self.plain()?;
+            self.bit_or(&right.right)?;
+            self.plain()?;
+            let op = match right.op {
+                ComparisonOp::Greater => Op::Greater,
+                ComparisonOp::GreaterEqual => Op::GreaterEqual,
+                ComparisonOp::Less => Op::Less,
+                ComparisonOp::LessEqual => Op::LessEqual,
+            };
+            self.emit_operator(op, overload, 1, right.position);
+        }
+        Ok(())
+    }
+
+    fn bit_or(&mut self, bit_or: &BitOr) -> Result<(), CodegenError> {
+        match &bit_or.left {
+            BitOrLeft::BitXor(left) => self.bit_xor(left)?,
+            BitOrLeft::BitOr(left) => self.bit_or(left)?,
+        }
+
+        if let Some(right) = &bit_or.right {
+            self.plain()?;
+            self.bit_xor(&right.right)?;
+            self.plain()?;
+            self.emit_at(Op::BitOr, right.position);
+        }
+        Ok(())
+    }
+
+    fn bit_xor(&mut self, bit_xor: &BitXor) -> Result<(), CodegenError> {
+        match &bit_xor.left {
+            BitXorLeft::BitAnd(left) => self.bit_and(left)?,
+            BitXorLeft::BitXor(left) => self.bit_xor(left)?,
+        }
+
+        if let Some(right) = &bit_xor.right {
+            self.plain()?;
+            self.bit_and(&right.right)?;
+            self.plain()?;
+            self.emit_at(Op::BitXor, right.position);
+        }
+        Ok(())
+    }
+
+    fn bit_and(&mut self, bit_and: &BitAnd) -> Result<(), CodegenError> {
+        match &bit_and.left {
+            BitAndLeft::Shift(left) => self.shift(left)?,
+            BitAndLeft::BitAnd(left) => self.bit_and(left)?,
+        }
+
+        if let Some(right) = &bit_and.right {
+            self.plain()?;
+            self.shift(&right.right)?;
+            self.plain()?;
+            self.emit_at(Op::BitAnd, right.position);
+        }
+        Ok(())
+    }
+
+    fn shift(&mut self, shift: &Shift) -> Result<(), CodegenError> {
+        match &shift.left {
+            ShiftLeft::Term(left) => self.term(left)?,
+            ShiftLeft::Shift(left) => self.shift(left)?,
+        }
+
+        if let Some(right) = &shift.right {
+            self.plain()?;
+            self.term(&right.right)?;
+            self.plain()?;
+            let op = match right.op {
+                ShiftOp::Left => Op::ShiftLeft,
+                ShiftOp::Right => Op::ShiftRight,
+            };
+            self.emit_at(op, right.position);
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, term: &Term) -> Result<(), CodegenError> {
+        let overload = self.check_operands(self.term_operands(term))?;
+        match &term.left {
+            TermLeft::Factor(left) => self.factor(left)?,
+            TermLeft::Term(left) => self.term(left)?,
+        }
+
+        if let Some(right) = &term.right {
+            self.plain()?;
+            self.factor(&right.right)?;
+            self.plain()?;
+            let op = match right.op {
+                TermOp::Minus => Op::Subtract,
+                TermOp::Plus => Op::Add,
+            };
+            self.emit_operator(op, overload, 1, right.position);
+        }
+        Ok(())
+    }
+
+    fn factor(&mut self, factor: &Factor) -> Result<(), CodegenError> {
+        let overload = self.check_operands(self.factor_operands(factor))?;
+        match &factor.left {
+            FactorLeft::Unary(left) => self.unary(left)?,
+            FactorLeft::Factor(left) => self.factor(left)?,
+        }
+
+        if let Some(right) = &factor.right {
+            self.plain()?;
+            self.unary(&right.right)?;
+            self.plain()?;
+            let op = match right.op {
+                FactorOp::Div => Op::Divide,
+                FactorOp::Mult => Op::Multiply,
+                FactorOp::Mod => Op::Modulo,
+            };
+            self.emit_operator(op, overload, 1, right.position);
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self, unary: &Unary) -> Result<(), CodegenError> {
+        match unary.right.as_ref() {
+            UnaryRight::Unary(right) => self.unary(right)?,
+            UnaryRight::Power(right) => self.power(right)?,
+        }
+        if unary.op.is_some() {
+            self.plain()?;
+        }
+
+        match unary.op {
+            Some(UnaryOp::Not) => {
+                self.emit_at(Op::Not, unary.position);
+            }
+            Some(UnaryOp::Minus) => {
+                let operand = self.unary_operand(unary);
+                if let Ty::Struct(..) = operand {
+                    self.negation_type(&operand, unary.position)?;
+                }
+                let overload = self.overload(NEG, &operand);
+                self.emit_operator(Op::Negate, overload, 0, unary.position);
+            }
+            Some(UnaryOp::BitNot) => {
+                self.emit_at(Op::BitNot, unary.position);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn power(&mut self, power: &Power) -> Result<(), CodegenError> {
+        self.check_operands(self.power_operands(power))?;
+        self.call(&power.left)?;
+
+        if let Some(right) = &power.right {
+            self.plain()?;
+            self.unary(&right.right)?;
+            self.plain()?;
+            self.emit_at(Op::Power, right.position);
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, call: &Call) -> Result<(), CodegenError> {
+        // A call of a field is a method call
+        if let (CallLeft::Call(inner), Some(CallRight::Args { args, position })) =
+            (&call.left, &call.right)
+        {
+            if let Some(CallRight::Field { ident, .. }) = &inner.right {
+                if let Some(member) = self.module_member(&inner.left, ident)? {
+                    let slot = match member {
+                        Member::Global(slot) => slot,
+                        Member::Constant(index) => {
+                            self.emit_constant(self.constants[index as usize].clone());
+                            self.call_args(args, &ident.0, None, None, *position)?;
+                            self.emit_call(args, *position);
+                            return Ok(());
+                        }
+                    };
+                    self.emit(Op::GetGlobal(slot));
+                    let signature = self.globals[slot as usize].known.signature.clone();
+                    self.call_args(args, &ident.0, signature.as_ref(), None, *position)?;
+                    self.emit_call(args, *position);
+                    self.call_result(&ident.0, signature.as_ref(), call, *position);
+                    return Ok(());
+                }
+                if let Some(constructor) = self.enum_variant(&inner.left, ident)? {
+                    self.emit_constant(constructor);
+                    match self.constructor_signature(&inner.left, ident) {
+                        Some((name, signature)) => {
+                            self.call_args(args, &name, Some(&signature), None, *position)?
+                        }
+                        None => self.call_args(args, &ident.0, None, None, *position)?,
+                    }
+                    self.emit_call(args, *position);
+                    return Ok(());
+                }
+
+                self.check_method(&inner.left, ident)?;
+                self.call_left(&inner.left)?;
+                self.plain()?;
+                self.invoke(call, &inner.left, ident, args, *position)?;
+                return Ok(());
+            }
+
+            if let Some(CallRight::SafeField { ident, .. }) = &inner.right {
+                self.check_method(&inner.left, ident)?;
+                self.call_left(&inner.left)?;
+                let receiver = self.optional.take();
+                let skip = self.emit(Op::JumpIfNone(0));
+                self.invoke(call, &inner.left, ident, args, *position)?;
+                self.patch(skip);
+                let call = receiver.map(|_| (format!("`?.{}()`", ident.0), *position));
+                self.optional = self.optional.take().or(call);
+                return Ok(());
+            }
+        }
+
+        if let Some(CallRight::Field { ident, .. }) = &call.right {
+            if let Some(member) = self.module_member(&call.left, ident)? {
+                let slot = match member {
+                    Member::Global(slot) => slot,
+                    Member::Constant(index) => {
+                        self.emit_constant(self.constants[index as usize].clone());
+                        self.optional = None;
+                        return Ok(());
+                    }
+                };
+                self.emit(Op::GetGlobal(slot));
+                self.optional = self.globals[slot as usize]
+                    .known
+                    .optional
+                    .then(|| (format!("`{}`", ident.0), ident.1));
+                return Ok(());
+            }
+            if let Some(value) = self.enum_variant(&call.left, ident)? {
+                self.emit_constant(value);
+                self.optional = None;
+                return Ok(());
+            }
+        }
+
+        self.call_left(&call.left)?;
+
+        match &call.right {
+            Some(CallRight::Args { args, position }) => {
+                self.plain()?;
+                let name = match &call.left {
+                    CallLeft::Primary(Primary::Identifier(ident)) => match self.known(ident) {
+                        // `ok` and `err` are named after their variant, like other constructors
+                        None => result_constructor(&ident.0).map_or(ident.0.clone(), |variant| {
+                            self.enums[RESULT].variant_name(variant)
+                        }),
+                        Some(_) => ident.0.clone(),
+                    },
+                    _ => LAMBDA_NAME.to_string(),
+                };
+                let name = name.as_str();
+                let signature = self.callee_signature(&call.left);
+                self.call_args(args, name, signature.as_ref(), None, *position)?;
+                self.emit_call(args, *position);
+                self.call_result(name, signature.as_ref(), call, *position);
+            }
+            Some(CallRight::Index { index, position }) => {
+                self.plain()?;
+                let target = self.left_type(&call.left);
+                if let Ty::Struct(..) = target {
+                    self.index_type(&target, index, *position)?;
+                }
+                self.expr(index)?;
+                self.plain()?;
+                match self.overload(INDEX, &target) {
+                    Some(overload) => self.emit_operator(Op::Index, Some(overload), 1, *position),
+                    None => {
+                        self.emit_at(Op::Index, *position);
+                    }
+                }
+                let element =
+                    self.optional_type(call, *position, || "the element at the index".to_string());
+                self.optional = self.optional.take().or(element);
+            }
+            Some(CallRight::Slice {
+                start,
+                end,
+                position,
+            }) => {
+                self.plain()?;
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => {
+                            self.expr(bound)?;
+                            self.plain()?;
+                        }
+                        None => {
+                            self.emit(Op::Unit);
+                        }
+                    }
+                }
+                self.emit_at(Op::Slice, *position);
+            }
+            Some(CallRight::Field { ident, position }) => {
+                self.plain()?;
+                self.check_field(&call.left, ident)?;
+                let name = self.name_constant(ident);
+                self.emit_at(Op::GetField(name), *position);
+                let optional = self.field_optional(&call.left, ident) || self.is_optional(call);
+                self.optional = optional.then(|| (format!("field `{}`", ident.0), ident.1));
+            }
+            Some(CallRight::SafeField { ident, position }) => {
+                self.check_field(&call.left, ident)?;
+                let receiver = self.optional.take();
+                let skip = self.emit(Op::JumpIfNone(0));
+                let name = self.name_constant(ident);
+                self.emit_at(Op::GetField(name), *position);
+                self.patch(skip);
+                let field = self.field_optional(&call.left, ident)
+                    || self
+                        .field_type(self.left_type(&call.left).plain(), ident)
+                        .is_optional();
+                self.optional = if field {
+                    Some((format!("field `{}`", ident.0), ident.1))
+                } else {
+                    receiver.map(|_| (format!("`?.{}`", ident.0), ident.1))
+                };
+            }
+            Some(CallRight::Element { index, position }) => {
+                self.plain()?;
+                self.emit_at(Op::Element(*index), *position);
+                self.optional = self.optional_type(call, *position, || {
+                    format!("element {} of the tuple", index)
+                });
+            }
+            Some(CallRight::Try { position }) => {
+                if self.state().top_level {
+                    return Err(CodegenError::ReturnOutsideFunction { what: "`?`" });
+                }
+                if self.state().function.generator {
+                    return Err(self.generator_return("`?`"));
+                }
+                self.plain()?;
+                if self.state().entry_point.is_some() {
+                    self.emit_at(Op::CheckErr, *position);
+                }
+                self.emit_at(Op::Try, *position);
+                self.optional =
+                    self.optional_type(call, *position, || "the value of `?`".to_string());
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Compiles `call`, a call of the method `method` on the receiver on top of the stack
+    fn invoke(
+        &mut self,
+        call: &Call,
+        left: &CallLeft,
+        method: &Identifier,
+        args: &Args,
+        position: Position,
+    ) -> Result<(), CodegenError> {
+        // The receiver is the first parameter of a method
+        let signature = self.method_signature(left, method);
+        let receiver = self.left_type(left);
+        self.call_args(
+            args,
+            &method.0,
+            signature.as_ref(),
+            Some(receiver),
+            position,
+        )?;
+        let name = self.name_constant(method);
+        let count = (args.args.len() + args.named.len()) as u32;
+        let invoke = match self.names_constant(args) {
+            Some(names) => Op::InvokeNamed {
+                name,
+                args: count,
+                names,
+            },
+            None => Op::Invoke { name, args: count },
+        };
+        self.emit_at(invoke, position);
+        self.call_result(&method.0, signature.as_ref(), call, position);
+        Ok(())
+    }
+
+    /// Compiles the arguments of a call of the function `name` at `position`, checking them
+    /// against its signature when it is known. The receiver of a method call is its first
+    /// parameter.
+    fn call_args(
+        &mut self,
+        args: &Args,
+        name: &str,
+        signature: Option<&Signature>,
+        receiver: Option<Ty>,
+        position: Position,
+    ) -> Result<(), CodegenError> {
+        let skip = receiver.is_some() as usize;
+        if let Some(signature) = signature {
+            self.check_call(name, signature, receiver, args, position)?;
+        }
+        for (i, arg) in args.args.iter().enumerate() {
+            self.expr(arg)?;
+            let optional = signature
+                .is_none_or(|s| s.positional(i + skip).map(|p| s.params[p]) != Some(false));
+            self.store(optional, || format!("argument {} of `{}`", i + 1, name))?;
+        }
+        for (i, arg) in args.named.iter().enumerate() {
+            let argument = &arg.ident.0;
+            if args.named[..i]
+                .iter()
+                .any(|other| other.ident.0 == *argument)
+            {
+                return Err(CodegenError::DuplicateArgument {
+                    name: name.to_string(),
+                    argument: argument.clone(),
+                    position: arg.ident.1,
+                });
+            }
+            self.expr(&arg.value)?;
+            let optional =
+                signature.is_none_or(|s| s.named(argument).map(|p| s.params[p]) != Some(false));
+            self.store(optional, || {
+                format!("argument `{}` of `{}`", argument, name)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Emits a call of the value below the arguments `args`, which were just compiled
+    fn emit_call(&mut self, args: &Args, position: Position) {
+        let count = (args.args.len() + args.named.len()) as u32;
+        let call = match self.names_constant(args) {
+            Some(names) => Op::CallNamed { args: count, names },
+            None => Op::Call(count),
+        };
+        self.emit_at(call, position);
+    }
+
+    /// Adds a constant holding the names of the arguments of `args` passed by name, if there
+    /// are any
+    fn names_constant(&mut self, args: &Args) -> Option<u32> {
+        if args.named.is_empty() {
+            return None;
+        }
+        let names = args
+            .named
+            .iter()
+            .map(|arg| Value::String(arg.ident.0.as_str().into()))
+            .collect();
+        Some(self.chunk().add_constant(Value::Tuple(names)))
+    }
+
+    /// Records whether the result of `call`, which calls `name` at `position`, may be `none`
+    fn call_result(
+        &mut self,
+        name: &str,
+        signature: Option<&Signature>,
+        call: &Call,
+        position: Position,
+    ) {
+        let optional = signature.is_some_and(|s| s.returns_optional) || self.is_optional(call);
+        self.optional = optional.then(|| (format!("the result of `{}`", name), position));
+    }
+
+    /// Whether the type of the value of `call` says it may be `none`
+    fn is_optional(&self, call: &Call) -> bool {
+        self.call_type(call).is_optional()
+    }
+
+    /// Describes the value of `call` at `position` with `what` if its type says it may be `none`
+    fn optional_type(
+        &self,
+        call: &Call,
+        position: Position,
+        what: impl FnOnce() -> String,
+    ) -> Option<(String, Position)> {
+        self.is_optional(call).then(|| (what(), position))
+    }
+
+    fn call_left(&mut self, left: &CallLeft) -> Result<(), CodegenError> {
+        match left {
+            CallLeft::Primary(left) => self.primary(left),
+            CallLeft::Call(left) => self.call(left),
+        }
+    }
+
+    fn primary(&mut self, primary: &Primary) -> Result<(), CodegenError> {
+        self.optional = None;
+        match primary {
+            Primary::Int(literal) => {
+                let value =
+                    Value::parse_int(literal).ok_or_else(|| CodegenError::InvalidLiteral {
+                        literal: literal.clone(),
+                    })?;
+                self.emit_constant(value);
+            }
+            Primary::Float(literal) => {
+                let value = literal.parse().map_err(|_| CodegenError::InvalidLiteral {
+                    literal: literal.clone(),
+                })?;
+                self.emit_constant(Value::Float(value));
+            }
+            Primary::Decimal(literal) => {
+                let value =
+                    Value::parse_decimal(literal).ok_or_else(|| CodegenError::InvalidLiteral {
+                        literal: literal.clone(),
+                    })?;
+                self.emit_constant(value);
+            }
+            Primary::String(literal) => self.emit_constant(Value::String(literal.as_str().into())),
+            Primary::Char(c) => self.emit_constant(Value::Char(*c)),
+            Primary::Identifier(ident) => {
+                match self.resolve(ident)? {
+                    Resolved::Local(slot, _) => self.emit(Op::GetLocal(slot)),
+                    Resolved::Upvalue(index, _) => self.emit(Op::GetUpvalue(index)),
+                    Resolved::Global(slot, _) => self.emit(Op::GetGlobal(slot)),
+                    Resolved::Constant(index) => {
+                        let value = self.constants[index as usize].clone();
+                        let index = self.chunk().add_constant(value);
+                        self.emit(Op::Constant(index))
+                    }
+                    Resolved::Builtin(builtin) => {
+                        let index = self.chunk().add_constant(Value::Builtin(builtin));
+                        self.emit(Op::Constant(index))
+                    }
+                    Resolved::Constructor(variant) => {
+                        let constructor = Value::Constructor(self.enums[RESULT].clone(), variant);
+                        let index = self.chunk().add_constant(constructor);
+                        self.emit(Op::Constant(index))
+                    }
+                };
+                if self.known(ident).is_some_and(Known::may_be_none) {
+                    self.optional = Some((format!("`{}`", ident.0), ident.1));
+                }
+            }
+            Primary::True => {
+                self.emit(Op::True);
+            }
+            Primary::False => {
+                self.emit(Op::False);
+            }
+            Primary::None(position) => {
+                self.emit_constant(Value::None);
+                self.optional = Some(("`none`".to_string(), *position));
+            }
+            Primary::Grouping(expr) => self.expr(expr)?,
+            Primary::List(elements) => {
+                for element in &elements.args {
+                    self.expr(element)?;
+                    self.optional = None;
+                }
+                self.emit(Op::List(elements.args.len() as u32));
+            }
+            Primary::Struct(literal) => self.struct_literal(literal)?,
+            Primary::Map(map) => {
+                for entry in &map.entries {
+                    self.expr(&entry.key)?;
+                    self.plain()?;
+                    self.expr(&entry.value)?;
+                    self.optional = None;
+                }
+                self.emit_at(Op::Map(map.entries.len() as u32), map.position);
+            }
+            // Lambdas have no declared result, so they may return `none`
+            Primary::Lambda(lambda) => self.closure(
+                LAMBDA_NAME,
+                lambda.args.as_ref(),
+                true,
+                TypeParams::default(),
+                |this| {
+                    match &lambda.body {
+                        Body::Block(block) => this.function_block(block, lambda.generator)?,
+                        Body::Expr(expr) if lambda.generator => {
+                            this.state().function.generator = true;
+                            this.expr(expr)?;
+                            this.optional = None;
+                            this.emit(Op::Pop);
+                            this.emit(Op::Unit);
+                            this.emit_return()?;
+                        }
+                        Body::Expr(expr) => {
+                            this.expr(expr)?;
+                            this.emit_return()?;
+                        }
+                    }
+                    let result = if lambda.generator {
+                        Ty::Iterator(Box::default())
+                    } else if this.state().returned_none {
+                        Ty::optional(Ty::Unknown)
+                    } else {
+                        Ty::Unknown
+                    };
+                    let ty = Ty::Function(Box::new(result));
+                    this.lambda_types.insert(&**lambda, ty);
+                    Ok(())
+                },
+            )?,
+            Primary::Tuple(elements) => {
+                for element in &elements.args {
+                    self.expr(element)?;
+                    self.optional = None;
+                }
+                self.emit(Op::Tuple(elements.args.len() as u32));
+            }
+            Primary::Match(match_expr) => self.match_expr(match_expr)?,
+            Primary::If(if_expr) => self.if_expr(if_expr)?,
+            Primary::Loop(loop_expr) => self.loop_expr(loop_expr)?,
+            Primary::Block(block) => self.block_value(block)?,
+        }
+        Ok(())
+    }
+
+    fn struct_literal(&mut self, literal: &StructLiteral) -> Result<(), CodegenError> {
+        let index = self.struct_index(&literal.ident)?;
+        let state = &self.structs[index as usize];
+
+        for (i, init) in literal.fields.iter().enumerate() {
+            if !state.fields.contains(&init.ident.0) {
+                return Err(CodegenError::UnknownField {
+                    name: state.name.clone(),
+                    field: init.ident.0.clone(),
+                    position: init.ident.1,
+                });
+            }
+            if literal.fields[..i]
+                .iter()
+                .any(|f| f.ident.0 == init.ident.0)
+            {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: init.ident.0.clone(),
+                    position: init.ident.1,
+                });
+            }
+        }
+
+        // Values are generated in declaration order, which is the layout of the instance
+        let mut values = vec![];
+        for field in &state.fields {
+            match literal.fields.iter().find(|f| f.ident.0 == *field) {
+                Some(init) => values.push(&init.value),
+                None => {
+                    return Err(CodegenError::MissingField {
+                        name: state.name.clone(),
+                        field: field.clone(),
+                        position: literal.position,
+                    })
+                }
+            }
+        }
+        self.check_struct_literal(index, literal)?;
+        let optional = self.structs[index as usize].optional.clone();
+        for ((value, optional), field) in values.into_iter().zip(optional).zip(&literal.fields) {
+            self.expr(value)?;
+            self.store(optional, || format!("field `{}`", field.ident.0))?;
+        }
+        self.emit_at(Op::Construct(index), literal.position);
+        Ok(())
+    }
+}
+
+fn assign_op(op: &AssignOp) -> Op {
+    match op {
+        AssignOp::Plus => Op::Add,
+        AssignOp::Minus => Op::Subtract,
+        AssignOp::Mult => Op::Multiply,
+        AssignOp::Div => Op::Divide,
+        AssignOp::Mod => Op::Modulo,
+    }
+}
+
+fn is_mutable(v_type: &VariableType) -> bool {
+    matches!(v_type, VariableType::Var)
+}
+
+/// Whether a top level variable may hold `none`, which is when it is declared with an optional
+/// type or without a value
+fn declared_optional(variable: &Variable) -> bool {
+    match &variable.target.ty {
+        Some(ty) => ty.is_optional(),
+        None => variable.value.is_none(),
+    }
+}
+
+/// The parameters of the function `name` declared as `args` with their `defaults`, which fails
+/// when one without a default follows one with a default
+pub(super) fn function_params(
+    name: &str,
+    args: &[BindingPattern],
+    defaults: &[Option<Expr>],
+    variadic: bool,
+) -> Result<Params, CodegenError> {
+    let required = defaults.iter().take_while(|d| d.is_none()).count();
+    if let Some(missing) = defaults[required..].iter().position(Option::is_none) {
+        let missing = required + missing;
+        return Err(CodegenError::MissingDefault {
+            name: name.to_string(),
+            param: match args[missing].ident() {
+                Some(ident) => format!("parameter `{}`", ident.0),
+                None => format!("parameter {}", missing + 1),
+            },
+        });
+    }
+    Ok(Params {
+        names: args
+            .iter()
+            .map(|arg| arg.ident().map_or("", |ident| &ident.0).into())
+            .collect(),
+        required,
+        variadic,
+    })
+}
+
+/// The signature of the `next` method of an iterator over `element`, which gives `none` once the
+/// iterator has run out
+fn next_signature(element: Ty) -> Signature {
+    Signature {
+        params: vec![false],
+        returns_optional: true,
+        ret: Ty::optional(element),
+        names: Some(vec![]),
+        ..Signature::default()
+    }
+}
+
+/// The signature of `builtin`. Builtins take a collection followed by anything, except those of
+/// channels and persistent collections, which check the values they hold against the type of the
+/// collection, and those of numbers, which give a number of a known type.
+fn builtin_signature(builtin: Builtin) -> Signature {
+    let value = || Ty::Param("T".to_string());
+    let channel = || Ty::Chan(Box::new(value()));
+    let vector = || Ty::Vector(Box::new(value()));
+    let (key, entry) = (|| Ty::Param("K".to_string()), || Ty::Param("V".to_string()));
+    let dict = || Ty::Dict(Box::new(key()), Box::new(entry()));
+    let rounding = || Ty::Enum(ROUNDING as u32, vec![]);
+    let (types, ret) = match builtin {
+        Builtin::Chan => (vec![], channel()),
+        Builtin::Send => (vec![channel(), value()], Ty::Unit),
+        Builtin::Recv => (vec![channel()], value()),
+        Builtin::Select => (
+            vec![Ty::List(Box::new(channel()))],
+            Ty::Tuple(vec![Ty::Int, value()]),
+        ),
+        Builtin::Int => (vec![Ty::Unknown], Ty::Int),
+        Builtin::Float => (vec![Ty::Unknown], Ty::Float),
+        Builtin::Decimal => (vec![Ty::Unknown], Ty::Decimal),
+        Builtin::Rational => (vec![Ty::Unknown; 2], Ty::Rational),
+        Builtin::Round => (vec![Ty::Unknown, Ty::Int, rounding()], Ty::Decimal),
+        Builtin::Div => (
+            vec![Ty::Unknown, Ty::Unknown, Ty::Int, rounding()],
+            Ty::Decimal,
+        ),
+        Builtin::Vector => (vec![Ty::List(Box::new(value()))], vector()),
+        Builtin::Append => (vec![vector(), value()], vector()),
+        Builtin::Set => (vec![vector(), Ty::Int, value()], vector()),
+        Builtin::Dict => (vec![Ty::Map(Box::new(key()), Box::new(entry()))], dict()),
+        Builtin::Insert => (vec![dict(), key(), entry()], dict()),
+        Builtin::Without => (vec![dict(), key()], dict()),
+        _ => {
+            return Signature {
+                params: (0..builtin.arity()).map(|i| i > 0).collect(),
+                names: Some(vec![]),
+                required: Some(builtin.arity()),
+                ..Signature::default()
+            }
+        }
+    };
+    let type_params: &[&str] = match builtin {
+        Builtin::Chan
+        | Builtin::Send
+        | Builtin::Recv
+        | Builtin::Select
+        | Builtin::Vector
+        | Builtin::Append
+        | Builtin::Set => &["T"],
+        Builtin::Dict | Builtin::Insert | Builtin::Without => &["K", "V"],
+        _ => &[],
+    };
+    // Like those of other collections, persistent collections may hold `none`
+    let persistent = matches!(
+        builtin,
+        Builtin::Vector
+            | Builtin::Append
+            | Builtin::Set
+            | Builtin::Dict
+            | Builtin::Insert
+            | Builtin::Without
+    );
+    Signature {
+        params: (0..builtin.arity()).map(|i| persistent && i > 0).collect(),
+        required: Some(builtin.arity()),
+        type_params: type_params.iter().map(|p| p.to_string()).collect(),
+        types,
+        ret,
+        // Builtins only take arguments by position
+        names: Some(vec![]),
+        ..Signature::default()
+    }
+}
+
+/// The variant of the prelude `Result` constructed by the function `name`
+fn result_constructor(name: &str) -> Option<usize> {
+    match name {
+        "ok" => Some(OK),
+        "err" => Some(ERR),
+        _ => None,
+    }
+}
@@ -0,0 +1,793 @@
+//! Compilation of `match` expressions, along with the checks for non-exhaustive matches and
+//! unreachable arms.
+//!
+//! Arms are tried in order. The scrutinee is kept in a hidden local while the pattern of an arm
+//! is tested piece by piece, any failed test jumps to the next arm. Only once the whole pattern
+//! has matched are its bindings pushed as locals, so a failed test never has locals to clean up.
+//!
+//! Declarations, parameters and `for` loops destructure values with the same patterns. There a
+//! value that doesn't match is a runtime error rather than a reason to try another arm.
+//!
+//! The checks use the usefulness algorithm described in Luc Maranget's "Warnings for pattern
+//! matching". An arm is unreachable if its pattern matches nothing the unguarded arms above it
+//! don't already match, and a match is exhaustive if a wildcard after the last arm would be
+//! unreachable.
+
+use std::rc::Rc;
+
+use super::{Codegen, CodegenError};
+use crate::codegen::bytecode::Op;
+use crate::parser::ast::*;
+use crate::value::{EnumDef, Value};
+
+/// A pattern reduced to the values it matches, bindings are wildcards
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+/// What a pattern checks about a value before looking at the values inside it
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant {
+        def: u32,
+        variant: usize,
+    },
+    Bool(bool),
+    Tuple(usize),
+    /// A list of `len` values, or at least `len` values with `rest`
+    List {
+        len: usize,
+        rest: bool,
+    },
+    Struct {
+        def: u32,
+        fields: usize,
+    },
+    /// Any other literal, in its printed form. There are too many of these for a match to list
+    /// them all, so only a wildcard or binding makes a match on them exhaustive.
+    Literal(String),
+}
+
+impl Ctor {
+    fn arity(&self, enums: &[Rc<EnumDef>]) -> usize {
+        match self {
+            Ctor::Variant { def, variant } => enums[*def as usize].variants[*variant].arity,
+            Ctor::Tuple(len) | Ctor::List { len, .. } => *len,
+            Ctor::Struct { fields, .. } => *fields,
+            Ctor::Bool(_) | Ctor::Literal(_) => 0,
+        }
+    }
+}
+
+/// A step from a value to a value inside it
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Element(u32),
+    /// The list of the elements from the given index on
+    Rest(u32),
+}
+
+impl Codegen {
+    pub(super) fn match_expr(&mut self, match_expr: &Match) -> Result<(), CodegenError> {
+        let pats = match_expr
+            .arms
+            .iter()
+            .map(|arm| self.lower(&arm.pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.check_arms(match_expr, &pats)?;
+
+        self.expr(&match_expr.expr)?;
+        // A scrutinee that may be `none` is still `none` when it reaches an arm, unless an arm
+        // before it matched `none`
+        let mut scrutinee_optional = self.optional.take().is_some();
+        let scrutinee = self.add_local("", false);
+
+        let mut ends = vec![];
+        let mut optional = None;
+        for arm in &match_expr.arms {
+            let mut fails = vec![];
+            self.pattern_tests(&arm.pattern, scrutinee, &mut vec![], &mut fails)?;
+
+            self.begin_scope();
+            let depth = self.state().scope_depth - 1;
+            let mut bindings = vec![];
+            self.collect_bindings(&arm.pattern, &mut vec![], &mut bindings)?;
+            let mut optional_bindings = self.optional_bindings(&arm.pattern)?;
+            if let (Pattern::Binding(ident), true) = (&arm.pattern, scrutinee_optional) {
+                optional_bindings.push(ident.0.clone());
+            }
+            for (name, path) in bindings {
+                self.load(scrutinee, &path);
+                self.add_local(&name.0, false);
+                if optional_bindings.contains(&name.0) {
+                    self.mark_optional();
+                }
+            }
+
+            let guard_fail = match &arm.guard {
+                Some(guard) => {
+                    self.expr(guard)?;
+                    self.plain()?;
+                    let jump = self.emit(Op::JumpIfFalse(0));
+                    self.emit(Op::Pop);
+                    Some(jump)
+                }
+                None => None,
+            };
+
+            // The value of the arm replaces the scrutinee, which is below the bindings
+            self.arm_body(&arm.body, scrutinee)?;
+            optional = optional.or(self.optional.take());
+            if arm.guard.is_none() && matches!(arm.pattern, Pattern::Literal(LiteralPattern::None))
+            {
+                scrutinee_optional = false;
+            }
+            self.pop_locals(depth);
+            ends.push(self.emit(Op::Jump(0)));
+
+            let mut skip = None;
+            if let Some(guard_fail) = guard_fail {
+                self.patch(guard_fail);
+                self.emit(Op::Pop);
+                self.pop_locals(depth);
+                if !fails.is_empty() {
+                    skip = Some(self.emit(Op::Jump(0)));
+                }
+            }
+            self.discard_scope();
+
+            if !fails.is_empty() {
+                for fail in fails {
+                    self.patch(fail);
+                }
+                self.emit(Op::Pop);
+            }
+            if let Some(skip) = skip {
+                self.patch(skip);
+            }
+        }
+
+        for end in ends {
+            self.patch(end);
+        }
+        // The scrutinee's slot now holds the value of the match, which stays as a temporary
+        self.state().locals.pop();
+        self.optional = optional;
+        Ok(())
+    }
+
+    /// Destructures the value in local `slot` with the pattern of a declaration, parameter or
+    /// `for` loop. The bindings are added as locals, or defined as globals at the top level.
+    pub(super) fn bind_pattern(
+        &mut self,
+        target: &BindingPattern,
+        slot: u32,
+        mutable: bool,
+    ) -> Result<(), CodegenError> {
+        self.lower(&target.pattern)?;
+
+        let mut fails = vec![];
+        self.pattern_tests(&target.pattern, slot, &mut vec![], &mut fails)?;
+        if !fails.is_empty() {
+            let matched = self.emit(Op::Jump(0));
+            for fail in fails {
+                self.patch(fail);
+            }
+            self.emit_at(Op::NoMatch, target.position);
+            self.patch(matched);
+        }
+
+        let mut bindings = vec![];
+        self.collect_bindings(&target.pattern, &mut vec![], &mut bindings)?;
+        let optional = self.optional_bindings(&target.pattern)?;
+        for (name, path) in bindings {
+            self.load(slot, &path);
+            if self.state().scope_depth == 0 {
+                // Top level variables were declared up front
+                let global = self.scope.global_slots[&name.0];
+                self.emit(Op::DefineGlobal(global));
+            } else {
+                self.add_local(&name.0, mutable);
+                if optional.contains(&name.0) {
+                    self.mark_optional();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Names that `pattern` binds directly to a struct field or enum value declared optional
+    pub(super) fn optional_bindings(&self, pattern: &Pattern) -> Result<Vec<String>, CodegenError> {
+        let mut names = vec![];
+        let mut optional = |pattern: &Pattern, declared: bool| {
+            if let (Pattern::Binding(ident), true) = (pattern, declared) {
+                names.push(ident.0.clone());
+            }
+        };
+        match pattern {
+            Pattern::Struct(struct_pattern) => {
+                let def = self.struct_index(&struct_pattern.ident)?;
+                for (index, sub) in self.sub_patterns(pattern)? {
+                    optional(sub, self.structs[def as usize].optional[index as usize]);
+                }
+            }
+            Pattern::Variant(variant_pattern) => {
+                let def = self.pattern_enum(variant_pattern)?;
+                let variant = self.variant_index(def, &variant_pattern.variant)?;
+                for (index, sub) in self.sub_patterns(pattern)? {
+                    let key = (def, variant, index as usize);
+                    optional(sub, self.optional_payloads.contains(&key));
+                }
+            }
+            _ => {}
+        }
+        for (_, sub) in self.sub_patterns(pattern)? {
+            names.extend(self.optional_bindings(sub)?);
+        }
+        Ok(names)
+    }
+
+    /// Names bound by `pattern`, in the order they are bound
+    pub(super) fn binding_names(&self, pattern: &Pattern) -> Result<Vec<Identifier>, CodegenError> {
+        let mut bindings = vec![];
+        self.collect_bindings(pattern, &mut vec![], &mut bindings)?;
+        Ok(bindings.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Compiles the body of an arm and stores its value in `slot`
+    fn arm_body(&mut self, body: &Body, slot: u32) -> Result<(), CodegenError> {
+        match body {
+            Body::Expr(expr) => self.expr(expr)?,
+            Body::Block(block) => self.block_value(block)?,
+        }
+        self.emit(Op::SetLocal(slot));
+        self.emit(Op::Pop);
+        Ok(())
+    }
+
+    /// Pushes the value found by following `path` into the value in local `slot`
+    fn load(&mut self, slot: u32, path: &[Step]) {
+        self.emit(Op::GetLocal(slot));
+        for step in path {
+            match *step {
+                Step::Element(index) => {
+                    self.emit(Op::Element(index));
+                }
+                Step::Rest(start) => {
+                    self.emit_constant(Value::Int(start as i64));
+                    self.emit(Op::Unit);
+                    self.emit(Op::Slice);
+                }
+            }
+        }
+    }
+
+    /// Emits the tests of `pattern` against the value at `path`, adding a jump for each failed
+    /// test to `fails`. Each failed test leaves false on the stack.
+    fn pattern_tests(
+        &mut self,
+        pattern: &Pattern,
+        slot: u32,
+        path: &mut Vec<Step>,
+        fails: &mut Vec<usize>,
+    ) -> Result<(), CodegenError> {
+        let test = match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => return Ok(()),
+            Pattern::Literal(literal) => {
+                self.load(slot, path);
+                let value = literal_value(literal)?;
+                self.emit_constant(value);
+                Op::Equal
+            }
+            Pattern::Tuple(patterns) => {
+                self.load(slot, path);
+                Op::IsTuple(patterns.len() as u32)
+            }
+            Pattern::Variant(pattern) => {
+                let def = self.pattern_enum(pattern)?;
+                let variant = self.variant_index(def, &pattern.variant)?;
+                self.load(slot, path);
+                Op::IsVariant {
+                    def,
+                    variant: variant as u32,
+                }
+            }
+            Pattern::List(pattern) => {
+                self.load(slot, path);
+                Op::IsList {
+                    len: pattern.elements.len() as u32,
+                    rest: pattern.rest.is_some(),
+                }
+            }
+            Pattern::Struct(pattern) => {
+                let def = self.struct_index(&pattern.ident)?;
+                self.load(slot, path);
+                Op::IsStruct(def)
+            }
+        };
+        self.emit(test);
+        fails.push(self.emit(Op::JumpIfFalse(0)));
+        self.emit(Op::Pop);
+
+        for (index, pattern) in self.sub_patterns(pattern)? {
+            path.push(Step::Element(index));
+            self.pattern_tests(pattern, slot, path, fails)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// The patterns inside `pattern` along with the index of the value each of them matches
+    fn sub_patterns<'p>(
+        &self,
+        pattern: &'p Pattern,
+    ) -> Result<Vec<(u32, &'p Pattern)>, CodegenError> {
+        let indexed = |patterns: &'p [Pattern]| {
+            patterns
+                .iter()
+                .enumerate()
+                .map(|(i, pattern)| (i as u32, pattern))
+                .collect()
+        };
+        Ok(match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) | Pattern::Literal(_) => vec![],
+            Pattern::Tuple(patterns) => indexed(patterns),
+            Pattern::Variant(pattern) => indexed(&pattern.args),
+            Pattern::List(pattern) => indexed(&pattern.elements),
+            Pattern::Struct(pattern) => {
+                let def = self.struct_index(&pattern.ident)?;
+                let state = &self.structs[def as usize];
+                pattern
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        let index = state
+                            .fields
+                            .iter()
+                            .position(|name| *name == field.ident.0)
+                            .ok_or_else(|| CodegenError::UnknownField {
+                                name: state.name.clone(),
+                                field: field.ident.0.clone(),
+                                position: field.ident.1,
+                            })?;
+                        Ok((index as u32, &field.pattern))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        })
+    }
+
+    /// Names bound by `pattern`
+    pub(super) fn bound_names(&self, pattern: &Pattern) -> Result<Vec<Identifier>, CodegenError> {
+        let mut bindings = vec![];
+        self.collect_bindings(pattern, &mut vec![], &mut bindings)?;
+        Ok(bindings.into_iter().map(|(ident, _)| ident).collect())
+    }
+
+    /// Names bound by `pattern` along with the path to their value
+    fn collect_bindings(
+        &self,
+        pattern: &Pattern,
+        path: &mut Vec<Step>,
+        bindings: &mut Vec<(Identifier, Vec<Step>)>,
+    ) -> Result<(), CodegenError> {
+        let mut bind = |ident: &Identifier, path: Vec<Step>| {
+            if bindings.iter().any(|(name, _)| name.0 == ident.0) {
+                return Err(CodegenError::DuplicateBinding {
+                    name: ident.0.clone(),
+                    position: ident.1,
+                });
+            }
+            bindings.push((Identifier(ident.0.clone(), ident.1), path));
+            Ok(())
+        };
+        match pattern {
+            Pattern::Binding(ident) => return bind(ident, path.clone()),
+            Pattern::List(ListPattern {
+                elements,
+                rest: Some(Some(ident)),
+            }) => {
+                let mut rest_path = path.clone();
+                rest_path.push(Step::Rest(elements.len() as u32));
+                bind(ident, rest_path)?;
+            }
+            _ => {}
+        }
+
+        for (index, pattern) in self.sub_patterns(pattern)? {
+            path.push(Step::Element(index));
+            self.collect_bindings(pattern, path, bindings)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    fn pattern_enum(&self, pattern: &VariantPattern) -> Result<u32, CodegenError> {
+        self.scope
+            .enum_slots
+            .get(&pattern.ident.0)
+            .copied()
+            .ok_or_else(|| CodegenError::UndefinedEnum {
+                name: pattern.ident.0.clone(),
+                position: pattern.ident.1,
+            })
+    }
+
+    /// Checks that enums in `pattern` exist and are given the right number of patterns
+    fn lower(&self, pattern: &Pattern) -> Result<Pat, CodegenError> {
+        Ok(match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => Pat::Wild,
+            Pattern::Literal(LiteralPattern::Bool(b)) => Pat::Ctor(Ctor::Bool(*b), vec![]),
+            Pattern::Literal(literal) => {
+                Pat::Ctor(Ctor::Literal(literal_value(literal)?.repr()), vec![])
+            }
+            Pattern::Tuple(patterns) => Pat::Ctor(
+                Ctor::Tuple(patterns.len()),
+                patterns
+                    .iter()
+                    .map(|p| self.lower(p))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Pattern::Variant(pattern) => {
+                let def = self.pattern_enum(pattern)?;
+                let variant = self.variant_index(def, &pattern.variant)?;
+                let expected = self.enums[def as usize].variants[variant].arity;
+                if pattern.args.len() != expected {
+                    return Err(CodegenError::PatternArity {
+                        name: self.enums[def as usize].variant_name(variant),
+                        expected,
+                        found: pattern.args.len(),
+                        position: pattern.position,
+                    });
+                }
+
+                Pat::Ctor(
+                    Ctor::Variant { def, variant },
+                    pattern
+                        .args
+                        .iter()
+                        .map(|p| self.lower(p))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Pattern::List(pattern) => Pat::Ctor(
+                Ctor::List {
+                    len: pattern.elements.len(),
+                    rest: pattern.rest.is_some(),
+                },
+                pattern
+                    .elements
+                    .iter()
+                    .map(|p| self.lower(p))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Pattern::Struct(pattern) => {
+                let def = self.struct_index(&pattern.ident)?;
+                let state = &self.structs[def as usize];
+                let mut args: Vec<Option<Pat>> = vec![None; state.fields.len()];
+                for field in &pattern.fields {
+                    let index = state
+                        .fields
+                        .iter()
+                        .position(|name| *name == field.ident.0)
+                        .ok_or_else(|| CodegenError::UnknownField {
+                            name: state.name.clone(),
+                            field: field.ident.0.clone(),
+                            position: field.ident.1,
+                        })?;
+                    if args[index].is_some() {
+                        return Err(CodegenError::DuplicateDefinition {
+                            name: field.ident.0.clone(),
+                            position: field.ident.1,
+                        });
+                    }
+                    args[index] = Some(self.lower(&field.pattern)?);
+                }
+
+                // Fields can only be left out with `..`
+                let args = args
+                    .into_iter()
+                    .zip(&state.fields)
+                    .map(|(arg, name)| match arg {
+                        Some(arg) => Ok(arg),
+                        None if pattern.rest => Ok(Pat::Wild),
+                        None => Err(CodegenError::MissingField {
+                            name: state.name.clone(),
+                            field: name.clone(),
+                            position: pattern.ident.1,
+                        }),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Pat::Ctor(
+                    Ctor::Struct {
+                        def,
+                        fields: state.fields.len(),
+                    },
+                    args,
+                )
+            }
+        })
+    }
+
+    /// A pattern as it would be written in source
+    fn show(&self, pat: &Pat) -> String {
+        let list = |args: &[Pat]| {
+            args.iter()
+                .map(|arg| self.show(arg))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match pat {
+            Pat::Wild => "_".to_string(),
+            Pat::Ctor(Ctor::Variant { def, variant }, args) => {
+                let name = self.enums[*def as usize].variant_name(*variant);
+                if args.is_empty() {
+                    name
+                } else {
+                    format!("{}({})", name, list(args))
+                }
+            }
+            Pat::Ctor(Ctor::Bool(b), _) => b.to_string(),
+            Pat::Ctor(Ctor::Tuple(1), args) => format!("({},)", list(args)),
+            Pat::Ctor(Ctor::Tuple(_), args) => format!("({})", list(args)),
+            Pat::Ctor(Ctor::List { rest: false, .. }, args) => format!("[{}]", list(args)),
+            Pat::Ctor(Ctor::List { rest: true, .. }, args) if args.is_empty() => "[..]".to_string(),
+            Pat::Ctor(Ctor::List { rest: true, .. }, args) => format!("[{}, ..]", list(args)),
+            Pat::Ctor(Ctor::Struct { def, .. }, args) => {
+                let state = &self.structs[*def as usize];
+                let fields = state
+                    .fields
+                    .iter()
+                    .zip(args)
+                    .map(|(name, arg)| format!("{}: {}", name, self.show(arg)))
+                    .collect::<Vec<_>>();
+                if fields.is_empty() {
+                    format!("{} {{}}", state.name)
+                } else {
+                    format!("{} {{ {} }}", state.name, fields.join(", "))
+                }
+            }
+            Pat::Ctor(Ctor::Literal(literal), _) => literal.clone(),
+        }
+    }
+
+    /// Reports the first arm that can never match, or a value no arm matches. Guarded arms may
+    /// not match, so they don't count toward exhaustiveness.
+    fn check_arms(&self, match_expr: &Match, pats: &[Pat]) -> Result<(), CodegenError> {
+        let mut matrix: Vec<Vec<Pat>> = vec![];
+        for (arm, pat) in match_expr.arms.iter().zip(pats) {
+            let row = vec![pat.clone()];
+            if useful(&self.enums, &matrix, &row).is_none() {
+                return Err(CodegenError::UnreachableArm {
+                    position: arm.position,
+                });
+            }
+            if arm.guard.is_none() {
+                matrix.push(row);
+            }
+        }
+
+        if let Some(witness) = useful(&self.enums, &matrix, &[Pat::Wild]) {
+            return Err(CodegenError::NonExhaustiveMatch {
+                missing: self.show(&witness[0]),
+                position: match_expr.position,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn literal_value(literal: &LiteralPattern) -> Result<Value, CodegenError> {
+    let invalid = |literal: &String| CodegenError::InvalidLiteral {
+        literal: literal.clone(),
+    };
+    Ok(match literal {
+        LiteralPattern::Int(literal) => {
+            Value::parse_int(literal).ok_or_else(|| invalid(literal))?
+        }
+        LiteralPattern::Float(literal) => {
+            Value::Float(literal.parse().map_err(|_| invalid(literal))?)
+        }
+        LiteralPattern::Decimal(literal) => {
+            Value::parse_decimal(literal).ok_or_else(|| invalid(literal))?
+        }
+        LiteralPattern::String(literal) => Value::String(literal.as_str().into()),
+        LiteralPattern::Char(c) => Value::Char(*c),
+        LiteralPattern::Bool(b) => Value::Bool(*b),
+        LiteralPattern::None => Value::None,
+    })
+}
+
+/// Returns a row of patterns matching values that `row` matches and no row of `matrix` does,
+/// or `None` if there are no such values
+fn useful(enums: &[Rc<EnumDef>], matrix: &[Vec<Pat>], row: &[Pat]) -> Option<Vec<Pat>> {
+    let Some((head, rest)) = row.split_first() else {
+        return if matrix.is_empty() {
+            Some(vec![])
+        } else {
+            None
+        };
+    };
+
+    match head {
+        Pat::Ctor(ctor, args) => {
+            let mut heads = column_heads(matrix);
+            heads.push(ctor);
+            split(ctor, &heads).iter().find_map(|split_ctor| {
+                // A split constructor is always one `ctor` covers
+                let mut specialized_row = covers(ctor, args, split_ctor)?;
+                specialized_row.extend_from_slice(rest);
+                let witness = useful(
+                    enums,
+                    &specialize(enums, matrix, split_ctor),
+                    &specialized_row,
+                )?;
+                Some(rebuild(split_ctor, split_ctor.arity(enums), witness))
+            })
+        }
+        Pat::Wild => {
+            let heads = column_heads(matrix);
+
+            match complete_signature(enums, &heads) {
+                Some(ctors) => ctors.iter().find_map(|ctor| {
+                    let arity = ctor.arity(enums);
+                    let mut specialized_row = vec![Pat::Wild; arity];
+                    specialized_row.extend_from_slice(rest);
+                    let witness =
+                        useful(enums, &specialize(enums, matrix, ctor), &specialized_row)?;
+                    Some(rebuild(ctor, arity, witness))
+                }),
+                None => {
+                    let default: Vec<Vec<Pat>> = matrix
+                        .iter()
+                        .filter(|row| matches!(row[0], Pat::Wild))
+                        .map(|row| row[1..].to_vec())
+                        .collect();
+                    let mut witness = useful(enums, &default, rest)?;
+                    let missing = match missing_ctor(enums, &heads) {
+                        Some(ctor) => {
+                            let arity = ctor.arity(enums);
+                            Pat::Ctor(ctor, vec![Pat::Wild; arity])
+                        }
+                        None => Pat::Wild,
+                    };
+                    witness.insert(0, missing);
+                    Some(witness)
+                }
+            }
+        }
+    }
+}
+
+/// Constructors in the first column of `matrix`
+fn column_heads(matrix: &[Vec<Pat>]) -> Vec<&Ctor> {
+    matrix
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Ctor(ctor, _) => Some(ctor),
+            Pat::Wild => None,
+        })
+        .collect()
+}
+
+/// The constructors `ctor` is checked as. A list pattern with a rest matches lists of its own
+/// length and up, which are split into the lengths the list patterns in `heads` tell apart.
+fn split(ctor: &Ctor, heads: &[&Ctor]) -> Vec<Ctor> {
+    match ctor {
+        Ctor::List { len, rest: true } => {
+            let longest = list_split_len(heads).max(*len);
+            let mut ctors: Vec<Ctor> = (*len..longest)
+                .map(|len| Ctor::List { len, rest: false })
+                .collect();
+            ctors.push(Ctor::List {
+                len: longest,
+                rest: true,
+            });
+            ctors
+        }
+        ctor => vec![ctor.clone()],
+    }
+}
+
+/// The length from which no list pattern in `heads` tells lists apart
+fn list_split_len(heads: &[&Ctor]) -> usize {
+    heads
+        .iter()
+        .map(|ctor| match ctor {
+            Ctor::List { len, rest: false } => len + 1,
+            Ctor::List { len, rest: true } => *len,
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// The patterns for the values inside `target` if a pattern with `head` and `args` matches all
+/// of its values. `target` is a constructor produced by `split`.
+fn covers(head: &Ctor, args: &[Pat], target: &Ctor) -> Option<Vec<Pat>> {
+    match (head, target) {
+        (
+            Ctor::List { len, rest: true },
+            Ctor::List {
+                len: target_len, ..
+            },
+        ) if len <= target_len => {
+            let mut args = args.to_vec();
+            args.resize(*target_len, Pat::Wild);
+            Some(args)
+        }
+        _ if head == target => Some(args.to_vec()),
+        _ => None,
+    }
+}
+
+/// Rows of `matrix` that match `ctor` in their first column, with that column replaced by the
+/// patterns for the values inside it
+fn specialize(enums: &[Rc<EnumDef>], matrix: &[Vec<Pat>], ctor: &Ctor) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter_map(|row| {
+            let mut specialized = match &row[0] {
+                Pat::Ctor(head, args) => covers(head, args, ctor)?,
+                Pat::Wild => vec![Pat::Wild; ctor.arity(enums)],
+            };
+            specialized.extend_from_slice(&row[1..]);
+            Some(specialized)
+        })
+        .collect()
+}
+
+/// Folds the first `arity` patterns of `witness` back into `ctor`
+fn rebuild(ctor: &Ctor, arity: usize, mut witness: Vec<Pat>) -> Vec<Pat> {
+    let rest = witness.split_off(arity);
+    let mut rebuilt = vec![Pat::Ctor(ctor.clone(), witness)];
+    rebuilt.extend(rest);
+    rebuilt
+}
+
+/// Every constructor of the type of `heads` if all of them appear in `heads`
+fn complete_signature(enums: &[Rc<EnumDef>], heads: &[&Ctor]) -> Option<Vec<Ctor>> {
+    let all = match heads.first()? {
+        Ctor::Variant { def, .. } => (0..enums[*def as usize].variants.len())
+            .map(|variant| Ctor::Variant { def: *def, variant })
+            .collect(),
+        Ctor::Bool(_) => vec![Ctor::Bool(false), Ctor::Bool(true)],
+        Ctor::Tuple(len) => vec![Ctor::Tuple(*len)],
+        Ctor::Struct { def, fields } => vec![Ctor::Struct {
+            def: *def,
+            fields: *fields,
+        }],
+        // Every length below the split length, and a rest for the longer lists
+        Ctor::List { .. } => {
+            let longest = list_split_len(heads);
+            let mut all: Vec<Ctor> = (0..longest)
+                .map(|len| Ctor::List { len, rest: false })
+                .collect();
+            all.push(Ctor::List {
+                len: longest,
+                rest: true,
+            });
+            return Some(all);
+        }
+        Ctor::Literal(_) => return None,
+    };
+    if all.iter().all(|ctor| heads.contains(&ctor)) {
+        Some(all)
+    } else {
+        None
+    }
+}
+
+/// A constructor of the type of `heads` that is not in `heads`, if one can be named
+fn missing_ctor(enums: &[Rc<EnumDef>], heads: &[&Ctor]) -> Option<Ctor> {
+    let candidates = match heads.first()? {
+        Ctor::Variant { def, .. } => (0..enums[*def as usize].variants.len())
+            .map(|variant| Ctor::Variant { def: *def, variant })
+            .collect(),
+        Ctor::Bool(_) => vec![Ctor::Bool(false), Ctor::Bool(true)],
+        Ctor::Tuple(_) | Ctor::List { .. } | Ctor::Struct { .. } | Ctor::Literal(_) => vec![],
+    };
+    candidates.into_iter().find(|ctor| !heads.contains(&ctor))
+}
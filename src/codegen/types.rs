@@ -0,0 +1,1325 @@
+//! Static types, checked against the types declared for parameters, fields and variables.
+//!
+//! Values are represented the same way whatever their type, so generic functions and types are
+//! compiled once and their type parameters only matter here. Each use of a generic declaration
+//! infers its type arguments from the values given in their place, and fails when two values need
+//! different types for the same parameter. A type that isn't known agrees with every type, so
+//! code without annotations is never rejected.
+
+use std::collections::HashMap;
+
+use super::{Codegen, CodegenError, Known, Member, Signature, RESULT};
+use crate::decimal::Rounding;
+use crate::parser::ast::*;
+use crate::token::Position;
+use crate::value::{operator_method, Value, CMP, EQ, INDEX, NEG};
+
+/// A type known at compile time. The code generator also tracks whether the value it just
+/// compiled may be `none` and reports it where the value is used, so a `T?` is accepted where a
+/// `T` is expected directly. Inside collections, tuples and the results of functions only the
+/// type says that a value may be `none`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) enum Ty {
+    Int,
+    Float,
+    Decimal,
+    Rational,
+    Bool,
+    Char,
+    String,
+    Unit,
+    /// A function with the type of its result
+    Function(Box<Ty>),
+    List(Box<Ty>),
+    Map(Box<Ty>, Box<Ty>),
+    Vector(Box<Ty>),
+    Dict(Box<Ty>, Box<Ty>),
+    /// An iterator over values of a type, which is what calling a generator gives
+    Iterator(Box<Ty>),
+    /// A channel carrying values of a type between tasks
+    Chan(Box<Ty>),
+    Tuple(Vec<Ty>),
+    /// The struct at an index of the program's structs with its type arguments
+    Struct(u32, Vec<Ty>),
+    /// The enum at an index of the program's enums with its type arguments
+    Enum(u32, Vec<Ty>),
+    /// A value of any struct implementing the trait at an index of the program's traits
+    Dyn(u32),
+    /// A type parameter of an enclosing declaration, which stands for one type chosen by each use
+    Param(String),
+    /// A value of a type or `none`
+    Optional(Box<Ty>),
+    /// A value of any of several types that don't agree, like the elements of a list literal
+    /// holding different structs
+    Mixed(Vec<Ty>),
+    #[default]
+    Unknown,
+}
+
+impl Ty {
+    /// The type of values of `ty` or `none`
+    pub(super) fn optional(ty: Ty) -> Ty {
+        match ty {
+            Ty::Optional(_) => ty,
+            ty => Ty::Optional(Box::new(ty)),
+        }
+    }
+
+    /// The type of the value when it isn't `none`
+    pub(super) fn plain(self) -> Ty {
+        match self {
+            Ty::Optional(ty) => *ty,
+            ty => ty,
+        }
+    }
+
+    pub(super) fn is_optional(&self) -> bool {
+        matches!(self, Ty::Optional(_))
+    }
+}
+
+/// Type parameters declared by a function or type, along with the traits bounding them
+#[derive(Debug, Clone, Default)]
+pub(super) struct TypeParams {
+    pub(super) names: Vec<String>,
+    /// Each parameter with a trait its types must implement
+    pub(super) bounds: Vec<(String, u32)>,
+}
+
+/// Type parameters and the declared types of the variants of an enum
+pub(super) struct EnumTypes {
+    pub(super) params: Vec<String>,
+    pub(super) bounds: Vec<(String, u32)>,
+    pub(super) variants: Vec<Vec<Ty>>,
+}
+
+impl EnumTypes {
+    /// The types of the prelude `Result<T, E>`
+    pub(super) fn result() -> Self {
+        let params = vec!["T".to_string(), "E".to_string()];
+        let variants = params.iter().map(|p| vec![Ty::Param(p.clone())]).collect();
+        Self {
+            params,
+            bounds: vec![],
+            variants,
+        }
+    }
+
+    /// The types of the prelude `Rounding`, whose variants hold no values
+    pub(super) fn rounding() -> Self {
+        Self {
+            params: vec![],
+            bounds: vec![],
+            variants: vec![vec![]; Rounding::ALL.len()],
+        }
+    }
+}
+
+/// A method of a struct that an operator applied to the struct calls
+pub(super) struct Overload {
+    pub(super) method: &'static str,
+    /// Whether the method may return `none`
+    pub(super) returns_optional: bool,
+}
+
+/// Why a type can't be used where another is expected
+enum Disagreement {
+    Mismatch,
+    /// The type parameter `param` would have to be both `first` and `second`
+    Conflict {
+        param: String,
+        first: Ty,
+        second: Ty,
+    },
+}
+
+/// Type arguments inferred for the type parameters of a declaration at one of its uses
+pub(super) struct Inference<'a> {
+    codegen: &'a Codegen,
+    params: &'a [String],
+    bound: HashMap<String, Ty>,
+    /// Whether the types being bound are those of a `Mixed`, where a parameter takes each of
+    /// the types it is bound to instead of conflicting
+    mixing: bool,
+}
+
+impl<'a> Inference<'a> {
+    fn new(codegen: &'a Codegen, params: &'a [String]) -> Self {
+        Self {
+            codegen,
+            params,
+            bound: HashMap::new(),
+            mixing: false,
+        }
+    }
+
+    /// Checks that a value of type `found` can be used where `expected` is declared, binding the
+    /// type parameters in `expected`
+    fn bind(&mut self, expected: &Ty, found: &Ty) -> Result<(), Disagreement> {
+        match (expected, found) {
+            (Ty::Unknown, _) | (_, Ty::Unknown) => Ok(()),
+            (Ty::Param(param), _) if self.params.contains(param) => {
+                let ty = match self.bound.get(param) {
+                    Some(bound) => match merge(bound, found) {
+                        Some(merged) => merged,
+                        None if self.mixing => mixed([bound.clone(), found.clone()]),
+                        None => {
+                            return Err(Disagreement::Conflict {
+                                param: param.clone(),
+                                first: bound.clone(),
+                                second: found.clone(),
+                            })
+                        }
+                    },
+                    None => found.clone(),
+                };
+                self.bound.insert(param.clone(), ty);
+                Ok(())
+            }
+            // Each of the types must be usable, e.g. every struct in a `List<dyn Show>`
+            (_, Ty::Mixed(types)) => {
+                let mixing = std::mem::replace(&mut self.mixing, true);
+                let bound = types.iter().try_for_each(|ty| self.bind(expected, ty));
+                self.mixing = mixing;
+                bound
+            }
+            (Ty::Optional(expected), Ty::Optional(found)) => self.bind(expected, found),
+            (Ty::Optional(expected), _) => self.bind(expected, found),
+            // A value that may be `none` can only be held where `none` is allowed
+            (_, Ty::Optional(_)) => Err(Disagreement::Mismatch),
+            (Ty::Dyn(index), _) if self.codegen.implements(found, *index) => Ok(()),
+            (Ty::List(expected), Ty::List(found))
+            | (Ty::Vector(expected), Ty::Vector(found))
+            | (Ty::Iterator(expected), Ty::Iterator(found))
+            | (Ty::Chan(expected), Ty::Chan(found))
+            | (Ty::Function(expected), Ty::Function(found)) => self.bind(expected, found),
+            (Ty::Map(expected_key, expected), Ty::Map(found_key, found))
+            | (Ty::Dict(expected_key, expected), Ty::Dict(found_key, found)) => {
+                self.bind(expected_key, found_key)?;
+                self.bind(expected, found)
+            }
+            (Ty::Tuple(expected), Ty::Tuple(found)) if expected.len() == found.len() => {
+                self.bind_all(expected, found)
+            }
+            (Ty::Struct(a, expected), Ty::Struct(b, found))
+            | (Ty::Enum(a, expected), Ty::Enum(b, found))
+                if a == b =>
+            {
+                self.bind_all(expected, found)
+            }
+            _ if expected == found => Ok(()),
+            _ => Err(Disagreement::Mismatch),
+        }
+    }
+
+    fn bind_all(&mut self, expected: &[Ty], found: &[Ty]) -> Result<(), Disagreement> {
+        for (expected, found) in expected.iter().zip(found) {
+            self.bind(expected, found)?;
+        }
+        Ok(())
+    }
+
+    /// `ty` with its type parameters replaced by their inferred types. Parameters that weren't
+    /// inferred are kept when `keep` is set and unknown otherwise.
+    fn apply(&self, ty: &Ty, keep: bool) -> Ty {
+        let all = |types: &[Ty]| types.iter().map(|t| self.apply(t, keep)).collect();
+        match ty {
+            Ty::Param(param) if self.params.contains(param) => match self.bound.get(param) {
+                Some(bound) => bound.clone(),
+                None if keep => ty.clone(),
+                None => Ty::Unknown,
+            },
+            Ty::List(element) => Ty::List(Box::new(self.apply(element, keep))),
+            Ty::Iterator(element) => Ty::Iterator(Box::new(self.apply(element, keep))),
+            Ty::Chan(element) => Ty::Chan(Box::new(self.apply(element, keep))),
+            Ty::Vector(element) => Ty::Vector(Box::new(self.apply(element, keep))),
+            Ty::Map(key, value) => Ty::Map(
+                Box::new(self.apply(key, keep)),
+                Box::new(self.apply(value, keep)),
+            ),
+            Ty::Dict(key, value) => Ty::Dict(
+                Box::new(self.apply(key, keep)),
+                Box::new(self.apply(value, keep)),
+            ),
+            Ty::Tuple(types) => Ty::Tuple(all(types)),
+            Ty::Struct(index, args) => Ty::Struct(*index, all(args)),
+            Ty::Enum(index, args) => Ty::Enum(*index, all(args)),
+            Ty::Function(result) => Ty::Function(Box::new(self.apply(result, keep))),
+            Ty::Optional(ty) => Ty::optional(self.apply(ty, keep)),
+            Ty::Mixed(types) => Ty::Mixed(all(types)),
+            _ => ty.clone(),
+        }
+    }
+
+    /// The type `ty` of a use of the declaration, with what was inferred filled in
+    pub(super) fn result(&self, ty: &Ty) -> Ty {
+        self.apply(ty, false)
+    }
+}
+
+/// The type that agrees with both `a` and `b` and is known wherever either of them is
+fn merge(a: &Ty, b: &Ty) -> Option<Ty> {
+    let all = |a: &[Ty], b: &[Ty]| -> Option<Vec<Ty>> {
+        a.iter().zip(b).map(|(a, b)| merge(a, b)).collect()
+    };
+    Some(match (a, b) {
+        (Ty::Unknown, other) | (other, Ty::Unknown) => other.clone(),
+        (Ty::Optional(a), Ty::Optional(b)) => Ty::optional(merge(a, b)?),
+        (Ty::Optional(a), other) | (other, Ty::Optional(a)) => Ty::optional(merge(a, other)?),
+        // Functions with different results are still functions
+        (Ty::Function(a), Ty::Function(b)) => {
+            Ty::Function(Box::new(merge(a, b).unwrap_or_default()))
+        }
+        (Ty::List(a), Ty::List(b)) => Ty::List(Box::new(merge(a, b)?)),
+        (Ty::Iterator(a), Ty::Iterator(b)) => Ty::Iterator(Box::new(merge(a, b)?)),
+        (Ty::Chan(a), Ty::Chan(b)) => Ty::Chan(Box::new(merge(a, b)?)),
+        (Ty::Vector(a), Ty::Vector(b)) => Ty::Vector(Box::new(merge(a, b)?)),
+        (Ty::Map(a_key, a), Ty::Map(b_key, b)) => {
+            Ty::Map(Box::new(merge(a_key, b_key)?), Box::new(merge(a, b)?))
+        }
+        (Ty::Dict(a_key, a), Ty::Dict(b_key, b)) => {
+            Ty::Dict(Box::new(merge(a_key, b_key)?), Box::new(merge(a, b)?))
+        }
+        (Ty::Tuple(a), Ty::Tuple(b)) if a.len() == b.len() => Ty::Tuple(all(a, b)?),
+        (Ty::Struct(i, a), Ty::Struct(j, b)) if i == j => Ty::Struct(*i, all(a, b)?),
+        (Ty::Enum(i, a), Ty::Enum(j, b)) if i == j => Ty::Enum(*i, all(a, b)?),
+        _ if a == b => a.clone(),
+        _ => return None,
+    })
+}
+
+/// The type of a constant's value
+pub(super) fn value_type(value: &Value) -> Ty {
+    match value {
+        Value::Int(_) | Value::BigInt(_) => Ty::Int,
+        Value::Float(_) => Ty::Float,
+        Value::Decimal(_) => Ty::Decimal,
+        Value::Rational(_) => Ty::Rational,
+        Value::Bool(_) => Ty::Bool,
+        Value::Char(_) => Ty::Char,
+        Value::String(_) => Ty::String,
+        Value::Unit => Ty::Unit,
+        _ => Ty::Unknown,
+    }
+}
+
+/// How exact a number type is, Ints promote to Decimals and both to Rationals
+fn exactness(ty: &Ty) -> Option<u8> {
+    match ty {
+        Ty::Int => Some(0),
+        Ty::Decimal => Some(1),
+        Ty::Rational => Some(2),
+        _ => None,
+    }
+}
+
+/// The type of the result of the binary operator `op` applied to values of types `left` and
+/// `right`, `None` when they are numbers that it can't combine. Numbers give the type both
+/// promote to: Floats only mix with Ints, and exact numbers with each other.
+fn binary_type(op: &str, left: &Ty, right: &Ty) -> Option<Ty> {
+    Some(match (op, left, right) {
+        ("+", Ty::String, Ty::String | Ty::Char) | ("+", Ty::Char, Ty::String) => Ty::String,
+        // The exponent of an exact number is an Int
+        ("**", Ty::Int | Ty::Decimal | Ty::Rational, Ty::Decimal | Ty::Rational) => return None,
+        ("**", Ty::Decimal | Ty::Rational, Ty::Int) => left.clone(),
+        (_, Ty::Float, Ty::Int | Ty::Float) | (_, Ty::Int, Ty::Float) => Ty::Float,
+        (_, Ty::Float, Ty::Decimal | Ty::Rational) | (_, Ty::Decimal | Ty::Rational, Ty::Float) => {
+            return None
+        }
+        _ => match (exactness(left), exactness(right)) {
+            (Some(a), Some(b)) if a >= b => left.clone(),
+            (Some(_), Some(_)) => right.clone(),
+            _ => Ty::Unknown,
+        },
+    })
+}
+
+/// The element type of a collection literal, the type of each element when they don't agree
+fn element_type(types: impl Iterator<Item = Ty>) -> Ty {
+    let types: Vec<Ty> = types.collect();
+    let mut element = Ty::Unknown;
+    for ty in &types {
+        match merge(&element, ty) {
+            Some(merged) => element = merged,
+            None => return mixed(types),
+        }
+    }
+    element
+}
+
+/// The type of values of any of `types`, which don't all agree
+fn mixed(types: impl IntoIterator<Item = Ty>) -> Ty {
+    let mut optional = false;
+    let mut mixed: Vec<Ty> = vec![];
+    for ty in types {
+        optional |= ty.is_optional();
+        let flattened = match ty.plain() {
+            Ty::Mixed(types) => types,
+            ty => vec![ty],
+        };
+        for ty in flattened {
+            if ty != Ty::Unknown && !mixed.contains(&ty) {
+                mixed.push(ty);
+            }
+        }
+    }
+    match optional {
+        true => Ty::optional(Ty::Mixed(mixed)),
+        false => Ty::Mixed(mixed),
+    }
+}
+
+/// Checks that a call of `name` at `position` passing `positional` arguments, counting the
+/// receiver of a method call, and the arguments `named` gives every parameter of `signature`
+/// without a default value an argument, reporting a mismatch like the call would when it runs
+fn check_arity(
+    name: &str,
+    signature: &Signature,
+    positional: usize,
+    named: &[&str],
+    position: Position,
+) -> Result<(), CodegenError> {
+    let Some(required) = signature.required else {
+        return Ok(());
+    };
+    let fixed = signature.params.len() - signature.variadic as usize;
+    let found = positional + named.len();
+    // Functions without defaults or named arguments report any difference in the count
+    let exact = required == fixed && !signature.variadic;
+    let arity_mismatch = || CodegenError::ArityMismatch {
+        name: name.to_string(),
+        expected: fixed,
+        found,
+        position,
+    };
+
+    if positional > fixed && !signature.variadic {
+        if exact {
+            return Err(arity_mismatch());
+        }
+        return Err(CodegenError::TooManyArguments {
+            name: name.to_string(),
+            expected: fixed,
+            found,
+            position,
+        });
+    }
+    let passed = |param: usize| {
+        param < positional || named.iter().any(|n| signature.named(n) == Some(param))
+    };
+    if let Some(missing) = (0..required).find(|param| !passed(*param)) {
+        if exact && named.is_empty() {
+            return Err(arity_mismatch());
+        }
+        return Err(CodegenError::MissingArgument {
+            name: name.to_string(),
+            argument: signature.describe(missing),
+            position,
+        });
+    }
+    Ok(())
+}
+
+impl Codegen {
+    /// The type parameters of a declaration, which must be distinct
+    pub(super) fn type_params(&self, params: &[TypeParam]) -> Result<TypeParams, CodegenError> {
+        let mut type_params = TypeParams::default();
+        for param in params {
+            let name = &param.ident.0;
+            if type_params.names.contains(name) {
+                return Err(CodegenError::DuplicateDefinition {
+                    name: name.clone(),
+                    position: param.ident.1,
+                });
+            }
+            type_params.names.push(name.clone());
+            for bound in &param.bounds {
+                let index = self.trait_index(bound)?;
+                type_params.bounds.push((name.clone(), index));
+            }
+        }
+        Ok(type_params)
+    }
+
+    /// Type parameters visible in the function being compiled
+    pub(super) fn visible_type_params(&self) -> &[String] {
+        // There is always at least the script state while generating
+        &self.functions.last().unwrap().type_params
+    }
+
+    /// Traits bounding the type parameter `param` in the function being compiled
+    pub(super) fn visible_bounds(&self, param: &str) -> Vec<u32> {
+        // There is always at least the script state while generating
+        let state = self.functions.last().unwrap();
+        state
+            .bounds
+            .iter()
+            .filter(|(name, _)| name == param)
+            .map(|(_, index)| *index)
+            .collect()
+    }
+
+    /// Whether values of type `ty` implement the trait at `index`, which is assumed of those whose
+    /// type isn't known
+    pub(super) fn implements(&self, ty: &Ty, index: u32) -> bool {
+        match ty {
+            Ty::Struct(i, _) => self.structs[*i as usize].traits.contains(&index),
+            Ty::Dyn(i) => *i == index,
+            Ty::Param(param) => self.visible_bounds(param).contains(&index),
+            Ty::Mixed(types) => types.iter().all(|ty| self.implements(ty, index)),
+            Ty::Unknown => true,
+            _ => false,
+        }
+    }
+
+    /// The type a type annotation names, where `params` are the type parameters in scope
+    pub(super) fn resolve_type(&self, ty: &Type, params: &[String]) -> Result<Ty, CodegenError> {
+        let (ident, args) = match ty {
+            Type::Optional(ty) => return Ok(Ty::optional(self.resolve_type(ty, params)?)),
+            Type::Dyn(ident) => return Ok(Ty::Dyn(self.trait_index(ident)?)),
+            Type::Tuple(types) => {
+                let types = types
+                    .iter()
+                    .map(|ty| self.resolve_type(ty, params))
+                    .collect::<Result<_, _>>()?;
+                return Ok(Ty::Tuple(types));
+            }
+            Type::Named(ident, args) => (ident, args),
+        };
+
+        let name = ident.0.as_str();
+        let struct_index = self.scope.struct_slots.get(name).copied();
+        let enum_index = self.scope.enum_slots.get(name).copied();
+        let arity = if params.iter().any(|p| p == name) {
+            0
+        } else if let Some(index) = struct_index {
+            self.structs[index as usize].type_params.len()
+        } else if let Some(index) = enum_index {
+            self.enum_types[index as usize].params.len()
+        } else {
+            match name {
+                "Int" | "Float" | "Decimal" | "Rational" | "Bool" | "Char" | "String" | "Unit"
+                | "Function" => 0,
+                "List" | "Vector" | "Iterator" | "Chan" => 1,
+                "Map" | "Dict" => 2,
+                _ => {
+                    return Err(CodegenError::UndefinedType {
+                        name: name.to_string(),
+                        position: ident.1,
+                    })
+                }
+            }
+        };
+        if !args.is_empty() && args.len() != arity {
+            return Err(CodegenError::TypeArguments {
+                name: name.to_string(),
+                expected: arity,
+                found: args.len(),
+            });
+        }
+
+        // Type arguments that are left out are unknown
+        let mut args = args
+            .iter()
+            .map(|ty| self.resolve_type(ty, params))
+            .collect::<Result<Vec<_>, _>>()?;
+        args.resize(arity, Ty::Unknown);
+        let mut args = args.into_iter();
+        let mut arg = || Box::new(args.next().unwrap_or_default());
+
+        Ok(if params.iter().any(|p| p == name) {
+            Ty::Param(name.to_string())
+        } else if let Some(index) = struct_index {
+            Ty::Struct(index, args.collect())
+        } else if let Some(index) = enum_index {
+            Ty::Enum(index, args.collect())
+        } else {
+            match name {
+                "Int" => Ty::Int,
+                "Float" => Ty::Float,
+                "Decimal" => Ty::Decimal,
+                "Rational" => Ty::Rational,
+                "Bool" => Ty::Bool,
+                "Char" => Ty::Char,
+                "String" => Ty::String,
+                "Unit" => Ty::Unit,
+                "Function" => Ty::Function(Box::default()),
+                "List" => Ty::List(arg()),
+                "Vector" => Ty::Vector(arg()),
+                "Iterator" => Ty::Iterator(arg()),
+                "Chan" => Ty::Chan(arg()),
+                "Dict" => Ty::Dict(arg(), arg()),
+                _ => Ty::Map(arg(), arg()),
+            }
+        })
+    }
+
+    /// The declared type of `ty`, unknown when there is none
+    pub(super) fn annotation(&self, ty: Option<&Type>) -> Result<Ty, CodegenError> {
+        match ty {
+            Some(ty) => self.resolve_type(ty, self.visible_type_params()),
+            None => Ok(Ty::Unknown),
+        }
+    }
+
+    /// The signature of `function`, which is declared where the type parameters `visible` are in
+    /// scope. The parameters `generic` among them are inferred at each call along with the
+    /// function's own.
+    pub(super) fn signature(
+        &self,
+        function: &Function,
+        visible: &[String],
+        generic: &TypeParams,
+    ) -> Result<Signature, CodegenError> {
+        let args = function.args.as_ref();
+        let ret = function.ret.as_ref();
+        let mut signature =
+            self.declared_signature(&function.type_params, args, ret, visible, generic)?;
+        if function.generator {
+            // Calling a generator gives an iterator over the values it yields
+            let iterator = Ty::Iterator(Box::new(Ty::Unknown));
+            let what = || format!("the result of generator `{}`", function.ident.0);
+            self.check_type(what, &signature.ret, iterator.clone(), function.ident.1)?;
+            if signature.ret == Ty::Unknown {
+                signature.ret = iterator;
+            }
+        }
+        Ok(signature)
+    }
+
+    /// The signature of a function or trait method declared with the type parameters
+    /// `type_params`, parameters `args` and result `ret`, see `signature`
+    pub(super) fn declared_signature(
+        &self,
+        type_params: &[TypeParam],
+        args: Option<&ArgsDecl>,
+        ret: Option<&Type>,
+        visible: &[String],
+        generic: &TypeParams,
+    ) -> Result<Signature, CodegenError> {
+        let own = self.type_params(type_params)?;
+        let scope: Vec<String> = visible.iter().chain(&own.names).cloned().collect();
+        let variadic = args.and_then(|a| a.variadic.as_ref());
+        let defaults = args.map_or(&[][..], |a| &a.defaults[..]);
+        let args = args.map_or(&[][..], |a| &a.args[..]);
+
+        // A variadic parameter is checked against the type of each argument it takes
+        let mut types = vec![];
+        for arg in args.iter().chain(variadic) {
+            types.push(match &arg.ty {
+                Some(ty) => self.resolve_type(ty, &scope)?,
+                None => Ty::Unknown,
+            });
+        }
+        let returns_optional = ret.is_some_and(Type::is_optional);
+        let ret = match ret {
+            Some(ty) => self.resolve_type(ty, &scope)?,
+            None => Ty::Unknown,
+        };
+
+        let names = args
+            .iter()
+            .map(|arg| arg.ident().map_or(String::new(), |ident| ident.0.clone()))
+            .collect();
+        Ok(Signature {
+            params: args
+                .iter()
+                .chain(variadic)
+                .map(BindingPattern::is_optional)
+                .collect(),
+            returns_optional,
+            type_params: generic.names.iter().chain(&own.names).cloned().collect(),
+            bounds: generic.bounds.iter().chain(&own.bounds).cloned().collect(),
+            types,
+            ret,
+            names: Some(names),
+            variadic: variadic.is_some(),
+            required: Some(defaults.iter().take_while(|d| d.is_none()).count()),
+        })
+    }
+
+    /// The signature of the constructor of a variant of the enum at `def`
+    pub(super) fn variant_signature(&self, def: u32, variant: usize) -> Signature {
+        let types = &self.enum_types[def as usize];
+        let arity = self.enums[def as usize].variants[variant].arity;
+        Signature {
+            params: (0..arity)
+                .map(|i| self.optional_payloads.contains(&(def, variant, i)))
+                .collect(),
+            returns_optional: false,
+            type_params: types.params.clone(),
+            bounds: types.bounds.clone(),
+            types: types.variants[variant].clone(),
+            ret: Ty::Enum(def, types.params.iter().cloned().map(Ty::Param).collect()),
+            // The values of a variant are only passed by position
+            names: Some(vec![]),
+            variadic: false,
+            required: Some(arity),
+        }
+    }
+
+    /// Checks values against the types `expected` declared with the type parameters `params` of
+    /// `owner`, which must be inferred as types implementing their `bounds`. Returns what is
+    /// inferred for the parameters, `what` describes the value at an index in errors, which are
+    /// reported at `position`.
+    pub(super) fn infer<'p>(
+        &'p self,
+        owner: &str,
+        params: &'p [String],
+        bounds: &[(String, u32)],
+        values: Vec<(&Ty, Ty)>,
+        what: impl Fn(usize) -> String,
+        position: Position,
+    ) -> Result<Inference<'p>, CodegenError> {
+        let mut inference = Inference::new(self, params);
+        for (i, (expected, found)) in values.into_iter().enumerate() {
+            // Whether the value itself may be `none` is checked where it is stored
+            let found = match expected {
+                Ty::Optional(_) => found,
+                _ => found.plain(),
+            };
+            match inference.bind(expected, &found) {
+                Ok(()) => {}
+                Err(Disagreement::Mismatch) => {
+                    return Err(CodegenError::TypeMismatch {
+                        what: what(i),
+                        expected: self.type_name(&inference.apply(expected, true)),
+                        found: self.type_name(&found),
+                        position,
+                    })
+                }
+                Err(Disagreement::Conflict {
+                    param,
+                    first,
+                    second,
+                }) => {
+                    return Err(CodegenError::ConflictingTypes {
+                        param,
+                        owner: owner.to_string(),
+                        first: self.type_name(&first),
+                        second: self.type_name(&second),
+                        position,
+                    })
+                }
+            }
+        }
+        for (param, index) in bounds {
+            if let Some(ty) = inference.bound.get(param) {
+                if !self.implements(ty, *index) {
+                    return Err(CodegenError::NotImplemented {
+                        ty: self.type_name(ty),
+                        name: self.traits[*index as usize].name.clone(),
+                        position,
+                    });
+                }
+            }
+        }
+        Ok(inference)
+    }
+
+    /// Checks that a value of type `found` at `position` can be stored in `what`, which is
+    /// declared `expected`
+    pub(super) fn check_type(
+        &self,
+        what: impl Fn() -> String,
+        expected: &Ty,
+        found: Ty,
+        position: Position,
+    ) -> Result<(), CodegenError> {
+        let values = vec![(expected, found)];
+        self.infer("", &[], &[], values, |_| what(), position)
+            .map(|_| ())
+    }
+
+    /// Checks the arguments of a call of the function `name` at `position` against its signature
+    /// and returns the type of its result. A method call has the type of its receiver as well.
+    pub(super) fn check_call(
+        &self,
+        name: &str,
+        signature: &Signature,
+        receiver: Option<Ty>,
+        args: &Args,
+        position: Position,
+    ) -> Result<Ty, CodegenError> {
+        let skip = receiver.is_some() as usize;
+        let found = receiver
+            .into_iter()
+            .chain(args.args.iter().map(|arg| self.static_type(arg)));
+        let mut values = vec![];
+        let mut described = vec![];
+        for (i, found) in found.enumerate() {
+            let expected = signature.positional(i).and_then(|p| signature.types.get(p));
+            if let Some(expected) = expected {
+                values.push((expected, found));
+                described.push(if i < skip {
+                    format!("the receiver of `{}`", name)
+                } else {
+                    format!("argument {} of `{}`", i + 1 - skip, name)
+                });
+            }
+        }
+        for arg in &args.named {
+            let Some(param) = signature.named(&arg.ident.0) else {
+                if signature.names.is_none() {
+                    continue;
+                }
+                return Err(CodegenError::UnknownArgument {
+                    name: name.to_string(),
+                    argument: arg.ident.0.clone(),
+                    position: arg.ident.1,
+                });
+            };
+            if param < skip + args.args.len() {
+                return Err(CodegenError::DuplicateArgument {
+                    name: name.to_string(),
+                    argument: arg.ident.0.clone(),
+                    position: arg.ident.1,
+                });
+            }
+            if let Some(expected) = signature.types.get(param) {
+                values.push((expected, self.static_type(&arg.value)));
+                described.push(format!("argument `{}` of `{}`", arg.ident.0, name));
+            }
+        }
+        let named: Vec<&str> = args.named.iter().map(|arg| &*arg.ident.0).collect();
+        check_arity(name, signature, skip + args.args.len(), &named, position)?;
+        let params = &signature.type_params;
+        let what = |i: usize| described[i].clone();
+        let inference = self.infer(name, params, &signature.bounds, values, what, position)?;
+        Ok(inference.result(&signature.ret))
+    }
+
+    /// Checks the fields of a struct literal against the declared types of the fields and
+    /// returns the type of the instance. Mismatches are reported at the literal.
+    pub(super) fn check_struct_literal(
+        &self,
+        index: u32,
+        literal: &StructLiteral,
+    ) -> Result<Ty, CodegenError> {
+        let state = &self.structs[index as usize];
+        let mut values = vec![];
+        for init in &literal.fields {
+            if let Some(field) = state.fields.iter().position(|f| *f == init.ident.0) {
+                values.push((&state.field_types[field], self.static_type(&init.value)));
+            }
+        }
+        let params = &state.type_params;
+        let what = |i: usize| format!("field `{}` of `{}`", literal.fields[i].ident.0, state.name);
+        let inference = self.infer(
+            &state.name,
+            params,
+            &state.bounds,
+            values,
+            what,
+            literal.position,
+        )?;
+        let params = state.type_params.iter().cloned().map(Ty::Param).collect();
+        Ok(inference.result(&Ty::Struct(index, params)))
+    }
+
+    /// The type of the field `field` of a value of type `ty`
+    pub(super) fn field_type(&self, ty: Ty, field: &Identifier) -> Ty {
+        let Ty::Struct(index, args) = ty else {
+            return Ty::Unknown;
+        };
+        let state = &self.structs[index as usize];
+        let Some(field) = state.fields.iter().position(|f| *f == field.0) else {
+            return Ty::Unknown;
+        };
+        let mut inference = Inference::new(self, &state.type_params);
+        inference.bound = state.type_params.iter().cloned().zip(args).collect();
+        inference.result(&state.field_types[field])
+    }
+
+    /// The type of the value of `expr`, as far as it is known at compile time
+    pub(super) fn static_type(&self, expr: &Expr) -> Ty {
+        match expr.as_term() {
+            Some(term) => self.term_type(term),
+            None => Ty::Unknown,
+        }
+    }
+
+    /// Fails when a binary operator can't be applied to its operands, given by one of the
+    /// `_operands` methods. Gives the method that the operator calls when the left operand is a
+    /// struct.
+    pub(super) fn check_operands(
+        &self,
+        operands: Option<(&'static str, Ty, Ty, Position)>,
+    ) -> Result<Option<Overload>, CodegenError> {
+        let Some((op, left, right, position)) = operands else {
+            return Ok(None);
+        };
+        self.operator_type(op, &left, &right, position)?;
+        Ok(operator_method(op).and_then(|method| self.overload(method, &left)))
+    }
+
+    /// The method `method` of the struct type `ty` that an operator calls, if `ty` is a struct
+    /// that has it
+    pub(super) fn overload(&self, method: &'static str, ty: &Ty) -> Option<Overload> {
+        let Ty::Struct(index, _) = ty else {
+            return None;
+        };
+        let signature = self.structs[*index as usize].signatures.get(method)?;
+        Some(Overload {
+            method,
+            returns_optional: signature.returns_optional,
+        })
+    }
+
+    /// The type of the result of the binary operator `op` at `position` applied to values of types
+    /// `left` and `right`. A struct on the left calls its method for `op`, and one on the right is
+    /// only supported where the left operand might be a struct as well.
+    fn operator_type(
+        &self,
+        op: &'static str,
+        left: &Ty,
+        right: &Ty,
+        position: Position,
+    ) -> Result<Ty, CodegenError> {
+        let unsupported = || CodegenError::UnsupportedOperands {
+            op,
+            left: self.type_name(left),
+            right: self.type_name(right),
+        };
+        match (left, right) {
+            // Operands that may be `none` are reported where they are compiled
+            (Ty::Optional(_), _) | (_, Ty::Optional(_)) => Ok(Ty::Unknown),
+            (Ty::Struct(..), _) => {
+                let method = operator_method(op).ok_or_else(unsupported)?;
+                let result = self.overload_type(op, method, left, vec![right.clone()], position)?;
+                // An ordering compares the result of `cmp` with zero
+                Ok(if method == CMP { Ty::Bool } else { result })
+            }
+            (Ty::Unknown | Ty::Param(_) | Ty::Dyn(_) | Ty::Mixed(_), Ty::Struct(..)) => {
+                Ok(Ty::Unknown)
+            }
+            (_, Ty::Struct(..)) => Err(unsupported()),
+            _ => binary_type(op, left, right).ok_or_else(unsupported),
+        }
+    }
+
+    /// The type of the result of the method `method` of the struct type `ty` that the operator
+    /// `op` at `position` calls with `args`, which are checked against its parameters
+    fn overload_type(
+        &self,
+        op: &'static str,
+        method: &'static str,
+        ty: &Ty,
+        args: Vec<Ty>,
+        position: Position,
+    ) -> Result<Ty, CodegenError> {
+        let Ty::Struct(index, _) = ty else {
+            unreachable!("overloaded operator of {:?}", ty)
+        };
+        let Some(signature) = self.structs[*index as usize].signatures.get(method) else {
+            return Err(CodegenError::NoOperatorMethod {
+                op,
+                ty: self.type_name(ty),
+                method,
+            });
+        };
+        check_arity(method, signature, 1 + args.len(), &[], position)?;
+        let found = std::iter::once(ty.clone()).chain(args);
+        let values = signature.types.iter().zip(found).collect();
+        let params = &signature.type_params;
+        let what = |i: usize| {
+            if i == 0 {
+                format!("the receiver of `{}`", method)
+            } else {
+                format!("the operand of `{}`", op)
+            }
+        };
+        let inference = self.infer(method, params, &signature.bounds, values, what, position)?;
+        let result = inference.result(&signature.ret);
+        let expected = match method {
+            EQ => Ty::Bool,
+            CMP => Ty::Int,
+            _ => return Ok(result),
+        };
+        let what = || format!("the result of `{}`", method);
+        self.check_type(what, &expected, result, position)?;
+        Ok(expected)
+    }
+
+    /// The operator of `comparison`, the types of its operands and its position, if it has one
+    pub(super) fn comparison_operands(
+        &self,
+        comparison: &Comparison,
+    ) -> Option<(&'static str, Ty, Ty, Position)> {
+        let right = comparison.right.as_ref()?;
+        let operand = |bit_or: &BitOr| bit_or.as_term().map_or(Ty::Unknown, |t| self.term_type(t));
+        let left = match &comparison.left {
+            ComparisonLeft::BitOr(left) => operand(left),
+            ComparisonLeft::Comparison(_) => Ty::Bool,
+        };
+        let op = match right.op {
+            ComparisonOp::Greater => ">",
+            ComparisonOp::GreaterEqual => ">=",
+            ComparisonOp::Less => "<",
+            ComparisonOp::LessEqual => "<=",
+        };
+        Some((op, left, operand(&right.right), right.position))
+    }
+
+    /// The operator of `equality`, the types of its operands and its position, if it has one
+    pub(super) fn equality_operands(
+        &self,
+        equality: &Equality,
+    ) -> Option<(&'static str, Ty, Ty, Position)> {
+        let right = equality.right.as_ref()?;
+        let operand = |comparison: &Comparison| match &comparison.right {
+            Some(_) => Ty::Bool,
+            None => match &comparison.left {
+                ComparisonLeft::BitOr(left) => {
+                    left.as_term().map_or(Ty::Unknown, |t| self.term_type(t))
+                }
+                ComparisonLeft::Comparison(_) => Ty::Bool,
+            },
+        };
+        let left = match &equality.left {
+            EqualityLeft::Comparison(left) => operand(left),
+            EqualityLeft::Equality(_) => Ty::Bool,
+        };
+        let op = match right.op {
+            EqualityOp::Equal => "==",
+            EqualityOp::NotEqual => "!=",
+        };
+        Some((op, left, operand(&right.right), right.position))
+    }
+
+    /// Fails when `eq` of a struct on the left of an `==` or `!=`, given by `equality_operands`,
+    /// can't take the right operand. Values of different types are otherwise never equal, and
+    /// structs without `eq` compare their fields.
+    pub(super) fn check_equality(
+        &self,
+        operands: Option<(&'static str, Ty, Ty, Position)>,
+    ) -> Result<(), CodegenError> {
+        match operands {
+            Some((op, left, right, position)) if self.overload(EQ, &left).is_some() => self
+                .overload_type(op, EQ, &left, vec![right], position)
+                .map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The operator of `term`, the types of its operands and its position, if it has one
+    pub(super) fn term_operands(&self, term: &Term) -> Option<(&'static str, Ty, Ty, Position)> {
+        let right = term.right.as_ref()?;
+        let op = match right.op {
+            TermOp::Minus => "-",
+            TermOp::Plus => "+",
+        };
+        let left = match &term.left {
+            TermLeft::Factor(left) => self.factor_type(left),
+            TermLeft::Term(left) => self.term_type(left),
+        };
+        Some((op, left, self.factor_type(&right.right), right.position))
+    }
+
+    fn term_type(&self, term: &Term) -> Ty {
+        match (self.term_operands(term), &term.left) {
+            (Some((op, left, right, position)), _) => self
+                .operator_type(op, &left, &right, position)
+                .unwrap_or_default(),
+            (None, TermLeft::Factor(left)) => self.factor_type(left),
+            (None, TermLeft::Term(left)) => self.term_type(left),
+        }
+    }
+
+    /// The operator of `factor`, the types of its operands and its position, if it has one
+    pub(super) fn factor_operands(
+        &self,
+        factor: &Factor,
+    ) -> Option<(&'static str, Ty, Ty, Position)> {
+        let right = factor.right.as_ref()?;
+        let op = match right.op {
+            FactorOp::Div => "/",
+            FactorOp::Mult => "*",
+            FactorOp::Mod => "%",
+        };
+        let left = match &factor.left {
+            FactorLeft::Unary(left) => self.unary_type(left),
+            FactorLeft::Factor(left) => self.factor_type(left),
+        };
+        Some((op, left, self.unary_type(&right.right), right.position))
+    }
+
+    fn factor_type(&self, factor: &Factor) -> Ty {
+        match (self.factor_operands(factor), &factor.left) {
+            (Some((op, left, right, position)), _) => self
+                .operator_type(op, &left, &right, position)
+                .unwrap_or_default(),
+            (None, FactorLeft::Unary(left)) => self.unary_type(left),
+            (None, FactorLeft::Factor(left)) => self.factor_type(left),
+        }
+    }
+
+    /// The type of the operand of `unary`
+    pub(super) fn unary_operand(&self, unary: &Unary) -> Ty {
+        match unary.right.as_ref() {
+            UnaryRight::Unary(right) => self.unary_type(right),
+            UnaryRight::Power(right) => self.power_type(right),
+        }
+    }
+
+    fn unary_type(&self, unary: &Unary) -> Ty {
+        match (&unary.op, self.unary_operand(unary)) {
+            (None, ty) => ty,
+            (Some(UnaryOp::Not), _) => Ty::Bool,
+            (Some(UnaryOp::Minus), ty @ (Ty::Int | Ty::Float | Ty::Decimal | Ty::Rational))
+            | (Some(UnaryOp::BitNot), ty @ Ty::Int) => ty,
+            (Some(UnaryOp::Minus), ty @ Ty::Struct(..)) => {
+                self.negation_type(&ty, unary.position).unwrap_or_default()
+            }
+            (Some(_), _) => Ty::Unknown,
+        }
+    }
+
+    /// The type of the result of `neg` of the struct type `ty`, negated at `position`
+    pub(super) fn negation_type(&self, ty: &Ty, position: Position) -> Result<Ty, CodegenError> {
+        self.overload_type("-", NEG, ty, vec![], position)
+    }
+
+    /// The type of the result of `index` of the struct type `ty` called with `index` at
+    /// `position`
+    pub(super) fn index_type(
+        &self,
+        ty: &Ty,
+        index: &Expr,
+        position: Position,
+    ) -> Result<Ty, CodegenError> {
+        self.overload_type("[]", INDEX, ty, vec![self.static_type(index)], position)
+    }
+
+    /// The types of the operands of the `**` of `power` and its position, if it has one
+    pub(super) fn power_operands(&self, power: &Power) -> Option<(&'static str, Ty, Ty, Position)> {
+        let right = power.right.as_ref()?;
+        Some((
+            "**",
+            self.call_type(&power.left),
+            self.unary_type(&right.right),
+            right.position,
+        ))
+    }
+
+    fn power_type(&self, power: &Power) -> Ty {
+        match self.power_operands(power) {
+            Some((op, left, right, position)) => self
+                .operator_type(op, &left, &right, position)
+                .unwrap_or_default(),
+            None => self.call_type(&power.left),
+        }
+    }
+
+    pub(super) fn left_type(&self, left: &CallLeft) -> Ty {
+        match left {
+            CallLeft::Primary(primary) => self.primary_type(primary),
+            CallLeft::Call(call) => self.call_type(call),
+        }
+    }
+
+    pub(super) fn call_type(&self, call: &Call) -> Ty {
+        let Some(right) = &call.right else {
+            return self.left_type(&call.left);
+        };
+        match right {
+            CallRight::Args { args, position } => self.result_type(&call.left, args, *position),
+            CallRight::Index { index, position } => match self.left_type(&call.left).plain() {
+                Ty::List(element) | Ty::Vector(element) => *element,
+                Ty::Map(_, value) | Ty::Dict(_, value) => *value,
+                ty @ Ty::Struct(..) => self.index_type(&ty, index, *position).unwrap_or_default(),
+                _ => Ty::Unknown,
+            },
+            CallRight::Slice { .. } => match self.left_type(&call.left).plain() {
+                ty @ (Ty::List(_) | Ty::Vector(_) | Ty::String) => ty,
+                _ => Ty::Unknown,
+            },
+            CallRight::Field { ident, .. } | CallRight::SafeField { ident, .. } => {
+                match self.module_member(&call.left, ident) {
+                    Ok(Some(Member::Global(slot))) => {
+                        return self.globals[slot as usize].known.ty.clone()
+                    }
+                    Ok(Some(Member::Constant(index))) => {
+                        return value_type(&self.constants[index as usize])
+                    }
+                    _ => {}
+                }
+                if let Some(def) = self.named_enum(&call.left) {
+                    return match self.variant_index(def, ident) {
+                        Ok(variant) if self.enums[def as usize].variants[variant].arity == 0 => {
+                            let params = self.enum_types[def as usize].params.len();
+                            Ty::Enum(def, vec![Ty::Unknown; params])
+                        }
+                        Ok(_) => {
+                            let params = self.enum_types[def as usize].params.len();
+                            Ty::Function(Box::new(Ty::Enum(def, vec![Ty::Unknown; params])))
+                        }
+                        Err(_) => Ty::Unknown,
+                    };
+                }
+                let ty = self.field_type(self.left_type(&call.left).plain(), ident);
+                match (right, self.left_type(&call.left)) {
+                    (CallRight::SafeField { .. }, Ty::Optional(_)) => Ty::optional(ty),
+                    _ => ty,
+                }
+            }
+            CallRight::Element { index, .. } => match self.left_type(&call.left).plain() {
+                Ty::Tuple(mut types) if (*index as usize) < types.len() => {
+                    types.swap_remove(*index as usize)
+                }
+                _ => Ty::Unknown,
+            },
+            CallRight::Try { .. } => match self.left_type(&call.left).plain() {
+                Ty::Enum(def, mut args) if def as usize == RESULT => args.swap_remove(0),
+                _ => Ty::Unknown,
+            },
+        }
+    }
+
+    /// The enum named by `left`, if it is the name of one
+    fn named_enum(&self, left: &CallLeft) -> Option<u32> {
+        match left {
+            CallLeft::Primary(Primary::Identifier(ident)) => self.enum_index(ident),
+            _ => None,
+        }
+    }
+
+    /// The type of the result of calling `left` with `args` at `position`, unknown when the call
+    /// is invalid
+    fn result_type(&self, left: &CallLeft, args: &Args, position: Position) -> Ty {
+        let call = |name: &str, signature: Option<Signature>, receiver: Option<Ty>| {
+            signature
+                .and_then(|s| self.check_call(name, &s, receiver, args, position).ok())
+                .unwrap_or_default()
+        };
+
+        if let CallLeft::Call(inner) = left {
+            if let Some(CallRight::Field { ident, .. }) = &inner.right {
+                if let Ok(Some(member)) = self.module_member(&inner.left, ident) {
+                    let signature = match member {
+                        Member::Global(slot) => self.globals[slot as usize].known.signature.clone(),
+                        Member::Constant(_) => None,
+                    };
+                    return call(&ident.0, signature, None);
+                }
+                if self.named_enum(&inner.left).is_some() {
+                    let signature = self.constructor_signature(&inner.left, ident);
+                    let signature = signature.map(|(_, signature)| signature);
+                    return call(&ident.0, signature, None);
+                }
+                let receiver = self.left_type(&inner.left);
+                let signature = self.method_signature(&inner.left, ident);
+                return call(&ident.0, signature, Some(receiver));
+            }
+        }
+        match (self.callee_signature(left), self.left_type(left)) {
+            (None, Ty::Function(result)) => *result,
+            (signature, _) => call("", signature, None),
+        }
+    }
+
+    fn primary_type(&self, primary: &Primary) -> Ty {
+        match primary {
+            Primary::Int(_) => Ty::Int,
+            Primary::Float(_) => Ty::Float,
+            Primary::Decimal(_) => Ty::Decimal,
+            Primary::String(_) => Ty::String,
+            Primary::Char(_) => Ty::Char,
+            Primary::True | Primary::False => Ty::Bool,
+            Primary::Identifier(ident) => match self.known(ident) {
+                Some(Known {
+                    signature: Some(signature),
+                    ..
+                }) => Ty::Function(Box::new(signature.ret.clone())),
+                Some(known) if known.optional => Ty::optional(known.ty.clone()),
+                Some(known) => known.ty.clone(),
+                None => self.constant(ident).map_or(Ty::Unknown, value_type),
+            },
+            Primary::Grouping(expr) => self.static_type(expr),
+            Primary::List(elements) => Ty::List(Box::new(element_type(
+                elements.args.iter().map(|e| self.static_type(e)),
+            ))),
+            Primary::Map(map) => Ty::Map(
+                Box::new(element_type(
+                    map.entries.iter().map(|e| self.static_type(&e.key)),
+                )),
+                Box::new(element_type(
+                    map.entries.iter().map(|e| self.static_type(&e.value)),
+                )),
+            ),
+            Primary::Tuple(elements) => {
+                Ty::Tuple(elements.args.iter().map(|e| self.static_type(e)).collect())
+            }
+            Primary::Struct(literal) => match self.scope.struct_slots.get(&literal.ident.0) {
+                Some(index) => self
+                    .check_struct_literal(*index, literal)
+                    .unwrap_or_default(),
+                None => Ty::Unknown,
+            },
+            // What a lambda returns is only known once it is compiled
+            Primary::Lambda(lambda) => match self.lambda_types.get(&(&**lambda as *const Lambda)) {
+                Some(ty) => ty.clone(),
+                None => Ty::Function(Box::default()),
+            },
+            Primary::None(_) => Ty::optional(Ty::Unknown),
+            Primary::Match(_) | Primary::If(_) | Primary::Loop(_) | Primary::Block(_) => {
+                Ty::Unknown
+            }
+        }
+    }
+
+    /// How `ty` is written in error messages, with `_` for what isn't known
+    pub(super) fn type_name(&self, ty: &Ty) -> String {
+        let list = |types: &[Ty]| {
+            types
+                .iter()
+                .map(|t| self.type_name(t))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let generic = |name: &str, args: &[Ty]| {
+            if args.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}<{}>", name, list(args))
+            }
+        };
+        match ty {
+            Ty::Int => "Int".to_string(),
+            Ty::Float => "Float".to_string(),
+            Ty::Decimal => "Decimal".to_string(),
+            Ty::Rational => "Rational".to_string(),
+            Ty::Bool => "Bool".to_string(),
+            Ty::Char => "Char".to_string(),
+            Ty::String => "String".to_string(),
+            Ty::Unit => "Unit".to_string(),
+            Ty::Function(_) => "Function".to_string(),
+            Ty::List(element) => generic("List", std::slice::from_ref(&**element)),
+            Ty::Vector(element) => generic("Vector", std::slice::from_ref(&**element)),
+            Ty::Iterator(element) => generic("Iterator", std::slice::from_ref(&**element)),
+            Ty::Chan(element) => generic("Chan", std::slice::from_ref(&**element)),
+            Ty::Map(key, value) => {
+                format!("Map<{}, {}>", self.type_name(key), self.type_name(value))
+            }
+            Ty::Dict(key, value) => {
+                format!("Dict<{}, {}>", self.type_name(key), self.type_name(value))
+            }
+            Ty::Tuple(types) if types.len() == 1 => format!("({},)", list(types)),
+            Ty::Tuple(types) => format!("({})", list(types)),
+            Ty::Struct(index, args) => generic(&self.structs[*index as usize].name, args),
+            Ty::Enum(index, args) => generic(&self.enums[*index as usize].name, args),
+            Ty::Dyn(index) => format!("dyn {}", self.traits[*index as usize].name),
+            Ty::Param(name) => name.clone(),
+            Ty::Optional(ty) => match **ty {
+                Ty::Mixed(_) => format!("({})?", self.type_name(ty)),
+                _ => format!("{}?", self.type_name(ty)),
+            },
+            Ty::Mixed(types) => types
+                .iter()
+                .map(|t| self.type_name(t))
+                .collect::<Vec<_>>()
+                .join(" or "),
+            Ty::Unknown => "_".to_string(),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Digits after the point that a quotient of Decimals has at least, rounded half to even
+pub const DIVISION_SCALE: u32 = 16;
+
+/// Largest scale a Decimal can be rounded to, which keeps the number of digits within reason
+pub const MAX_SCALE: u32 = 1000;
+
+/// How a number is rounded to fewer digits. The order is that of the variants of the prelude
+/// `Rounding` enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// To the nearest, ties to the even neighbour
+    HalfEven,
+    /// To the nearest, ties away from zero
+    HalfUp,
+    /// To the nearest, ties toward zero
+    HalfDown,
+    /// Away from zero
+    Up,
+    /// Toward zero
+    Down,
+    /// Toward positive infinity
+    Ceiling,
+    /// Toward negative infinity
+    Floor,
+}
+
+impl Rounding {
+    pub const ALL: [Rounding; 7] = [
+        Rounding::HalfEven,
+        Rounding::HalfUp,
+        Rounding::HalfDown,
+        Rounding::Up,
+        Rounding::Down,
+        Rounding::Ceiling,
+        Rounding::Floor,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rounding::HalfEven => "HalfEven",
+            Rounding::HalfUp => "HalfUp",
+            Rounding::HalfDown => "HalfDown",
+            Rounding::Up => "Up",
+            Rounding::Down => "Down",
+            Rounding::Ceiling => "Ceiling",
+            Rounding::Floor => "Floor",
+        }
+    }
+
+    /// `numerator / denominator` rounded to an integer, `denominator` must be positive
+    fn divide(self, numerator: &BigInt, denominator: &BigInt) -> BigInt {
+        let (quotient, remainder) = numerator.div_rem(denominator);
+        if remainder.is_zero() {
+            return quotient;
+        }
+        // The remainder takes the sign of the numerator, so the quotient was truncated
+        let away = |quotient: BigInt| quotient + numerator.signum();
+        let half = (remainder.abs() * 2u32).cmp(denominator);
+        match self {
+            Rounding::Down => quotient,
+            Rounding::Up => away(quotient),
+            Rounding::Ceiling if numerator.is_positive() => away(quotient),
+            Rounding::Floor if numerator.is_negative() => away(quotient),
+            Rounding::Ceiling | Rounding::Floor => quotient,
+            _ if half == Ordering::Greater => away(quotient),
+            _ if half == Ordering::Less => quotient,
+            Rounding::HalfUp => away(quotient),
+            Rounding::HalfEven if quotient.is_odd() => away(quotient),
+            _ => quotient,
+        }
+    }
+}
+
+fn ten_to(exponent: u32) -> BigInt {
+    BigInt::from(10u32).pow(exponent)
+}
+
+/// An exact decimal number, `digits` scaled down by `scale` decimal places. The scale is kept
+/// by arithmetic, so `1.50d` has the scale 2 and prints its trailing zero, but Decimals of
+/// different scales with the same value are equal.
+#[derive(Debug, Clone)]
+pub struct Decimal {
+    digits: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(digits: BigInt, scale: u32) -> Decimal {
+        Decimal { digits, scale }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// The Decimal written as `literal`, digits with an optional point and exponent such as
+    /// `19.99` or `1.5e-7`
+    pub fn parse(literal: &str) -> Option<Decimal> {
+        let (mantissa, exponent) = match literal.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+            None => (literal, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let unsigned = whole.trim_start_matches(['-', '+']);
+        if unsigned.is_empty() && fraction.is_empty()
+            || !unsigned
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let digits = format!("{}{}", whole, fraction).parse().ok()?;
+        let scale = fraction.len() as i64 - exponent;
+        Some(match u32::try_from(scale) {
+            Ok(scale) => Decimal { digits, scale },
+            Err(_) if scale < 0 => {
+                let exponent = u32::try_from(-scale).ok()?;
+                Decimal {
+                    digits: digits * ten_to(exponent),
+                    scale: 0,
+                }
+            }
+            Err(_) => return None,
+        })
+    }
+
+    /// The shortest Decimal that converts back to `value`, `None` for infinities and NaN
+    pub fn from_f64(value: f64) -> Option<Decimal> {
+        if !value.is_finite() {
+            return None;
+        }
+        let decimal = Decimal::parse(&format!("{:?}", value))?;
+        // `{:?}` writes whole numbers with a trailing `.0`
+        Some(decimal.trim(0))
+    }
+
+    /// `numerator / denominator` rounded to `scale` places, `None` when dividing by zero
+    pub fn from_ratio(
+        numerator: &BigInt,
+        denominator: &BigInt,
+        scale: u32,
+        rounding: Rounding,
+    ) -> Option<Decimal> {
+        if denominator.is_zero() {
+            return None;
+        }
+        let (numerator, denominator) = if denominator.is_negative() {
+            (-numerator, -denominator)
+        } else {
+            (numerator.clone(), denominator.clone())
+        };
+        Some(Decimal {
+            digits: rounding.divide(&(numerator * ten_to(scale)), &denominator),
+            scale,
+        })
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Parsing the digits rounds correctly, which dividing as floats wouldn't
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn to_rational(&self) -> BigRational {
+        BigRational::new(self.digits.clone(), ten_to(self.scale))
+    }
+
+    /// The integer part, truncated toward zero
+    pub fn trunc(&self) -> BigInt {
+        &self.digits / ten_to(self.scale)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_zero()
+    }
+
+    /// The digits of `self` at the scale `scale`, which is at least its own
+    fn digits_at(&self, scale: u32) -> BigInt {
+        &self.digits * ten_to(scale - self.scale)
+    }
+
+    /// The digits of both operands at the larger of their scales, along with that scale
+    fn align(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+        let scale = self.scale.max(other.scale);
+        (self.digits_at(scale), other.digits_at(scale), scale)
+    }
+
+    /// `self` rounded, or padded with zeros, to `scale` places
+    pub fn round(&self, scale: u32, rounding: Rounding) -> Decimal {
+        if scale >= self.scale {
+            return Decimal {
+                digits: self.digits_at(scale),
+                scale,
+            };
+        }
+        Decimal {
+            digits: rounding.divide(&self.digits, &ten_to(self.scale - scale)),
+            scale,
+        }
+    }
+
+    /// `self` without trailing zeros beyond `scale` places
+    fn trim(mut self, scale: u32) -> Decimal {
+        let ten = BigInt::from(10u32);
+        while self.scale > scale && (&self.digits % &ten).is_zero() {
+            self.digits /= &ten;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let (a, b, scale) = self.align(other);
+        Decimal::new(a + b, scale)
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Decimal {
+        let (a, b, scale) = self.align(other);
+        Decimal::new(a - b, scale)
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal::new(&self.digits * &other.digits, self.scale + other.scale)
+    }
+
+    /// The quotient rounded to `scale` places, `None` when dividing by zero
+    pub fn div(&self, other: &Decimal, scale: u32, rounding: Rounding) -> Option<Decimal> {
+        let numerator = &self.digits * ten_to(other.scale);
+        let denominator = &other.digits * ten_to(self.scale);
+        Decimal::from_ratio(&numerator, &denominator, scale, rounding)
+    }
+
+    /// The quotient as `/` computes it: to at least `DIVISION_SCALE` places, without the
+    /// trailing zeros beyond the scales of the operands
+    pub fn quotient(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let quotient = self.div(other, scale.max(DIVISION_SCALE), Rounding::HalfEven)?;
+        Some(quotient.trim(scale))
+    }
+
+    /// The remainder of truncating division, taking the sign of the dividend. `None` when
+    /// dividing by zero.
+    pub fn rem(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        let (a, b, scale) = self.align(other);
+        Some(Decimal::new(a % b, scale))
+    }
+
+    /// `self` to the power of `exponent`, `None` if the scale of the result would be too large
+    pub fn pow(&self, exponent: u32) -> Option<Decimal> {
+        Some(Decimal {
+            digits: self.digits.pow(exponent),
+            scale: self.scale.checked_mul(exponent)?,
+        })
+    }
+
+    pub fn neg(&self) -> Decimal {
+        Decimal::new(-&self.digits, self.scale)
+    }
+}
+
+impl From<BigInt> for Decimal {
+    fn from(value: BigInt) -> Self {
+        Decimal::new(value, 0)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b, _) = self.align(other);
+        a.cmp(&b)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.digits.is_negative() { "-" } else { "" };
+        let digits = self.digits.abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        // Pad with zeros so that there is at least one digit before the point
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(literal: &str) -> Decimal {
+        Decimal::parse(literal).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for (literal, shown) in [
+            ("19.99", "19.99"),
+            ("0.050", "0.050"),
+            ("-0.5", "-0.5"),
+            ("7", "7"),
+            ("23.", "23"),
+            ("1.5e-7", "0.00000015"),
+            ("1e3", "1000"),
+        ] {
+            assert_eq!(decimal(literal).to_string(), shown, "{}", literal);
+        }
+        assert!(Decimal::parse("1.2.3").is_none());
+        assert!(Decimal::parse("").is_none());
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            (Rounding::HalfEven, ["2", "2", "-2", "2", "3"]),
+            (Rounding::HalfUp, ["3", "2", "-3", "2", "3"]),
+            (Rounding::HalfDown, ["2", "2", "-2", "1", "3"]),
+            (Rounding::Up, ["3", "3", "-3", "2", "3"]),
+            (Rounding::Down, ["2", "2", "-2", "1", "2"]),
+            (Rounding::Ceiling, ["3", "3", "-2", "2", "3"]),
+            (Rounding::Floor, ["2", "2", "-3", "1", "2"]),
+        ];
+        for (rounding, expected) in cases {
+            let rounded: Vec<_> = ["2.5", "2.1", "-2.5", "1.5", "2.7"]
+                .iter()
+                .map(|d| decimal(d).round(0, rounding).to_string())
+                .collect();
+            assert_eq!(rounded, expected, "{:?}", rounding);
+        }
+        assert_eq!(
+            decimal("1.005").round(2, Rounding::HalfUp).to_string(),
+            "1.01"
+        );
+        assert_eq!(decimal("1.5").round(3, Rounding::Down).to_string(), "1.500");
+    }
+
+    #[test]
+    fn arithmetic_keeps_scale() {
+        assert_eq!(decimal("0.1").add(&decimal("0.20")).to_string(), "0.30");
+        assert_eq!(decimal("1.5").mul(&decimal("1.5")).to_string(), "2.25");
+        assert_eq!(
+            decimal("10.00")
+                .quotient(&decimal("4"))
+                .unwrap()
+                .to_string(),
+            "2.50"
+        );
+        assert_eq!(
+            decimal("1").quotient(&decimal("3")).unwrap().to_string(),
+            "0.3333333333333333"
+        );
+        assert_eq!(
+            decimal("-7.5").rem(&decimal("2")).unwrap().to_string(),
+            "-1.5"
+        );
+        assert!(decimal("1").quotient(&decimal("0.0")).is_none());
+        assert_eq!(decimal("1.0"), decimal("1.000"));
+        assert!(decimal("-0.01") < decimal("0"));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(Decimal::from_f64(0.1).unwrap().to_string(), "0.1");
+        assert_eq!(Decimal::from_f64(2.0).unwrap().to_string(), "2");
+        assert_eq!(
+            Decimal::from_f64(1e20).unwrap().to_string(),
+            "100000000000000000000"
+        );
+        assert!(Decimal::from_f64(f64::NAN).is_none());
+        assert_eq!(decimal("19.99").to_f64(), 19.99);
+    }
+}
@@ -1,253 +1,518 @@
-use std::iter::Peekable;
+use snafu::prelude::*;
+use std::collections::HashSet;
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
 
-use crate::token::{Token, TokenType};
+use crate::symbol::{Interner, Symbol};
+use crate::token::{Span, Token, TokenType};
 
-pub struct Lexer<T: Iterator<Item = char>> {
-    input_iter: Peekable<T>,
-    char: usize,
+/// The reason a token was lexed as `TokenType::Illegal`
+#[derive(Debug, Clone, Copy, PartialEq, Snafu)]
+pub enum LexError {
+    #[snafu(display("unexpected character"))]
+    UnexpectedCharacter,
+
+    #[snafu(display("malformed number"))]
+    MalformedNumber,
+
+    #[snafu(display("unterminated string literal"))]
+    UnterminatedString,
+
+    #[snafu(display("unterminated character literal"))]
+    UnterminatedChar,
+
+    #[snafu(display("empty character literal"))]
+    EmptyChar,
+
+    #[snafu(display("character literal may only contain one character"))]
+    MultiCharLiteral,
+
+    #[snafu(display("invalid escape sequence"))]
+    InvalidEscape,
+}
+
+/// Keywords are interned before anything else and in this order, so the symbol of a keyword is
+/// its index in this table.
+const KEYWORDS: [(&str, TokenType); 28] = [
+    ("true", TokenType::True),
+    ("false", TokenType::False),
+    ("fun", TokenType::Fun),
+    ("for", TokenType::For),
+    ("loop", TokenType::Loop),
+    ("if", TokenType::If),
+    ("else", TokenType::Else),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("val", TokenType::Val),
+    ("var", TokenType::Var),
+    ("break", TokenType::Break),
+    ("continue", TokenType::Continue),
+    ("and", TokenType::And),
+    ("or", TokenType::Or),
+    ("in", TokenType::In),
+    ("struct", TokenType::Struct),
+    ("impl", TokenType::Impl),
+    ("enum", TokenType::Enum),
+    ("match", TokenType::Match),
+    ("import", TokenType::Import),
+    ("pub", TokenType::Pub),
+    ("none", TokenType::NoneLiteral),
+    ("trait", TokenType::Trait),
+    ("dyn", TokenType::Dyn),
+    ("const", TokenType::Const),
+    ("yield", TokenType::Yield),
+    ("spawn", TokenType::Spawn),
+];
+
+const BYTE_ORDER_MARK: &str = "\u{FEFF}";
+
+/// First occurrence of an identifier containing non-ASCII characters
+#[derive(Debug, Clone, Copy)]
+pub struct UnicodeIdentifier {
+    pub sym: Symbol,
+    pub line: usize,
+    pub char: usize,
+}
+
+/// Lexer over an in-memory source string.
+///
+/// Tokens reference the source by byte range rather than owning their text, and identifiers are
+/// interned into the lexer's `Interner`.
+///
+/// Identifiers follow UAX #31, they start with `_` or an XID_Start character and continue with
+/// XID_Continue characters. Identifiers are NFC normalized before they are interned so names that
+/// look the same compare equal. A leading byte order mark is skipped and `\r\n` is treated as a
+/// single line ending.
+///
+/// Newlines end statements by inserting a semicolon, except directly inside parentheses or
+/// brackets so that argument lists and list literals can span lines.
+pub struct Lexer<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
     line: usize,
-    newline: bool,
+    // Column tracking is done lazily, `column` is the char column of `column_pos`
+    column: usize,
+    column_pos: usize,
     last_match: TokenType,
-    held_token: Option<Token>,
+    // Open delimiters enclosing the current position
+    nesting: Vec<u8>,
+    interner: Interner,
+    unicode_identifiers: Vec<UnicodeIdentifier>,
+    seen_unicode: HashSet<Symbol>,
 }
 
-impl<T: Iterator<Item = char>> Lexer<T> {
-    pub fn new(iter: T) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut interner = Interner::new();
+        for (keyword, _) in KEYWORDS.iter() {
+            interner.intern(keyword);
+        }
+
+        let start = if source.starts_with(BYTE_ORDER_MARK) {
+            BYTE_ORDER_MARK.len()
+        } else {
+            0
+        };
+
         Self {
-            input_iter: iter.peekable(),
-            char: 0,
+            source,
+            bytes: source.as_bytes(),
+            pos: start,
             line: 1,
-            newline: true,
+            column: 0,
+            column_pos: start,
             last_match: TokenType::Semicolon,
-            held_token: None,
+            nesting: vec![],
+            interner,
+            unicode_identifiers: vec![],
+            seen_unicode: HashSet::new(),
         }
     }
 
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The source text that `token` was lexed from
+    pub fn text(&self, token: &Token) -> &'a str {
+        &self.source[token.span.start..token.span.end]
+    }
+
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        self.interner.resolve(sym)
+    }
+
+    /// Whether `sym` is one of the language's keywords rather than an identifier
+    pub fn is_keyword(sym: Symbol) -> bool {
+        sym.index() < KEYWORDS.len()
+    }
+
+    /// Identifiers containing non-ASCII characters seen so far, in order of first occurrence
+    pub fn unicode_identifiers(&self) -> &[UnicodeIdentifier] {
+        &self.unicode_identifiers
+    }
+
     fn next_token(&mut self) -> Option<Token> {
-        if let Some(token) = self.held_token.take() {
-            return Some(token);
-        }
+        loop {
+            let start = self.pos;
+            let c = *self.bytes.get(self.pos)?;
+            self.pos += 1;
 
-        while let Some(c) = self.advance() {
             let token_match = match c {
-                c if c.is_whitespace() => continue,
-                ';' => TokenType::Semicolon,
-                '(' => TokenType::LeftParen,
-                ')' => TokenType::RightParen,
-                '{' => TokenType::LeftBrace,
-                '}' => TokenType::RightBrace,
-                ',' => TokenType::Comma,
-                '-' => TokenType::Minus,
-                '+' => TokenType::Plus,
-                '*' => TokenType::Star,
-                '<' => match self.peek() {
-                    Some('=') => {
-                        self.advance();
-                        TokenType::LessEqual
+                b'\n' => {
+                    let insert_semicolon = !matches!(self.nesting.last(), Some(b'(' | b'['))
+                        && matches!(
+                            self.last_match,
+                            TokenType::Identifier(_)
+                                | TokenType::Int
+                                | TokenType::Float
+                                | TokenType::Decimal
+                                | TokenType::String
+                                | TokenType::Char
+                                | TokenType::True
+                                | TokenType::False
+                                | TokenType::NoneLiteral
+                                | TokenType::Question
+                                | TokenType::RightParen
+                                | TokenType::RightBrace
+                                | TokenType::RightBracket
+                                | TokenType::Return
+                                | TokenType::Continue
+                                | TokenType::Break
+                        );
+                    let token = self.make_token(TokenType::Semicolon, start);
+                    self.line += 1;
+                    self.column = 0;
+                    self.column_pos = self.pos;
+
+                    if insert_semicolon {
+                        self.last_match = TokenType::Semicolon;
+                        return Some(token);
+                    }
+                    continue;
+                }
+                b' ' | b'\t' | b'\r' => continue,
+                b';' => TokenType::Semicolon,
+                b'(' | b'{' | b'[' => {
+                    self.nesting.push(c);
+                    match c {
+                        b'(' => TokenType::LeftParen,
+                        b'{' => TokenType::LeftBrace,
+                        _ => TokenType::LeftBracket,
+                    }
+                }
+                b')' | b'}' | b']' => {
+                    self.nesting.pop();
+                    match c {
+                        b')' => TokenType::RightParen,
+                        b'}' => TokenType::RightBrace,
+                        _ => TokenType::RightBracket,
                     }
-                    None | Some(_) => TokenType::Less,
+                }
+                b',' => TokenType::Comma,
+                b':' => TokenType::Colon,
+                b'.' => match self.peek() {
+                    Some(b'.') => {
+                        self.pos += 1;
+                        self.either(b'.', TokenType::Ellipsis, TokenType::DotDot)
+                    }
+                    _ => TokenType::Dot,
                 },
-                '>' => match self.peek() {
-                    Some('=') => {
-                        self.advance();
-                        TokenType::GreaterEqual
+                b'-' => match self.peek() {
+                    Some(b'>') => {
+                        self.pos += 1;
+                        TokenType::Arrow
                     }
-                    None | Some(_) => TokenType::Greater,
+                    _ => self.either(b'=', TokenType::MinusEqual, TokenType::Minus),
                 },
-                '=' => match self.peek() {
-                    Some('=') => {
-                        self.advance();
-                        TokenType::EqualEqual
+                b'+' => self.either(b'=', TokenType::PlusEqual, TokenType::Plus),
+                b'*' => match self.peek() {
+                    Some(b'*') => {
+                        self.pos += 1;
+                        TokenType::StarStar
                     }
-                    None | Some(_) => TokenType::Equal,
+                    _ => self.either(b'=', TokenType::StarEqual, TokenType::Star),
                 },
-                '!' => match self.peek() {
-                    Some('=') => {
-                        self.advance();
-                        TokenType::BangEqual
+                b'%' => self.either(b'=', TokenType::PercentEqual, TokenType::Percent),
+                b'&' => TokenType::Ampersand,
+                b'|' => TokenType::Pipe,
+                b'^' => TokenType::Caret,
+                b'~' => TokenType::Tilde,
+                b'<' => match self.peek() {
+                    Some(b'<') => {
+                        self.pos += 1;
+                        TokenType::LessLess
                     }
-                    None | Some(_) => TokenType::Bang,
+                    _ => self.either(b'=', TokenType::LessEqual, TokenType::Less),
                 },
-                '/' => match self.peek() {
-                    Some('/') => {
+                b'>' => match self.peek() {
+                    Some(b'>') => {
+                        self.pos += 1;
+                        TokenType::GreaterGreater
+                    }
+                    _ => self.either(b'=', TokenType::GreaterEqual, TokenType::Greater),
+                },
+                b'=' => match self.peek() {
+                    Some(b'>') => {
+                        self.pos += 1;
+                        TokenType::FatArrow
+                    }
+                    _ => self.either(b'=', TokenType::EqualEqual, TokenType::Equal),
+                },
+                b'!' => self.either(b'=', TokenType::BangEqual, TokenType::Bang),
+                b'?' => match self.peek() {
+                    Some(b'.') => {
+                        self.pos += 1;
+                        TokenType::QuestionDot
+                    }
+                    _ => self.either(b'?', TokenType::QuestionQuestion, TokenType::Question),
+                },
+                b'/' => match self.peek() {
+                    Some(b'/') => {
                         self.line_comment();
                         continue;
                     }
+                    Some(b'=') => {
+                        self.pos += 1;
+                        TokenType::SlashEqual
+                    }
                     None | Some(_) => TokenType::Slash,
                 },
-                '"' => self.handle_string(),
-                c if c.is_numeric() => self.handle_digits(c),
-                c if c.is_alphabetic() || c == '_' => self.handle_letters(c),
-                _ => TokenType::Illegal(c.to_string()),
+                b'"' => self.handle_string(),
+                b'\'' => self.handle_char(start),
+                b'0'..=b'9' => self.handle_digits(),
+                b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.handle_letters(start),
+                c if c >= 0x80 => {
+                    // This unwrap is safe because `start` is always on a char boundary
+                    let ch = self.source[start..].chars().next().unwrap();
+                    self.pos = start + ch.len_utf8();
+                    match ch {
+                        ch if ch.is_whitespace() => continue,
+                        ch if is_xid_start(ch) => self.handle_letters(start),
+                        _ => TokenType::Illegal(LexError::UnexpectedCharacter),
+                    }
+                }
+                _ => TokenType::Illegal(LexError::UnexpectedCharacter),
             };
-            self.last_match = token_match.clone();
-
-            if let Some(token) = self.held_token.take() {
-                self.held_token = Some(Token {
-                    token_type: token_match,
-                    char: self.char,
-                    line: self.line,
-                });
-                return Some(token);
-            }
+            self.last_match = token_match;
 
-            return Some(Token {
-                token_type: token_match,
-                char: self.char,
-                line: self.line,
-            });
-        }
-        if let Some(token) = self.held_token.take() {
+            let token = self.make_token(token_match, start);
+            if let TokenType::Identifier(sym) = token_match {
+                if !self.source[start..self.pos].is_ascii() && self.seen_unicode.insert(sym) {
+                    self.unicode_identifiers.push(UnicodeIdentifier {
+                        sym,
+                        line: token.line,
+                        char: token.char,
+                    });
+                }
+            }
             return Some(token);
         }
+    }
 
-        None
-    }
-
-    fn advance(&mut self) -> Option<char> {
-        let next = self.input_iter.next();
-
-        match next {
-            None => {}
-            Some('\n') => {
-                match self.last_match {
-                    TokenType::Identifier(_)
-                    | TokenType::Int(_)
-                    | TokenType::Float(_)
-                    | TokenType::String(_)
-                    | TokenType::True
-                    | TokenType::False
-                    | TokenType::RightParen
-                    | TokenType::RightBrace
-                    | TokenType::Return
-                    | TokenType::Continue
-                    | TokenType::Break => {
-                        self.held_token = Some(Token {
-                            token_type: TokenType::Semicolon,
-                            char: self.char,
-                            line: self.line,
-                        })
-                    }
-                    _ => {}
-                }
-                self.char = 0;
-                self.line += 1;
-                self.newline = true;
-            }
-            Some(_) => {
-                if self.newline {
-                    self.newline = false
-                } else {
-                    self.char += 1
-                }
-            }
+    fn make_token(&mut self, token_type: TokenType, start: usize) -> Token {
+        self.column += self.bytes[self.column_pos..start]
+            .iter()
+            .filter(|b| (**b & 0xC0) != 0x80)
+            .count();
+        self.column_pos = start;
+
+        Token {
+            token_type,
+            span: Span::new(start, self.pos),
+            char: self.column,
+            line: self.line,
         }
-        next
     }
 
-    fn peek(&mut self) -> Option<&char> {
-        self.input_iter.peek()
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    /// Consumes `next` and returns `matched` if it is the next byte, otherwise returns `single`
+    fn either(&mut self, next: u8, matched: TokenType, single: TokenType) -> TokenType {
+        if self.peek() == Some(next) {
+            self.pos += 1;
+            matched
+        } else {
+            single
+        }
     }
 
     fn line_comment(&mut self) {
         while let Some(c) = self.peek() {
-            if *c == '\n' {
+            if c == b'\n' {
                 return;
             }
-            self.advance();
+            self.pos += 1;
         }
     }
 
-    fn handle_letters(&mut self, c: char) -> TokenType {
-        let mut literal = String::from(c);
+    fn handle_letters(&mut self, start: usize) -> TokenType {
         loop {
-            let c = self.peek();
-            match c {
-                None => break,
-                Some(c) if c.is_alphanumeric() || *c == '_' => (),
+            match self.peek() {
+                Some(b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_') => self.pos += 1,
+                Some(c) if c >= 0x80 => match self.peek_char() {
+                    Some(ch) if is_xid_continue(ch) => self.pos += ch.len_utf8(),
+                    _ => break,
+                },
                 _ => break,
             }
-            let d = self.advance().unwrap();
-            literal.push(d);
         }
-        self.match_keywords(literal)
-    }
-
-    fn match_keywords(&self, s: String) -> TokenType {
-        match s.as_str() {
-            "true" => TokenType::True,
-            "false" => TokenType::False,
-            "fun" => TokenType::Fun,
-            "for" => TokenType::For,
-            "loop" => TokenType::Loop,
-            "if" => TokenType::If,
-            "else" => TokenType::Else,
-            "print" => TokenType::Print,
-            "return" => TokenType::Return,
-            "val" => TokenType::Val,
-            "var" => TokenType::Var,
-            "break" => TokenType::Break,
-            "continue" => TokenType::Continue,
-            "and" => TokenType::And,
-            "or" => TokenType::Or,
-
-            _ => TokenType::Identifier(s),
+
+        let text = &self.source[start..self.pos];
+        let sym = if text.is_ascii() || is_nfc_quick(text.chars()) == IsNormalized::Yes {
+            self.interner.intern(text)
+        } else {
+            self.interner.intern(&text.nfc().collect::<String>())
+        };
+        match KEYWORDS.get(sym.index()) {
+            Some((_, keyword)) => *keyword,
+            None => TokenType::Identifier(sym),
         }
     }
 
-    fn handle_digits(&mut self, c: char) -> TokenType {
-        let mut literal = String::from(c);
+    fn handle_digits(&mut self) -> TokenType {
         let mut is_float = false;
         loop {
-            let c = self.peek();
-            match c {
-                None => break,
-                Some('f') => {
-                    self.advance();
-                    is_float = true;
-                    break;
+            match self.peek() {
+                Some(b'f') => {
+                    self.pos += 1;
+                    return TokenType::Float;
                 }
-                Some('.') => {
+                Some(b'd') => {
+                    self.pos += 1;
+                    return TokenType::Decimal;
+                }
+                // `1..2` is a range between two ints
+                Some(b'.') if self.bytes.get(self.pos + 1) == Some(&b'.') => break,
+                Some(b'.') => {
+                    self.pos += 1;
                     if is_float {
-                        // This unwrap is safe because of the peek
-                        let c = self.advance().unwrap();
-                        literal.push(c);
-                        return TokenType::Illegal(literal);
-                    } else {
-                        is_float = true;
+                        return TokenType::Illegal(LexError::MalformedNumber);
                     }
+                    is_float = true;
                 }
-                Some(c) if c.is_numeric() => {}
-                Some(_) => break,
+                Some(b'0'..=b'9') => self.pos += 1,
+                _ => break,
             }
-            let d = self.advance().unwrap();
-            literal.push(d)
         }
         if is_float {
-            TokenType::Float(literal)
+            TokenType::Float
         } else {
-            TokenType::Int(literal)
+            TokenType::Int
         }
     }
 
     fn handle_string(&mut self) -> TokenType {
-        let mut literal = String::new();
-        while let Some(_) = self.peek() {
-            // this is a safe unwrap because we just peeked and its not a None
-            let ch = self.advance().unwrap();
-            match ch {
-                '\n' => return TokenType::Illegal(literal),
-                '"' => return TokenType::String(literal),
-                _ => {}
-            };
-            // this is a safe unwrap because we just peeked and its not a None
-            literal.push(ch)
+        let start = self.pos;
+        if !self.scan_quoted(b'"') {
+            return TokenType::Illegal(LexError::UnterminatedString);
+        }
+
+        match unescape(&self.source[start..self.pos - 1], |_| ()) {
+            Ok(()) => TokenType::String,
+            Err(err) => TokenType::Illegal(err),
+        }
+    }
+
+    fn handle_char(&mut self, start: usize) -> TokenType {
+        if !self.scan_quoted(b'\'') {
+            return TokenType::Illegal(LexError::UnterminatedChar);
+        }
+
+        let mut count = 0;
+        if let Err(err) = unescape(&self.source[start + 1..self.pos - 1], |_| count += 1) {
+            return TokenType::Illegal(err);
+        }
+        match count {
+            0 => TokenType::Illegal(LexError::EmptyChar),
+            1 => TokenType::Char,
+            _ => TokenType::Illegal(LexError::MultiCharLiteral),
+        }
+    }
+
+    /// Advances past the closing `quote`, skipping over escaped characters. Returns false if the
+    /// line or input ends first.
+    fn scan_quoted(&mut self, quote: u8) -> bool {
+        while let Some(c) = self.peek() {
+            match c {
+                b'\n' => return false,
+                b'\\' => {
+                    self.pos += 1;
+                    if self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                c if c == quote => {
+                    self.pos += 1;
+                    return true;
+                }
+                _ => self.pos += 1,
+            }
+        }
+        false
+    }
+}
+
+/// Decodes the escape sequences in the body of a string or char literal, passing each resulting
+/// char to `emit`.
+///
+/// Supported escapes are `\n`, `\r`, `\t`, `\0`, `\\`, `\'`, `\"` and `\u{XXXX}` with one to six
+/// hex digits.
+pub fn unescape(literal: &str, mut emit: impl FnMut(char)) -> Result<(), LexError> {
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            emit(c);
+            continue;
         }
-        TokenType::Illegal(literal)
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err(LexError::InvalidEscape);
+                }
+
+                let mut code: u32 = 0;
+                let mut digits = 0;
+                loop {
+                    match chars.next() {
+                        Some('}') if digits > 0 => break,
+                        Some(c) if digits < 6 && c.is_ascii_hexdigit() => {
+                            // This unwrap is safe because of the hex digit check
+                            code = code * 16 + c.to_digit(16).unwrap();
+                            digits += 1;
+                        }
+                        _ => return Err(LexError::InvalidEscape),
+                    }
+                }
+                char::from_u32(code).ok_or(LexError::InvalidEscape)?
+            }
+            _ => return Err(LexError::InvalidEscape),
+        };
+        emit(escaped);
     }
+    Ok(())
 }
 
-impl<T: Iterator<Item = char>> Iterator for Lexer<T> {
+impl<'a> Iterator for Lexer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -255,4 +520,261 @@ impl<T: Iterator<Item = char>> Iterator for Lexer<T> {
     }
 }
 
-mod test {}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn types(source: &str) -> Vec<TokenType> {
+        Lexer::new(source).map(|t| t.token_type).collect()
+    }
+
+    #[test]
+    fn identifiers_are_interned() {
+        let mut lexer = Lexer::new("foo bar foo");
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+
+        assert_eq!(tokens[0].token_type, tokens[2].token_type);
+        assert_ne!(tokens[0].token_type, tokens[1].token_type);
+        match tokens[1].token_type {
+            TokenType::Identifier(sym) => assert_eq!(lexer.resolve(sym), "bar"),
+            other => panic!("expected identifier, got {}", other),
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            types("fun val var return"),
+            vec![
+                TokenType::Fun,
+                TokenType::Val,
+                TokenType::Var,
+                TokenType::Return
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_reference_source() {
+        let lexer = Lexer::new("val x = 14.0 + \"hi\"");
+        let source = lexer.source();
+        let texts: Vec<&str> = lexer.map(|t| &source[t.span.start..t.span.end]).collect();
+
+        assert_eq!(texts, vec!["val", "x", "=", "14.0", "+", "\"hi\""]);
+    }
+
+    #[test]
+    fn positions() {
+        let tokens: Vec<Token> = Lexer::new("a\n  héllo b").collect();
+
+        assert_eq!((tokens[2].line, tokens[2].char), (2, 2));
+        assert_eq!((tokens[3].line, tokens[3].char), (2, 8));
+    }
+
+    #[test]
+    fn semicolon_insertion() {
+        assert_eq!(
+            types("x = 1\ny +\n2"),
+            vec![
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::Equal,
+                TokenType::Int,
+                TokenType::Semicolon,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32 + 1)),
+                TokenType::Plus,
+                TokenType::Int,
+            ]
+        );
+    }
+
+    #[test]
+    fn no_semicolons_inside_parens_or_brackets() {
+        assert_eq!(
+            types("f(\n[1,\n2\n]\n)\n"),
+            vec![
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::LeftParen,
+                TokenType::LeftBracket,
+                TokenType::Int,
+                TokenType::Comma,
+                TokenType::Int,
+                TokenType::RightBracket,
+                TokenType::RightParen,
+                TokenType::Semicolon,
+            ]
+        );
+        assert_eq!(types("(fun() {\nx\n})").len(), 9);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            types("1..3 1.5..x.y"),
+            vec![
+                TokenType::Int,
+                TokenType::DotDot,
+                TokenType::Int,
+                TokenType::Float,
+                TokenType::DotDot,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::Dot,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32 + 1)),
+            ]
+        );
+        assert_eq!(
+            types("...xs .. ."),
+            vec![
+                TokenType::Ellipsis,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::DotDot,
+                TokenType::Dot,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            types("7 14.0 23. 19f 19.99d 5d 1.2.3"),
+            vec![
+                TokenType::Int,
+                TokenType::Float,
+                TokenType::Float,
+                TokenType::Float,
+                TokenType::Decimal,
+                TokenType::Decimal,
+                TokenType::Illegal(LexError::MalformedNumber),
+                TokenType::Int,
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_assignment_operators() {
+        assert_eq!(
+            types("+= -= *= /= %= + - * /"),
+            vec![
+                TokenType::PlusEqual,
+                TokenType::MinusEqual,
+                TokenType::StarEqual,
+                TokenType::SlashEqual,
+                TokenType::PercentEqual,
+                TokenType::Plus,
+                TokenType::Minus,
+                TokenType::Star,
+                TokenType::Slash,
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_and_bitwise_operators() {
+        assert_eq!(
+            types("% ** *= & | ^ ~ << <= < >> >= >"),
+            vec![
+                TokenType::Percent,
+                TokenType::StarStar,
+                TokenType::StarEqual,
+                TokenType::Ampersand,
+                TokenType::Pipe,
+                TokenType::Caret,
+                TokenType::Tilde,
+                TokenType::LessLess,
+                TokenType::LessEqual,
+                TokenType::Less,
+                TokenType::GreaterGreater,
+                TokenType::GreaterEqual,
+                TokenType::Greater,
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_operators() {
+        assert_eq!(
+            types("x?.y ?? none -> Int?"),
+            vec![
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::QuestionDot,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32 + 1)),
+                TokenType::QuestionQuestion,
+                TokenType::NoneLiteral,
+                TokenType::Arrow,
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32 + 2)),
+                TokenType::Question,
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        // XID_Continue allows combining marks and connector punctuation but not symbols
+        assert_eq!(types("_x x\u{301} a\u{203F}b").len(), 3);
+        assert_eq!(
+            types("a☺"),
+            vec![
+                TokenType::Identifier(Symbol(KEYWORDS.len() as u32)),
+                TokenType::Illegal(LexError::UnexpectedCharacter),
+            ]
+        );
+        // Combining marks may not start an identifier
+        assert_eq!(
+            types("\u{301}"),
+            vec![TokenType::Illegal(LexError::UnexpectedCharacter)]
+        );
+    }
+
+    #[test]
+    fn identifiers_are_nfc_normalized() {
+        let mut lexer = Lexer::new("caf\u{E9} cafe\u{301}");
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+
+        assert_eq!(tokens[0].token_type, tokens[1].token_type);
+        assert_eq!(lexer.unicode_identifiers().len(), 1);
+        match tokens[1].token_type {
+            TokenType::Identifier(sym) => assert_eq!(lexer.resolve(sym), "caf\u{E9}"),
+            other => panic!("expected identifier, got {}", other),
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_and_crlf() {
+        let tokens: Vec<Token> = Lexer::new("\u{FEFF}a = 1\r\nb").collect();
+
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0].span, Span::new(3, 4));
+        assert_eq!((tokens[0].line, tokens[0].char), (1, 0));
+        assert_eq!(tokens[3].token_type, TokenType::Semicolon);
+        assert_eq!((tokens[4].line, tokens[4].char), (2, 0));
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(
+            types(r"'a' '\n' '\u{263A}' '\'' 'é'"),
+            vec![TokenType::Char; 5]
+        );
+        assert_eq!(
+            types("'' 'ab' '\\q' 'a"),
+            vec![
+                TokenType::Illegal(LexError::EmptyChar),
+                TokenType::Illegal(LexError::MultiCharLiteral),
+                TokenType::Illegal(LexError::InvalidEscape),
+                TokenType::Illegal(LexError::UnterminatedChar),
+            ]
+        );
+    }
+
+    #[test]
+    fn unescape_literals() {
+        let mut out = String::new();
+        unescape(r"a\tb\u{263A}\\", |c| out.push(c)).unwrap();
+
+        assert_eq!(out, "a\tb\u{263A}\\");
+        assert_eq!(
+            unescape(r"\u{110000}", |_| ()),
+            Err(LexError::InvalidEscape)
+        );
+        assert_eq!(unescape(r"\u{}", |_| ()), Err(LexError::InvalidEscape));
+    }
+}
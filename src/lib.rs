@@ -1,77 +1,162 @@
-use std::fs::File;
-use std::io::{Bytes, Read};
-use utf8_decode::UnsafeDecoder;
-
 use snafu::prelude::*;
-
-mod lexer;
-mod parser;
-mod token;
-
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub mod codegen;
+pub mod decimal;
+pub mod lexer;
+pub mod lint;
+pub mod modules;
+pub mod parser;
+pub mod persistent;
+pub mod symbol;
+pub mod token;
+pub mod value;
+pub mod vm;
+
+use codegen::{Codegen, CodegenError};
 use lexer::Lexer;
+use lint::Lint;
+use modules::{ModuleGraph, ModuleLoader};
 use parser::{ParseError, Parser};
 use token::Tokens;
+use vm::{RuntimeError, TraceFrame, Uncaught, Vm};
 
 #[derive(Debug, Snafu)]
 pub enum CompilerError {
+    #[snafu(display("cannot read source{}: {source}", in_file(file)))]
     ReadError {
         source: std::io::Error,
+        file: Option<String>,
     },
 
+    #[snafu(display("source is not valid UTF-8 at byte offset {offset}{}", in_file(file)))]
+    InvalidUtf8 { offset: usize, file: Option<String> },
+
     #[snafu(display("encountered errors during lexing `{tokens}`"))]
-    LexError {
-        tokens: Tokens,
-    },
+    LexError { tokens: Tokens },
 
-    #[snafu(display("encountered an error during parsing `{err}`"))]
+    #[snafu(display("encountered an error during parsing `{err}`{}", in_file(file)))]
     ParseError {
         err: ParseError,
+        file: Option<String>,
     },
-}
 
-// FileReader is used to read a stream of chars from a file
-struct FileReader {
-    iter: UnsafeDecoder<Bytes<File>>,
-    err: Result<(), std::io::Error>,
-}
+    #[snafu(display("cannot find module `{name}` imported at {at}"))]
+    ModuleNotFound { name: String, at: String },
 
-impl FileReader {
-    fn open(path: &str) -> Result<Self, std::io::Error> {
-        let file = File::open(path)?;
+    #[snafu(display("import cycle `{cycle}`"))]
+    ImportCycle { cycle: String },
 
-        Ok(Self {
-            iter: UnsafeDecoder::new(file.bytes()),
-            err: Ok(()),
-        })
-    }
+    #[snafu(display("encountered an error during code generation `{err}`"))]
+    CodegenError { err: CodegenError },
+
+    #[snafu(display("encountered an error while running `{err}`{}", stack_trace(trace)))]
+    RuntimeError {
+        err: RuntimeError,
+        /// The calls that were in progress, innermost first
+        trace: Vec<TraceFrame>,
+    },
 }
 
-impl Iterator for &mut FileReader {
-    type Item = char;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.iter.next() {
-            None => None,
-            Some(Err(e)) => {
-                self.err = Err(e);
-                None
-            }
-            Some(Ok(c)) => Some(c),
+/// The frames of `trace`, under a header for each task when tasks other than the main program
+/// are in it
+fn stack_trace(trace: &[TraceFrame]) -> String {
+    if trace.iter().all(|frame| frame.task == 0) {
+        return trace.iter().map(|frame| format!("\n  {}", frame)).collect();
+    }
+    let mut lines = String::new();
+    for (i, frame) in trace.iter().enumerate() {
+        if i == 0 || trace[i - 1].task != frame.task {
+            lines.push_str(&format!("\n  task {}", frame.task));
         }
+        lines.push_str(&format!("\n    {}", frame));
     }
+    lines
 }
 
-pub fn compile(path: &str) -> Result<(), CompilerError> {
-    let mut reader =
-        FileReader::open(&path).map_err(|err| CompilerError::ReadError { source: err })?;
+fn in_file(file: &Option<String>) -> String {
+    match file {
+        Some(file) => format!(" in {}", file),
+        None => String::new(),
+    }
+}
+
+/// Reads the source file at `path`, which must be valid UTF-8
+pub fn read_source(path: impl AsRef<Path>) -> Result<String, CompilerError> {
+    let file = Some(path.as_ref().display().to_string());
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(CompilerError::ReadError { source: err, file }),
+    };
+
+    String::from_utf8(bytes).map_err(|err| CompilerError::InvalidUtf8 {
+        offset: err.utf8_error().valid_up_to(),
+        file,
+    })
+}
 
-    let lexer = Lexer::new(&mut reader);
+/// Parses `source`, along with the lints found in it
+fn parse(source: &str) -> Result<(parser::ast::Program, Vec<Lint>), CompilerError> {
+    let mut parser = Parser::new(Lexer::new(source));
 
-    let ast = Parser::new(lexer)
+    let ast = parser
         .parse()
-        .map_err(|err| CompilerError::ParseError { err })?;
+        .map_err(|err| CompilerError::ParseError { err, file: None })?;
 
-    print!("{:?}", ast);
+    Ok((ast, lint::identifier_lints(parser.lexer())))
+}
+
+/// Parses the program at `path` and the modules it imports, looking for them in `search_path`
+/// and `YAPL_PATH` when they are not next to the importing file
+pub fn load(path: &Path, search_path: Vec<PathBuf>) -> Result<ModuleGraph, CompilerError> {
+    ModuleLoader::with_env(search_path).load(path)
+}
+
+/// Prints the ast of each module of `graph`
+pub fn print_ast(graph: &ModuleGraph) {
+    for module in &graph.modules {
+        print!("{:?}", module.ast);
+    }
+}
 
+/// Parses the program at `path` and the modules it imports, printing the ast of each module
+pub fn compile(path: &str) -> Result<(), CompilerError> {
+    let graph = load(Path::new(path), vec![])?;
+    print_ast(&graph);
     Ok(())
 }
+
+/// Compiles and runs the program at `path`, printing to stdout
+pub fn run(path: &str) -> Result<(), CompilerError> {
+    run_file(Path::new(path), vec![], &mut std::io::stdout())
+}
+
+/// Compiles and runs the program at `path`, looking for the modules it imports in
+/// `search_path` and `YAPL_PATH` when they are not next to the importing file. The output of
+/// `print` is written to `out`.
+pub fn run_file(
+    path: &Path,
+    search_path: Vec<PathBuf>,
+    out: &mut dyn Write,
+) -> Result<(), CompilerError> {
+    let graph = load(path, search_path)?;
+    run_graph(&graph, out)
+}
+
+/// Compiles and runs `source`, writing the output of `print` to `out`
+pub fn run_source(source: &str, out: &mut dyn Write) -> Result<(), CompilerError> {
+    let graph = ModuleLoader::with_env(vec![]).load_source(source)?;
+    run_graph(&graph, out)
+}
+
+/// Compiles and runs the modules of `graph`, writing the output of `print` to `out`
+pub fn run_graph(graph: &ModuleGraph, out: &mut dyn Write) -> Result<(), CompilerError> {
+    let program = Codegen::new()
+        .generate(graph)
+        .map_err(|err| CompilerError::CodegenError { err })?;
+
+    Vm::new(out)
+        .run(&program)
+        .map_err(|Uncaught { err, trace }| CompilerError::RuntimeError { err, trace })
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use unicode_security::{skeleton, MixedScript};
+
+use crate::lexer::Lexer;
+use crate::symbol::Symbol;
+
+/// A warning about the source that does not stop compilation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    /// An identifier mixes characters from scripts that are not normally used together
+    MixedScript {
+        name: String,
+        line: usize,
+        char: usize,
+    },
+
+    /// Two different identifiers that look the same, as defined by UTS #39 confusable detection
+    Confusable {
+        name: String,
+        other: String,
+        line: usize,
+        char: usize,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::MixedScript { name, line, char } => write!(
+                f,
+                "warning - identifier `{}` mixes multiple scripts at {}:{}",
+                name, line, char
+            ),
+            Lint::Confusable {
+                name,
+                other,
+                line,
+                char,
+            } => write!(
+                f,
+                "warning - identifier `{}` is confusable with `{}` at {}:{}",
+                name, other, line, char
+            ),
+        }
+    }
+}
+
+/// Checks the identifiers seen by `lexer` for mixed scripts and confusables.
+///
+/// Only identifiers containing non-ASCII characters are reported, so purely ASCII programs never
+/// produce these lints.
+pub fn identifier_lints(lexer: &Lexer) -> Vec<Lint> {
+    let unicode = lexer.unicode_identifiers();
+    if unicode.is_empty() {
+        return vec![];
+    }
+
+    let mut lints = vec![];
+    for ident in unicode {
+        let name = lexer.resolve(ident.sym);
+        if !name.is_single_script() {
+            lints.push(Lint::MixedScript {
+                name: name.to_string(),
+                line: ident.line,
+                char: ident.char,
+            });
+        }
+    }
+
+    // Symbols are allocated in order of first occurrence, so a group's symbols are sorted
+    let interner = lexer.interner();
+    let mut skeletons: HashMap<String, Vec<Symbol>> = HashMap::new();
+    for index in 0..interner.len() {
+        let sym = Symbol(index as u32);
+        if Lexer::is_keyword(sym) {
+            continue;
+        }
+        skeletons
+            .entry(skeleton(interner.resolve(sym)).collect())
+            .or_default()
+            .push(sym);
+    }
+
+    for ident in unicode {
+        let name = lexer.resolve(ident.sym);
+        let group = &skeletons[&skeleton(name).collect::<String>()];
+
+        // Pairs of non-ASCII identifiers are only reported at the later one
+        let other = group
+            .iter()
+            .find(|s| **s != ident.sym && lexer.resolve(**s).is_ascii())
+            .or_else(|| group.iter().find(|s| **s < ident.sym));
+        if let Some(other) = other {
+            lints.push(Lint::Confusable {
+                name: name.to_string(),
+                other: lexer.resolve(*other).to_string(),
+                line: ident.line,
+                char: ident.char,
+            });
+        }
+    }
+
+    lints
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lints(source: &str) -> Vec<Lint> {
+        let mut lexer = Lexer::new(source);
+        lexer.by_ref().for_each(drop);
+        identifier_lints(&lexer)
+    }
+
+    #[test]
+    fn ascii_programs_have_no_lints() {
+        assert!(lints("val Il = 1\nval ll = 2").is_empty());
+    }
+
+    #[test]
+    fn mixed_script() {
+        // The first letter is a Cyrillic a
+        assert_eq!(
+            lints("val \u{430}pple = 1"),
+            vec![Lint::MixedScript {
+                name: "\u{430}pple".to_string(),
+                line: 1,
+                char: 4,
+            }]
+        );
+        assert!(lints("val café = 1\nval мир = 2").is_empty());
+    }
+
+    #[test]
+    fn confusable() {
+        let expected = |line, char| {
+            vec![Lint::Confusable {
+                name: "\u{430}".to_string(),
+                other: "a".to_string(),
+                line,
+                char,
+            }]
+        };
+
+        assert_eq!(lints("val a = 1\nprint(\u{430})"), expected(2, 6));
+        assert_eq!(lints("val \u{430} = 1\nprint(a)"), expected(1, 4));
+    }
+}
@@ -0,0 +1,268 @@
+//! Loading of a program spread over several files.
+//!
+//! `import a.b` refers to the file `a/b.ypl`, looked up relative to the directory of the
+//! importing file first and then in each directory of the search path. Starting from the file
+//! that is run, every imported file is loaded and parsed once, no matter how many modules import
+//! it, and the modules are ordered so that each one comes after everything it imports.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::lint::Lint;
+use crate::parser::ast::{Declaration, Program};
+use crate::token::Position;
+use crate::{parse, read_source, CompilerError};
+
+/// Extension of source files
+const EXTENSION: &str = "ypl";
+
+/// Environment variable holding extra directories to look for modules in, separated like `PATH`
+pub const SEARCH_PATH_VAR: &str = "YAPL_PATH";
+
+/// Index of a file in a `SourceMap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(usize);
+
+struct SourceFile {
+    /// `None` for source that was not read from a file
+    path: Option<PathBuf>,
+    source: String,
+}
+
+/// The source of every file in a program, so that diagnostics can name the file they are about
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, path: Option<PathBuf>, source: String) -> FileId {
+        self.files.push(SourceFile { path, source });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn path(&self, id: FileId) -> Option<&Path> {
+        self.files[id.0].path.as_deref()
+    }
+
+    pub fn source(&self, id: FileId) -> &str {
+        &self.files[id.0].source
+    }
+
+    /// Name of the file as shown in diagnostics, `None` if it isn't a file
+    pub fn name(&self, id: FileId) -> Option<String> {
+        self.path(id).map(|path| path.display().to_string())
+    }
+}
+
+impl fmt::Debug for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.files.iter().map(|file| &file.path))
+            .finish()
+    }
+}
+
+/// A parsed source file
+#[derive(Debug)]
+pub struct Module {
+    /// Name the module is imported as, the file name without its extension
+    pub name: String,
+    pub file: FileId,
+    pub ast: Program,
+    /// Warnings about the source of the module, which don't stop compilation
+    pub lints: Vec<Lint>,
+    /// Index in the graph of the module each import refers to, in the order of the imports
+    pub imports: Vec<usize>,
+}
+
+/// The modules of a program, each one after the modules it imports so the program's root
+/// module is last
+#[derive(Debug)]
+pub struct ModuleGraph {
+    pub sources: SourceMap,
+    pub modules: Vec<Module>,
+}
+
+impl ModuleGraph {
+    /// Name of the file of `module` as shown in diagnostics
+    pub fn file_name(&self, module: &Module) -> Option<String> {
+        self.sources.name(module.file)
+    }
+}
+
+/// Loads a module and everything it imports
+pub struct ModuleLoader {
+    search_path: Vec<PathBuf>,
+    sources: SourceMap,
+    modules: Vec<Module>,
+    /// Graph index of each module that has been loaded, by canonical path
+    loaded: HashMap<PathBuf, usize>,
+    /// Canonical paths and import paths of the modules being loaded, each imported by the one
+    /// before
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl ModuleLoader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            sources: SourceMap::default(),
+            modules: vec![],
+            loaded: HashMap::new(),
+            loading: vec![],
+        }
+    }
+
+    /// Creates a loader that searches the directories in the `YAPL_PATH` environment variable
+    /// after `search_path`
+    pub fn with_env(mut search_path: Vec<PathBuf>) -> Self {
+        if let Some(paths) = std::env::var_os(SEARCH_PATH_VAR) {
+            search_path.extend(std::env::split_paths(&paths));
+        }
+        Self::new(search_path)
+    }
+
+    /// Loads the file at `path` as the root of the program
+    pub fn load(mut self, path: &Path) -> Result<ModuleGraph, CompilerError> {
+        let source = read_source(path)?;
+        let name = module_name(path);
+        self.load_module(path.to_path_buf(), name, source)?;
+        Ok(self.finish())
+    }
+
+    /// Loads `source` as the root of the program. Its imports are relative to the current
+    /// directory.
+    pub fn load_source(mut self, source: &str) -> Result<ModuleGraph, CompilerError> {
+        let file = self.sources.add(None, source.to_string());
+        let (ast, lints) = parse(source).map_err(|err| in_file(err, None))?;
+        let imports = self.load_imports(&ast, Path::new("."), None)?;
+        self.modules.push(Module {
+            name: "main".to_string(),
+            file,
+            ast,
+            lints,
+            imports,
+        });
+        Ok(self.finish())
+    }
+
+    fn finish(self) -> ModuleGraph {
+        ModuleGraph {
+            sources: self.sources,
+            modules: self.modules,
+        }
+    }
+
+    /// Loads the module in the file at `path` unless it already was, returning its index.
+    /// `import_path` is the module's dotted path as written in the import.
+    fn load_module(
+        &mut self,
+        path: PathBuf,
+        import_path: String,
+        source: String,
+    ) -> Result<usize, CompilerError> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|err| CompilerError::ReadError {
+                source: err,
+                file: Some(path.display().to_string()),
+            })?;
+        if let Some(index) = self.loaded.get(&canonical) {
+            return Ok(*index);
+        }
+        if let Some(start) = self.loading.iter().position(|(p, _)| *p == canonical) {
+            let mut cycle: Vec<&str> = self.loading[start..]
+                .iter()
+                .map(|(_, import_path)| import_path.as_str())
+                .collect();
+            cycle.push(&import_path);
+            return Err(CompilerError::ImportCycle {
+                cycle: cycle.join(" -> "),
+            });
+        }
+
+        let file = self.sources.add(Some(path.clone()), source);
+        let (ast, lints) = parse(self.sources.source(file))
+            .map_err(|err| in_file(err, self.sources.name(file)))?;
+
+        self.loading.push((canonical.clone(), import_path.clone()));
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let imports = self.load_imports(&ast, dir, self.sources.name(file))?;
+        self.loading.pop();
+
+        // The module is bound by the last segment of its path
+        let name = match import_path.rsplit_once('.') {
+            Some((_, name)) => name.to_string(),
+            None => import_path,
+        };
+        self.modules.push(Module {
+            name,
+            file,
+            ast,
+            lints,
+            imports,
+        });
+        let index = self.modules.len() - 1;
+        self.loaded.insert(canonical, index);
+        Ok(index)
+    }
+
+    /// Loads the modules imported by `ast`, looking in `dir` before the search path
+    fn load_imports(
+        &mut self,
+        ast: &Program,
+        dir: &Path,
+        file: Option<String>,
+    ) -> Result<Vec<usize>, CompilerError> {
+        let mut imports = vec![];
+        for declaration in &ast.declarations {
+            let Declaration::Import(import) = declaration else {
+                continue;
+            };
+
+            let segments: Vec<&str> = import.path.iter().map(|i| i.0.as_str()).collect();
+            let mut relative: PathBuf = segments.iter().collect();
+            relative.set_extension(EXTENSION);
+
+            let found = std::iter::once(dir)
+                .chain(self.search_path.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(&relative))
+                .find(|path| path.is_file());
+            let Some(path) = found else {
+                return Err(CompilerError::ModuleNotFound {
+                    name: segments.join("."),
+                    at: location(&file, import.position),
+                });
+            };
+
+            let source = read_source(&path)?;
+            imports.push(self.load_module(path, segments.join("."), source)?);
+        }
+        Ok(imports)
+    }
+}
+
+/// Name of the module in the file at `path`
+fn module_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Attaches the name of the file being parsed to a parse error
+fn in_file(err: CompilerError, file: Option<String>) -> CompilerError {
+    match err {
+        CompilerError::ParseError { err, .. } => CompilerError::ParseError { err, file },
+        err => err,
+    }
+}
+
+/// `position` prefixed by the file it is in
+pub fn location(file: &Option<String>, position: Position) -> String {
+    match file {
+        Some(file) => format!("{}:{}", file, position),
+        None => position.to_string(),
+    }
+}
@@ -1,34 +1,100 @@
 /*
+The grammar of yapl. What programs written in it do is described in docs/language.md.
+
 program        ->  declaration* EOF
 
-declaration    ->  function | var | statement
-function       ->  FUN IDENTIFIER "(" arg_decl? ")" block
-var            ->  ( "val" | "var" ) IDENT "=" expression
-statement      ->  loop | print | return | if | expression
-loop           ->  "loop" block
+declaration    ->  import | "pub"? ( "const"? function | var | const | struct | enum | trait ) | impl
+                   | statement
+import         ->  "import" IDENT ( "." IDENT )* ( "." "{" IDENT ( "," IDENT )* ","? "}" )?
+function       ->  FUN IDENTIFIER type_params? "(" args_decl? ")" ( "->" type )? block
+struct         ->  "struct" IDENT type_params? "{" ( IDENT ":" type ","? )* "}"
+impl           ->  "impl" ( IDENT "for" )? IDENT "{" function* "}"
+trait          ->  "trait" IDENT "{" ( FUN IDENTIFIER type_params? "(" args_decl? ")" ( "->" type )? )* "}"
+enum           ->  "enum" IDENT type_params? "{" ( IDENT ( "(" type ( "," type )* ")" )? ","? )* "}"
+type_params    ->  "<" type_param ( "," type_param )* ","? ">"
+type_param     ->  IDENT ( ":" IDENT ( "+" IDENT )* )?
+type           ->  ( IDENT ( "<" type ( "," type )* ","? ">" )? | "(" type "," ( type ( "," type )* ","? )? ")"
+                   | "dyn" IDENT ) "?"?
+const          ->  "const" IDENT ( ":" type )? "=" expression
+var            ->  "val" binding "=" expression | "var" binding ( "=" expression )?
+binding        ->  pattern ( ":" type )?
+statement      ->  for | print | return | "yield" expression | "spawn" call | "break" expression?
+                   | "continue" | expression
+for            ->  "for" binding "in" expression block
 print          ->  "print(" expression ")"
-return         ->  "return" expression
-if             -> "if" expression block ("else" block)?
+return         ->  "return" expression?
+
+// Struct literals are not allowed directly in the expression of an `if`, `for` or `match`,
+// where the brace starts the block instead. They can still be used there inside parentheses.
 
 // Misc
 block          ->  "{" declaration* "}"
-args_decl      ->  IDENT ("," IDENT )*
-args           ->  expression ("," expression)*
+args_decl      ->  binding ("," binding )*
+args           ->  expression ("," expression)* ","?
 
 // Expressions
 expression     ->  assignment
-assignment     ->  IDENT "=" expression | logic_or
+assignment     ->  target ( "=" | "+=" | "-=" | "*=" | "/=" | "%=" ) expression | coalesce
+coalesce       ->  logic_or ( "??" coalesce )?
+target         ->  IDENT | call "[" expression "]" | call "." IDENT
 logic_or       ->  logic_and ( "or" logic_and )*
 logic_and      ->  equality ( "and" equality )*
 equality       ->  comparison ( ( "!=" | "==" ) comparison )*
-comparison     ->  term ( ( ">" | ">=" | "<" | "<=" ) term )*
+comparison     ->  bit_or ( ( ">" | ">=" | "<" | "<=" ) bit_or )*
+bit_or         ->  bit_xor ( "|" bit_xor )*
+bit_xor        ->  bit_and ( "^" bit_and )*
+bit_and        ->  shift ( "&" shift )*
+shift          ->  term ( ( "<<" | ">>" ) term )*
 term           ->  factor ( ( "-" | "+" ) factor )*
-factor         ->  unary ( ( "/" | "*" ) unary )*
-unary          ->  ( "!" | "-" ) unary | primary
-primary        ->  INT | FLOAT | STRING | call | "true" | "false" | "(" expression ")"
-call           ->  IDENT"(" args? ")" | IDENT // TODO: Need to implement call args
+factor         ->  unary ( ( "/" | "*" | "%" ) unary )*
+unary          ->  ( "!" | "-" | "~" ) unary | power
+power          ->  call ( "**" unary )?
+call           ->  primary ( "(" args? ")" | "[" index "]" | "." IDENT | "?." IDENT | "." INT | "?" )*
+index          ->  expression | expression? ".." expression?
+primary        ->  INT | FLOAT | DECIMAL | STRING | CHAR | IDENT | "true" | "false" | "none"
+                   | "(" expression ")"
+                   | "(" expression "," args? ")" | "[" args? "]" | map | struct_literal | lambda
+                   | match | if | loop | block
+if             ->  "if" ( "val" binding "=" )? expression block ( "else" ( if | block ) )?
+loop           ->  "loop" block
+struct_literal ->  IDENT "{" ( IDENT ":" expression ( "," IDENT ":" expression )* ","? )? "}"
+map            ->  "{" ( expression ":" expression ( "," expression ":" expression )* ","? )? "}"
+
+// A `{` in an expression starts a map if it is empty or its first expression is followed by a
+// ":", otherwise it starts a block.
+lambda         ->  "fun" "(" args_decl? ")" block | "|" args_decl? "|" expression
+match          ->  "match" expression "{" ( arm ( "," | ";" ) )* "}"
+arm            ->  pattern ( "if" expression )? "=>" ( block | expression )
+pattern        ->  "_" | IDENT | "-"? INT | "-"? FLOAT | "-"? DECIMAL | STRING | CHAR | "true" | "false" | "none"
+                   | "(" pattern ")" | "(" pattern "," ( pattern ( "," pattern )* ","? )? ")"
+                   | IDENT "." IDENT ( "(" pattern ( "," pattern )* ","? ")" )?
+                   | "[" ( pattern "," )* ( pattern | ".." IDENT? )? "]"
+                   | IDENT "{" ( field_pattern "," )* ( field_pattern | ".." )? "}"
+field_pattern  ->  IDENT ( ":" pattern )?
+
+// Operator precedence, loosest to tightest. Everything is left associative except assignment
+// and "**", which are right associative. "**" binds tighter than a unary operator on its left
+// and looser than one on its right, so -2 ** 2 is -(2 ** 2) and 2 ** -1 is 2 ** (-1).
+//
+//   =  +=  -=  *=  /=  %=       assignment
+//   ??                          coalesce
+//   or                          logic_or
+//   and                         logic_and
+//   ==  !=                      equality
+//   >  >=  <  <=                comparison
+//   |                           bit_or
+//   ^                           bit_xor
+//   &                           bit_and
+//   <<  >>                      shift
+//   +  -                        term
+//   *  /  %                     factor
+//   !  -  ~                     unary
+//   **                          power
+//   ()  []  .  ?.  ?            call
  */
 
+use crate::token::Position;
+
 // Declarations
 
 #[derive(Debug)]
@@ -41,18 +107,139 @@ pub enum Declaration {
     Variable(Variable),
     Statement(Statement),
     Function(Function),
+    Struct(Struct),
+    Impl(Impl),
+    Enum(Enum),
+    Trait(Trait),
+    Const(Const),
+    Import(Import),
 }
 
+/// `import a.b` binds the module `a/b.ypl` as `b`, `import a.b.{c, d}` binds its public items
+/// `c` and `d` instead
+#[derive(Debug)]
+pub struct Import {
+    pub path: Vec<Identifier>,
+    pub items: Option<Vec<Identifier>>,
+    pub position: Position,
+}
+
+/// The parameters of a function, e.g. `(name, greeting = "hi", ...rest)`. Parameters with a
+/// default value come after those without one.
 #[derive(Debug)]
 pub struct ArgsDecl {
-    pub args: Vec<Identifier>,
+    pub args: Vec<BindingPattern>,
+    /// The default value of each parameter in `args`, evaluated when a call leaves it out
+    pub defaults: Vec<Option<Expr>>,
+    /// The trailing `...name` parameter, which takes the positional arguments left over as a list
+    pub variadic: Option<BindingPattern>,
+}
+
+/// A type parameter with the traits its types must implement, e.g. `T: Show`
+#[derive(Debug)]
+pub struct TypeParam {
+    pub ident: Identifier,
+    pub bounds: Vec<Identifier>,
 }
 
 #[derive(Debug)]
 pub struct Function {
     pub ident: Identifier,
+    pub type_params: Vec<TypeParam>,
     pub args: Option<ArgsDecl>,
+    pub ret: Option<Type>,
     pub block: Block,
+    /// Whether other modules can import the function
+    pub public: bool,
+    /// Whether the function is a `const fun`, which constants can call
+    pub constant: bool,
+    /// Whether the block contains `yield`, making the function a generator
+    pub generator: bool,
+}
+
+/// A constant, e.g. `const MAX: Int = 100 * 1024`
+#[derive(Debug)]
+pub struct Const {
+    pub ident: Identifier,
+    pub ty: Option<Type>,
+    pub value: Expr,
+    pub public: bool,
+}
+
+#[derive(Debug)]
+pub struct Struct {
+    pub ident: Identifier,
+    pub type_params: Vec<TypeParam>,
+    pub fields: Vec<FieldDecl>,
+    pub public: bool,
+}
+
+#[derive(Debug)]
+pub struct FieldDecl {
+    pub ident: Identifier,
+    pub ty: Type,
+}
+
+/// A type annotation
+#[derive(Debug)]
+pub enum Type {
+    /// A type with its type arguments, which are empty when it has none or they are left out
+    Named(Identifier, Vec<Type>),
+    Tuple(Vec<Type>),
+    /// `dyn Trait`, a value of any struct implementing the trait
+    Dyn(Identifier),
+    /// `T?`, a `T` or `none`
+    Optional(Box<Type>),
+}
+
+impl Type {
+    pub fn is_optional(&self) -> bool {
+        matches!(self, Type::Optional(_))
+    }
+}
+
+/// Methods of a struct. A method is called with the struct it was called on as its first
+/// argument, conventionally named `self`.
+#[derive(Debug)]
+pub struct Impl {
+    /// The trait the methods implement, if any
+    pub trait_ident: Option<Identifier>,
+    pub ident: Identifier,
+    pub methods: Vec<Function>,
+}
+
+/// A trait declaration, e.g. `trait Show { fun show(self) -> String }`
+#[derive(Debug)]
+pub struct Trait {
+    pub ident: Identifier,
+    pub methods: Vec<MethodDecl>,
+    pub public: bool,
+}
+
+/// A method a trait requires, declared without a body
+#[derive(Debug)]
+pub struct MethodDecl {
+    pub ident: Identifier,
+    pub type_params: Vec<TypeParam>,
+    pub args: Option<ArgsDecl>,
+    pub ret: Option<Type>,
+}
+
+/// An enum declaration, e.g. `enum Shape { Circle(Float), Rect(Float, Float), Empty }`
+#[derive(Debug)]
+pub struct Enum {
+    pub ident: Identifier,
+    pub type_params: Vec<TypeParam>,
+    pub variants: Vec<VariantDecl>,
+    pub public: bool,
+}
+
+/// A variant of an enum along with the types of its values, which are empty for a variant
+/// without any
+#[derive(Debug)]
+pub struct VariantDecl {
+    pub ident: Identifier,
+    pub fields: Vec<Type>,
 }
 
 #[derive(Debug)]
@@ -64,17 +251,49 @@ pub enum VariableType {
 #[derive(Debug)]
 pub struct Variable {
     pub v_type: VariableType,
-    pub ident: Identifier,
-    pub value: Expr,
+    pub target: BindingPattern,
+    /// Only a `var` can leave out its value, which is then `none`
+    pub value: Option<Expr>,
+    pub public: bool,
+}
+
+/// A pattern that declares variables, e.g. `(q, r)` in `val (q, r) = divmod(7, 2)`
+#[derive(Debug)]
+pub struct BindingPattern {
+    pub pattern: Pattern,
+    pub ty: Option<Type>,
+    /// Where a value that doesn't match the pattern is reported
+    pub position: Position,
+}
+
+impl BindingPattern {
+    /// The name bound when the pattern is a plain name
+    pub fn ident(&self) -> Option<&Identifier> {
+        match &self.pattern {
+            Pattern::Binding(ident) => Some(ident),
+            _ => None,
+        }
+    }
+
+    /// Whether the binding is declared with an optional type
+    pub fn is_optional(&self) -> bool {
+        self.ty.as_ref().is_some_and(Type::is_optional)
+    }
 }
 
 #[derive(Debug)]
 pub enum Statement {
     Expression(Expr),
-    Loop(Loop),
+    For(Box<For>),
     Print(Print),
     Return(Return),
-    If(If),
+    /// Hands a value to the caller stepping a generator
+    Yield(Expr),
+    /// Starts a task running the call
+    Spawn(Expr),
+    /// The value is only allowed when breaking out of a `loop`
+    Break(Option<Expr>),
+    Continue,
 }
 
 #[derive(Debug)]
@@ -82,6 +301,13 @@ pub struct Loop {
     pub block: Block,
 }
 
+#[derive(Debug)]
+pub struct For {
+    pub target: BindingPattern,
+    pub expr: Expr,
+    pub block: Block,
+}
+
 #[derive(Debug)]
 pub struct Print {
     pub expr: Expr,
@@ -89,14 +315,23 @@ pub struct Print {
 
 #[derive(Debug)]
 pub struct Return {
-    pub expr: Expr,
+    pub expr: Option<Expr>,
 }
 
+/// An if expression, whose value is unit when the condition is false and there is no `else`
 #[derive(Debug)]
 pub struct If {
+    /// For `if val x = expr`, which runs the block with `x` bound when `expr` is not `none`
+    pub binding: Option<BindingPattern>,
     pub expr: Expr,
     pub block: Block,
-    pub else_block: Option<Block>,
+    pub else_branch: Option<Else>,
+}
+
+#[derive(Debug)]
+pub enum Else {
+    Block(Block),
+    If(Box<If>),
 }
 
 // Misc
@@ -106,6 +341,20 @@ pub struct Block {
     pub declarations: Vec<Declaration>,
 }
 
+#[derive(Debug)]
+pub struct Args {
+    pub args: Vec<Expr>,
+    /// Arguments passed by the name of their parameter, `name: value`, which only calls have.
+    /// They follow the positional ones.
+    pub named: Vec<NamedArg>,
+}
+
+#[derive(Debug)]
+pub struct NamedArg {
+    pub ident: Identifier,
+    pub value: Expr,
+}
+
 // Expressions
 
 #[derive(Debug)]
@@ -113,18 +362,140 @@ pub enum Expr {
     Assignment(Assignment),
 }
 
+impl Expr {
+    /// The primary this expression consists of, if it is nothing more than a primary
+    pub fn as_primary(&self) -> Option<&Primary> {
+        match self.as_call()? {
+            Call {
+                left: CallLeft::Primary(primary),
+                right: None,
+            } => Some(primary),
+            _ => None,
+        }
+    }
+
+    /// The call this expression consists of, if it has no operators
+    pub fn as_call(&self) -> Option<&Call> {
+        let term = self.as_term()?;
+        let (TermLeft::Factor(factor), None) = (&term.left, &term.right) else {
+            return None;
+        };
+        let (FactorLeft::Unary(unary), None) = (&factor.left, &factor.right) else {
+            return None;
+        };
+        let (None, UnaryRight::Power(power)) = (&unary.op, unary.right.as_ref()) else {
+            return None;
+        };
+        match &power.right {
+            None => Some(&power.left),
+            Some(_) => None,
+        }
+    }
+
+    /// The term this expression consists of, if it has no operators looser than `+` and `-`
+    pub fn as_term(&self) -> Option<&Term> {
+        let Expr::Assignment(Assignment::LogicOr(logic_or)) = self else {
+            return None;
+        };
+        let (LogicOrLeft::LogicAnd(logic_and), None) = (&logic_or.left, &logic_or.right) else {
+            return None;
+        };
+        let (LogicAndLeft::Equality(equality), None) = (&logic_and.left, &logic_and.right) else {
+            return None;
+        };
+        let (EqualityLeft::Comparison(comparison), None) = (&equality.left, &equality.right) else {
+            return None;
+        };
+        let (ComparisonLeft::BitOr(bit_or), None) = (&comparison.left, &comparison.right) else {
+            return None;
+        };
+        bit_or.as_term()
+    }
+}
+
+impl BitOr {
+    /// The term this operand consists of, if it has no bitwise or shift operators
+    pub fn as_term(&self) -> Option<&Term> {
+        let (BitOrLeft::BitXor(bit_xor), None) = (&self.left, &self.right) else {
+            return None;
+        };
+        let (BitXorLeft::BitAnd(bit_and), None) = (&bit_xor.left, &bit_xor.right) else {
+            return None;
+        };
+        let (BitAndLeft::Shift(shift), None) = (&bit_and.left, &bit_and.right) else {
+            return None;
+        };
+        match (&shift.left, &shift.right) {
+            (ShiftLeft::Term(term), None) => Some(term),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AssignTarget {
+    Identifier(Identifier),
+    Index(IndexTarget),
+    Field(FieldTarget),
+}
+
+/// Assignment to a field, e.g. `p.x = 1.0`
+#[derive(Debug)]
+pub struct FieldTarget {
+    pub target: CallLeft,
+    pub field: Identifier,
+    pub position: Position,
+}
+
+/// Assignment to an element, e.g. `xs[i] = v`
+#[derive(Debug)]
+pub struct IndexTarget {
+    pub target: CallLeft,
+    pub index: Box<Expr>,
+    pub position: Position,
+}
+
 #[derive(Debug)]
 pub struct AssignedVal {
-    pub ident: Identifier,
+    pub target: AssignTarget,
     pub expr: Box<Expr>,
 }
 
+#[derive(Debug)]
+pub enum AssignOp {
+    Plus,
+    Minus,
+    Mult,
+    Div,
+    Mod,
+}
+
+/// Assignment that combines the target's current value with the expression, e.g. `x += 1`
+#[derive(Debug)]
+pub struct CompoundAssignment {
+    pub target: AssignTarget,
+    pub op: AssignOp,
+    pub expr: Box<Expr>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
 #[derive(Debug)]
 pub enum Assignment {
     AssignedVal(AssignedVal),
+    Compound(CompoundAssignment),
+    Coalesce(Coalesce),
     LogicOr(LogicOr),
 }
 
+/// `value ?? default`, which evaluates `default` only when `value` is `none`. The default is a
+/// `Coalesce` which makes `??` right associative.
+#[derive(Debug)]
+pub struct Coalesce {
+    pub left: LogicOr,
+    pub right: Option<Box<Coalesce>>,
+}
+
 #[derive(Debug)]
 pub enum LogicOrLeft {
     LogicAnd(LogicAnd),
@@ -134,7 +505,7 @@ pub enum LogicOrLeft {
 #[derive(Debug)]
 pub struct LogicOr {
     pub left: LogicOrLeft,
-    pub right: Option<LogicAnd>,
+    pub right: Option<Box<LogicAnd>>,
 }
 
 #[derive(Debug)]
@@ -146,7 +517,7 @@ pub enum LogicAndLeft {
 #[derive(Debug)]
 pub struct LogicAnd {
     pub left: LogicAndLeft,
-    pub right: Option<Equality>,
+    pub right: Option<Box<Equality>>,
 }
 
 #[derive(Debug)]
@@ -158,12 +529,14 @@ pub enum EqualityOp {
 #[derive(Debug)]
 pub struct EqualityRight {
     pub op: EqualityOp,
-    pub right: Comparison,
+    pub right: Box<Comparison>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
 }
 
 #[derive(Debug)]
 pub enum EqualityLeft {
-    Comparison(Comparison),
+    Comparison(Box<Comparison>),
     Equality(Box<Equality>),
 }
 
@@ -184,12 +557,14 @@ pub enum ComparisonOp {
 #[derive(Debug)]
 pub struct ComparisonRight {
     pub op: ComparisonOp,
-    pub right: Term,
+    pub right: Box<BitOr>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
 }
 
 #[derive(Debug)]
 pub enum ComparisonLeft {
-    Term(Term),
+    BitOr(BitOr),
     Comparison(Box<Comparison>),
 }
 
@@ -199,6 +574,89 @@ pub struct Comparison {
     pub right: Option<ComparisonRight>,
 }
 
+#[derive(Debug)]
+pub enum BitOrLeft {
+    BitXor(BitXor),
+    BitOr(Box<BitOr>),
+}
+
+#[derive(Debug)]
+pub struct BitOrRight {
+    pub right: BitXor,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct BitOr {
+    pub left: BitOrLeft,
+    pub right: Option<Box<BitOrRight>>,
+}
+
+#[derive(Debug)]
+pub enum BitXorLeft {
+    BitAnd(BitAnd),
+    BitXor(Box<BitXor>),
+}
+
+#[derive(Debug)]
+pub struct BitXorRight {
+    pub right: BitAnd,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct BitXor {
+    pub left: BitXorLeft,
+    pub right: Option<Box<BitXorRight>>,
+}
+
+#[derive(Debug)]
+pub enum BitAndLeft {
+    Shift(Shift),
+    BitAnd(Box<BitAnd>),
+}
+
+#[derive(Debug)]
+pub struct BitAndRight {
+    pub right: Shift,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct BitAnd {
+    pub left: BitAndLeft,
+    pub right: Option<Box<BitAndRight>>,
+}
+
+#[derive(Debug)]
+pub enum ShiftOp {
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
+pub struct ShiftRight {
+    pub op: ShiftOp,
+    pub right: Box<Term>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub enum ShiftLeft {
+    Term(Term),
+    Shift(Box<Shift>),
+}
+
+#[derive(Debug)]
+pub struct Shift {
+    pub left: ShiftLeft,
+    pub right: Option<ShiftRight>,
+}
+
 #[derive(Debug)]
 pub enum TermOp {
     Minus,
@@ -208,7 +666,9 @@ pub enum TermOp {
 #[derive(Debug)]
 pub struct TermRight {
     pub op: TermOp,
-    pub right: Factor,
+    pub right: Box<Factor>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
 }
 
 #[derive(Debug)]
@@ -227,12 +687,15 @@ pub struct Term {
 pub enum FactorOp {
     Div,
     Mult,
+    Mod,
 }
 
 #[derive(Debug)]
 pub struct FactorRight {
     pub op: FactorOp,
-    pub right: Unary,
+    pub right: Box<Unary>,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
 }
 
 #[derive(Debug)]
@@ -251,30 +714,232 @@ pub struct Factor {
 pub enum UnaryOp {
     Not,
     Minus,
+    BitNot,
 }
 
 #[derive(Debug)]
 pub enum UnaryRight {
     Unary(Unary),
-    Primary(Primary),
+    Power(Power),
 }
 
 #[derive(Debug)]
 pub struct Unary {
     pub op: Option<UnaryOp>,
     pub right: Box<UnaryRight>,
+    /// Position of the operator, or of the operand when there is none
+    pub position: Position,
+}
+
+/// Exponentiation, the exponent is a `Unary` which makes `**` right associative
+#[derive(Debug)]
+pub struct Power {
+    pub left: Call,
+    pub right: Option<Box<PowerRight>>,
+}
+
+#[derive(Debug)]
+pub struct PowerRight {
+    pub right: Unary,
+    /// Position of the operator, where runtime errors for the operation are reported
+    pub position: Position,
+}
+
+/// The postfix part of a call. `position` is the position of the opening delimiter, which is
+/// where runtime errors for the operation are reported.
+#[derive(Debug)]
+pub enum CallRight {
+    Args {
+        args: Args,
+        position: Position,
+    },
+    Index {
+        index: Box<Expr>,
+        position: Position,
+    },
+    /// `xs[start..end]`, either bound may be left out
+    Slice {
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        position: Position,
+    },
+    Field {
+        ident: Identifier,
+        position: Position,
+    },
+    /// `value?.field`, which is `none` when the value is. A call of it is a method call that is
+    /// skipped when the value is `none`.
+    SafeField {
+        ident: Identifier,
+        position: Position,
+    },
+    /// `tuple.0`
+    Element {
+        index: u32,
+        position: Position,
+    },
+    /// `result?`, the value of an `ok` or an early return of an `err`
+    Try {
+        position: Position,
+    },
+}
+
+#[derive(Debug)]
+pub enum CallLeft {
+    Primary(Primary),
+    Call(Box<Call>),
+}
+
+#[derive(Debug)]
+pub struct Call {
+    pub left: CallLeft,
+    pub right: Option<CallRight>,
 }
 
 #[derive(Debug)]
 pub enum Primary {
     Int(String),
     Float(String),
+    Decimal(String),
     String(String),
+    Char(char),
     Identifier(Identifier),
     True,
     False,
-    Grouping(Expr),
+    None(Position),
+    Grouping(Box<Expr>),
+    List(Args),
+    Map(MapLiteral),
+    Struct(StructLiteral),
+    Lambda(Box<Lambda>),
+    /// `(a, b)`, a single element tuple needs a trailing comma
+    Tuple(Args),
+    Match(Box<Match>),
+    If(Box<If>),
+    Loop(Box<Loop>),
+    Block(Block),
+}
+
+/// Construction of a struct, e.g. `Point { x: 1.0, y: 2.0 }`
+#[derive(Debug)]
+pub struct StructLiteral {
+    pub ident: Identifier,
+    pub fields: Vec<FieldInit>,
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct FieldInit {
+    pub ident: Identifier,
+    pub value: Expr,
+}
+
+/// A map literal
+#[derive(Debug)]
+pub struct MapLiteral {
+    pub entries: Vec<MapEntry>,
+    /// Position of the opening brace, where invalid keys are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct MapEntry {
+    pub key: Expr,
+    pub value: Expr,
+}
+
+/// An anonymous function, `fun(x) { x * 2 }` or the short form `|x| x * 2`
+#[derive(Debug)]
+pub struct Lambda {
+    pub args: Option<ArgsDecl>,
+    pub body: Body,
+    /// Whether the body contains `yield`, making the lambda a generator
+    pub generator: bool,
+}
+
+/// Body of a lambda or match arm, whose value is the value of the expression or the trailing
+/// expression statement of the block
+#[derive(Debug)]
+pub enum Body {
+    Block(Block),
+    Expr(Expr),
+}
+
+/// `match value { pattern => body, ... }`, which evaluates to the body of the first arm whose
+/// pattern matches and whose guard, if any, is true
+#[derive(Debug)]
+pub struct Match {
+    pub expr: Expr,
+    pub arms: Vec<MatchArm>,
+    /// Position of the `match` keyword, where non-exhaustive matches are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Body,
+    /// Position of the start of the pattern, where unreachable arms are reported
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub enum Pattern {
+    /// `_`, matches anything
+    Wildcard,
+    /// Matches anything and binds it to a new `val`
+    Binding(Identifier),
+    Literal(LiteralPattern),
+    Tuple(Vec<Pattern>),
+    Variant(VariantPattern),
+    List(ListPattern),
+    Struct(StructPattern),
+}
+
+#[derive(Debug)]
+pub enum LiteralPattern {
+    Int(String),
+    Float(String),
+    Decimal(String),
+    String(String),
+    Char(char),
+    Bool(bool),
+    None,
+}
+
+/// `[first, second, ..rest]`, the rest is a list of the remaining elements
+#[derive(Debug)]
+pub struct ListPattern {
+    pub elements: Vec<Pattern>,
+    /// `Some` if the list may be longer than `elements`, holding the name bound to the rest
+    pub rest: Option<Option<Identifier>>,
+}
+
+/// `Point { x, y: py }`, with a trailing `..` if some fields are left out
+#[derive(Debug)]
+pub struct StructPattern {
+    pub ident: Identifier,
+    pub fields: Vec<FieldPattern>,
+    pub rest: bool,
+}
+
+/// A field of a struct pattern, `x` is short for `x: x`
+#[derive(Debug)]
+pub struct FieldPattern {
+    pub ident: Identifier,
+    pub pattern: Pattern,
+}
+
+/// `Shape.Circle(r)`, or `Shape.Empty` for a variant without values
+#[derive(Debug)]
+pub struct VariantPattern {
+    pub ident: Identifier,
+    pub variant: Identifier,
+    pub args: Vec<Pattern>,
+    pub position: Position,
 }
 
+/// A name and where it appears in the source
 #[derive(Debug)]
-pub struct Identifier(pub String);
+pub struct Identifier(pub String, pub Position);
@@ -1,23 +1,16 @@
-mod ast;
+pub mod ast;
 
 use snafu::prelude::*;
 use std::collections::VecDeque;
 
-pub use ast::*;
+use ast::*;
 
+use crate::lexer::{unescape, LexError, Lexer};
+use crate::symbol::Symbol;
+use crate::token::Span;
 use crate::token::TokenType::Identifier;
 use crate::token::TokenType::*;
-pub use crate::token::{Token, TokenType};
-
-/*
-expression     → equality ;
-equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
-term           → factor ( ( "-" | "+" ) factor )* ;
-factor         → unary ( ( "/" | "*" ) unary )* ;
-unary          → ( "!" | "-" ) unary | primary ;
-primary        → INT | FLOAT | STRING | "true" | "false" | "(" expression ")" ;
- */
+pub use crate::token::{Position, Token, TokenType};
 
 #[derive(Debug, Snafu)]
 pub enum ParseError {
@@ -26,18 +19,147 @@ pub enum ParseError {
 
     #[snafu(display("parse error - unexpected end of file"))]
     EndOfFile,
+
+    #[snafu(display("parse error - {err} at {line}:{char}"))]
+    IllegalToken {
+        err: LexError,
+        line: usize,
+        char: usize,
+    },
+
+    #[snafu(display("parse error - invalid assignment target at {position}"))]
+    InvalidAssignmentTarget { position: Position },
+}
+
+/// Converts the left hand side of an assignment into its target. Only identifiers and index
+/// expressions can be assigned to.
+fn assign_target(logic_or: LogicOr) -> Option<AssignTarget> {
+    let LogicOr {
+        left: LogicOrLeft::LogicAnd(logic_and),
+        right: None,
+    } = logic_or
+    else {
+        return None;
+    };
+    let LogicAnd {
+        left: LogicAndLeft::Equality(equality),
+        right: None,
+    } = logic_and
+    else {
+        return None;
+    };
+    let Equality {
+        left: EqualityLeft::Comparison(comparison),
+        right: None,
+    } = equality
+    else {
+        return None;
+    };
+    let Comparison {
+        left: ComparisonLeft::BitOr(bit_or),
+        right: None,
+    } = *comparison
+    else {
+        return None;
+    };
+    let BitOr {
+        left: BitOrLeft::BitXor(bit_xor),
+        right: None,
+    } = bit_or
+    else {
+        return None;
+    };
+    let BitXor {
+        left: BitXorLeft::BitAnd(bit_and),
+        right: None,
+    } = bit_xor
+    else {
+        return None;
+    };
+    let BitAnd {
+        left: BitAndLeft::Shift(shift),
+        right: None,
+    } = bit_and
+    else {
+        return None;
+    };
+    let Shift {
+        left: ShiftLeft::Term(term),
+        right: None,
+    } = shift
+    else {
+        return None;
+    };
+    let Term {
+        left: TermLeft::Factor(factor),
+        right: None,
+    } = term
+    else {
+        return None;
+    };
+    let Factor {
+        left: FactorLeft::Unary(unary),
+        right: None,
+    } = factor
+    else {
+        return None;
+    };
+    let Unary {
+        op: None, right, ..
+    } = unary
+    else {
+        return None;
+    };
+    let UnaryRight::Power(Power {
+        left: call,
+        right: None,
+    }) = *right
+    else {
+        return None;
+    };
+
+    // The last postfix operation of a call is nested in its left side
+    match call.left {
+        CallLeft::Primary(Primary::Identifier(ident)) => Some(AssignTarget::Identifier(ident)),
+        CallLeft::Call(call) => match *call {
+            Call {
+                left,
+                right: Some(CallRight::Index { index, position }),
+            } => Some(AssignTarget::Index(IndexTarget {
+                target: left,
+                index,
+                position,
+            })),
+            Call {
+                left,
+                right: Some(CallRight::Field { ident, position }),
+            } => Some(AssignTarget::Field(FieldTarget {
+                target: left,
+                field: ident,
+                position,
+            })),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
-pub struct Parser<T: Iterator<Item = Token>> {
-    input_iter: T,
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
     held: VecDeque<Token>,
+    // Set while parsing the expression of an `if` or `for`, where `IDENT {` starts the block
+    no_struct: bool,
+    // Set once a `yield` is parsed in the body of the innermost function
+    yields: bool,
 }
 
-impl<T: Iterator<Item = Token>> Parser<T> {
-    pub fn new(iter: T) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
         Self {
-            input_iter: iter,
+            lexer,
             held: VecDeque::new(),
+            no_struct: false,
+            yields: false,
         }
     }
 
@@ -47,14 +169,92 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             return Some(token);
         }
 
-        self.input_iter.next()
+        self.lexer.next()
     }
 
     /// Stores a token for a following call to next
     ///
-    /// This operates in a FIFO manner when called multiple times.
+    /// This operates in a LIFO manner when called multiple times, so tokens must be stored in the
+    /// reverse of the order they were read.
     fn store(&mut self, token: Token) {
-        self.held.push_back(token);
+        self.held.push_front(token);
+    }
+
+    /// The identifier for `sym`. The AST holds names as strings rather than symbols because each
+    /// module is lexed with its own `Interner`, so symbols of different modules can't be
+    /// compared, while codegen resolves names across modules and hands them to the VM as strings.
+    fn ident(&self, sym: Symbol, position: Position) -> ast::Identifier {
+        ast::Identifier(self.lexer.resolve(sym).to_string(), position)
+    }
+
+    /// Source text of a literal token, with quotes removed and escapes decoded
+    fn literal(&self, token: &Token) -> std::string::String {
+        let text = self.lexer.text(token);
+        match token.token_type {
+            Float => text.trim_end_matches('f').to_string(),
+            Decimal => text.trim_end_matches('d').to_string(),
+            String | Char => {
+                let mut literal = std::string::String::new();
+                // The lexer has already rejected literals with invalid escapes
+                let _ = unescape(&text[1..text.len() - 1], |c| literal.push(c));
+                literal
+            }
+            _ => text.to_string(),
+        }
+    }
+
+    /// Reads the next token, which must be of type `expected`
+    fn expect(&mut self, expected: TokenType) -> Result<Token, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type != expected {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+        Ok(token)
+    }
+
+    fn identifier(&mut self) -> Result<ast::Identifier, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        match token.token_type {
+            Identifier(sym) => Ok(self.ident(sym, token.position())),
+            _ => Err(ParseError::UnexpectedToken { token }),
+        }
+    }
+
+    /// Next token that isn't a semicolon, for places where newlines are insignificant
+    fn next_skipping_semicolons(&mut self) -> Result<Token, ParseError> {
+        loop {
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            if !matches!(token.token_type, Semicolon) {
+                return Ok(token);
+            }
+        }
+    }
+
+    /// Runs `rule` with struct literals allowed or not, restoring the previous setting after
+    fn structs<T>(
+        &mut self,
+        allowed: bool,
+        rule: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let saved = std::mem::replace(&mut self.no_struct, !allowed);
+        let result = rule(self);
+        self.no_struct = saved;
+        result
+    }
+
+    /// Runs `rule` on the body of a function, also returning whether the body contains `yield`
+    fn body<T>(
+        &mut self,
+        rule: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<(T, bool), ParseError> {
+        let saved = std::mem::replace(&mut self.yields, false);
+        let result = rule(self);
+        let yields = std::mem::replace(&mut self.yields, saved);
+        Ok((result?, yields))
+    }
+
+    pub fn lexer(&self) -> &Lexer<'a> {
+        &self.lexer
     }
 
     /// Parses the input and returns the resulting ast.
@@ -70,13 +270,13 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             declarations: vec![],
         };
 
-        loop {
-            if let Some(token) = self.next() {
-                self.store(token);
-                program.declarations.push(self.declaration()?)
-            } else {
-                break;
+        while let Some(token) = self.next() {
+            if matches!(token.token_type, Semicolon) {
+                continue;
             }
+
+            self.store(token);
+            program.declarations.push(self.declaration()?)
         }
 
         Ok(program)
@@ -89,13 +289,63 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
         Ok(match token.token_type {
             Fun => {
+                // `fun` followed by a parameter list is a lambda expression, not a declaration
+                let next = self.next().ok_or(ParseError::EndOfFile)?;
+                let is_lambda = matches!(next.token_type, LeftParen);
+                self.store(next);
                 self.store(token);
-                Declaration::Function(self.function()?)
+
+                if is_lambda {
+                    Declaration::Statement(self.statement()?)
+                } else {
+                    Declaration::Function(self.function()?)
+                }
             }
             Val | Var => {
                 self.store(token);
                 Declaration::Variable(self.variable()?)
             }
+            Struct => Declaration::Struct(self.struct_decl()?),
+            Impl => Declaration::Impl(self.impl_decl()?),
+            Enum => Declaration::Enum(self.enum_decl()?),
+            Trait => Declaration::Trait(self.trait_decl()?),
+            Const => {
+                let next = self.next().ok_or(ParseError::EndOfFile)?;
+                let is_function = matches!(next.token_type, Fun);
+                self.store(next);
+
+                if is_function {
+                    let mut function = self.function()?;
+                    function.constant = true;
+                    Declaration::Function(function)
+                } else {
+                    Declaration::Const(self.const_decl()?)
+                }
+            }
+            Import => Declaration::Import(self.import(token.position())?),
+            Pub => {
+                let next = self.next().ok_or(ParseError::EndOfFile)?;
+                if !matches!(
+                    next.token_type,
+                    Fun | Val | Var | Const | Struct | Enum | Trait
+                ) {
+                    return Err(ParseError::UnexpectedToken { token: next });
+                }
+                self.store(next);
+
+                let mut declaration = self.declaration()?;
+                match &mut declaration {
+                    Declaration::Function(Function { public, .. })
+                    | Declaration::Variable(Variable { public, .. })
+                    | Declaration::Struct(ast::Struct { public, .. })
+                    | Declaration::Enum(ast::Enum { public, .. })
+                    | Declaration::Trait(ast::Trait { public, .. })
+                    | Declaration::Const(ast::Const { public, .. }) => *public = true,
+                    // `pub fun(...)` is a lambda
+                    _ => return Err(ParseError::UnexpectedToken { token: next }),
+                }
+                declaration
+            }
             _ => {
                 self.store(token);
                 Declaration::Statement(self.statement()?)
@@ -103,6 +353,56 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         })
     }
 
+    /// Parses an import after the `import` keyword
+    fn import(&mut self, position: Position) -> Result<ast::Import, ParseError> {
+        let mut path = vec![self.identifier()?];
+        let mut items = None;
+
+        while let Some(token) = self.next() {
+            if !matches!(token.token_type, Dot) {
+                self.store(token);
+                break;
+            }
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                Identifier(sym) => path.push(self.ident(sym, token.position())),
+                LeftBrace => {
+                    items = Some(self.import_items()?);
+                    break;
+                }
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(ast::Import {
+            path,
+            items,
+            position,
+        })
+    }
+
+    /// Parses the names imported from a module after the opening `{`
+    fn import_items(&mut self) -> Result<Vec<ast::Identifier>, ParseError> {
+        let mut items = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                RightBrace if !items.is_empty() => break,
+                Identifier(sym) => items.push(self.ident(sym, token.position())),
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+        Ok(items)
+    }
+
     fn variable(&mut self) -> Result<Variable, ParseError> {
         let token = self.next().ok_or(ParseError::EndOfFile)?;
         let v_type = match token.token_type {
@@ -111,20 +411,213 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             _ => return Err(ParseError::UnexpectedToken { token }),
         };
 
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        let ident = match token.token_type {
-            Identifier(s) => ast::Identifier(s),
-            _ => return Err(ParseError::UnexpectedToken { token }),
+        let target = self.binding_pattern()?;
+
+        let value = match self.next() {
+            Some(token) if matches!(token.token_type, Equal) => Some(self.expr()?),
+            Some(token) if matches!(v_type, VariableType::Var) => {
+                self.store(token);
+                None
+            }
+            None if matches!(v_type, VariableType::Var) => None,
+            Some(token) => return Err(ParseError::UnexpectedToken { token }),
+            None => return Err(ParseError::EndOfFile),
         };
 
         Ok(Variable {
             v_type,
+            target,
+            value,
+            public: false,
+        })
+    }
+
+    fn binding_pattern(&mut self) -> Result<BindingPattern, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        let position = token.position();
+        self.store(token);
+        let pattern = self.pattern()?;
+
+        let ty = match self.next() {
+            Some(token) if matches!(token.token_type, Colon) => Some(self.type_annotation()?),
+            Some(token) => {
+                self.store(token);
+                None
+            }
+            None => None,
+        };
+
+        Ok(BindingPattern {
+            pattern,
+            ty,
+            position,
+        })
+    }
+
+    fn type_annotation(&mut self) -> Result<Type, ParseError> {
+        let ty = self.plain_type()?;
+        match self.next() {
+            Some(token) if matches!(token.token_type, Question) => Ok(Type::Optional(Box::new(ty))),
+            Some(token) => {
+                self.store(token);
+                Ok(ty)
+            }
+            None => Ok(ty),
+        }
+    }
+
+    /// Parses a type that isn't optional
+    fn plain_type(&mut self) -> Result<Type, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        match token.token_type {
+            Identifier(sym) => {
+                let ident = self.ident(sym, token.position());
+                let mut args = vec![];
+                match self.next() {
+                    Some(token) if matches!(token.token_type, Less) => loop {
+                        args.push(self.type_annotation()?);
+                        let token = self.next().ok_or(ParseError::EndOfFile)?;
+                        match token.token_type {
+                            Comma => {}
+                            Greater => break,
+                            _ => {
+                                self.store(self.split_greater(token)?);
+                                break;
+                            }
+                        }
+                    },
+                    Some(token) => self.store(token),
+                    None => {}
+                }
+                Ok(Type::Named(ident, args))
+            }
+            Dyn => Ok(Type::Dyn(self.identifier()?)),
+            LeftParen => {
+                let mut types = vec![self.type_annotation()?];
+                self.expect(Comma)?;
+                loop {
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    if matches!(token.token_type, RightParen) {
+                        break;
+                    }
+                    self.store(token);
+                    types.push(self.type_annotation()?);
+
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    match token.token_type {
+                        Comma => {}
+                        RightParen => break,
+                        _ => return Err(ParseError::UnexpectedToken { token }),
+                    }
+                }
+                Ok(Type::Tuple(types))
+            }
+            _ => Err(ParseError::UnexpectedToken { token }),
+        }
+    }
+
+    /// Splits a `>>` closing two lists of type arguments, returning the second `>`
+    fn split_greater(&self, token: Token) -> Result<Token, ParseError> {
+        if !matches!(token.token_type, GreaterGreater) {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+        Ok(Token {
+            token_type: Greater,
+            span: Span::new(token.span.start + 1, token.span.end),
+            char: token.char + 1,
+            line: token.line,
+        })
+    }
+
+    /// Parses the type parameters of a declaration, if it has any
+    fn type_params(&mut self) -> Result<Vec<TypeParam>, ParseError> {
+        let mut params = vec![];
+        match self.next() {
+            Some(token) if matches!(token.token_type, Less) => {}
+            Some(token) => {
+                self.store(token);
+                return Ok(params);
+            }
+            None => return Ok(params),
+        }
+
+        loop {
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                Greater if !params.is_empty() => break,
+                Identifier(sym) => {
+                    let ident = self.ident(sym, token.position());
+                    params.push(TypeParam {
+                        ident,
+                        bounds: vec![],
+                    });
+                }
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+
+            let mut token = self.next().ok_or(ParseError::EndOfFile)?;
+            if matches!(token.token_type, Colon) {
+                // This unwrap is safe because a parameter was pushed above
+                let bounds = &mut params.last_mut().unwrap().bounds;
+                loop {
+                    bounds.push(self.identifier()?);
+                    token = self.next().ok_or(ParseError::EndOfFile)?;
+                    if !matches!(token.token_type, Plus) {
+                        break;
+                    }
+                }
+            }
+            match token.token_type {
+                Comma => {}
+                Greater => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+        Ok(params)
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let MethodDecl {
+            ident,
+            type_params,
+            args,
+            ret,
+        } = self.method_decl()?;
+        let (block, generator) = self.body(Self::block)?;
+        Ok(Function {
+            ident,
+            type_params,
+            args,
+            ret,
+            block,
+            public: false,
+            constant: false,
+            generator,
+        })
+    }
+
+    /// Parses a constant after the `const` keyword
+    fn const_decl(&mut self) -> Result<ast::Const, ParseError> {
+        let ident = self.identifier()?;
+        let ty = match self.next().ok_or(ParseError::EndOfFile)? {
+            token if matches!(token.token_type, Colon) => Some(self.type_annotation()?),
+            token => {
+                self.store(token);
+                None
+            }
+        };
+        self.expect(Equal)?;
+
+        Ok(ast::Const {
             ident,
+            ty,
             value: self.expr()?,
+            public: false,
         })
     }
 
-    fn function(&mut self) -> Result<Function, ParseError> {
+    /// Parses a function up to its body
+    fn method_decl(&mut self) -> Result<MethodDecl, ParseError> {
         let token = self.next().ok_or(ParseError::EndOfFile)?;
         if !matches!(token.token_type, Fun) {
             return Err(ParseError::UnexpectedToken { token });
@@ -132,34 +625,166 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
         let token = self.next().ok_or(ParseError::EndOfFile)?;
         let ident = match token.token_type {
-            Identifier(s) => ast::Identifier(s),
+            Identifier(sym) => self.ident(sym, token.position()),
             _ => return Err(ParseError::UnexpectedToken { token }),
         };
 
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        if !matches!(token.token_type, LeftParen) {
-            return Err(ParseError::UnexpectedToken { token });
+        let type_params = self.type_params()?;
+        let args = self.params(LeftParen, RightParen)?;
+        let ret = match self.next().ok_or(ParseError::EndOfFile)? {
+            token if matches!(token.token_type, Arrow) => Some(self.type_annotation()?),
+            token => {
+                self.store(token);
+                None
+            }
+        };
+
+        Ok(MethodDecl {
+            ident,
+            type_params,
+            args,
+            ret,
+        })
+    }
+
+    /// Parses a struct declaration after the `struct` keyword
+    fn struct_decl(&mut self) -> Result<ast::Struct, ParseError> {
+        let ident = self.identifier()?;
+        let type_params = self.type_params()?;
+        self.expect(LeftBrace)?;
+
+        let mut fields = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                RightBrace => break,
+                Identifier(sym) => {
+                    let ident = self.ident(sym, token.position());
+                    self.expect(Colon)?;
+                    let ty = self.type_annotation()?;
+                    fields.push(FieldDecl { ident, ty });
+                }
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
         }
 
+        Ok(ast::Struct {
+            ident,
+            type_params,
+            fields,
+            public: false,
+        })
+    }
+
+    /// Parses an impl block after the `impl` keyword
+    fn impl_decl(&mut self) -> Result<ast::Impl, ParseError> {
+        let mut ident = self.identifier()?;
+        let mut trait_ident = None;
         let token = self.next().ok_or(ParseError::EndOfFile)?;
-        let args = match token.token_type {
-            RightParen => None,
-            _ => {
-                self.store(token);
-                let args = self.args_decl()?;
+        if matches!(token.token_type, For) {
+            trait_ident = Some(ident);
+            ident = self.identifier()?;
+        } else {
+            self.store(token);
+        }
+        self.expect(LeftBrace)?;
 
-                let token = self.next().ok_or(ParseError::EndOfFile)?;
-                if !matches!(token.token_type, RightParen) {
-                    return Err(ParseError::UnexpectedToken { token });
+        let mut methods = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                RightBrace => break,
+                Fun => {
+                    self.store(token);
+                    methods.push(self.function()?);
                 }
-                Some(args)
+                _ => return Err(ParseError::UnexpectedToken { token }),
             }
-        };
+        }
 
-        Ok(Function {
+        Ok(ast::Impl {
+            trait_ident,
             ident,
-            args,
-            block: self.block()?,
+            methods,
+        })
+    }
+
+    /// Parses a trait declaration after the `trait` keyword
+    fn trait_decl(&mut self) -> Result<ast::Trait, ParseError> {
+        let ident = self.identifier()?;
+        self.expect(LeftBrace)?;
+
+        let mut methods = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                RightBrace => break,
+                Fun => {
+                    self.store(token);
+                    methods.push(self.method_decl()?);
+                }
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(ast::Trait {
+            ident,
+            methods,
+            public: false,
+        })
+    }
+
+    /// Parses an enum declaration after the `enum` keyword
+    fn enum_decl(&mut self) -> Result<ast::Enum, ParseError> {
+        let ident = self.identifier()?;
+        let type_params = self.type_params()?;
+        self.expect(LeftBrace)?;
+
+        let mut variants = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            let ident = match token.token_type {
+                RightBrace => break,
+                Identifier(sym) => self.ident(sym, token.position()),
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            };
+
+            let mut fields = vec![];
+            let mut token = self.next_skipping_semicolons()?;
+            if matches!(token.token_type, LeftParen) {
+                loop {
+                    fields.push(self.type_annotation()?);
+
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    match token.token_type {
+                        Comma => {}
+                        RightParen => break,
+                        _ => return Err(ParseError::UnexpectedToken { token }),
+                    }
+                }
+                token = self.next_skipping_semicolons()?;
+            }
+            variants.push(VariantDecl { ident, fields });
+
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(ast::Enum {
+            ident,
+            type_params,
+            variants,
+            public: false,
         })
     }
 
@@ -167,9 +792,9 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         let token = self.next().ok_or(ParseError::EndOfFile)?;
 
         Ok(match token.token_type {
-            Loop => {
+            For => {
                 self.store(token);
-                Statement::Loop(self.loop_stmt()?)
+                Statement::For(Box::new(self.for_stmt()?))
             }
             Print => {
                 self.store(token);
@@ -179,10 +804,23 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                 self.store(token);
                 Statement::Return(self.return_stmt()?)
             }
-            If => {
-                self.store(token);
-                Statement::If(self.if_stmt()?)
+            Yield => {
+                self.yields = true;
+                Statement::Yield(self.expr()?)
             }
+            Spawn => Statement::Spawn(self.expr()?),
+            Break => match self.next() {
+                Some(token) if !matches!(token.token_type, Semicolon | RightBrace) => {
+                    self.store(token);
+                    Statement::Break(Some(self.expr()?))
+                }
+                Some(token) => {
+                    self.store(token);
+                    Statement::Break(None)
+                }
+                None => Statement::Break(None),
+            },
+            Continue => Statement::Continue,
             _ => {
                 self.store(token);
                 Statement::Expression(self.expr()?)
@@ -190,13 +828,22 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         })
     }
 
-    fn loop_stmt(&mut self) -> Result<ast::Loop, ParseError> {
+    fn for_stmt(&mut self) -> Result<ast::For, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if !matches!(token.token_type, For) {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+
+        let target = self.binding_pattern()?;
+
         let token = self.next().ok_or(ParseError::EndOfFile)?;
-        if !matches!(token.token_type, Loop) {
+        if !matches!(token.token_type, In) {
             return Err(ParseError::UnexpectedToken { token });
         }
 
-        Ok(ast::Loop {
+        Ok(ast::For {
+            target,
+            expr: self.structs(false, Self::expr)?,
             block: self.block()?,
         })
     }
@@ -228,89 +875,168 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             return Err(ParseError::UnexpectedToken { token });
         }
 
-        Ok(ast::Return { expr: self.expr()? })
-    }
-
-    fn if_stmt(&mut self) -> Result<ast::If, ParseError> {
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        if !matches!(token.token_type, If) {
-            return Err(ParseError::UnexpectedToken { token });
-        }
-
-        let expr = self.expr()?;
-        let block = self.block()?;
-
         let token = self.next().ok_or(ParseError::EndOfFile)?;
-        let else_block = if matches!(token.token_type, Else) {
-            Some(self.block()?)
-        } else {
-            self.store(token);
-            None
+        let expr = match token.token_type {
+            Semicolon | RightBrace => {
+                self.store(token);
+                None
+            }
+            _ => {
+                self.store(token);
+                Some(self.expr()?)
+            }
         };
 
-        Ok(ast::If {
-            expr,
-            block,
-            else_block,
-        })
+        Ok(ast::Return { expr })
     }
 
     // Misc
 
     fn block(&mut self) -> Result<Block, ParseError> {
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        if !matches!(token.token_type, LeftBrace) {
-            return Err(ParseError::UnexpectedToken { token });
-        }
-
-        let mut block = Block {
-            declarations: vec![],
-        };
+        self.expect(LeftBrace)?;
+        self.structs(true, |this| this.block_rest(vec![]))
+    }
 
+    /// Parses the rest of a block after `declarations`
+    fn block_rest(&mut self, mut declarations: Vec<Declaration>) -> Result<Block, ParseError> {
         loop {
-            if let Some(token) = self.next() {
-                if matches!(token.token_type, RightBrace) {
-                    break;
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                RightBrace => break,
+                Semicolon => continue,
+                _ => {
+                    self.store(token);
+                    declarations.push(self.declaration()?)
                 }
-
-                self.store(token);
-                block.declarations.push(self.declaration()?)
-            } else {
-                break;
             }
         }
 
-        Ok(block)
+        Ok(Block { declarations })
     }
 
-    fn args_decl(&mut self) -> Result<ArgsDecl, ParseError> {
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        let arg = if let Identifier(ident) = token.token_type {
-            ident
-        } else {
-            return Err(ParseError::UnexpectedToken { token });
+    /// Parses parameters, which may only have default values when `defaults` is set. The
+    /// default of a lambda's `|x|` parameters would run into the closing `|`.
+    fn args_decl(&mut self, defaults: bool) -> Result<ArgsDecl, ParseError> {
+        let mut args = ArgsDecl {
+            args: vec![],
+            defaults: vec![],
+            variadic: None,
         };
 
-        let mut args = ArgsDecl {
-            args: vec![ast::Identifier(arg)],
+        loop {
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            if matches!(token.token_type, Ellipsis) {
+                // The variadic parameter is always the last
+                args.variadic = Some(self.binding_pattern()?);
+                break;
+            }
+            self.store(token);
+            args.args.push(self.binding_pattern()?);
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            let default = if defaults && matches!(token.token_type, Equal) {
+                Some(self.expr()?)
+            } else {
+                self.store(token);
+                None
+            };
+            args.defaults.push(default);
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            if !matches!(token.token_type, Comma) {
+                self.store(token);
+                break;
+            }
+        }
+
+        Ok(args)
+    }
+
+    /// Parses a possibly empty parameter list enclosed by `open` and `close`
+    fn params(
+        &mut self,
+        open: TokenType,
+        close: TokenType,
+    ) -> Result<Option<ArgsDecl>, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type != open {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type == close {
+            return Ok(None);
+        }
+
+        self.store(token);
+        let args = self.args_decl(open != Pipe)?;
+
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type != close {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+        Ok(Some(args))
+    }
+
+    /// Parses possibly empty arguments followed by `close`
+    fn args_until(&mut self, close: TokenType) -> Result<Args, ParseError> {
+        self.structs(true, |this| this.args_until_inner(close, false))
+    }
+
+    /// Parses the possibly empty arguments of a call up to the closing `)`, which may be passed
+    /// by name
+    fn call_args(&mut self) -> Result<Args, ParseError> {
+        self.structs(true, |this| this.args_until_inner(RightParen, true))
+    }
+
+    fn args_until_inner(&mut self, close: TokenType, named: bool) -> Result<Args, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type == close {
+            return Ok(Args {
+                args: vec![],
+                named: vec![],
+            });
+        }
+
+        self.store(token);
+        let args = self.args(named)?;
+
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        if token.token_type != close {
+            return Err(ParseError::UnexpectedToken { token });
+        }
+        Ok(args)
+    }
+
+    /// Parses comma separated arguments. When `named` is set, `name: value` passes an argument
+    /// by name, after which every argument has to be.
+    fn args(&mut self, named: bool) -> Result<Args, ParseError> {
+        let mut args = Args {
+            args: vec![],
+            named: vec![],
         };
 
         loop {
-            if let Some(token) = self.next() {
-                if !matches!(token.token_type, Comma) {
-                    self.store(token);
-                    break;
+            match self.named_arg(named)? {
+                Some(arg) => args.named.push(arg),
+                None if args.named.is_empty() => args.args.push(self.expr()?),
+                None => {
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    return Err(ParseError::UnexpectedToken { token });
                 }
+            }
 
-                let token = self.next().ok_or(ParseError::EndOfFile)?;
-                let arg = if let Identifier(ident) = token.token_type {
-                    ident
-                } else {
-                    return Err(ParseError::UnexpectedToken { token });
-                };
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            if !matches!(token.token_type, Comma) {
+                self.store(token);
+                break;
+            }
 
-                args.args.push(ast::Identifier(arg))
-            } else {
+            // A trailing comma is allowed before the closing delimiter
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            let is_closed = matches!(token.token_type, RightParen | RightBracket);
+            self.store(token);
+            if is_closed {
                 break;
             }
         }
@@ -318,6 +1044,28 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         Ok(args)
     }
 
+    /// Parses `name: value` if it comes next and `named` is set
+    fn named_arg(&mut self, named: bool) -> Result<Option<NamedArg>, ParseError> {
+        if !named {
+            return Ok(None);
+        }
+        let first = self.next().ok_or(ParseError::EndOfFile)?;
+        let Identifier(sym) = first.token_type else {
+            self.store(first);
+            return Ok(None);
+        };
+        let second = self.next().ok_or(ParseError::EndOfFile)?;
+        if !matches!(second.token_type, Colon) {
+            self.store(second);
+            self.store(first);
+            return Ok(None);
+        }
+        Ok(Some(NamedArg {
+            ident: self.ident(sym, first.position()),
+            value: self.expr()?,
+        }))
+    }
+
     // Expressions
 
     fn expr(&mut self) -> Result<Expr, ParseError> {
@@ -325,37 +1073,73 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     }
 
     fn assignment(&mut self) -> Result<Assignment, ParseError> {
-        let token = self.next().ok_or(ParseError::EndOfFile)?;
-        let assignment = if let Identifier(ident) = token.token_type.clone() {
-            let token2 = self.next().ok_or(ParseError::EndOfFile)?;
-            if matches!(token2.token_type, Equal) {
-                Assignment::AssignedVal(AssignedVal {
-                    ident: ast::Identifier(ident),
-                    expr: Box::new(self.expr()?),
-                })
-            } else {
+        let left = self.logic_or()?;
+
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Ok(Assignment::LogicOr(left)),
+        };
+        let op = match token.token_type {
+            QuestionQuestion => {
+                return Ok(Assignment::Coalesce(Coalesce {
+                    left,
+                    right: Some(Box::new(self.coalesce()?)),
+                }))
+            }
+            Equal => None,
+            PlusEqual => Some(AssignOp::Plus),
+            MinusEqual => Some(AssignOp::Minus),
+            StarEqual => Some(AssignOp::Mult),
+            SlashEqual => Some(AssignOp::Div),
+            PercentEqual => Some(AssignOp::Mod),
+            _ => {
                 self.store(token);
-                self.store(token2);
-                Assignment::LogicOr(self.logic_or()?)
+                return Ok(Assignment::LogicOr(left));
             }
+        };
+
+        let target = assign_target(left).ok_or(ParseError::InvalidAssignmentTarget {
+            position: token.position(),
+        })?;
+        let expr = Box::new(self.expr()?);
+        Ok(match op {
+            None => Assignment::AssignedVal(AssignedVal { target, expr }),
+            Some(op) => Assignment::Compound(CompoundAssignment {
+                target,
+                op,
+                expr,
+                position: token.position(),
+            }),
+        })
+    }
+
+    fn coalesce(&mut self) -> Result<Coalesce, ParseError> {
+        let left = self.logic_or()?;
+
+        let token = match self.next() {
+            None => return Ok(Coalesce { left, right: None }),
+            Some(t) => t,
+        };
+        let right = if matches!(token.token_type, QuestionQuestion) {
+            Some(Box::new(self.coalesce()?))
         } else {
             self.store(token);
-            Assignment::LogicOr(self.logic_or()?)
+            None
         };
 
-        Ok(assignment)
+        Ok(Coalesce { left, right })
     }
 
     fn logic_or(&mut self) -> Result<LogicOr, ParseError> {
         let mut left = LogicOrLeft::LogicAnd(self.logic_and()?);
-        let mut right: Option<LogicAnd>;
+        let mut right: Option<Box<LogicAnd>>;
 
         loop {
             right = None;
 
             if let Some(token) = self.next() {
                 match token.token_type {
-                    And => {}
+                    Or => {}
                     _ => {
                         self.store(token);
                         break;
@@ -365,7 +1149,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                 break;
             }
 
-            right = Some(self.logic_and()?);
+            right = Some(Box::new(self.logic_and()?));
             left = LogicOrLeft::LogicOr(Box::new(LogicOr { left, right }));
         }
 
@@ -374,7 +1158,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
     fn logic_and(&mut self) -> Result<LogicAnd, ParseError> {
         let mut left = LogicAndLeft::Equality(self.equality()?);
-        let mut right: Option<Equality>;
+        let mut right: Option<Box<Equality>>;
 
         loop {
             right = None;
@@ -391,7 +1175,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
                 break;
             }
 
-            right = Some(self.equality()?);
+            right = Some(Box::new(self.equality()?));
             left = LogicAndLeft::LogicAnd(Box::new(LogicAnd { left, right }));
         }
 
@@ -399,7 +1183,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     }
 
     fn equality(&mut self) -> Result<Equality, ParseError> {
-        let mut left = EqualityLeft::Comparison(self.comparison()?);
+        let mut left = EqualityLeft::Comparison(Box::new(self.comparison()?));
         let mut right: Option<EqualityRight>;
 
         loop {
@@ -421,7 +1205,8 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
             right = Some(EqualityRight {
                 op,
-                right: self.comparison()?,
+                right: Box::new(self.comparison()?),
+                position: token.position(),
             });
             left = EqualityLeft::Equality(Box::new(Equality { left, right }));
         }
@@ -430,7 +1215,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
     }
 
     fn comparison(&mut self) -> Result<Comparison, ParseError> {
-        let mut left = ComparisonLeft::Term(self.term()?);
+        let mut left = ComparisonLeft::BitOr(self.bit_or()?);
         let mut right: Option<ComparisonRight>;
 
         loop {
@@ -454,7 +1239,8 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
             right = Some(ComparisonRight {
                 op,
-                right: self.term()?,
+                right: Box::new(self.bit_or()?),
+                position: token.position(),
             });
             left = ComparisonLeft::Comparison(Box::new(Comparison { left, right }));
         }
@@ -462,6 +1248,116 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         Ok(Comparison { left, right })
     }
 
+    fn bit_or(&mut self) -> Result<BitOr, ParseError> {
+        let mut left = BitOrLeft::BitXor(self.bit_xor()?);
+        let mut right: Option<Box<BitOrRight>>;
+
+        loop {
+            right = None;
+
+            let position = match self.next() {
+                Some(token) if matches!(token.token_type, Pipe) => token.position(),
+                Some(token) => {
+                    self.store(token);
+                    break;
+                }
+                None => break,
+            };
+
+            right = Some(Box::new(BitOrRight {
+                right: self.bit_xor()?,
+                position,
+            }));
+            left = BitOrLeft::BitOr(Box::new(BitOr { left, right }));
+        }
+
+        Ok(BitOr { left, right })
+    }
+
+    fn bit_xor(&mut self) -> Result<BitXor, ParseError> {
+        let mut left = BitXorLeft::BitAnd(self.bit_and()?);
+        let mut right: Option<Box<BitXorRight>>;
+
+        loop {
+            right = None;
+
+            let position = match self.next() {
+                Some(token) if matches!(token.token_type, Caret) => token.position(),
+                Some(token) => {
+                    self.store(token);
+                    break;
+                }
+                None => break,
+            };
+
+            right = Some(Box::new(BitXorRight {
+                right: self.bit_and()?,
+                position,
+            }));
+            left = BitXorLeft::BitXor(Box::new(BitXor { left, right }));
+        }
+
+        Ok(BitXor { left, right })
+    }
+
+    fn bit_and(&mut self) -> Result<BitAnd, ParseError> {
+        let mut left = BitAndLeft::Shift(self.shift()?);
+        let mut right: Option<Box<BitAndRight>>;
+
+        loop {
+            right = None;
+
+            let position = match self.next() {
+                Some(token) if matches!(token.token_type, Ampersand) => token.position(),
+                Some(token) => {
+                    self.store(token);
+                    break;
+                }
+                None => break,
+            };
+
+            right = Some(Box::new(BitAndRight {
+                right: self.shift()?,
+                position,
+            }));
+            left = BitAndLeft::BitAnd(Box::new(BitAnd { left, right }));
+        }
+
+        Ok(BitAnd { left, right })
+    }
+
+    fn shift(&mut self) -> Result<Shift, ParseError> {
+        let mut left = ShiftLeft::Term(self.term()?);
+        let mut right: Option<ShiftRight>;
+
+        loop {
+            right = None;
+
+            let token = match self.next() {
+                None => break,
+                Some(t) => t,
+            };
+
+            let op = match token.token_type {
+                LessLess => ShiftOp::Left,
+                GreaterGreater => ShiftOp::Right,
+                _ => {
+                    self.store(token);
+                    break;
+                }
+            };
+
+            right = Some(ShiftRight {
+                op,
+                right: Box::new(self.term()?),
+                position: token.position(),
+            });
+            left = ShiftLeft::Shift(Box::new(Shift { left, right }));
+        }
+
+        Ok(Shift { left, right })
+    }
+
     fn term(&mut self) -> Result<Term, ParseError> {
         let mut left = TermLeft::Factor(self.factor()?);
         let mut right: Option<TermRight>;
@@ -485,7 +1381,8 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
             right = Some(TermRight {
                 op,
-                right: self.factor()?,
+                right: Box::new(self.factor()?),
+                position: token.position(),
             });
             left = TermLeft::Term(Box::new(Term { left, right }));
         }
@@ -508,6 +1405,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             let op = match token.token_type {
                 Slash => FactorOp::Div,
                 Star => FactorOp::Mult,
+                Percent => FactorOp::Mod,
                 _ => {
                     self.store(token);
                     break;
@@ -516,7 +1414,8 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
             right = Some(FactorRight {
                 op,
-                right: self.unary()?,
+                right: Box::new(self.unary()?),
+                position: token.position(),
             });
             left = FactorLeft::Factor(Box::new(Factor { left, right }));
         }
@@ -526,6 +1425,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
 
     fn unary(&mut self) -> Result<Unary, ParseError> {
         let token = self.next().ok_or(ParseError::EndOfFile)?;
+        let position = token.position();
 
         let mut op: Option<UnaryOp> = None;
         let mut matched = true;
@@ -533,6 +1433,7 @@ impl<T: Iterator<Item = Token>> Parser<T> {
         match token.token_type {
             Bang => op = Some(UnaryOp::Not),
             Minus => op = Some(UnaryOp::Minus),
+            Tilde => op = Some(UnaryOp::BitNot),
             _ => {
                 matched = {
                     self.store(token);
@@ -541,35 +1442,630 @@ impl<T: Iterator<Item = Token>> Parser<T> {
             }
         }
 
-        let right: Box<UnaryRight>;
+        let right = if matched {
+            Box::new(UnaryRight::Unary(self.unary()?))
+        } else {
+            Box::new(UnaryRight::Power(self.power()?))
+        };
 
-        if matched {
-            right = Box::new(UnaryRight::Unary(self.unary()?));
+        Ok(Unary {
+            op,
+            right,
+            position,
+        })
+    }
+
+    fn power(&mut self) -> Result<Power, ParseError> {
+        let left = self.call()?;
+
+        let token = match self.next() {
+            None => return Ok(Power { left, right: None }),
+            Some(t) => t,
+        };
+        let right = if matches!(token.token_type, StarStar) {
+            Some(Box::new(PowerRight {
+                right: self.unary()?,
+                position: token.position(),
+            }))
         } else {
-            right = Box::new(UnaryRight::Primary(self.primary()?))
+            self.store(token);
+            None
+        };
+
+        Ok(Power { left, right })
+    }
+
+    fn call(&mut self) -> Result<Call, ParseError> {
+        let mut left = CallLeft::Primary(self.primary()?);
+        let mut right: Option<CallRight>;
+
+        loop {
+            right = None;
+
+            let token = match self.next() {
+                None => break,
+                Some(t) => t,
+            };
+
+            let position = token.position();
+            let call_right = match token.token_type {
+                LeftParen => CallRight::Args {
+                    args: self.call_args()?,
+                    position,
+                },
+                LeftBracket => self.structs(true, |this| this.index(position))?,
+                Question => CallRight::Try { position },
+                QuestionDot => CallRight::SafeField {
+                    ident: self.identifier()?,
+                    position,
+                },
+                Dot => {
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    match token.token_type {
+                        Int => CallRight::Element {
+                            index: self.element_index(&token)?,
+                            position,
+                        },
+                        // `pair.0.1` lexes as `pair`, `.` and the float `0.1`
+                        Float => {
+                            let text = self.lexer.text(&token).to_string();
+                            let (outer, inner) = match text.split_once('.') {
+                                Some((outer, inner)) if !inner.is_empty() => (outer, inner),
+                                _ => return Err(ParseError::UnexpectedToken { token }),
+                            };
+                            let outer = CallRight::Element {
+                                index: outer
+                                    .parse()
+                                    .map_err(|_| ParseError::UnexpectedToken { token })?,
+                                position,
+                            };
+                            left = CallLeft::Call(Box::new(Call {
+                                left,
+                                right: Some(outer),
+                            }));
+                            CallRight::Element {
+                                index: inner
+                                    .parse()
+                                    .map_err(|_| ParseError::UnexpectedToken { token })?,
+                                position,
+                            }
+                        }
+                        _ => {
+                            self.store(token);
+                            CallRight::Field {
+                                ident: self.identifier()?,
+                                position,
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    self.store(token);
+                    break;
+                }
+            };
+
+            right = Some(call_right);
+            left = CallLeft::Call(Box::new(Call { left, right }));
         }
 
-        Ok(Unary { op, right })
+        Ok(Call { left, right })
+    }
+
+    fn element_index(&self, token: &Token) -> Result<u32, ParseError> {
+        self.lexer
+            .text(token)
+            .parse()
+            .map_err(|_| ParseError::UnexpectedToken { token: *token })
+    }
+
+    /// Parses the rest of an index or slice after the opening `[`
+    fn index(&mut self, position: Position) -> Result<CallRight, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        let start = match token.token_type {
+            DotDot => None,
+            _ => {
+                self.store(token);
+                let start = Box::new(self.expr()?);
+
+                let token = self.next().ok_or(ParseError::EndOfFile)?;
+                match token.token_type {
+                    RightBracket => {
+                        return Ok(CallRight::Index {
+                            index: start,
+                            position,
+                        })
+                    }
+                    DotDot => Some(start),
+                    _ => return Err(ParseError::UnexpectedToken { token }),
+                }
+            }
+        };
+
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        let end = match token.token_type {
+            RightBracket => None,
+            _ => {
+                self.store(token);
+                let end = Box::new(self.expr()?);
+
+                let token = self.next().ok_or(ParseError::EndOfFile)?;
+                if !matches!(token.token_type, RightBracket) {
+                    return Err(ParseError::UnexpectedToken { token });
+                }
+                Some(end)
+            }
+        };
+
+        Ok(CallRight::Slice {
+            start,
+            end,
+            position,
+        })
+    }
+
+    /// Parses the rest of an if expression after the `if` keyword
+    fn if_expr(&mut self) -> Result<ast::If, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+        let binding = if matches!(token.token_type, Val) {
+            let binding = self.binding_pattern()?;
+            self.expect(Equal)?;
+            Some(binding)
+        } else {
+            self.store(token);
+            None
+        };
+        let expr = self.structs(false, Self::expr)?;
+        let block = self.block()?;
+
+        let else_branch = match self.next() {
+            Some(token) if matches!(token.token_type, Else) => {
+                let token = self.next().ok_or(ParseError::EndOfFile)?;
+                if matches!(token.token_type, If) {
+                    Some(ast::Else::If(Box::new(self.if_expr()?)))
+                } else {
+                    self.store(token);
+                    Some(ast::Else::Block(self.block()?))
+                }
+            }
+            Some(token) => {
+                self.store(token);
+                None
+            }
+            None => None,
+        };
+
+        Ok(ast::If {
+            binding,
+            expr,
+            block,
+            else_branch,
+        })
+    }
+
+    /// Parses the rest of a block or map after the opening `{`
+    fn block_or_map(&mut self, position: Position) -> Result<Primary, ParseError> {
+        let token = self.next_skipping_semicolons()?;
+        match token.token_type {
+            RightBrace => {
+                return Ok(Primary::Map(MapLiteral {
+                    entries: vec![],
+                    position,
+                }))
+            }
+            Val | Var | Fun | For | Print | Return | Yield | Spawn | Break | Continue | Struct
+            | Impl | Enum | Trait | Const | Import | Pub => {
+                self.store(token);
+                return Ok(Primary::Block(self.block_rest(vec![])?));
+            }
+            _ => self.store(token),
+        }
+
+        let expr = self.expr()?;
+        let token = self.next_skipping_semicolons()?;
+        if matches!(token.token_type, Colon) {
+            return Ok(Primary::Map(self.map(expr, position)?));
+        }
+
+        self.store(token);
+        let first = Declaration::Statement(Statement::Expression(expr));
+        Ok(Primary::Block(self.block_rest(vec![first])?))
+    }
+
+    /// Parses the rest of a map literal after its first key and the following `:`
+    fn map(&mut self, key: Expr, position: Position) -> Result<MapLiteral, ParseError> {
+        let mut entries = vec![MapEntry {
+            key,
+            value: self.expr()?,
+        }];
+
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+
+            let token = self.next_skipping_semicolons()?;
+            if matches!(token.token_type, RightBrace) {
+                break;
+            }
+            self.store(token);
+
+            let key = self.expr()?;
+            let token = self.next_skipping_semicolons()?;
+            if !matches!(token.token_type, Colon) {
+                return Err(ParseError::UnexpectedToken { token });
+            }
+            let value = self.expr()?;
+            entries.push(MapEntry { key, value });
+        }
+
+        Ok(MapLiteral { entries, position })
+    }
+
+    /// Parses the rest of a match expression after the `match` keyword
+    fn match_expr(&mut self, position: Position) -> Result<ast::Match, ParseError> {
+        let expr = self.structs(false, Self::expr)?;
+        self.expect(LeftBrace)?;
+
+        let mut arms = vec![];
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            if matches!(token.token_type, RightBrace) {
+                break;
+            }
+            let arm_position = token.position();
+            self.store(token);
+
+            let pattern = self.pattern()?;
+            let mut token = self.next().ok_or(ParseError::EndOfFile)?;
+            let guard = if matches!(token.token_type, If) {
+                let guard = self.expr()?;
+                token = self.next().ok_or(ParseError::EndOfFile)?;
+                Some(guard)
+            } else {
+                None
+            };
+            if !matches!(token.token_type, FatArrow) {
+                return Err(ParseError::UnexpectedToken { token });
+            }
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            let is_block = matches!(token.token_type, LeftBrace);
+            self.store(token);
+            let body = if is_block {
+                Body::Block(self.block()?)
+            } else {
+                Body::Expr(self.expr()?)
+            };
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+                position: arm_position,
+            });
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                Comma | Semicolon => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(ast::Match {
+            expr,
+            arms,
+            position,
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        let token = self.next().ok_or(ParseError::EndOfFile)?;
+
+        Ok(match token.token_type {
+            Identifier(sym) => {
+                let ident = self.ident(sym, token.position());
+                if ident.0 == "_" {
+                    return Ok(Pattern::Wildcard);
+                }
+
+                match self.next() {
+                    Some(next) if matches!(next.token_type, LeftBrace) => {
+                        Pattern::Struct(self.struct_pattern(ident)?)
+                    }
+                    Some(next) if matches!(next.token_type, Dot) => {
+                        let variant = self.identifier()?;
+                        let args = match self.next() {
+                            Some(next) if matches!(next.token_type, LeftParen) => {
+                                self.patterns_until(RightParen)?.0
+                            }
+                            Some(next) => {
+                                self.store(next);
+                                vec![]
+                            }
+                            None => vec![],
+                        };
+                        Pattern::Variant(VariantPattern {
+                            ident,
+                            variant,
+                            args,
+                            position: token.position(),
+                        })
+                    }
+                    Some(next) => {
+                        self.store(next);
+                        Pattern::Binding(ident)
+                    }
+                    None => Pattern::Binding(ident),
+                }
+            }
+            Int => Pattern::Literal(LiteralPattern::Int(self.literal(&token))),
+            Float => Pattern::Literal(LiteralPattern::Float(self.literal(&token))),
+            Decimal => Pattern::Literal(LiteralPattern::Decimal(self.literal(&token))),
+            String => Pattern::Literal(LiteralPattern::String(self.literal(&token))),
+            // The lexer guarantees char literals decode to exactly one char
+            Char => Pattern::Literal(LiteralPattern::Char(
+                self.literal(&token).chars().next().unwrap(),
+            )),
+            True => Pattern::Literal(LiteralPattern::Bool(true)),
+            False => Pattern::Literal(LiteralPattern::Bool(false)),
+            NoneLiteral => Pattern::Literal(LiteralPattern::None),
+            Minus => {
+                let token = self.next().ok_or(ParseError::EndOfFile)?;
+                let literal = format!("-{}", self.literal(&token));
+                match token.token_type {
+                    Int => Pattern::Literal(LiteralPattern::Int(literal)),
+                    Float => Pattern::Literal(LiteralPattern::Float(literal)),
+                    Decimal => Pattern::Literal(LiteralPattern::Decimal(literal)),
+                    _ => return Err(ParseError::UnexpectedToken { token }),
+                }
+            }
+            LeftBracket => Pattern::List(self.list_pattern()?),
+            LeftParen => {
+                let (mut patterns, trailing_comma) = self.patterns_until(RightParen)?;
+                if patterns.len() == 1 && !trailing_comma {
+                    patterns.pop().unwrap()
+                } else {
+                    Pattern::Tuple(patterns)
+                }
+            }
+            _ => return Err(ParseError::UnexpectedToken { token }),
+        })
+    }
+
+    /// Parses the rest of a list pattern after the opening `[`
+    fn list_pattern(&mut self) -> Result<ListPattern, ParseError> {
+        let mut elements = vec![];
+        let mut rest = None;
+
+        loop {
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                RightBracket => break,
+                DotDot => {
+                    let token = self.next().ok_or(ParseError::EndOfFile)?;
+                    rest = Some(match token.token_type {
+                        Identifier(sym) => {
+                            self.expect(RightBracket)?;
+                            Some(self.ident(sym, token.position()))
+                        }
+                        RightBracket => None,
+                        _ => return Err(ParseError::UnexpectedToken { token }),
+                    });
+                    break;
+                }
+                _ => {
+                    self.store(token);
+                    elements.push(self.pattern()?);
+                }
+            }
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                Comma => {}
+                RightBracket => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(ListPattern { elements, rest })
+    }
+
+    /// Parses the rest of a struct pattern after the opening `{`
+    fn struct_pattern(&mut self, ident: ast::Identifier) -> Result<StructPattern, ParseError> {
+        let mut fields = vec![];
+        let mut rest = false;
+
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            let field = match token.token_type {
+                RightBrace => break,
+                DotDot => {
+                    rest = true;
+                    let token = self.next_skipping_semicolons()?;
+                    if !matches!(token.token_type, RightBrace) {
+                        return Err(ParseError::UnexpectedToken { token });
+                    }
+                    break;
+                }
+                Identifier(sym) => self.ident(sym, token.position()),
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            };
+
+            let token = self.next_skipping_semicolons()?;
+            let pattern = if matches!(token.token_type, Colon) {
+                self.pattern()?
+            } else {
+                self.store(token);
+                Pattern::Binding(ast::Identifier(field.0.clone(), field.1))
+            };
+            fields.push(FieldPattern {
+                ident: field,
+                pattern,
+            });
+
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(StructPattern {
+            ident,
+            fields,
+            rest,
+        })
+    }
+
+    /// Parses a comma separated list of patterns followed by `close`, also returning whether
+    /// there was a trailing comma
+    fn patterns_until(&mut self, close: TokenType) -> Result<(Vec<Pattern>, bool), ParseError> {
+        let mut patterns = vec![];
+        let mut trailing_comma = false;
+
+        loop {
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            if token.token_type == close && !patterns.is_empty() {
+                break;
+            }
+            self.store(token);
+            patterns.push(self.pattern()?);
+
+            let token = self.next().ok_or(ParseError::EndOfFile)?;
+            match token.token_type {
+                Comma => trailing_comma = true,
+                _ if token.token_type == close => {
+                    trailing_comma = false;
+                    break;
+                }
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok((patterns, trailing_comma))
+    }
+
+    /// Parses the rest of a struct literal after the opening `{`
+    fn struct_literal(
+        &mut self,
+        ident: ast::Identifier,
+        position: Position,
+    ) -> Result<StructLiteral, ParseError> {
+        let mut fields = vec![];
+
+        loop {
+            let token = self.next_skipping_semicolons()?;
+            let field = match token.token_type {
+                RightBrace => break,
+                Identifier(sym) => self.ident(sym, token.position()),
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            };
+            self.expect(Colon)?;
+            fields.push(FieldInit {
+                ident: field,
+                value: self.expr()?,
+            });
+
+            let token = self.next_skipping_semicolons()?;
+            match token.token_type {
+                Comma => {}
+                RightBrace => break,
+                _ => return Err(ParseError::UnexpectedToken { token }),
+            }
+        }
+
+        Ok(StructLiteral {
+            ident,
+            fields,
+            position,
+        })
     }
 
     fn primary(&mut self) -> Result<Primary, ParseError> {
         let token = self.next().ok_or(ParseError::EndOfFile)?;
 
         match token.token_type {
-            Int(lit) => Ok(Primary::Int(lit)),
-            Float(lit) => Ok(Primary::Float(lit)),
-            String(lit) => Ok(Primary::String(lit)),
+            Int => Ok(Primary::Int(self.literal(&token))),
+            Float => Ok(Primary::Float(self.literal(&token))),
+            Decimal => Ok(Primary::Decimal(self.literal(&token))),
+            String => Ok(Primary::String(self.literal(&token))),
+            // The lexer guarantees char literals decode to exactly one char
+            Char => Ok(Primary::Char(self.literal(&token).chars().next().unwrap())),
             True => Ok(Primary::True),
             False => Ok(Primary::False),
-            Identifier(lit) => Ok(Primary::Identifier(ast::Identifier(lit))),
+            NoneLiteral => Ok(Primary::None(token.position())),
+            Identifier(sym) => {
+                let ident = self.ident(sym, token.position());
+                if self.no_struct {
+                    return Ok(Primary::Identifier(ident));
+                }
+
+                match self.next() {
+                    Some(next) if matches!(next.token_type, LeftBrace) => {
+                        Ok(Primary::Struct(self.structs(true, |this| {
+                            this.struct_literal(ident, token.position())
+                        })?))
+                    }
+                    Some(next) => {
+                        self.store(next);
+                        Ok(Primary::Identifier(ident))
+                    }
+                    None => Ok(Primary::Identifier(ident)),
+                }
+            }
             LeftParen => {
-                let expr = self.expr()?;
+                let expr = self.structs(true, Self::expr)?;
                 let right = self.next().ok_or(ParseError::EndOfFile)?;
                 match right.token_type {
-                    RightParen => Ok(Primary::Grouping(expr)),
+                    RightParen => Ok(Primary::Grouping(Box::new(expr))),
+                    Comma => {
+                        let mut elements = self.args_until(RightParen)?;
+                        elements.args.insert(0, expr);
+                        Ok(Primary::Tuple(elements))
+                    }
                     _ => Err(ParseError::UnexpectedToken { token: right }),
                 }
             }
+            LeftBracket => Ok(Primary::List(self.args_until(RightBracket)?)),
+            LeftBrace => self.structs(true, |this| this.block_or_map(token.position())),
+            If => Ok(Primary::If(Box::new(self.if_expr()?))),
+            Loop => Ok(Primary::Loop(Box::new(ast::Loop {
+                block: self.block()?,
+            }))),
+            Fun => {
+                let args = self.params(LeftParen, RightParen)?;
+                let (block, generator) = self.body(Self::block)?;
+                Ok(Primary::Lambda(Box::new(Lambda {
+                    args,
+                    body: Body::Block(block),
+                    generator,
+                })))
+            }
+            Pipe => {
+                self.store(token);
+                let args = self.params(Pipe, Pipe)?;
+                let (expr, generator) = self.body(Self::expr)?;
+                Ok(Primary::Lambda(Box::new(Lambda {
+                    args,
+                    body: Body::Expr(expr),
+                    generator,
+                })))
+            }
+            Match => {
+                Ok(Primary::Match(Box::new(self.structs(true, |this| {
+                    this.match_expr(token.position())
+                })?)))
+            }
+            Illegal(err) => Err(ParseError::IllegalToken {
+                err,
+                line: token.line,
+                char: token.char,
+            }),
             _ => Err(ParseError::UnexpectedToken { token }),
         }
     }
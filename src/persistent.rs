@@ -0,0 +1,734 @@
+//! Persistent collections, which are updated by copying only the nodes on the path to the change
+//! and sharing the rest with the versions they were copied from.
+//!
+//! Cloning a collection is O(1) and gives a version that shares every node with the original.
+//! Updating a version then copies the nodes it still shares on the path to the update, which is
+//! O(log32 n) of them, so the other versions are never changed. A version that shares nothing is
+//! updated in place.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::rc::Rc;
+
+/// Bits of an index or hash that pick a child at each level of a trie
+const BITS: u32 = 5;
+/// Most children a node of a trie has
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    /// `WIDTH` elements, except in the tail where there may be fewer
+    Leaf(Vec<T>),
+}
+
+impl<T> Node<T> {
+    fn values(&self) -> &[T] {
+        match self {
+            Node::Leaf(values) => values,
+            Node::Branch(_) => unreachable!("branch where a leaf was expected"),
+        }
+    }
+}
+
+/// A persistent vector, a trie of nodes indexed by `BITS` bits of the index at each level. The
+/// last elements are kept in a tail outside of the trie, which moves into it once it is full, so
+/// that pushing usually copies just the tail.
+pub struct Vector<T> {
+    len: usize,
+    /// Bits of an index below the root, `BITS` times the number of levels of branches
+    shift: u32,
+    /// A branch holding the full leaves
+    root: Rc<Node<T>>,
+    /// A leaf with the elements after the last full leaf of the trie
+    tail: Rc<Node<T>>,
+}
+
+impl<T> Clone for Vector<T> {
+    fn clone(&self) -> Self {
+        Vector {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+            tail: self.tail.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Default for Vector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Vector<T> {
+    pub fn new() -> Self {
+        Vector {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(Node::Branch(vec![])),
+            tail: Rc::new(Node::Leaf(vec![])),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Index of the first element in the tail
+    fn tail_offset(&self) -> usize {
+        if self.len < WIDTH {
+            0
+        } else {
+            ((self.len - 1) >> BITS) << BITS
+        }
+    }
+
+    /// The leaf holding the element at `index`, which is within the vector
+    fn leaf(&self, index: usize) -> &[T] {
+        if index >= self.tail_offset() {
+            return self.tail.values();
+        }
+        let mut node = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    node = &children[(index >> shift) & MASK];
+                    shift -= BITS;
+                }
+                Node::Leaf(values) => return values,
+            }
+        }
+    }
+
+    /// The element at `index`, in O(log32 n) time
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(&self.leaf(index)[index & MASK])
+    }
+
+    /// The element at `index` to change, copying the nodes on its path that are shared
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = if index >= self.tail_offset() {
+            Rc::make_mut(&mut self.tail)
+        } else {
+            Rc::make_mut(&mut self.root)
+        };
+        let mut shift = self.shift;
+        loop {
+            node = match node {
+                Node::Branch(children) => {
+                    let child = &mut children[(index >> shift) & MASK];
+                    shift -= BITS;
+                    Rc::make_mut(child)
+                }
+                Node::Leaf(values) => return Some(&mut values[index & MASK]),
+            };
+        }
+    }
+
+    /// Adds `value` at the end, in O(log32 n) time and O(1) amortized
+    pub fn push(&mut self, value: T) {
+        if self.len - self.tail_offset() < WIDTH {
+            match Rc::make_mut(&mut self.tail) {
+                Node::Leaf(values) => values.push(value),
+                Node::Branch(_) => unreachable!("branch as the tail"),
+            }
+            self.len += 1;
+            return;
+        }
+
+        // The full tail moves into the trie, which grows a level once it is full
+        let leaf = std::mem::replace(&mut self.tail, Rc::new(Node::Leaf(vec![value])));
+        if (self.len >> BITS) > (1 << self.shift) {
+            let path = new_path(self.shift, leaf);
+            self.root = Rc::new(Node::Branch(vec![self.root.clone(), path]));
+            self.shift += BITS;
+        } else {
+            push_leaf(Rc::make_mut(&mut self.root), self.shift, self.len - 1, leaf);
+        }
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len)
+            .step_by(WIDTH)
+            .flat_map(move |start| self.leaf(start).iter())
+    }
+}
+
+/// A chain of branches down to `leaf` at the level of `shift`
+fn new_path<T>(shift: u32, leaf: Rc<Node<T>>) -> Rc<Node<T>> {
+    if shift == 0 {
+        leaf
+    } else {
+        Rc::new(Node::Branch(vec![new_path(shift - BITS, leaf)]))
+    }
+}
+
+/// Adds `leaf` to the trie below `parent` as the leaf holding the element at `last`
+fn push_leaf<T: Clone>(parent: &mut Node<T>, shift: u32, last: usize, leaf: Rc<Node<T>>) {
+    let Node::Branch(children) = parent else {
+        unreachable!("leaf above the level of the leaves")
+    };
+    let index = (last >> shift) & MASK;
+    if shift == BITS {
+        children.push(leaf);
+    } else if let Some(child) = children.get_mut(index) {
+        push_leaf(Rc::make_mut(child), shift - BITS, last, leaf);
+    } else {
+        children.push(new_path(shift - BITS, leaf));
+    }
+}
+
+impl<T: Clone> Index<usize> for Vector<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(value) => value,
+            None => panic!(
+                "index {} is out of bounds of a vector of {}",
+                index, self.len
+            ),
+        }
+    }
+}
+
+impl<T: Clone> FromIterator<T> for Vector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vector = Vector::new();
+        for value in iter {
+            vector.push(value);
+        }
+        vector
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for Vector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone)]
+enum Child<K, V> {
+    /// An entry along with the hash of its key
+    Entry(u64, K, V),
+    Node(Rc<HashNode<K, V>>),
+}
+
+#[derive(Clone)]
+enum HashNode<K, V> {
+    /// A child for each bit set in `bitmap`, picked by `BITS` bits of the hash
+    Branch {
+        bitmap: u32,
+        children: Vec<Child<K, V>>,
+    },
+    /// Entries whose keys have the same hash
+    Collision(u64, Vec<(K, V)>),
+}
+
+impl<K: Clone, V: Clone> HashNode<K, V> {
+    /// The only entry of the node, if it holds just one
+    fn single_entry(&self) -> Option<Child<K, V>> {
+        match self {
+            HashNode::Branch { children, .. } => match &children[..] {
+                [entry @ Child::Entry(..)] => Some(entry.clone()),
+                _ => None,
+            },
+            HashNode::Collision(hash, entries) => match &entries[..] {
+                [(key, value)] => Some(Child::Entry(*hash, key.clone(), value.clone())),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn hash<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The bit of a branch's bitmap for `hash` at the level of `shift`
+fn bit(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) as usize & MASK)
+}
+
+/// Index of the child for `bit` among the children of a branch with `bitmap`
+fn position(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+/// A persistent map, a hash array mapped trie that picks a child by `BITS` bits of the hash of a
+/// key at each level. Branches only have the children they use, and a key is kept as high up as
+/// the hashes of the other keys allow. Entries are visited in the order of their hashes.
+pub struct Dict<K, V> {
+    len: usize,
+    /// A branch, the only one that may have fewer than two entries below it
+    root: Rc<HashNode<K, V>>,
+}
+
+impl<K, V> Clone for Dict<K, V> {
+    fn clone(&self) -> Self {
+        Dict {
+            len: self.len,
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for Dict<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Dict<K, V> {
+    pub fn new() -> Self {
+        Dict {
+            len: 0,
+            root: Rc::new(HashNode::Branch {
+                bitmap: 0,
+                children: vec![],
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The value of `key`, in O(log32 n) time
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = hash(key);
+        let mut node = &*self.root;
+        let mut shift = 0;
+        loop {
+            match node {
+                HashNode::Branch { bitmap, children } => {
+                    let bit = bit(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[position(*bitmap, bit)] {
+                        Child::Entry(_, k, value) => return (k == key).then_some(value),
+                        Child::Node(child) => node = child,
+                    }
+                    shift += BITS;
+                }
+                HashNode::Collision(_, entries) => {
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+                }
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets the value of `key`, giving the value it replaces
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = hash(&key);
+        let old = insert(Rc::make_mut(&mut self.root), 0, hash, key, value);
+        self.len += old.is_none() as usize;
+        old
+    }
+
+    /// Removes `key`, giving its value. Nothing is copied when it isn't there.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if !self.contains_key(key) {
+            return None;
+        }
+        let value = remove(Rc::make_mut(&mut self.root), 0, hash(key), key);
+        self.len -= 1;
+        value
+    }
+
+    pub fn iter(&self) -> DictIter<'_, K, V> {
+        let children = match &*self.root {
+            HashNode::Branch { children, .. } => children.iter(),
+            HashNode::Collision(..) => unreachable!("collision as the root"),
+        };
+        DictIter {
+            branches: vec![children],
+            collision: [].iter(),
+        }
+    }
+}
+
+fn insert<K: Eq + Clone, V: Clone>(
+    node: &mut HashNode<K, V>,
+    shift: u32,
+    hash: u64,
+    key: K,
+    value: V,
+) -> Option<V> {
+    let (bitmap, children) = match node {
+        HashNode::Branch { bitmap, children } => (bitmap, children),
+        HashNode::Collision(_, entries) => {
+            if let Some((_, old)) = entries.iter_mut().find(|(k, _)| *k == key) {
+                return Some(std::mem::replace(old, value));
+            }
+            entries.push((key, value));
+            return None;
+        }
+    };
+    let bit = bit(hash, shift);
+    let index = position(*bitmap, bit);
+    if *bitmap & bit == 0 {
+        *bitmap |= bit;
+        children.insert(index, Child::Entry(hash, key, value));
+        return None;
+    }
+    match &mut children[index] {
+        Child::Node(child) => insert(Rc::make_mut(child), shift + BITS, hash, key, value),
+        Child::Entry(_, k, old) if *k == key => Some(std::mem::replace(old, value)),
+        child => {
+            // The entries move down into a node of their own
+            let Child::Entry(other_hash, other_key, other_value) = child.clone() else {
+                unreachable!("matched an entry above")
+            };
+            let pair = pair(
+                shift + BITS,
+                (other_hash, other_key, other_value),
+                (hash, key, value),
+            );
+            *child = Child::Node(Rc::new(pair));
+            None
+        }
+    }
+}
+
+/// A node holding the two entries `a` and `b` at the level of `shift`
+fn pair<K, V>(shift: u32, a: (u64, K, V), b: (u64, K, V)) -> HashNode<K, V> {
+    // Keys whose hashes agree in every bit can only be told apart by comparing them
+    if shift >= u64::BITS {
+        return HashNode::Collision(a.0, vec![(a.1, a.2), (b.1, b.2)]);
+    }
+    let (a_bit, b_bit) = (bit(a.0, shift), bit(b.0, shift));
+    let children = if a_bit == b_bit {
+        vec![Child::Node(Rc::new(pair(shift + BITS, a, b)))]
+    } else {
+        let (a, b) = (Child::Entry(a.0, a.1, a.2), Child::Entry(b.0, b.1, b.2));
+        if a_bit < b_bit {
+            vec![a, b]
+        } else {
+            vec![b, a]
+        }
+    };
+    HashNode::Branch {
+        bitmap: a_bit | b_bit,
+        children,
+    }
+}
+
+/// Removes `key`, which is in the trie below `node`, giving its value
+fn remove<K: Eq + Clone, V: Clone>(
+    node: &mut HashNode<K, V>,
+    shift: u32,
+    hash: u64,
+    key: &K,
+) -> Option<V> {
+    let (bitmap, children) = match node {
+        HashNode::Branch { bitmap, children } => (bitmap, children),
+        HashNode::Collision(_, entries) => {
+            let index = entries.iter().position(|(k, _)| k == key)?;
+            return Some(entries.swap_remove(index).1);
+        }
+    };
+    let bit = bit(hash, shift);
+    if *bitmap & bit == 0 {
+        return None;
+    }
+    let index = position(*bitmap, bit);
+    match &mut children[index] {
+        Child::Entry(_, k, _) if k == key => {
+            *bitmap &= !bit;
+            match children.remove(index) {
+                Child::Entry(_, _, value) => Some(value),
+                Child::Node(_) => unreachable!("matched an entry above"),
+            }
+        }
+        Child::Entry(..) => None,
+        Child::Node(child) => {
+            let value = remove(Rc::make_mut(child), shift + BITS, hash, key);
+            // A node left with one entry is replaced by it, so that the trie has the same shape
+            // whatever order the keys were added and removed in
+            if let Some(entry) = child.single_entry() {
+                children[index] = entry;
+            }
+            value
+        }
+    }
+}
+
+/// The entries of a `Dict`, found by walking its trie depth first
+pub struct DictIter<'a, K, V> {
+    /// The children left to visit of each branch on the path to the current one
+    branches: Vec<std::slice::Iter<'a, Child<K, V>>>,
+    collision: std::slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for DictIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.collision.next() {
+                return Some((key, value));
+            }
+            let children = self.branches.last_mut()?;
+            match children.next() {
+                None => {
+                    self.branches.pop();
+                }
+                Some(Child::Entry(_, key, value)) => return Some((key, value)),
+                Some(Child::Node(node)) => match &**node {
+                    HashNode::Branch { children, .. } => self.branches.push(children.iter()),
+                    HashNode::Collision(_, entries) => self.collision = entries.iter(),
+                },
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for Dict<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut dict = Dict::new();
+        for (key, value) in iter {
+            dict.insert(key, value);
+        }
+        dict
+    }
+}
+
+impl<K: Hash + Eq + Clone + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for Dict<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Number of levels of the trie of `vector`, including the leaves
+    fn depth<T>(vector: &Vector<T>) -> u32 {
+        vector.shift / BITS + 1
+    }
+
+    fn vector_nodes<T>(node: &Rc<Node<T>>, seen: &mut HashSet<*const Node<T>>) {
+        seen.insert(Rc::as_ptr(node));
+        if let Node::Branch(children) = &**node {
+            for child in children {
+                vector_nodes(child, seen);
+            }
+        }
+    }
+
+    /// Nodes of `new` that aren't shared with `old`
+    fn vector_copies<T>(old: &Vector<T>, new: &Vector<T>) -> usize {
+        let (mut old_nodes, mut new_nodes) = (HashSet::new(), HashSet::new());
+        for (vector, nodes) in [(old, &mut old_nodes), (new, &mut new_nodes)] {
+            vector_nodes(&vector.root, nodes);
+            vector_nodes(&vector.tail, nodes);
+        }
+        new_nodes.difference(&old_nodes).count()
+    }
+
+    fn dict_nodes<K, V>(
+        node: &Rc<HashNode<K, V>>,
+        seen: &mut HashSet<*const HashNode<K, V>>,
+    ) -> u32 {
+        seen.insert(Rc::as_ptr(node));
+        let mut depth = 0;
+        if let HashNode::Branch { children, .. } = &**node {
+            for child in children {
+                if let Child::Node(child) = child {
+                    depth = depth.max(dict_nodes(child, seen));
+                }
+            }
+        }
+        depth + 1
+    }
+
+    /// Nodes of `new` that aren't shared with `old`
+    fn dict_copies<K, V>(old: &Dict<K, V>, new: &Dict<K, V>) -> usize {
+        let (mut old_nodes, mut new_nodes) = (HashSet::new(), HashSet::new());
+        dict_nodes(&old.root, &mut old_nodes);
+        dict_nodes(&new.root, &mut new_nodes);
+        new_nodes.difference(&old_nodes).count()
+    }
+
+    fn dict_depth<K, V>(dict: &Dict<K, V>) -> u32 {
+        dict_nodes(&dict.root, &mut HashSet::new())
+    }
+
+    #[test]
+    fn vector_push_and_get() {
+        // Sizes around where the tail fills and the trie grows a level
+        for len in [
+            0,
+            1,
+            31,
+            32,
+            33,
+            64,
+            1055,
+            1056,
+            1057,
+            33 * 1024 + 1,
+            100_000,
+        ] {
+            let vector: Vector<usize> = (0..len).collect();
+            assert_eq!(vector.len(), len);
+            assert!(vector.iter().copied().eq(0..len), "{}", len);
+            assert_eq!(vector.get(len / 2), (len > 0).then_some(&(len / 2)));
+            assert_eq!(vector.get(len), None);
+        }
+    }
+
+    #[test]
+    fn vector_versions_are_unchanged() {
+        let old: Vector<usize> = (0..5000).collect();
+        let mut new = old.clone();
+        for i in (0..5000).step_by(7) {
+            *new.get_mut(i).unwrap() *= 2;
+        }
+        new.push(5000);
+        assert!(old.iter().copied().eq(0..5000));
+        assert_eq!(new.get(7), Some(&14));
+        assert_eq!(new.get(8), Some(&8));
+        assert_eq!(new.len(), 5001);
+    }
+
+    #[test]
+    fn vector_depth_is_logarithmic() {
+        let mut vector = Vector::new();
+        for len in 1..=(1 << 16) {
+            vector.push(len);
+            // The trie holds whole leaves of WIDTH elements, with up to WIDTH more in the tail
+            let leaves = (len - 1) / WIDTH;
+            let levels = (1..).find(|l| WIDTH.pow(*l) >= leaves.max(1)).unwrap();
+            assert!(depth(&vector) <= levels + 1, "{}", len);
+        }
+    }
+
+    #[test]
+    fn vector_updates_copy_a_path() {
+        let vector: Vector<usize> = (0..100_000).collect();
+        for index in [0, 1, 31_337, 65_535, 99_999] {
+            let mut updated = vector.clone();
+            *updated.get_mut(index).unwrap() = 0;
+            // One node for each level of branches, and the leaf
+            assert!(vector_copies(&vector, &updated) <= depth(&vector) as usize);
+        }
+
+        // Pushing copies the tail, and the path to where it goes in the trie once it is full
+        let partial: Vector<usize> = (0..WIDTH * 100 + 1).collect();
+        let mut pushed = partial.clone();
+        pushed.push(0);
+        assert_eq!(vector_copies(&partial, &pushed), 1);
+        let full: Vector<usize> = (0..WIDTH * 100).collect();
+        let mut pushed = full.clone();
+        pushed.push(0);
+        assert!(vector_copies(&full, &pushed) <= depth(&full) as usize + 1);
+    }
+
+    #[test]
+    fn dict_insert_get_and_remove() {
+        let mut dict: Dict<usize, usize> = (0..10_000).map(|i| (i, i * i)).collect();
+        assert_eq!(dict.len(), 10_000);
+        assert_eq!(dict.get(&99), Some(&9801));
+        assert_eq!(dict.insert(99, 0), Some(9801));
+        assert_eq!(dict.len(), 10_000);
+        for i in (0..10_000).step_by(2) {
+            assert_eq!(dict.remove(&i), Some(i * i));
+        }
+        assert_eq!(dict.remove(&0), None);
+        assert_eq!(dict.len(), 5000);
+        assert_eq!(dict.get(&98), None);
+        assert_eq!(dict.get(&97), Some(&9409));
+        let mut keys: Vec<_> = dict.iter().map(|(k, _)| *k).collect();
+        keys.sort_unstable();
+        assert!(keys.into_iter().eq((1..10_000).step_by(2)));
+    }
+
+    #[test]
+    fn dict_shape_ignores_history() {
+        let all: Dict<usize, ()> = (0..2000).map(|i| (i, ())).collect();
+        let mut some = all.clone();
+        for i in 1000..2000 {
+            some.remove(&i);
+        }
+        let fresh: Dict<usize, ()> = (0..1000).map(|i| (i, ())).collect();
+        assert!(some.iter().map(|(k, _)| k).eq(fresh.iter().map(|(k, _)| k)));
+        assert_eq!(dict_depth(&some), dict_depth(&fresh));
+    }
+
+    #[test]
+    fn dict_updates_copy_a_path() {
+        let dict: Dict<usize, usize> = (0..100_000).map(|i| (i, i)).collect();
+        // log32 of 100,000 is 3.3, and keys only share a few more bits of hash than that
+        let depth = dict_depth(&dict);
+        assert!(depth <= 7, "{}", depth);
+        for key in [0, 4242, 99_999, 100_000, 123_456] {
+            let mut inserted = dict.clone();
+            inserted.insert(key, 0);
+            // A new key may need one more node to tell it apart from another
+            assert!(dict_copies(&dict, &inserted) <= depth as usize + 1);
+
+            let mut removed = dict.clone();
+            removed.remove(&key);
+            assert!(dict_copies(&dict, &removed) <= depth as usize);
+        }
+        assert_eq!(dict.get(&4242), Some(&4242));
+        assert_eq!(dict.len(), 100_000);
+    }
+
+    /// A key with a hash shared by every other
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Colliding(u32);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            0.hash(state)
+        }
+    }
+
+    #[test]
+    fn dict_collisions() {
+        let mut dict: Dict<Colliding, u32> = (0..10).map(|i| (Colliding(i), i)).collect();
+        assert_eq!(dict.len(), 10);
+        assert_eq!(dict.get(&Colliding(7)), Some(&7));
+        assert_eq!(dict.insert(Colliding(7), 70), Some(7));
+        for i in 0..9 {
+            assert!(dict.remove(&Colliding(i)).is_some());
+        }
+        assert_eq!(dict.iter().collect::<Vec<_>>(), [(&Colliding(9), &9)]);
+        // The last entry is kept directly in the root
+        assert_eq!(dict_depth(&dict), 1);
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+/// An interned string. Two symbols from the same `Interner` are equal exactly when the strings
+/// they were created from are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(pub u32);
+
+impl Symbol {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Symbol table mapping strings to `Symbol`s and back
+#[derive(Debug, Default)]
+pub struct Interner {
+    map: HashMap<Box<str>, Symbol>,
+    strings: Vec<Box<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the symbol for `s`, allocating a new one the first time a string is seen.
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(sym) = self.map.get(s) {
+            return *sym;
+        }
+
+        let sym = Symbol(self.strings.len() as u32);
+        self.strings.push(s.into());
+        self.map.insert(s.into(), sym);
+        sym
+    }
+
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        &self.strings[sym.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
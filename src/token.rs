@@ -1,32 +1,64 @@
 use std::fmt;
 use std::fmt::Formatter;
 
-#[derive(Debug, Clone)]
+use crate::lexer::LexError;
+use crate::symbol::Symbol;
+
+/// Literal tokens carry no text of their own, the text can be recovered from the source using the
+/// token's `Span`.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     // Special Tokens
-    Illegal(String),
+    Illegal(LexError),
     Semicolon,
 
     // Literals + Identifier
-    Identifier(String),
-    Int(String),
-    Float(String),
-    String(String),
+    Identifier(Symbol),
+    Int,
+    Float,
+    /// A number with the suffix `d`, e.g. `19.99d`
+    Decimal,
+    String,
+    Char,
     True,
     False,
+    /// `none`, named so that it doesn't clash with `Option::None`
+    NoneLiteral,
 
     // Delimiters
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
+    Dot,
+    DotDot,
+    /// `...`, which marks a variadic parameter
+    Ellipsis,
+    FatArrow,
+    Arrow,
 
     // Operators and Comparisons
     Minus,
     Plus,
     Slash,
     Star,
+    StarStar,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    LessLess,
+    GreaterGreater,
+    MinusEqual,
+    PlusEqual,
+    SlashEqual,
+    StarEqual,
+    PercentEqual,
     Bang,
     BangEqual,
     Equal,
@@ -35,6 +67,9 @@ pub enum TokenType {
     LessEqual,
     Greater,
     GreaterEqual,
+    Question,
+    QuestionDot,
+    QuestionQuestion,
 
     // Keywords
     Fun,
@@ -50,6 +85,18 @@ pub enum TokenType {
     Continue,
     And,
     Or,
+    In,
+    Struct,
+    Impl,
+    Enum,
+    Match,
+    Import,
+    Pub,
+    Trait,
+    Dyn,
+    Const,
+    Yield,
+    Spawn,
 }
 
 impl fmt::Display for TokenType {
@@ -58,13 +105,57 @@ impl fmt::Display for TokenType {
     }
 }
 
-#[derive(Debug)]
+/// Byte range of a token within the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// Line and char of a token, used to point at the source in errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub char: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.char)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Token {
     pub token_type: TokenType,
+    pub span: Span,
     pub char: usize,
     pub line: usize,
 }
 
+impl Token {
+    pub fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            char: self.char,
+        }
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -74,7 +165,7 @@ impl fmt::Display for Token {
 // This exists to make displaying tokens easier
 #[derive(Debug)]
 pub struct Tokens {
-    tokens: Vec<Token>,
+    pub tokens: Vec<Token>,
 }
 
 impl fmt::Display for Tokens {
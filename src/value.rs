@@ -0,0 +1,747 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::codegen::bytecode::Function;
+use crate::decimal::{Decimal, Rounding};
+use crate::persistent::{Dict, Vector};
+
+/// A runtime value
+#[derive(Debug, Clone)]
+pub enum Value {
+    Unit,
+    /// The absence of a value, only held by optionals
+    None,
+    Bool(bool),
+    Int(i64),
+    /// An Int too large for 64 bits, which Int arithmetic promotes to instead of overflowing.
+    /// It never holds a value that fits in an `i64`, so each Int has one representation.
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    /// An exact decimal number, which Ints promote to in arithmetic with one
+    Decimal(Rc<Decimal>),
+    /// An exact fraction in lowest terms, which Ints and Decimals promote to in arithmetic with
+    /// one
+    Rational(Rc<BigRational>),
+    Char(char),
+    String(Rc<str>),
+    /// Lists are shared by reference, so changes through one binding are seen by all of them
+    List(Rc<RefCell<Vec<Value>>>),
+    /// Maps are shared by reference like lists and iterate in insertion order
+    Map(Rc<RefCell<IndexMap<Key, Value>>>),
+    /// A persistent list, which is never changed, so it can be shared like a tuple. Updating it
+    /// gives a new version that shares most of its structure with the old one.
+    Vector(Rc<Vector<Value>>),
+    /// A persistent map, immutable like vectors. It iterates in an order given by the hashes of
+    /// its keys.
+    Dict(Rc<Dict<Key, Value>>),
+    /// Struct instances are shared by reference like lists
+    Struct(Rc<RefCell<Instance>>),
+    /// Tuples are immutable, so they can be shared without being observably shared
+    Tuple(Rc<[Value]>),
+    /// A variant of an enum, immutable like tuples
+    Enum(Rc<EnumValue>),
+    Function(Rc<Closure>),
+    /// A variant of an enum that holds values, called to construct it
+    Constructor(Rc<EnumDef>, usize),
+    Builtin(Builtin),
+    Iterator(Rc<RefCell<Iter>>),
+    Channel(Rc<Channel>),
+}
+
+impl Value {
+    /// Name of the value's type as used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "Unit",
+            Value::None => "None",
+            Value::Bool(_) => "Bool",
+            Value::Int(_) | Value::BigInt(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Decimal(_) => "Decimal",
+            Value::Rational(_) => "Rational",
+            Value::Char(_) => "Char",
+            Value::String(_) => "String",
+            Value::List(_) => "List",
+            Value::Map(_) => "Map",
+            Value::Vector(_) => "Vector",
+            Value::Dict(_) => "Dict",
+            Value::Struct(_) => "Struct",
+            Value::Tuple(_) => "Tuple",
+            Value::Enum(_) => "Enum",
+            Value::Function(_) => "Function",
+            Value::Constructor(..) => "Function",
+            Value::Builtin(_) => "Function",
+            Value::Iterator(_) => "Iterator",
+            Value::Channel(_) => "Chan",
+        }
+    }
+
+    /// The Int holding `value`, which is only big when it doesn't fit in 64 bits
+    pub fn int(value: BigInt) -> Value {
+        match i64::try_from(&value) {
+            Ok(value) => Value::Int(value),
+            Err(_) => Value::BigInt(Rc::new(value)),
+        }
+    }
+
+    /// The Int written as the digits `literal`, `None` if it has anything other than digits
+    pub fn parse_int(literal: &str) -> Option<Value> {
+        match literal.parse() {
+            Ok(value) => Some(Value::Int(value)),
+            Err(_) => literal.parse().ok().map(Value::int),
+        }
+    }
+
+    /// The Decimal written as `literal`, `None` if it isn't digits with an optional point
+    pub fn parse_decimal(literal: &str) -> Option<Value> {
+        Decimal::parse(literal).map(|decimal| Value::Decimal(Rc::new(decimal)))
+    }
+
+    /// The exact number `self` as a fraction, if it is an Int, Decimal or Rational
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i).into()),
+            Value::BigInt(i) => Some((**i).clone().into()),
+            Value::Decimal(d) => Some(d.to_rational()),
+            Value::Rational(r) => Some((**r).clone()),
+            _ => None,
+        }
+    }
+
+    /// The value as it appears inside a collection or error message, where strings and chars
+    /// are quoted
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Char(c) => format!("{:?}", c),
+            Value::String(s) => format!("{:?}", s),
+            other => other.to_string(),
+        }
+    }
+
+    /// Equality as defined by `==`. Ints and Floats compare by numeric value, as do Ints,
+    /// Decimals and Rationals, values of otherwise different types are never equal.
+    pub fn equals(&self, other: &Value) -> bool {
+        self.equals_within(other, &mut vec![])
+    }
+
+    /// `equals`, where `comparing` holds the addresses of the pairs of lists, maps and structs
+    /// being compared further up. Values can hold themselves through these, and a pair met again
+    /// is taken to be equal, leaving the rest of the comparison to decide.
+    fn equals_within(&self, other: &Value, comparing: &mut Vec<(usize, usize)>) -> bool {
+        match (self, other) {
+            (Value::Unit, Value::Unit) | (Value::None, Value::None) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+            (Value::BigInt(a), Value::Float(b)) | (Value::Float(b), Value::BigInt(a)) => {
+                a.to_f64() == Some(*b)
+            }
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::List(a), Value::List(b)) => compare_shared(a, b, comparing, |comparing| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.equals_within(b, comparing))
+            }),
+            (Value::Map(a), Value::Map(b)) => compare_shared(a, b, comparing, |comparing| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter().all(|(key, value)| {
+                        b.get(key)
+                            .is_some_and(|other| value.equals_within(other, comparing))
+                    })
+            }),
+            (Value::Vector(a), Value::Vector(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.equals_within(b, comparing))
+            }
+            (Value::Dict(a), Value::Dict(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, value)| {
+                        b.get(key)
+                            .is_some_and(|other| value.equals_within(other, comparing))
+                    })
+            }
+            (Value::Struct(a), Value::Struct(b)) => compare_shared(a, b, comparing, |comparing| {
+                let (a, b) = (a.borrow(), b.borrow());
+                Rc::ptr_eq(&a.def, &b.def)
+                    && a.fields
+                        .iter()
+                        .zip(b.fields.iter())
+                        .all(|(a, b)| a.equals_within(b, comparing))
+            }),
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.equals_within(b, comparing))
+            }
+            (Value::Enum(a), Value::Enum(b)) => {
+                Rc::ptr_eq(&a.def, &b.def)
+                    && a.variant == b.variant
+                    && a.values
+                        .iter()
+                        .zip(b.values.iter())
+                        .all(|(a, b)| a.equals_within(b, comparing))
+            }
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Constructor(a, i), Value::Constructor(b, j)) => Rc::ptr_eq(a, b) && i == j,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => Rc::ptr_eq(a, b),
+            (Value::Decimal(a), Value::Decimal(b)) => a == b,
+            _ => match (self.to_rational(), other.to_rational()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+impl Value {
+    /// Writes the value as `Display` does, where `printing` holds the addresses of the lists,
+    /// maps and structs it is within. One of those met again is written as `[...]`, `{...}` or
+    /// `Name { ... }` instead of being written forever.
+    fn write(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<usize>) -> fmt::Result {
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::None => write!(f, "none"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::BigInt(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Rational(r) => write!(f, "{}", r),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(list) => {
+                if printing.contains(&address(list)) {
+                    return write!(f, "[...]");
+                }
+                printing.push(address(list));
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write_repr(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                if printing.contains(&address(map)) {
+                    return write!(f, "{{...}}");
+                }
+                printing.push(address(map));
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key.to_value().repr())?;
+                    value.write_repr(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "}}")
+            }
+            // Persistent collections are written as the calls that make them
+            Value::Vector(vector) => {
+                write!(f, "vector([")?;
+                for (i, value) in vector.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write_repr(f, printing)?;
+                }
+                write!(f, "])")
+            }
+            Value::Dict(dict) => {
+                write!(f, "dict({{")?;
+                for (i, (key, value)) in dict.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key.to_value().repr())?;
+                    value.write_repr(f, printing)?;
+                }
+                write!(f, "}})")
+            }
+            Value::Struct(instance) => {
+                let address = address(instance);
+                let instance = instance.borrow();
+                if printing.contains(&address) {
+                    return write!(f, "{} {{ ... }}", instance.def.name);
+                }
+                printing.push(address);
+                write!(f, "{} {{", instance.def.name)?;
+                for (i, (name, value)) in
+                    instance.def.fields.iter().zip(&instance.fields).enumerate()
+                {
+                    let separator = if i > 0 { "," } else { "" };
+                    write!(f, "{} {}: ", separator, name)?;
+                    value.write_repr(f, printing)?;
+                }
+                printing.pop();
+                write!(f, " }}")
+            }
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write_repr(f, printing)?;
+                }
+                // A single element tuple is written with a trailing comma, like its literal
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::Enum(value) => {
+                write!(f, "{}", value.def.variant_name(value.variant))?;
+                if !value.values.is_empty() {
+                    write!(f, "(")?;
+                    for (i, value) in value.values.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        value.write_repr(f, printing)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Value::Function(closure) => write!(f, "<fun {}>", closure.function.name),
+            Value::Constructor(def, variant) => write!(f, "<fun {}>", def.variant_name(*variant)),
+            Value::Builtin(builtin) => write!(f, "<fun {}>", builtin.name()),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Channel(_) => write!(f, "<chan>"),
+        }
+    }
+
+    /// Writes the value as `repr` does, within the values in `printing`
+    fn write_repr(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<usize>) -> fmt::Result {
+        match self {
+            Value::Char(_) | Value::String(_) => write!(f, "{}", self.repr()),
+            other => other.write(f, printing),
+        }
+    }
+}
+
+/// Address of the value behind `rc`, which identifies a shared value
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// Compares the shared values `a` and `b` with `compare`, unless they are the same value or
+/// already being compared further up, which makes them equal as far as they decide
+fn compare_shared<T>(
+    a: &Rc<T>,
+    b: &Rc<T>,
+    comparing: &mut Vec<(usize, usize)>,
+    compare: impl FnOnce(&mut Vec<(usize, usize)>) -> bool,
+) -> bool {
+    let pair = (address(a), address(b));
+    if Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    let equal = compare(comparing);
+    comparing.pop();
+    equal
+}
+
+/// A struct declaration together with its methods
+#[derive(Debug)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub methods: HashMap<String, Rc<Closure>>,
+}
+
+impl StructDef {
+    /// Index of the field called `name`
+    pub fn field(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f == name)
+    }
+}
+
+/// A struct value, `fields` are in the order of the declaration
+#[derive(Debug)]
+pub struct Instance {
+    pub def: Rc<StructDef>,
+    pub fields: Vec<Value>,
+}
+
+/// Index of the `Ok` variant of `EnumDef::result`
+pub const OK: usize = 0;
+/// Index of the `Err` variant of `EnumDef::result`
+pub const ERR: usize = 1;
+
+/// An enum declaration
+#[derive(Debug)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<VariantDef>,
+}
+
+impl EnumDef {
+    /// The `Result` enum every module can use, with the variants `Ok` and `Err` that each hold
+    /// one value
+    pub fn result() -> EnumDef {
+        let variant = |name: &str| VariantDef {
+            name: name.to_string(),
+            arity: 1,
+        };
+        EnumDef {
+            name: "Result".to_string(),
+            variants: vec![variant("Ok"), variant("Err")],
+        }
+    }
+
+    /// The `Rounding` enum every module can use, with a variant without values for each
+    /// rounding mode
+    pub fn rounding() -> EnumDef {
+        EnumDef {
+            name: "Rounding".to_string(),
+            variants: Rounding::ALL
+                .iter()
+                .map(|rounding| VariantDef {
+                    name: rounding.name().to_string(),
+                    arity: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Name of a variant qualified by the enum, e.g. `Shape.Circle`
+    pub fn variant_name(&self, variant: usize) -> String {
+        format!("{}.{}", self.name, self.variants[variant].name)
+    }
+}
+
+#[derive(Debug)]
+pub struct VariantDef {
+    pub name: String,
+    /// Number of values the variant holds
+    pub arity: usize,
+}
+
+/// A value of an enum, `variant` indexes the variants of `def`
+#[derive(Debug)]
+pub struct EnumValue {
+    pub def: Rc<EnumDef>,
+    pub variant: usize,
+    pub values: Vec<Value>,
+}
+
+/// A value that can be used as a map key. Floats are excluded because they have no sensible
+/// equality for hashing, and Decimals and Rationals because they equal Ints of the same value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Char(char),
+    String(Rc<str>),
+}
+
+impl Key {
+    /// The key for `value`, if it is a valid key
+    pub fn from_value(value: &Value) -> Option<Key> {
+        Some(match value {
+            Value::Unit => Key::Unit,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Int(i) => Key::Int(*i),
+            Value::BigInt(i) => Key::BigInt(i.clone()),
+            Value::Char(c) => Key::Char(*c),
+            Value::String(s) => Key::String(s.clone()),
+            _ => return None,
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Key::Unit => Value::Unit,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Int(i) => Value::Int(*i),
+            Key::BigInt(i) => Value::BigInt(i.clone()),
+            Key::Char(c) => Value::Char(*c),
+            Key::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+/// A function implemented by the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Len,
+    Push,
+    Pop,
+    Keys,
+    Values,
+    Contains,
+    Remove,
+    Panic,
+    Iter,
+    Map,
+    Filter,
+    Take,
+    Zip,
+    Enumerate,
+    Chan,
+    Send,
+    Recv,
+    Select,
+    Int,
+    Float,
+    Decimal,
+    Rational,
+    Round,
+    Div,
+    Vector,
+    Dict,
+    Append,
+    Set,
+    Insert,
+    Without,
+}
+
+impl Builtin {
+    const ALL: [Builtin; 30] = [
+        Builtin::Len,
+        Builtin::Push,
+        Builtin::Pop,
+        Builtin::Keys,
+        Builtin::Values,
+        Builtin::Contains,
+        Builtin::Remove,
+        Builtin::Panic,
+        Builtin::Iter,
+        Builtin::Map,
+        Builtin::Filter,
+        Builtin::Take,
+        Builtin::Zip,
+        Builtin::Enumerate,
+        Builtin::Chan,
+        Builtin::Send,
+        Builtin::Recv,
+        Builtin::Select,
+        Builtin::Int,
+        Builtin::Float,
+        Builtin::Decimal,
+        Builtin::Rational,
+        Builtin::Round,
+        Builtin::Div,
+        Builtin::Vector,
+        Builtin::Dict,
+        Builtin::Append,
+        Builtin::Set,
+        Builtin::Insert,
+        Builtin::Without,
+    ];
+
+    /// The builtin called `name`, if any
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Self::ALL.iter().copied().find(|b| b.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::Push => "push",
+            Builtin::Pop => "pop",
+            Builtin::Keys => "keys",
+            Builtin::Values => "values",
+            Builtin::Contains => "contains",
+            Builtin::Remove => "remove",
+            Builtin::Panic => "panic",
+            Builtin::Iter => "iter",
+            Builtin::Map => "map",
+            Builtin::Filter => "filter",
+            Builtin::Take => "take",
+            Builtin::Zip => "zip",
+            Builtin::Enumerate => "enumerate",
+            Builtin::Chan => "chan",
+            Builtin::Send => "send",
+            Builtin::Recv => "recv",
+            Builtin::Select => "select",
+            Builtin::Int => "int",
+            Builtin::Float => "float",
+            Builtin::Decimal => "decimal",
+            Builtin::Rational => "rational",
+            Builtin::Round => "round",
+            Builtin::Div => "div",
+            Builtin::Vector => "vector",
+            Builtin::Dict => "dict",
+            Builtin::Append => "append",
+            Builtin::Set => "set",
+            Builtin::Insert => "insert",
+            Builtin::Without => "without",
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Builtin::Len
+            | Builtin::Pop
+            | Builtin::Keys
+            | Builtin::Values
+            | Builtin::Panic
+            | Builtin::Iter
+            | Builtin::Enumerate
+            | Builtin::Recv
+            | Builtin::Select
+            | Builtin::Int
+            | Builtin::Float
+            | Builtin::Decimal
+            | Builtin::Vector
+            | Builtin::Dict => 1,
+            Builtin::Push
+            | Builtin::Contains
+            | Builtin::Remove
+            | Builtin::Map
+            | Builtin::Filter
+            | Builtin::Take
+            | Builtin::Zip
+            | Builtin::Send
+            | Builtin::Rational
+            | Builtin::Append
+            | Builtin::Without => 2,
+            Builtin::Round | Builtin::Set | Builtin::Insert => 3,
+            Builtin::Div => 4,
+            Builtin::Chan => 0,
+        }
+    }
+}
+
+/// A function together with the variables it captured
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable. It refers to a stack slot while the variable is in scope and holds the
+/// value itself once the variable goes out of scope, so every closure sharing it sees writes.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// Name of the method that steps an iterator, which structs can implement to be iterable
+pub const NEXT: &str = "next";
+
+/// Name of the method that a struct implements to overload the binary operator `op` when it is
+/// the left operand, if `op` can be overloaded. `==` and `!=` call `eq`, which gives a Bool, and
+/// `<`, `<=`, `>` and `>=` call `cmp`, which gives an Int that is negative, zero or positive as
+/// the struct is less than, equal to or greater than the right operand.
+pub fn operator_method(op: &str) -> Option<&'static str> {
+    Some(match op {
+        "+" => "add",
+        "-" => "sub",
+        "*" => "mul",
+        "/" => "div",
+        "%" => "rem",
+        "==" | "!=" => EQ,
+        "<" | "<=" | ">" | ">=" => CMP,
+        _ => return None,
+    })
+}
+
+/// Name of the method overloading `==` and `!=`
+pub const EQ: &str = "eq";
+/// Name of the method overloading the orderings
+pub const CMP: &str = "cmp";
+/// Name of the method overloading unary `-`
+pub const NEG: &str = "neg";
+/// Name of the method overloading `value[index]`
+pub const INDEX: &str = "index";
+
+/// State of an in-progress `for` loop or of an iterator held by the program. Iterators are
+/// shared by reference, so every holder sees the values taken by the others.
+#[derive(Debug)]
+pub enum Iter {
+    /// Chars of a string, `offset` is the byte offset of the next char
+    Chars { string: Rc<str>, offset: usize },
+    /// Elements of a list, `index` is the index of the next element. Elements pushed while
+    /// iterating are visited.
+    List {
+        list: Rc<RefCell<Vec<Value>>>,
+        index: usize,
+    },
+    /// Keys of a map in insertion order, `index` is the index of the next entry
+    Keys {
+        map: Rc<RefCell<IndexMap<Key, Value>>>,
+        index: usize,
+    },
+    /// Elements of a vector, `index` is the index of the next element
+    Vector {
+        vector: Rc<Vector<Value>>,
+        index: usize,
+    },
+    /// A call of a generator function
+    Generator {
+        closure: Rc<Closure>,
+        state: Generator,
+    },
+    /// A struct whose `next` method gives the values, until it returns `none`
+    Struct(Rc<RefCell<Instance>>),
+    /// The values of an iterator passed through a function
+    Map(Rc<RefCell<Iter>>, Value),
+    /// The values of an iterator for which a function returns true
+    Filter(Rc<RefCell<Iter>>, Value),
+    /// At most the given number of values of an iterator
+    Take(Rc<RefCell<Iter>>, usize),
+    /// Tuples of the values of two iterators, until either runs out
+    Zip(Rc<RefCell<Iter>>, Rc<RefCell<Iter>>),
+    /// Tuples of the values of an iterator and their index, which is the next index
+    Enumerate(Rc<RefCell<Iter>>, i64),
+}
+
+/// Where a call of a generator function is
+#[derive(Debug)]
+pub enum Generator {
+    /// Waiting to continue from `ip`, initially before its first instruction
+    Suspended {
+        ip: usize,
+        /// The stack of the call's frame, starting with the slot of the function
+        stack: Vec<Value>,
+        /// Upvalues that referred to the frame's stack slots, which are closed while it is
+        /// suspended, along with their slots relative to the frame
+        upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,
+    },
+    /// Stepping, a step from within the generator's own body fails
+    Running,
+    /// Returned, every further step gives nothing
+    Done,
+}
+
+/// Values sent to a channel that no task has received yet, in the order they were sent
+#[derive(Debug, Default)]
+pub struct Channel {
+    pub values: RefCell<VecDeque<Value>>,
+}
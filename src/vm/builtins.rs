@@ -0,0 +1,277 @@
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use super::{ops, RuntimeError};
+use crate::codegen::ROUNDING;
+use crate::decimal::{Decimal, Rounding, MAX_SCALE};
+use crate::persistent::{Dict, Vector};
+use crate::value::{Builtin, EnumDef, Iter, Key, Value};
+
+fn list(values: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(values)))
+}
+
+fn iterator(iter: Iter) -> Value {
+    Value::Iterator(Rc::new(RefCell::new(iter)))
+}
+
+fn vector(vector: Vector<Value>) -> Value {
+    Value::Vector(Rc::new(vector))
+}
+
+fn dict(dict: Dict<Key, Value>) -> Value {
+    Value::Dict(Rc::new(dict))
+}
+
+fn decimal(value: Decimal) -> Value {
+    Value::Decimal(Rc::new(value))
+}
+
+fn not_convertible(value: &Value, to: &'static str) -> RuntimeError {
+    RuntimeError::NotConvertible {
+        value: value.to_string(),
+        to,
+    }
+}
+
+/// The number of decimal places `value` asks for
+fn scale(value: &Value) -> Option<Result<u32, RuntimeError>> {
+    let scale = match value {
+        Value::Int(scale) => u32::try_from(*scale).ok().filter(|s| *s <= MAX_SCALE),
+        Value::BigInt(_) => None,
+        _ => return None,
+    };
+    Some(scale.ok_or_else(|| RuntimeError::InvalidScale {
+        scale: value.to_string(),
+    }))
+}
+
+/// The rounding mode `value` is a variant of the prelude `Rounding` for
+fn rounding(value: &Value, enums: &[Rc<EnumDef>]) -> Option<Rounding> {
+    match value {
+        Value::Enum(mode) if Rc::ptr_eq(&mode.def, &enums[ROUNDING]) => {
+            Some(Rounding::ALL[mode.variant])
+        }
+        _ => None,
+    }
+}
+
+/// Calls `builtin` with `args`, which the VM has already checked against its arity. The builtins
+/// that receive from or send to a channel are called by the VM, since they may switch tasks.
+/// `enums` are those of the program, which include the prelude `Rounding`.
+pub fn call(
+    builtin: Builtin,
+    args: Vec<Value>,
+    enums: &[Rc<EnumDef>],
+) -> Result<Value, RuntimeError> {
+    let unsupported = |operand: &Value| RuntimeError::UnsupportedOperand {
+        op: builtin.name(),
+        operand: operand.type_name(),
+    };
+
+    match (builtin, &args[..]) {
+        (Builtin::Len, [value]) => {
+            let len = match value {
+                Value::String(s) => s.chars().count(),
+                Value::List(list) => list.borrow().len(),
+                Value::Map(map) => map.borrow().len(),
+                Value::Vector(vector) => vector.len(),
+                Value::Dict(dict) => dict.len(),
+                other => return Err(unsupported(other)),
+            };
+            // Collections can't hold anywhere near i64::MAX elements
+            Ok(Value::Int(len as i64))
+        }
+        (Builtin::Push, [Value::List(list), value]) => {
+            list.borrow_mut().push(value.clone());
+            Ok(Value::Unit)
+        }
+        (Builtin::Pop, [Value::List(list)]) => list
+            .borrow_mut()
+            .pop()
+            .ok_or(RuntimeError::EmptyList { op: "pop" }),
+        (Builtin::Keys, [Value::Map(map)]) => {
+            Ok(list(map.borrow().keys().map(|k| k.to_value()).collect()))
+        }
+        (Builtin::Values, [Value::Map(map)]) => Ok(list(map.borrow().values().cloned().collect())),
+        (Builtin::Keys, [Value::Dict(dict)]) => {
+            Ok(list(dict.iter().map(|(k, _)| k.to_value()).collect()))
+        }
+        (Builtin::Values, [Value::Dict(dict)]) => {
+            Ok(list(dict.iter().map(|(_, v)| v.clone()).collect()))
+        }
+        (Builtin::Contains, [Value::Map(map), key]) => {
+            Ok(Value::Bool(map.borrow().contains_key(&ops::key(key)?)))
+        }
+        (Builtin::Contains, [Value::List(list), value]) => {
+            Ok(Value::Bool(list.borrow().iter().any(|v| v.equals(value))))
+        }
+        (Builtin::Contains, [Value::Dict(dict), key]) => {
+            Ok(Value::Bool(dict.contains_key(&ops::key(key)?)))
+        }
+        (Builtin::Contains, [Value::Vector(vector), value]) => {
+            Ok(Value::Bool(vector.iter().any(|v| v.equals(value))))
+        }
+        (Builtin::Remove, [Value::Map(map), key]) => map
+            .borrow_mut()
+            .shift_remove(&ops::key(key)?)
+            .ok_or_else(|| RuntimeError::KeyNotFound { key: key.repr() }),
+        (Builtin::Panic, [message]) => Err(RuntimeError::Panic {
+            message: message.to_string(),
+        }),
+        // The adapters are lazy, the VM calls their functions as their values are taken
+        (Builtin::Iter, [value]) => Ok(Value::Iterator(ops::iter(value.clone())?)),
+        (Builtin::Map, [values, f]) => {
+            Ok(iterator(Iter::Map(ops::iter(values.clone())?, f.clone())))
+        }
+        (Builtin::Filter, [values, f]) => Ok(iterator(Iter::Filter(
+            ops::iter(values.clone())?,
+            f.clone(),
+        ))),
+        (Builtin::Take, [values, count @ (Value::Int(_) | Value::BigInt(_))]) => {
+            let count = match count {
+                Value::Int(count) => usize::try_from(*count).ok(),
+                Value::BigInt(count) if count.is_negative() => None,
+                // A big count is as good as unlimited
+                _ => Some(usize::MAX),
+            };
+            let count = count.ok_or_else(|| RuntimeError::NegativeCount {
+                count: args[1].to_string(),
+            })?;
+            Ok(iterator(Iter::Take(ops::iter(values.clone())?, count)))
+        }
+        (Builtin::Zip, [a, b]) => Ok(iterator(Iter::Zip(
+            ops::iter(a.clone())?,
+            ops::iter(b.clone())?,
+        ))),
+        (Builtin::Enumerate, [values]) => {
+            Ok(iterator(Iter::Enumerate(ops::iter(values.clone())?, 0)))
+        }
+        (Builtin::Chan, []) => Ok(Value::Channel(Rc::default())),
+        // Updates of persistent collections copy only the path to what changes, the original is
+        // left as it was for everything else holding it
+        (Builtin::Vector, [Value::List(list)]) => {
+            Ok(vector(list.borrow().iter().cloned().collect()))
+        }
+        (Builtin::Dict, [Value::Map(map)]) => Ok(dict(
+            map.borrow()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        )),
+        (Builtin::Append, [Value::Vector(old), value]) => {
+            let mut new = (**old).clone();
+            new.push(value.clone());
+            Ok(vector(new))
+        }
+        (Builtin::Set, [Value::Vector(old), index @ (Value::Int(_) | Value::BigInt(_)), value]) => {
+            let mut new = (**old).clone();
+            // `element` checked that the vector has more than `i` elements
+            let i = ops::element(index, old.len())?;
+            *new.get_mut(i).unwrap() = value.clone();
+            Ok(vector(new))
+        }
+        (Builtin::Set, [Value::Vector(_), index, _]) => Err(RuntimeError::InvalidIndex {
+            target: "Vector",
+            index: index.type_name(),
+        }),
+        (Builtin::Insert, [Value::Dict(old), key, value]) => {
+            let mut new = (**old).clone();
+            new.insert(ops::key(key)?, value.clone());
+            Ok(dict(new))
+        }
+        (Builtin::Without, [Value::Dict(old), key]) => {
+            let mut new = (**old).clone();
+            new.remove(&ops::key(key)?);
+            Ok(dict(new))
+        }
+        // Conversions to Int truncate toward zero
+        (Builtin::Int, [value]) => Ok(match value {
+            Value::Int(_) | Value::BigInt(_) => value.clone(),
+            Value::Float(x) => Value::int(
+                BigInt::from_f64(x.trunc()).ok_or_else(|| not_convertible(value, "Int"))?,
+            ),
+            Value::Decimal(d) => Value::int(d.trunc()),
+            Value::Rational(r) => Value::int(r.to_integer()),
+            other => return Err(unsupported(other)),
+        }),
+        (Builtin::Float, [value]) => Ok(Value::Float(match value {
+            Value::Int(i) => *i as f64,
+            Value::BigInt(i) => i.to_f64().unwrap_or(f64::NAN),
+            Value::Float(x) => *x,
+            Value::Decimal(d) => d.to_f64(),
+            Value::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            other => return Err(unsupported(other)),
+        })),
+        // A Rational is divided out like a quotient of Decimals, a Float gives the shortest
+        // Decimal that converts back to it
+        (Builtin::Decimal, [value]) => Ok(match value {
+            Value::Decimal(_) => value.clone(),
+            Value::Float(x) => {
+                decimal(Decimal::from_f64(*x).ok_or_else(|| not_convertible(value, "Decimal"))?)
+            }
+            Value::Rational(r) => {
+                let numerator = Decimal::from(r.numer().clone());
+                // This unwrap is safe because a Rational's denominator isn't zero
+                decimal(numerator.quotient(&r.denom().clone().into()).unwrap())
+            }
+            other => match other.to_rational() {
+                Some(int) => decimal(int.to_integer().into()),
+                None => return Err(unsupported(other)),
+            },
+        }),
+        (Builtin::Rational, [numerator, denominator]) => {
+            match (numerator.to_rational(), denominator.to_rational()) {
+                (Some(_), Some(d)) if d.is_zero() => Err(RuntimeError::DivisionByZero),
+                (Some(n), Some(d)) => Ok(Value::Rational(Rc::new(n / d))),
+                (None, _) => Err(unsupported(numerator)),
+                (_, None) => Err(unsupported(denominator)),
+            }
+        }
+        (Builtin::Round, [value, places, mode]) => {
+            let Some(places) = scale(places) else {
+                return Err(unsupported(places));
+            };
+            let Some(mode) = rounding(mode, enums) else {
+                return Err(unsupported(mode));
+            };
+            let places = places?;
+            match value {
+                Value::Decimal(d) => Ok(decimal(d.round(places, mode))),
+                other => match other.to_rational() {
+                    // This unwrap is safe because a Rational's denominator isn't zero
+                    Some(r) => Ok(decimal(
+                        Decimal::from_ratio(r.numer(), r.denom(), places, mode).unwrap(),
+                    )),
+                    None => Err(unsupported(other)),
+                },
+            }
+        }
+        (Builtin::Div, [dividend, divisor, places, mode]) => {
+            let (Some(a), Some(b)) = (dividend.to_rational(), divisor.to_rational()) else {
+                let exact = dividend.to_rational().is_some();
+                return Err(unsupported(if exact { divisor } else { dividend }));
+            };
+            let Some(places) = scale(places) else {
+                return Err(unsupported(places));
+            };
+            let Some(mode) = rounding(mode, enums) else {
+                return Err(unsupported(mode));
+            };
+            let places = places?;
+            if b.is_zero() {
+                return Err(RuntimeError::DivisionByZero);
+            }
+            let quotient = a / b;
+            // This unwrap is safe because a Rational's denominator isn't zero
+            Ok(decimal(
+                Decimal::from_ratio(quotient.numer(), quotient.denom(), places, mode).unwrap(),
+            ))
+        }
+        (_, [first, ..]) => Err(unsupported(first)),
+        (_, []) => unreachable!("only `chan` takes no arguments"),
+    }
+}
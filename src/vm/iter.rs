@@ -0,0 +1,264 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Frame, Returned, RuntimeError, Vm, MAX_FRAMES, MAX_NESTED};
+use crate::value::{Closure, Generator, Iter, Upvalue, Value, NEXT};
+
+/// What taking the next value of an iterator needs to do once the iterator is no longer borrowed,
+/// since it may run code that uses the same iterator
+enum Step {
+    Resume {
+        closure: Rc<Closure>,
+        ip: usize,
+        stack: Vec<Value>,
+        upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,
+    },
+    Method(Value),
+    Map(Rc<RefCell<Iter>>, Value),
+    Filter(Rc<RefCell<Iter>>, Value),
+    Inner(Rc<RefCell<Iter>>),
+    Zip(Rc<RefCell<Iter>>, Rc<RefCell<Iter>>),
+    Enumerate(Rc<RefCell<Iter>>),
+}
+
+impl Vm<'_> {
+    /// Takes the next value of `iter`, `None` once it has run out
+    pub(super) fn next(&mut self, iter: &Rc<RefCell<Iter>>) -> Result<Option<Value>, RuntimeError> {
+        let step = match &mut *iter.borrow_mut() {
+            Iter::Chars { string, offset } => {
+                let c = string[*offset..].chars().next();
+                *offset += c.map_or(0, char::len_utf8);
+                return Ok(c.map(Value::Char));
+            }
+            Iter::List { list, index } => {
+                let value = list.borrow().get(*index).cloned();
+                *index += value.is_some() as usize;
+                return Ok(value);
+            }
+            Iter::Keys { map, index } => {
+                let key = map
+                    .borrow()
+                    .get_index(*index)
+                    .map(|(key, _)| key.to_value());
+                *index += key.is_some() as usize;
+                return Ok(key);
+            }
+            Iter::Vector { vector, index } => {
+                let value = vector.get(*index).cloned();
+                *index += value.is_some() as usize;
+                return Ok(value);
+            }
+            Iter::Generator { closure, state } => {
+                match std::mem::replace(state, Generator::Running) {
+                    Generator::Suspended {
+                        ip,
+                        stack,
+                        upvalues,
+                    } => Step::Resume {
+                        closure: closure.clone(),
+                        ip,
+                        stack,
+                        upvalues,
+                    },
+                    Generator::Running => {
+                        return Err(RuntimeError::GeneratorRunning {
+                            name: closure.function.name.clone(),
+                        })
+                    }
+                    Generator::Done => {
+                        *state = Generator::Done;
+                        return Ok(None);
+                    }
+                }
+            }
+            Iter::Struct(instance) => Step::Method(Value::Struct(instance.clone())),
+            Iter::Map(inner, f) => Step::Map(inner.clone(), f.clone()),
+            Iter::Filter(inner, f) => Step::Filter(inner.clone(), f.clone()),
+            Iter::Take(inner, remaining) => {
+                // The inner iterator isn't stepped past the values taken
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                Step::Inner(inner.clone())
+            }
+            Iter::Zip(a, b) => Step::Zip(a.clone(), b.clone()),
+            Iter::Enumerate(inner, _) => Step::Enumerate(inner.clone()),
+        };
+
+        match step {
+            Step::Resume {
+                closure,
+                ip,
+                stack,
+                upvalues,
+            } => self.resume(iter, closure, ip, stack, upvalues),
+            Step::Method(instance) => {
+                self.check_nesting()?;
+                self.stack.push(instance);
+                let frames = self.frames.len();
+                self.invoke(NEXT, 0)?;
+                Ok(match self.finish(frames)? {
+                    Value::None => None,
+                    value => Some(value),
+                })
+            }
+            Step::Map(inner, f) => match self.next(&inner)? {
+                Some(value) => Ok(Some(self.call_value(f, vec![value])?)),
+                None => Ok(None),
+            },
+            Step::Filter(inner, f) => {
+                while let Some(value) = self.next(&inner)? {
+                    match self.call_value(f.clone(), vec![value.clone()])? {
+                        Value::Bool(true) => return Ok(Some(value)),
+                        Value::Bool(false) => {}
+                        other => {
+                            return Err(RuntimeError::ExpectedBool {
+                                found: other.type_name(),
+                            })
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Step::Inner(inner) => self.next(&inner),
+            Step::Zip(a, b) => {
+                let Some(a) = self.next(&a)? else {
+                    return Ok(None);
+                };
+                let Some(b) = self.next(&b)? else {
+                    return Ok(None);
+                };
+                Ok(Some(Value::Tuple(vec![a, b].into())))
+            }
+            Step::Enumerate(inner) => {
+                let Some(value) = self.next(&inner)? else {
+                    return Ok(None);
+                };
+                let Iter::Enumerate(_, index) = &mut *iter.borrow_mut() else {
+                    unreachable!("enumerate step of another iterator")
+                };
+                *index += 1;
+                Ok(Some(Value::Tuple(
+                    vec![Value::Int(*index - 1), value].into(),
+                )))
+            }
+        }
+    }
+
+    /// Runs the suspended generator `iter` from `ip` until it yields or returns. Its frame is
+    /// rebuilt on top of the stack from `stack`, and `upvalues` are opened again at their slots.
+    fn resume(
+        &mut self,
+        iter: &Rc<RefCell<Iter>>,
+        closure: Rc<Closure>,
+        ip: usize,
+        stack: Vec<Value>,
+        upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(RuntimeError::StackOverflow);
+        }
+        self.check_nesting()?;
+
+        let base = self.stack.len() + 1;
+        self.stack.extend(stack);
+        for (upvalue, slot) in upvalues {
+            // Closures outside of the generator may have changed the value while it was closed
+            let slot = base + slot;
+            match std::mem::replace(&mut *upvalue.borrow_mut(), Upvalue::Open(slot)) {
+                Upvalue::Closed(value) => self.stack[slot] = value,
+                Upvalue::Open(_) => unreachable!("open upvalue of a suspended generator"),
+            }
+            // The frame is on top of the stack, so its upvalues come last
+            self.open_upvalues.push(upvalue);
+        }
+        self.frames.push(Frame {
+            closure,
+            ip,
+            base,
+            returned: Returned::Value,
+        });
+        let depth = self.frames.len();
+        self.execute_nested(depth)?;
+
+        let (state, value) = if self.frames.len() < depth {
+            // Only a returned generator has left its frame, the result is always unit
+            self.pop();
+            (Generator::Done, None)
+        } else {
+            let value = self.pop();
+            // There is always the frame that yielded
+            let frame = self.frames.pop().unwrap();
+            let mut upvalues = vec![];
+            while let Some(upvalue) = self.open_upvalues.last() {
+                let slot = match *upvalue.borrow() {
+                    Upvalue::Open(slot) => slot,
+                    Upvalue::Closed(_) => unreachable!("closed upvalue in open list"),
+                };
+                if slot < frame.base {
+                    break;
+                }
+                // This unwrap is safe because of the loop condition
+                let upvalue = self.open_upvalues.pop().unwrap();
+                *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+                upvalues.push((upvalue, slot - frame.base));
+            }
+            upvalues.reverse();
+
+            let stack = self.stack.split_off(frame.base - 1);
+            let state = Generator::Suspended {
+                ip: frame.ip,
+                stack,
+                upvalues,
+            };
+            (state, Some(value))
+        };
+
+        if let Iter::Generator { state: current, .. } = &mut *iter.borrow_mut() {
+            *current = state;
+        }
+        Ok(value)
+    }
+
+    /// Calls `callee` with `args` and runs it to completion
+    pub(super) fn call_value(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        self.check_nesting()?;
+        let count = args.len();
+        self.stack.push(callee);
+        self.stack.extend(args);
+        let frames = self.frames.len();
+        self.call(count)?;
+        self.finish(frames)
+    }
+
+    /// Runs the frame a call pushed on top of the `frames` there were before it, if it pushed
+    /// one, and takes the call's result
+    fn finish(&mut self, frames: usize) -> Result<Value, RuntimeError> {
+        if self.frames.len() > frames {
+            self.execute_nested(frames + 1)?;
+        }
+        Ok(self.pop())
+    }
+
+    /// Fails if code run from within an instruction can't be nested any deeper, which is checked
+    /// before its frame is pushed
+    fn check_nesting(&self) -> Result<(), RuntimeError> {
+        if self.nested >= MAX_NESTED {
+            return Err(RuntimeError::StackOverflow);
+        }
+        Ok(())
+    }
+
+    /// Runs the frame at `depth` from within an instruction of a frame below it
+    fn execute_nested(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        self.nested += 1;
+        let result = self.execute(depth);
+        self.nested -= 1;
+        result
+    }
+}
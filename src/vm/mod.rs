@@ -0,0 +1,1160 @@
+mod builtins;
+mod iter;
+pub(crate) mod ops;
+mod tasks;
+
+use indexmap::IndexMap;
+use num_traits::Signed;
+use snafu::prelude::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::codegen::bytecode::{Capture, Function, Op};
+use crate::codegen::{Program, RESULT};
+use crate::decimal::MAX_SCALE;
+use crate::modules::location;
+use crate::token::Position;
+use crate::value::{
+    operator_method, Builtin, Closure, EnumDef, EnumValue, Generator, Instance, Iter, StructDef,
+    Upvalue, Value, CMP, EQ, INDEX, NEG, NEXT, OK,
+};
+use tasks::{Blocked, Task};
+
+/// Maximum call depth before the VM reports a stack overflow
+const MAX_FRAMES: usize = 1024;
+
+/// Maximum nesting of code run from within an instruction, such as a generator stepped by a `for`
+/// loop, before the VM reports a stack overflow. Each level takes a large native stack frame.
+const MAX_NESTED: usize = 64;
+
+#[derive(Debug, Snafu)]
+pub enum RuntimeError {
+    #[snafu(display("runtime error - cannot apply `{op}` to {left} and {right}"))]
+    UnsupportedOperands {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
+
+    #[snafu(display("runtime error - cannot apply `{op}` to {operand}"))]
+    UnsupportedOperand {
+        op: &'static str,
+        operand: &'static str,
+    },
+
+    #[snafu(display("runtime error - expected Bool condition, found {found}"))]
+    ExpectedBool { found: &'static str },
+
+    #[snafu(display("runtime error - {found} is not callable"))]
+    NotCallable { found: &'static str },
+
+    #[snafu(display("runtime error - `{name}` expects {expected} arguments, found {found}"))]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[snafu(display(
+        "runtime error - `{name}` expects at most {expected} arguments, found {found}"
+    ))]
+    TooManyArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[snafu(display("runtime error - call of `{name}` is missing {argument}"))]
+    MissingArgument { name: String, argument: String },
+
+    #[snafu(display("runtime error - `{name}` has no parameter `{argument}`"))]
+    UnknownArgument { name: String, argument: String },
+
+    #[snafu(display(
+        "runtime error - argument `{argument}` of `{name}` is passed more than once"
+    ))]
+    DuplicateArgument { name: String, argument: String },
+
+    #[snafu(display("runtime error - {found} cannot be indexed"))]
+    NotIndexable { found: &'static str },
+
+    #[snafu(display("runtime error - {target} cannot be indexed by {index}"))]
+    InvalidIndex {
+        target: &'static str,
+        index: &'static str,
+    },
+
+    #[snafu(display("runtime error - index {index} is out of bounds for length {len}"))]
+    IndexOutOfBounds { index: String, len: usize },
+
+    #[snafu(display("runtime error - slice {start}..{end} is out of bounds for length {len}"))]
+    SliceOutOfBounds {
+        start: String,
+        end: String,
+        len: usize,
+    },
+
+    #[snafu(display("runtime error - {found} does not support indexed assignment"))]
+    NotIndexAssignable { found: &'static str },
+
+    #[snafu(display("runtime error - key {key} is not in the map"))]
+    KeyNotFound { key: String },
+
+    #[snafu(display("runtime error - {found} cannot be used as a map key"))]
+    InvalidKey { found: &'static str },
+
+    #[snafu(display("runtime error - {found} has no field `{field}`"))]
+    NoField { found: String, field: String },
+
+    #[snafu(display("runtime error - {found} has no method `{method}`"))]
+    NoMethod { found: String, method: String },
+
+    #[snafu(display(
+        "runtime error - cannot apply `{op}` to {found}, which has no method `{method}`"
+    ))]
+    NoOperatorMethod {
+        op: &'static str,
+        found: String,
+        method: &'static str,
+    },
+
+    #[snafu(display("runtime error - `cmp` must return an Int, found {found}"))]
+    InvalidOrdering { found: &'static str },
+
+    #[snafu(display("runtime error - `eq` must return a Bool, found {found}"))]
+    InvalidEquality { found: &'static str },
+
+    #[snafu(display("runtime error - cannot {op} from an empty list"))]
+    EmptyList { op: &'static str },
+
+    #[snafu(display("runtime error - {found} has no element {index}"))]
+    NoElement { found: &'static str, index: usize },
+
+    #[snafu(display("runtime error - value does not match the pattern"))]
+    NoMatch,
+
+    #[snafu(display("runtime error - {found} is not iterable"))]
+    NotIterable { found: &'static str },
+
+    #[snafu(display("runtime error - division by zero"))]
+    DivisionByZero,
+
+    #[snafu(display("runtime error - negative exponent {exponent} for {ty} power"))]
+    NegativeExponent { exponent: String, ty: &'static str },
+
+    #[snafu(display("runtime error - exponent {exponent} is too large for {ty} power"))]
+    ExponentTooLarge { exponent: String, ty: &'static str },
+
+    #[snafu(display("runtime error - negative shift amount {amount}"))]
+    NegativeShift { amount: String },
+
+    #[snafu(display("runtime error - shift amount {amount} is too large"))]
+    ShiftTooLarge { amount: String },
+
+    #[snafu(display("runtime error - {value} cannot be converted to {to}"))]
+    NotConvertible { value: String, to: &'static str },
+
+    #[snafu(display("runtime error - scale {scale} is outside of 0..={}", MAX_SCALE))]
+    InvalidScale { scale: String },
+
+    #[snafu(display("runtime error - `{name}` used before it was defined"))]
+    Uninitialized { name: String },
+
+    #[snafu(display("runtime error - `?` expects a Result, found {found}"))]
+    NotResult { found: &'static str },
+
+    #[snafu(display("runtime error - panic: {message}"))]
+    Panic { message: String },
+
+    #[snafu(display("runtime error - `main` returned an error: {value}"))]
+    ErrReturned { value: String },
+
+    #[snafu(display("runtime error - cannot take a negative number of values, found {count}"))]
+    NegativeCount { count: String },
+
+    #[snafu(display("runtime error - generator `{name}` is stepped while it is running"))]
+    GeneratorRunning { name: String },
+
+    #[snafu(display("runtime error - {found} can't be spawned as a task"))]
+    NotSpawnable { found: String },
+
+    #[snafu(display(
+        "runtime error - `{op}` can't wait for a channel in a generator, in the `next` method of \
+         an iterator or in a function given to `map` or `filter`"
+    ))]
+    NestedWait { op: &'static str },
+
+    #[snafu(display("runtime error - deadlock, every task is waiting for a channel"))]
+    Deadlock,
+
+    #[snafu(display("runtime error - stack overflow"))]
+    StackOverflow,
+
+    #[snafu(display("runtime error - failed to write output"))]
+    Output { source: std::io::Error },
+
+    /// An error raised by an instruction with a known source position
+    #[snafu(display("{err} at {}", location(file, *position)))]
+    At {
+        err: Box<RuntimeError>,
+        position: Position,
+        /// Name of the file the position is in, if the source was read from one
+        file: Option<String>,
+    },
+}
+
+/// An error that unwound every call of the program
+#[derive(Debug)]
+pub struct Uncaught {
+    pub err: RuntimeError,
+    /// The calls that were in progress, innermost first
+    pub trace: Vec<TraceFrame>,
+}
+
+/// A call in progress when an error was raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    /// Where the function was when the error was raised, the position of the call it was making
+    /// for all but the innermost function
+    pub position: Option<Position>,
+    pub file: Option<String>,
+    /// The task making the call, 0 for the main program
+    pub task: usize,
+    /// Number of identical calls in a row the frame stands for, more than one when a function
+    /// keeps calling itself from the same place
+    pub calls: usize,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in {}", self.function)?;
+        if let Some(position) = self.position {
+            write!(f, " at {}", location(&self.file, position))?;
+        }
+        if self.calls > 1 {
+            write!(f, " (repeated {} times)", self.calls)?;
+        }
+        Ok(())
+    }
+}
+
+/// The functions of `frames` of the task `task`, innermost first. Identical frames in a row are
+/// collapsed into one.
+fn trace(task: usize, frames: &[Frame]) -> Vec<TraceFrame> {
+    let mut trace: Vec<TraceFrame> = vec![];
+    for frame in frames.iter().rev() {
+        let chunk = &frame.closure.function.chunk;
+        let frame = TraceFrame {
+            function: frame.closure.function.name.clone(),
+            position: chunk.positions[frame.ip - 1],
+            file: chunk.file.as_deref().map(str::to_string),
+            task,
+            calls: 1,
+        };
+        match trace.last_mut() {
+            Some(last)
+                if last.function == frame.function
+                    && last.position == frame.position
+                    && last.file == frame.file =>
+            {
+                last.calls += 1
+            }
+            _ => trace.push(frame),
+        }
+    }
+    trace
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+    /// What is made of the result when the frame returns
+    returned: Returned,
+}
+
+/// What the result of a frame is made into when it returns. The method of an overloaded operator
+/// runs in a frame like any other call, and its result becomes that of the operator on return.
+#[derive(Clone, Copy)]
+enum Returned {
+    Value,
+    /// The result of `eq`, which is negated for `!=`
+    Equal {
+        equal: bool,
+    },
+    /// The result of `cmp`, which orders the struct against the operand as it is against zero
+    Ordering {
+        test: fn(Ordering) -> bool,
+    },
+}
+
+impl Returned {
+    fn apply(self, value: Value) -> Result<Value, RuntimeError> {
+        match self {
+            Returned::Value => Ok(value),
+            Returned::Equal { equal } => match value {
+                Value::Bool(b) => Ok(Value::Bool(b == equal)),
+                other => Err(RuntimeError::InvalidEquality {
+                    found: other.type_name(),
+                }),
+            },
+            Returned::Ordering { test } => {
+                let ordering = match value {
+                    Value::Int(i) => i.cmp(&0),
+                    // A big Int is never zero
+                    Value::BigInt(i) if i.is_negative() => Ordering::Less,
+                    Value::BigInt(_) => Ordering::Greater,
+                    other => {
+                        return Err(RuntimeError::InvalidOrdering {
+                            found: other.type_name(),
+                        })
+                    }
+                };
+                Ok(Value::Bool(test(ordering)))
+            }
+        }
+    }
+}
+
+/// The name held by a value of a names constant
+fn name_str(name: &Value) -> &str {
+    match name {
+        Value::String(name) => name,
+        other => unreachable!("name {} is not a string", other),
+    }
+}
+
+/// Fails if a call of `name`, which only takes arguments by position, passes any by `names`
+fn positional_only(name: &str, names: &[Value]) -> Result<(), RuntimeError> {
+    match names.first() {
+        Some(argument) => Err(RuntimeError::UnknownArgument {
+            name: name.to_string(),
+            argument: name_str(argument).to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Stack based virtual machine that executes a generated `Program`
+pub struct Vm<'w> {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: Vec<Option<Value>>,
+    global_names: Vec<String>,
+    structs: Vec<Rc<StructDef>>,
+    enums: Vec<Rc<EnumDef>>,
+    /// Upvalues that still refer to the stack, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Number of nested runs of `execute` in progress
+    nested: usize,
+    /// The id of the running task
+    task: usize,
+    /// The id of the task spawned last
+    next_task: usize,
+    /// Tasks that can run, in the order they became ready
+    ready: VecDeque<Task>,
+    /// Tasks waiting for a value, in the order they started waiting
+    blocked: Vec<Blocked>,
+    out: &'w mut dyn Write,
+}
+
+impl<'w> Vm<'w> {
+    /// Creates a VM that writes the output of `print` to `out`
+    pub fn new(out: &'w mut dyn Write) -> Self {
+        Self {
+            stack: vec![],
+            frames: vec![],
+            globals: vec![],
+            global_names: vec![],
+            structs: vec![],
+            enums: vec![],
+            open_upvalues: vec![],
+            nested: 0,
+            task: 0,
+            next_task: 0,
+            ready: VecDeque::new(),
+            blocked: vec![],
+            out,
+        }
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), Uncaught> {
+        self.global_names = program.globals.clone();
+        self.globals = vec![None; program.globals.len()];
+        self.structs = program.structs.clone();
+        self.enums = program.enums.clone();
+
+        let script = Rc::new(Closure {
+            function: program.script.clone(),
+            upvalues: vec![],
+        });
+        self.stack.push(Value::Function(script.clone()));
+        self.frames.push(Frame {
+            closure: script,
+            ip: 0,
+            base: 1,
+            returned: Returned::Value,
+        });
+
+        let result = self.schedule().map_err(|err| match err {
+            RuntimeError::Deadlock => Uncaught {
+                trace: self.deadlock_trace(),
+                err,
+            },
+            err => Uncaught {
+                err: self.locate(err),
+                trace: self.trace(),
+            },
+        });
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.clear_tasks();
+        result
+    }
+
+    /// Attaches the position of the instruction that raised `err`, if it has one
+    fn locate(&self, err: RuntimeError) -> RuntimeError {
+        let Some(frame) = self.frames.last() else {
+            return err;
+        };
+        let chunk = &frame.closure.function.chunk;
+        // The ip has already moved past the failing instruction
+        match chunk.positions[frame.ip - 1] {
+            Some(position) => RuntimeError::At {
+                err: Box::new(err),
+                position,
+                file: chunk.file.as_deref().map(str::to_string),
+            },
+            None => err,
+        }
+    }
+
+    /// The functions of the frames of the running task, innermost first. The script that calls
+    /// the top level code of each module is left out.
+    fn trace(&self) -> Vec<TraceFrame> {
+        let skip = (self.task == 0) as usize;
+        trace(self.task, self.frames.get(skip..).unwrap_or_default())
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        // Instructions only execute while there is a frame
+        self.frames.last_mut().unwrap()
+    }
+
+    fn pop(&mut self) -> Value {
+        // Generated code never pops more than it pushed
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self) -> &Value {
+        // Generated code never peeks an empty stack
+        self.stack.last().unwrap()
+    }
+
+    /// Returns the upvalue for the stack slot `slot`, reusing an open one if it exists
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .rposition(|u| matches!(*u.borrow(), Upvalue::Open(s) if s <= slot));
+        if let Some(position) = position {
+            let upvalue = &self.open_upvalues[position];
+            if matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot) {
+                return upvalue.clone();
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let index = position.map_or(0, |p| p + 1);
+        self.open_upvalues.insert(index, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of open upvalues at or above `slot` off the stack
+    fn close_upvalues(&mut self, slot: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let open = match *upvalue.borrow() {
+                Upvalue::Open(s) => s,
+                Upvalue::Closed(_) => unreachable!("closed upvalue in open list"),
+            };
+            if open < slot {
+                break;
+            }
+
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[open].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: fn(Value, Value) -> Result<Value, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        let left = self.pop();
+        self.stack.push(op(left, right)?);
+        Ok(())
+    }
+
+    /// Applies the arithmetic operator `op`, which structs overload with a method
+    fn arithmetic(
+        &mut self,
+        op: &'static str,
+        apply: fn(Value, Value) -> Result<Value, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        // Every arithmetic operator but `**` has a method
+        let method = operator_method(op).unwrap();
+        match self.overload(op, method, 1)? {
+            Some(method) => self.call_overload(method, 1, Returned::Value),
+            None => self.binary(apply),
+        }
+    }
+
+    fn comparison(
+        &mut self,
+        op: &'static str,
+        test: fn(Ordering) -> bool,
+    ) -> Result<(), RuntimeError> {
+        if let Some(method) = self.overload(op, CMP, 1)? {
+            return self.call_overload(method, 1, Returned::Ordering { test });
+        }
+        let right = self.pop();
+        let left = self.pop();
+        let ordering = ops::compare(op, &left, &right)?;
+        self.stack.push(Value::Bool(ordering.is_some_and(test)));
+        Ok(())
+    }
+
+    /// Compares the top two values of the stack with `==`, or `!=` when `equal` is false. A
+    /// struct with an `eq` method on the left is compared by calling it, except with `none`.
+    fn equality(&mut self, equal: bool) -> Result<(), RuntimeError> {
+        let op = if equal { "==" } else { "!=" };
+        let overloaded = match &self.stack[self.stack.len() - 2..] {
+            [Value::Struct(_), Value::None] => false,
+            [Value::Struct(instance), _] => instance.borrow().def.methods.contains_key(EQ),
+            _ => false,
+        };
+        if !overloaded {
+            let right = self.pop();
+            let left = self.pop();
+            self.stack.push(Value::Bool(left.equals(&right) == equal));
+            return Ok(());
+        }
+        // This unwrap is safe because the struct was just found to have the method
+        let method = self.overload(op, EQ, 1)?.unwrap();
+        self.call_overload(method, 1, Returned::Equal { equal })
+    }
+
+    /// The method `method` overloading `op` when the operand below the top `args` values of the
+    /// stack is a struct, which fails if the struct doesn't have it
+    fn overload(
+        &self,
+        op: &'static str,
+        method: &'static str,
+        args: usize,
+    ) -> Result<Option<Rc<Closure>>, RuntimeError> {
+        let Value::Struct(instance) = &self.stack[self.stack.len() - 1 - args] else {
+            return Ok(None);
+        };
+        let instance = instance.borrow();
+        match instance.def.methods.get(method) {
+            Some(method) => Ok(Some(method.clone())),
+            None => Err(RuntimeError::NoOperatorMethod {
+                op,
+                found: instance.def.name.clone(),
+                method,
+            }),
+        }
+    }
+
+    /// Calls `method` with the struct below the top `args` values of the stack as its receiver,
+    /// making its result into that of the operator as `returned` says
+    fn call_overload(
+        &mut self,
+        method: Rc<Closure>,
+        args: usize,
+        returned: Returned,
+    ) -> Result<(), RuntimeError> {
+        let receiver = self.stack.len() - 1 - args;
+        self.stack.insert(receiver, Value::Function(method));
+        let frames = self.frames.len();
+        self.call(args + 1)?;
+        if self.frames.len() > frames {
+            self.frame().returned = returned;
+        } else {
+            // A generator method returns its iterator without running
+            let value = self.pop();
+            self.stack.push(returned.apply(value)?);
+        }
+        Ok(())
+    }
+
+    fn condition(&self) -> Result<bool, RuntimeError> {
+        match self.peek() {
+            Value::Bool(b) => Ok(*b),
+            other => Err(RuntimeError::ExpectedBool {
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn call(&mut self, arg_count: usize) -> Result<(), RuntimeError> {
+        self.call_named(arg_count, &[])
+    }
+
+    /// Calls the value below the top `arg_count` values with them, the last of which are passed
+    /// by `names`
+    fn call_named(&mut self, arg_count: usize, names: &[Value]) -> Result<(), RuntimeError> {
+        let callee = self.stack[self.stack.len() - 1 - arg_count].clone();
+        match callee {
+            Value::Function(closure) => {
+                let slots = self.arguments(&closure.function, arg_count, names)?;
+                if closure.function.generator {
+                    // The body runs as the generator is stepped, in a frame made of the callee
+                    // and arguments
+                    let stack = self.stack.split_off(self.stack.len() - 1 - slots);
+                    let state = Generator::Suspended {
+                        ip: 0,
+                        stack,
+                        upvalues: vec![],
+                    };
+                    let iter = Iter::Generator { closure, state };
+                    self.stack
+                        .push(Value::Iterator(Rc::new(RefCell::new(iter))));
+                    return Ok(());
+                }
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(RuntimeError::StackOverflow);
+                }
+
+                self.frames.push(Frame {
+                    closure,
+                    ip: 0,
+                    base: self.stack.len() - slots,
+                    returned: Returned::Value,
+                });
+                Ok(())
+            }
+            Value::Builtin(builtin) => {
+                positional_only(builtin.name(), names)?;
+                if builtin.arity() != arg_count {
+                    return Err(RuntimeError::ArityMismatch {
+                        name: builtin.name().to_string(),
+                        expected: builtin.arity(),
+                        found: arg_count,
+                    });
+                }
+                if matches!(builtin, Builtin::Send | Builtin::Recv | Builtin::Select) {
+                    return self.channel_call(builtin, arg_count);
+                }
+
+                let args = self.stack.split_off(self.stack.len() - arg_count);
+                self.pop();
+                self.stack.push(builtins::call(builtin, args, &self.enums)?);
+                Ok(())
+            }
+            Value::Constructor(def, variant) => {
+                positional_only(&def.variant_name(variant), names)?;
+                let arity = def.variants[variant].arity;
+                if arity != arg_count {
+                    return Err(RuntimeError::ArityMismatch {
+                        name: def.variant_name(variant),
+                        expected: arity,
+                        found: arg_count,
+                    });
+                }
+
+                let values = self.stack.split_off(self.stack.len() - arg_count);
+                self.pop();
+                self.stack.push(Value::Enum(Rc::new(EnumValue {
+                    def,
+                    variant,
+                    values,
+                })));
+                Ok(())
+            }
+            other => Err(RuntimeError::NotCallable {
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// Sorts the top `arg_count` values of the stack, the last of which are passed by `names`,
+    /// into the parameters of `function`, and gives the number of slots they take
+    fn arguments(
+        &mut self,
+        function: &Function,
+        arg_count: usize,
+        names: &[Value],
+    ) -> Result<usize, RuntimeError> {
+        let params = &function.params;
+        if names.is_empty() && !params.has_defaults() && !params.variadic {
+            if params.names.len() != arg_count {
+                return Err(RuntimeError::ArityMismatch {
+                    name: function.name.clone(),
+                    expected: params.names.len(),
+                    found: arg_count,
+                });
+            }
+            return Ok(arg_count);
+        }
+
+        let mut positional = self.stack.split_off(self.stack.len() - arg_count);
+        let named = positional.split_off(arg_count - names.len());
+        let named = names.iter().map(name_str).zip(named).collect();
+        let bound = ops::bind(&function.name, params, positional, named)?;
+        // The hidden local records which of the parameters with defaults were passed
+        let mut passed = vec![];
+        for (i, arg) in bound.params.into_iter().enumerate() {
+            if i >= params.required {
+                passed.push(Value::Bool(arg.is_some()));
+            }
+            self.stack.push(arg.unwrap_or(Value::Unit));
+        }
+        if params.variadic {
+            self.stack
+                .push(Value::List(Rc::new(RefCell::new(bound.rest))));
+        }
+        if params.has_defaults() {
+            self.stack.push(Value::Tuple(passed.into()));
+        }
+        Ok(params.slots())
+    }
+
+    /// The names held by the tuple constant at `index` of the current function
+    fn names(&mut self, index: u32) -> Rc<[Value]> {
+        match &self.frame().closure.function.chunk.constants[index as usize] {
+            Value::Tuple(names) => names.clone(),
+            other => unreachable!("names constant {} is not a tuple", other),
+        }
+    }
+
+    /// The name held by the constant at `index` of the current function
+    fn name(&mut self, index: u32) -> Rc<str> {
+        match &self.frame().closure.function.chunk.constants[index as usize] {
+            Value::String(name) => name.clone(),
+            other => unreachable!("name constant {} is not a string", other),
+        }
+    }
+
+    /// Calls the method `name` of the value below the top `arg_count` values
+    fn invoke(&mut self, name: &str, arg_count: usize) -> Result<(), RuntimeError> {
+        self.invoke_named(name, arg_count, &[])
+    }
+
+    /// Calls the method `name` like `invoke`, the last of the `arg_count` values being passed by
+    /// `names`
+    fn invoke_named(
+        &mut self,
+        name: &str,
+        arg_count: usize,
+        names: &[Value],
+    ) -> Result<(), RuntimeError> {
+        let receiver = self.stack.len() - 1 - arg_count;
+        let instance = match &self.stack[receiver] {
+            Value::Struct(instance) => instance.clone(),
+            Value::Iterator(iter) if name == NEXT => {
+                positional_only(NEXT, names)?;
+                if arg_count != 0 {
+                    return Err(RuntimeError::ArityMismatch {
+                        name: NEXT.to_string(),
+                        expected: 0,
+                        found: arg_count,
+                    });
+                }
+                let iter = iter.clone();
+                self.stack[receiver] = self.next(&iter)?.unwrap_or(Value::None);
+                return Ok(());
+            }
+            other => {
+                return Err(RuntimeError::NoMethod {
+                    found: other.type_name().to_string(),
+                    method: name.to_string(),
+                })
+            }
+        };
+
+        let instance = instance.borrow();
+        if let Some(method) = instance.def.methods.get(name) {
+            // The receiver becomes the first argument
+            let method = Value::Function(method.clone());
+            drop(instance);
+            self.stack.insert(receiver, method);
+            return self.call_named(arg_count + 1, names);
+        }
+        match instance.def.field(name) {
+            Some(index) => {
+                self.stack[receiver] = instance.fields[index].clone();
+                drop(instance);
+                self.call_named(arg_count, names)
+            }
+            None => Err(RuntimeError::NoMethod {
+                found: instance.def.name.clone(),
+                method: name.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the top of the stack from the current function
+    fn ret(&mut self) -> Result<(), RuntimeError> {
+        let result = self.pop();
+        // There is always a frame to return from
+        let frame = self.frames.pop().unwrap();
+        self.close_upvalues(frame.base);
+        self.stack.truncate(frame.base - 1);
+        self.stack.push(frame.returned.apply(result)?);
+        Ok(())
+    }
+
+    /// Runs the frame at `depth` of the frames, counting from 1, until it returns or yields.
+    /// Code that steps an iterator or calls a function from within an instruction runs a nested
+    /// loop for its frame.
+    fn execute(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        loop {
+            let frame = self.frame();
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(index) => {
+                    let frame = self.frame();
+                    let value = frame.closure.function.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Unit => self.stack.push(Value::Unit),
+                Op::True => self.stack.push(Value::Bool(true)),
+                Op::False => self.stack.push(Value::Bool(false)),
+                Op::Pop => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+
+                Op::GetLocal(slot) => {
+                    let base = self.frame().base;
+                    self.stack.push(self.stack[base + slot as usize].clone());
+                }
+                Op::SetLocal(slot) => {
+                    let base = self.frame().base;
+                    self.stack[base + slot as usize] = self.peek().clone();
+                }
+                Op::GetGlobal(slot) => match &self.globals[slot as usize] {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => {
+                        return Err(RuntimeError::Uninitialized {
+                            name: self.global_names[slot as usize].clone(),
+                        })
+                    }
+                },
+                Op::SetGlobal(slot) => {
+                    if self.globals[slot as usize].is_none() {
+                        return Err(RuntimeError::Uninitialized {
+                            name: self.global_names[slot as usize].clone(),
+                        });
+                    }
+                    self.globals[slot as usize] = Some(self.peek().clone());
+                }
+                Op::DefineGlobal(slot) => {
+                    let value = self.pop();
+                    self.globals[slot as usize] = Some(value);
+                }
+                Op::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = self.peek().clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+
+                Op::Add => self.arithmetic("+", ops::add)?,
+                Op::Subtract => self.arithmetic("-", ops::subtract)?,
+                Op::Multiply => self.arithmetic("*", ops::multiply)?,
+                Op::Divide => self.arithmetic("/", ops::divide)?,
+                Op::Modulo => self.arithmetic("%", ops::modulo)?,
+                Op::Power => self.binary(ops::power)?,
+                Op::Negate => match self.overload("-", NEG, 0)? {
+                    Some(method) => self.call_overload(method, 0, Returned::Value)?,
+                    None => {
+                        let value = self.pop();
+                        self.stack.push(ops::negate(value)?);
+                    }
+                },
+                Op::Not => {
+                    let value = self.pop();
+                    self.stack.push(ops::not(value)?);
+                }
+
+                Op::BitAnd => self.binary(ops::bit_and)?,
+                Op::BitOr => self.binary(ops::bit_or)?,
+                Op::BitXor => self.binary(ops::bit_xor)?,
+                Op::BitNot => {
+                    let value = self.pop();
+                    self.stack.push(ops::bit_not(value)?);
+                }
+                Op::ShiftLeft => self.binary(ops::shift_left)?,
+                Op::ShiftRight => self.binary(ops::shift_right)?,
+
+                Op::Equal => self.equality(true)?,
+                Op::NotEqual => self.equality(false)?,
+                Op::Greater => self.comparison(">", Ordering::is_gt)?,
+                Op::GreaterEqual => self.comparison(">=", Ordering::is_ge)?,
+                Op::Less => self.comparison("<", Ordering::is_lt)?,
+                Op::LessEqual => self.comparison("<=", Ordering::is_le)?,
+
+                Op::Jump(target) => self.frame().ip = target,
+                Op::JumpIfFalse(target) => {
+                    if !self.condition()? {
+                        self.frame().ip = target;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.condition()? {
+                        self.frame().ip = target;
+                    }
+                }
+                Op::JumpIfNone(target) => {
+                    if matches!(self.peek(), Value::None) {
+                        self.frame().ip = target;
+                    }
+                }
+                Op::JumpIfPassed { param, target } => {
+                    let frame = self.frame();
+                    let params = &frame.closure.function.params;
+                    let slot = frame.base + params.names.len() + params.variadic as usize;
+                    let index = param as usize - params.required;
+                    let Value::Tuple(passed) = &self.stack[slot] else {
+                        unreachable!("the parameters passed are not recorded in a tuple")
+                    };
+                    if matches!(passed[index], Value::Bool(true)) {
+                        self.frame().ip = target;
+                    }
+                }
+
+                Op::Closure(index) => {
+                    let frame = self.frame();
+                    let base = frame.base;
+                    let enclosing = frame.closure.clone();
+                    let function = enclosing.function.chunk.functions[index as usize].clone();
+
+                    let upvalues = function
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.capture(base + *slot as usize),
+                            Capture::Upvalue(index) => enclosing.upvalues[*index as usize].clone(),
+                        })
+                        .collect();
+                    self.stack
+                        .push(Value::Function(Rc::new(Closure { function, upvalues })));
+                }
+                Op::Construct(index) => {
+                    let def = self.structs[index as usize].clone();
+                    let fields = self.stack.split_off(self.stack.len() - def.fields.len());
+                    let instance = Instance { def, fields };
+                    self.stack
+                        .push(Value::Struct(Rc::new(RefCell::new(instance))));
+                }
+                Op::GetField(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    self.stack.push(ops::get_field(&value, &name)?);
+                }
+                Op::SetField(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    let target = self.pop();
+                    ops::set_field(&target, &name, value.clone())?;
+                    self.stack.push(value);
+                }
+                Op::Invoke { name, args } => {
+                    let name = self.name(name);
+                    self.invoke(&name, args as usize)?;
+                }
+                Op::InvokeNamed { name, args, names } => {
+                    let (name, names) = (self.name(name), self.names(names));
+                    self.invoke_named(&name, args as usize, &names)?;
+                }
+                Op::Call(arg_count) => self.call(arg_count as usize)?,
+                Op::CallNamed { args, names } => {
+                    let names = self.names(names);
+                    self.call_named(args as usize, &names)?;
+                }
+                Op::Spawn(arg_count) => self.spawn(arg_count as usize, &[])?,
+                Op::SpawnNamed { args, names } => {
+                    let names = self.names(names);
+                    self.spawn(args as usize, &names)?;
+                }
+                Op::List(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack
+                        .push(Value::List(Rc::new(RefCell::new(elements))));
+                }
+                Op::Map(count) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut map = IndexMap::with_capacity(count as usize);
+                    for pair in entries.chunks(2) {
+                        map.insert(ops::key(&pair[0])?, pair[1].clone());
+                    }
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                Op::Index => match self.overload("[]", INDEX, 1)? {
+                    Some(method) => self.call_overload(method, 1, Returned::Value)?,
+                    None => {
+                        let index = self.pop();
+                        let target = self.pop();
+                        self.stack.push(ops::index(target, index)?);
+                    }
+                },
+                Op::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let target = self.pop();
+                    ops::set_index(target, index, value.clone())?;
+                    self.stack.push(value);
+                }
+                Op::Slice => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let target = self.pop();
+                    self.stack.push(ops::slice(target, start, end)?);
+                }
+                Op::Tuple(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Value::Tuple(elements.into()));
+                }
+                Op::Element(index) => {
+                    let value = self.pop();
+                    let index = index as usize;
+                    let element = match &value {
+                        Value::Tuple(values) => values.get(index).cloned(),
+                        Value::Enum(value) => value.values.get(index).cloned(),
+                        Value::List(items) => items.borrow().get(index).cloned(),
+                        Value::Struct(instance) => instance.borrow().fields.get(index).cloned(),
+                        _ => None,
+                    };
+                    let element = element.ok_or_else(|| RuntimeError::NoElement {
+                        found: value.type_name(),
+                        index,
+                    })?;
+                    self.stack.push(element);
+                }
+                Op::IsTuple(len) => {
+                    let value = self.pop();
+                    let is_tuple =
+                        matches!(&value, Value::Tuple(values) if values.len() == len as usize);
+                    self.stack.push(Value::Bool(is_tuple));
+                }
+                Op::IsList { len, rest } => {
+                    let value = self.pop();
+                    let is_list = match &value {
+                        Value::List(items) if rest => items.borrow().len() >= len as usize,
+                        Value::List(items) => items.borrow().len() == len as usize,
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(is_list));
+                }
+                Op::IsStruct(index) => {
+                    let value = self.pop();
+                    let is_struct = match &value {
+                        Value::Struct(instance) => {
+                            Rc::ptr_eq(&instance.borrow().def, &self.structs[index as usize])
+                        }
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(is_struct));
+                }
+                Op::NoMatch => return Err(RuntimeError::NoMatch),
+                Op::IsVariant { def, variant } => {
+                    let value = self.pop();
+                    let is_variant = match &value {
+                        Value::Enum(value) => {
+                            Rc::ptr_eq(&value.def, &self.enums[def as usize])
+                                && value.variant == variant as usize
+                        }
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(is_variant));
+                }
+                Op::Duplicate => self.stack.push(self.peek().clone()),
+                Op::DupPair => {
+                    let len = self.stack.len();
+                    self.stack.extend_from_within(len - 2..);
+                }
+
+                Op::Iter => {
+                    let value = self.pop();
+                    self.stack.push(Value::Iterator(ops::iter(value)?));
+                }
+                Op::IterNext { slot, exit } => {
+                    let base = self.frame().base;
+                    let iter = match &self.stack[base + slot as usize] {
+                        Value::Iterator(iter) => iter.clone(),
+                        other => unreachable!("for loop over non iterator {}", other),
+                    };
+                    match self.next(&iter)? {
+                        Some(value) => self.stack.push(value),
+                        None => self.frame().ip = exit,
+                    }
+                }
+
+                Op::Print => {
+                    let value = self.pop();
+                    writeln!(self.out, "{}", value).context(OutputSnafu)?;
+                }
+                Op::Return => {
+                    self.ret()?;
+                    if self.frames.len() < depth {
+                        return Ok(());
+                    }
+                }
+                // The frame stays for the code stepping the generator to suspend
+                Op::Yield => return Ok(()),
+                Op::Try => {
+                    let value = self.peek();
+                    let result = match value {
+                        Value::Enum(value) if Rc::ptr_eq(&value.def, &self.enums[RESULT]) => {
+                            value.clone()
+                        }
+                        other => {
+                            return Err(RuntimeError::NotResult {
+                                found: other.type_name(),
+                            })
+                        }
+                    };
+                    if result.variant == OK {
+                        self.pop();
+                        self.stack.push(result.values[0].clone());
+                    } else {
+                        self.ret()?;
+                        if self.frames.len() < depth {
+                            return Ok(());
+                        }
+                    }
+                }
+                Op::CheckErr => {
+                    if let Value::Enum(value) = self.peek() {
+                        if Rc::ptr_eq(&value.def, &self.enums[RESULT]) && value.variant != OK {
+                            return Err(RuntimeError::ErrReturned {
+                                value: value.values[0].to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
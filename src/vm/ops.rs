@@ -0,0 +1,652 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use super::RuntimeError;
+use crate::codegen::bytecode::Params;
+use crate::decimal::Decimal;
+use crate::value::{Iter, Key, Value, NEXT};
+
+fn unsupported(op: &'static str, left: &Value, right: &Value) -> RuntimeError {
+    RuntimeError::UnsupportedOperands {
+        op,
+        left: left.type_name(),
+        right: right.type_name(),
+    }
+}
+
+/// The operands of an arithmetic operator in the representation it is computed in
+enum Operands {
+    Ints(i64, i64),
+    /// Ints of which at least one is big
+    BigInts(BigInt, BigInt),
+    /// Ints and Decimals of which at least one is a Decimal
+    Decimals(Decimal, Decimal),
+    /// Exact numbers of which at least one is a Rational
+    Rationals(BigRational, BigRational),
+    /// Ints and Floats of which at least one is a Float
+    Floats(f64, f64),
+}
+
+/// The Int `value` as a big integer, if it is an Int
+fn big(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(i) => Some(BigInt::from(*i)),
+        Value::BigInt(i) => Some((**i).clone()),
+        _ => None,
+    }
+}
+
+/// The Int or Decimal `value` as a Decimal
+fn decimal(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Decimal(d) => Some((**d).clone()),
+        _ => big(value).map(Decimal::from),
+    }
+}
+
+fn negative(value: &Value) -> bool {
+    match value {
+        Value::Int(i) => *i < 0,
+        Value::BigInt(i) => i.is_negative(),
+        _ => false,
+    }
+}
+
+/// Whether `value` is an exact number that is zero
+fn zero(value: &Value) -> bool {
+    match value {
+        Value::Int(i) => *i == 0,
+        Value::Decimal(d) => d.is_zero(),
+        Value::Rational(r) => r.is_zero(),
+        _ => false,
+    }
+}
+
+/// The Int or Float `value` as a Float. Ints too large for a Float become infinite. Decimals and
+/// Rationals are never converted implicitly, since that would lose their exactness.
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::BigInt(i) => Some(i.to_f64().unwrap_or(f64::NAN)),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+fn operands(op: &'static str, left: &Value, right: &Value) -> Result<Operands, RuntimeError> {
+    let operands = match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(Operands::Ints(*a, *b)),
+        (Value::Float(_), _) | (_, Value::Float(_)) => float(left)
+            .zip(float(right))
+            .map(|(a, b)| Operands::Floats(a, b)),
+        (Value::Rational(_), _) | (_, Value::Rational(_)) => left
+            .to_rational()
+            .zip(right.to_rational())
+            .map(|(a, b)| Operands::Rationals(a, b)),
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => decimal(left)
+            .zip(decimal(right))
+            .map(|(a, b)| Operands::Decimals(a, b)),
+        _ => big(left)
+            .zip(big(right))
+            .map(|(a, b)| Operands::BigInts(a, b)),
+    };
+    operands.ok_or_else(|| unsupported(op, left, right))
+}
+
+/// An arithmetic operator as computed in each representation of its operands
+struct Arithmetic {
+    op: &'static str,
+    /// `None` when the result doesn't fit in 64 bits
+    ints: fn(i64, i64) -> Option<i64>,
+    bigs: fn(BigInt, BigInt) -> BigInt,
+    decimals: fn(&Decimal, &Decimal) -> Decimal,
+    rationals: fn(BigRational, BigRational) -> BigRational,
+    floats: fn(f64, f64) -> f64,
+}
+
+impl Arithmetic {
+    /// Applies the operator, computing Ints that overflow 64 bits as big integers
+    fn apply(&self, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+        Ok(match operands(self.op, left, right)? {
+            Operands::Ints(a, b) => match (self.ints)(a, b) {
+                Some(result) => Value::Int(result),
+                None => Value::int((self.bigs)(a.into(), b.into())),
+            },
+            Operands::BigInts(a, b) => Value::int((self.bigs)(a, b)),
+            Operands::Decimals(a, b) => Value::Decimal(Rc::new((self.decimals)(&a, &b))),
+            Operands::Rationals(a, b) => Value::Rational(Rc::new((self.rationals)(a, b))),
+            Operands::Floats(a, b) => Value::Float((self.floats)(a, b)),
+        })
+    }
+}
+
+pub fn add(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    Ok(match (&left, &right) {
+        (Value::String(a), Value::String(b)) => Value::String(format!("{}{}", a, b).into()),
+        (Value::String(a), Value::Char(b)) => Value::String(format!("{}{}", a, b).into()),
+        (Value::Char(a), Value::String(b)) => Value::String(format!("{}{}", a, b).into()),
+        _ => Arithmetic {
+            op: "+",
+            ints: i64::checked_add,
+            bigs: |a, b| a + b,
+            decimals: Decimal::add,
+            rationals: |a, b| a + b,
+            floats: |a, b| a + b,
+        }
+        .apply(&left, &right)?,
+    })
+}
+
+pub fn subtract(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    Arithmetic {
+        op: "-",
+        ints: i64::checked_sub,
+        bigs: |a, b| a - b,
+        decimals: Decimal::sub,
+        rationals: |a, b| a - b,
+        floats: |a, b| a - b,
+    }
+    .apply(&left, &right)
+}
+
+pub fn multiply(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    Arithmetic {
+        op: "*",
+        ints: i64::checked_mul,
+        bigs: |a, b| a * b,
+        decimals: Decimal::mul,
+        rationals: |a, b| a * b,
+        floats: |a, b| a * b,
+    }
+    .apply(&left, &right)
+}
+
+/// Whether dividing `left` by `right` is an exact division by zero
+fn divides_by_zero(left: &Value, right: &Value) -> bool {
+    left.to_rational().is_some() && zero(right)
+}
+
+/// Int division truncates toward zero, Decimal division rounds half to even to at least
+/// `DIVISION_SCALE` places and Rational division is exact. Dividing an exact number by zero is
+/// an error. Float division follows IEEE 754, so dividing by zero gives an infinity or NaN.
+pub fn divide(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    if divides_by_zero(&left, &right) {
+        return Err(RuntimeError::DivisionByZero);
+    }
+    Arithmetic {
+        op: "/",
+        ints: i64::checked_div,
+        bigs: |a, b| a / b,
+        // This unwrap is safe because division by zero was rejected above
+        decimals: |a, b| a.quotient(b).unwrap(),
+        rationals: |a, b| a / b,
+        floats: |a, b| a / b,
+    }
+    .apply(&left, &right)
+}
+
+/// Remainder of truncating division, taking the sign of the dividend
+pub fn modulo(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    if divides_by_zero(&left, &right) {
+        return Err(RuntimeError::DivisionByZero);
+    }
+    Arithmetic {
+        op: "%",
+        ints: i64::checked_rem,
+        bigs: |a, b| a % b,
+        // This unwrap is safe because division by zero was rejected above
+        decimals: |a, b| a.rem(b).unwrap(),
+        rationals: |a, b| a % b,
+        floats: |a, b| a % b,
+    }
+    .apply(&left, &right)
+}
+
+/// Exponentiation. Ints, Decimals and Rationals raised to Int powers keep their type, and only
+/// Rationals may have a negative exponent. Anything involving a Float is a Float.
+pub fn power(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    let too_large = || RuntimeError::ExponentTooLarge {
+        exponent: right.to_string(),
+        ty: left.type_name(),
+    };
+    let exponent = big(&right);
+    Ok(match operands("**", &left, &right)? {
+        Operands::Floats(a, b) => Value::Float(a.powf(b)),
+        // The exponent of an exact number must be an Int
+        Operands::Decimals(..) | Operands::Rationals(..) if exponent.is_none() => {
+            return Err(unsupported("**", &left, &right))
+        }
+        Operands::Rationals(a, _) => {
+            // This unwrap is safe because the exponent was checked to be an Int above
+            let exponent = i32::try_from(&exponent.unwrap()).map_err(|_| too_large())?;
+            if a.is_zero() && exponent < 0 {
+                return Err(RuntimeError::DivisionByZero);
+            }
+            Value::Rational(Rc::new(a.pow(exponent)))
+        }
+        _ if negative(&right) => {
+            return Err(RuntimeError::NegativeExponent {
+                exponent: right.to_string(),
+                ty: left.type_name(),
+            })
+        }
+        Operands::Ints(a, b) => {
+            let exponent = u32::try_from(b).map_err(|_| too_large())?;
+            match a.checked_pow(exponent) {
+                Some(result) => Value::Int(result),
+                None => Value::int(BigInt::from(a).pow(exponent)),
+            }
+        }
+        Operands::BigInts(a, b) => {
+            // Only 0, 1 and -1 have powers this large that fit in memory
+            let exponent = u32::try_from(&b).map_err(|_| too_large())?;
+            Value::int(a.pow(exponent))
+        }
+        Operands::Decimals(a, _) => {
+            // This unwrap is safe because the exponent was checked to be an Int above
+            let power = u32::try_from(&exponent.unwrap())
+                .ok()
+                .and_then(|exponent| a.pow(exponent))
+                .ok_or_else(too_large)?;
+            Value::Decimal(Rc::new(power))
+        }
+    })
+}
+
+/// Applies a bitwise operator, which treats Ints as two's complement with infinitely many sign
+/// bits
+fn bitwise(
+    op: &'static str,
+    left: &Value,
+    right: &Value,
+    ints: fn(i64, i64) -> i64,
+    bigs: fn(BigInt, BigInt) -> BigInt,
+) -> Result<Value, RuntimeError> {
+    match operands(op, left, right)? {
+        Operands::Ints(a, b) => Ok(Value::Int(ints(a, b))),
+        Operands::BigInts(a, b) => Ok(Value::int(bigs(a, b))),
+        _ => Err(unsupported(op, left, right)),
+    }
+}
+
+pub fn bit_and(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    bitwise("&", &left, &right, |a, b| a & b, |a, b| a & b)
+}
+
+pub fn bit_or(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    bitwise("|", &left, &right, |a, b| a | b, |a, b| a | b)
+}
+
+pub fn bit_xor(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    bitwise("^", &left, &right, |a, b| a ^ b, |a, b| a ^ b)
+}
+
+pub fn bit_not(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(a) => Ok(Value::Int(!a)),
+        Value::BigInt(a) => Ok(Value::int(-&*a - 1)),
+        _ => Err(RuntimeError::UnsupportedOperand {
+            op: "~",
+            operand: value.type_name(),
+        }),
+    }
+}
+
+/// Shift amounts are limited to what fits in a u32, the same as Int exponents, since shifting a
+/// non-zero Int left by more would not fit in memory
+fn shift_amount(amount: &Value) -> Result<u32, RuntimeError> {
+    if negative(amount) {
+        return Err(RuntimeError::NegativeShift {
+            amount: amount.to_string(),
+        });
+    }
+    match amount {
+        Value::Int(amount) => u32::try_from(*amount).ok(),
+        _ => None,
+    }
+    .ok_or_else(|| RuntimeError::ShiftTooLarge {
+        amount: amount.to_string(),
+    })
+}
+
+/// Shifts left, promoting to a big integer instead of discarding bits shifted past the most
+/// significant bit
+pub fn shift_left(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    let (Some(value), Some(_)) = (big(&left), big(&right)) else {
+        return Err(unsupported("<<", &left, &right));
+    };
+    let amount = shift_amount(&right)?;
+    Ok(match left {
+        Value::Int(a) if a.leading_zeros().max(a.leading_ones()) > amount => {
+            Value::Int(a << amount)
+        }
+        _ => Value::int(value << amount),
+    })
+}
+
+/// Arithmetic shift right, the sign bit is preserved
+pub fn shift_right(left: Value, right: Value) -> Result<Value, RuntimeError> {
+    let (Some(value), Some(_)) = (big(&left), big(&right)) else {
+        return Err(unsupported(">>", &left, &right));
+    };
+    let amount = shift_amount(&right)?;
+    Ok(match left {
+        // Shifting by 64 or more leaves only the sign bits
+        Value::Int(a) => Value::Int(a >> amount.min(63)),
+        _ => Value::int(value >> amount),
+    })
+}
+
+pub fn negate(value: Value) -> Result<Value, RuntimeError> {
+    Ok(match value {
+        Value::Int(a) => match a.checked_neg() {
+            Some(result) => Value::Int(result),
+            None => Value::int(-BigInt::from(a)),
+        },
+        Value::BigInt(a) => Value::int(-&*a),
+        Value::Float(a) => Value::Float(-a),
+        Value::Decimal(a) => Value::Decimal(Rc::new(a.neg())),
+        Value::Rational(a) => Value::Rational(Rc::new(-&*a)),
+        _ => {
+            return Err(RuntimeError::UnsupportedOperand {
+                op: "-",
+                operand: value.type_name(),
+            })
+        }
+    })
+}
+
+pub fn not(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Bool(b) => Ok(Value::Bool(!b)),
+        _ => Err(RuntimeError::UnsupportedOperand {
+            op: "!",
+            operand: value.type_name(),
+        }),
+    }
+}
+
+/// Ordering used by the comparison operators, `None` when either side is NaN
+pub fn compare(
+    op: &'static str,
+    left: &Value,
+    right: &Value,
+) -> Result<Option<Ordering>, RuntimeError> {
+    Ok(match (left, right) {
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => match operands(op, left, right)? {
+            Operands::Ints(a, b) => Some(a.cmp(&b)),
+            Operands::BigInts(a, b) => Some(a.cmp(&b)),
+            Operands::Decimals(a, b) => Some(a.cmp(&b)),
+            Operands::Rationals(a, b) => Some(a.cmp(&b)),
+            Operands::Floats(a, b) => a.partial_cmp(&b),
+        },
+    })
+}
+
+/// Converts the Int `index` into a position within a collection of length `len`
+pub fn element(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let position = match index {
+        Value::Int(i) => usize::try_from(*i).ok(),
+        // Big Ints are never within a collection
+        _ => None,
+    };
+    position
+        .filter(|i| *i < len)
+        .ok_or_else(|| RuntimeError::IndexOutOfBounds {
+            index: index.to_string(),
+            len,
+        })
+}
+
+/// Converts `value` into a map key
+pub fn key(value: &Value) -> Result<Key, RuntimeError> {
+    Key::from_value(value).ok_or(RuntimeError::InvalidKey {
+        found: value.type_name(),
+    })
+}
+
+/// An iterator over `value`, which is `value` itself if it is an iterator
+pub fn iter(value: Value) -> Result<Rc<RefCell<Iter>>, RuntimeError> {
+    let iter = match value {
+        Value::Iterator(iter) => return Ok(iter),
+        Value::String(string) => Iter::Chars { string, offset: 0 },
+        Value::List(list) => Iter::List { list, index: 0 },
+        Value::Map(map) => Iter::Keys { map, index: 0 },
+        Value::Vector(vector) => Iter::Vector { vector, index: 0 },
+        // The keys are taken up front, which is as cheap as stepping through the trie
+        Value::Dict(dict) => Iter::List {
+            list: Rc::new(RefCell::new(
+                dict.iter().map(|(k, _)| k.to_value()).collect(),
+            )),
+            index: 0,
+        },
+        Value::Struct(instance) if instance.borrow().def.methods.contains_key(NEXT) => {
+            Iter::Struct(instance)
+        }
+        other => {
+            return Err(RuntimeError::NotIterable {
+                found: other.type_name(),
+            })
+        }
+    };
+    Ok(Rc::new(RefCell::new(iter)))
+}
+
+pub fn index(target: Value, index: Value) -> Result<Value, RuntimeError> {
+    match (&target, &index) {
+        (Value::String(s), Value::Int(_) | Value::BigInt(_)) => {
+            let i = element(&index, s.chars().count())?;
+            // `element` checked that the string has more than `i` chars
+            Ok(Value::Char(s.chars().nth(i).unwrap()))
+        }
+        (Value::List(list), Value::Int(_) | Value::BigInt(_)) => {
+            let list = list.borrow();
+            Ok(list[element(&index, list.len())?].clone())
+        }
+        (Value::Map(map), _) => map
+            .borrow()
+            .get(&key(&index)?)
+            .cloned()
+            .ok_or_else(|| RuntimeError::KeyNotFound { key: index.repr() }),
+        (Value::Vector(vector), Value::Int(_) | Value::BigInt(_)) => {
+            Ok(vector[element(&index, vector.len())?].clone())
+        }
+        (Value::Dict(dict), _) => dict
+            .get(&key(&index)?)
+            .cloned()
+            .ok_or_else(|| RuntimeError::KeyNotFound { key: index.repr() }),
+        (Value::String(_) | Value::List(_) | Value::Vector(_), _) => {
+            Err(RuntimeError::InvalidIndex {
+                target: target.type_name(),
+                index: index.type_name(),
+            })
+        }
+        _ => Err(RuntimeError::NotIndexable {
+            found: target.type_name(),
+        }),
+    }
+}
+
+pub fn set_index(target: Value, index: Value, value: Value) -> Result<(), RuntimeError> {
+    match (&target, &index) {
+        (Value::List(list), Value::Int(_) | Value::BigInt(_)) => {
+            let mut list = list.borrow_mut();
+            let i = element(&index, list.len())?;
+            list[i] = value;
+            Ok(())
+        }
+        (Value::Map(map), _) => {
+            map.borrow_mut().insert(key(&index)?, value);
+            Ok(())
+        }
+        (Value::List(_), _) => Err(RuntimeError::InvalidIndex {
+            target: target.type_name(),
+            index: index.type_name(),
+        }),
+        _ => Err(RuntimeError::NotIndexAssignable {
+            found: target.type_name(),
+        }),
+    }
+}
+
+/// Slices `target` from `start` up to but not including `end`. Unit bounds default to the start
+/// and end of the target.
+pub fn slice(target: Value, start: Value, end: Value) -> Result<Value, RuntimeError> {
+    let len = match &target {
+        Value::String(s) => s.chars().count(),
+        Value::List(list) => list.borrow().len(),
+        Value::Vector(vector) => vector.len(),
+        _ => {
+            return Err(RuntimeError::NotIndexable {
+                found: target.type_name(),
+            })
+        }
+    };
+
+    // Each bound as written in errors, along with its position if it has one
+    let bound = |value: &Value, default: usize| match value {
+        Value::Unit => Ok((default.to_string(), Some(default))),
+        Value::Int(i) => Ok((i.to_string(), usize::try_from(*i).ok())),
+        Value::BigInt(i) => Ok((i.to_string(), None)),
+        other => Err(RuntimeError::InvalidIndex {
+            target: target.type_name(),
+            index: other.type_name(),
+        }),
+    };
+    let ((start, s), (end, e)) = (bound(&start, 0)?, bound(&end, len)?);
+    let range = match (s, e) {
+        (Some(s), Some(e)) if s <= e && e <= len => s..e,
+        _ => return Err(RuntimeError::SliceOutOfBounds { start, end, len }),
+    };
+
+    Ok(match &target {
+        Value::String(s) => Value::String(
+            s.chars()
+                .skip(range.start)
+                .take(range.len())
+                .collect::<String>()
+                .into(),
+        ),
+        Value::List(list) => Value::List(Rc::new(RefCell::new(list.borrow()[range].to_vec()))),
+        Value::Vector(vector) => Value::Vector(Rc::new(range.map(|i| vector[i].clone()).collect())),
+        _ => unreachable!("slice of {} was rejected above", target.type_name()),
+    })
+}
+
+fn no_field(target: &Value, name: &str) -> RuntimeError {
+    let found = match target {
+        Value::Struct(instance) => instance.borrow().def.name.clone(),
+        other => other.type_name().to_string(),
+    };
+    RuntimeError::NoField {
+        found,
+        field: name.to_string(),
+    }
+}
+
+pub fn get_field(target: &Value, name: &str) -> Result<Value, RuntimeError> {
+    if let Value::Struct(instance) = target {
+        let instance = instance.borrow();
+        if let Some(index) = instance.def.field(name) {
+            return Ok(instance.fields[index].clone());
+        }
+    }
+    Err(no_field(target, name))
+}
+
+pub fn set_field(target: &Value, name: &str, value: Value) -> Result<(), RuntimeError> {
+    if let Value::Struct(instance) = target {
+        let mut instance = instance.borrow_mut();
+        if let Some(index) = instance.def.field(name) {
+            instance.fields[index] = value;
+            return Ok(());
+        }
+    }
+    Err(no_field(target, name))
+}
+
+/// The arguments of a call sorted into the parameters they are passed to
+pub struct Bound<T> {
+    /// The argument of each parameter, `None` for one left out that has a default value
+    pub params: Vec<Option<T>>,
+    /// The positional arguments left over, which a variadic parameter takes
+    pub rest: Vec<T>,
+}
+
+/// Matches the arguments of a call of the function `name` with its parameters `params`, the
+/// `positional` arguments in order and then the `named` ones by name
+pub fn bind<T>(
+    name: &str,
+    params: &Params,
+    positional: Vec<T>,
+    named: Vec<(&str, T)>,
+) -> Result<Bound<T>, RuntimeError> {
+    let found = positional.len() + named.len();
+    // Functions without defaults or named arguments report any difference in the count
+    let arity_mismatch = || RuntimeError::ArityMismatch {
+        name: name.to_string(),
+        expected: params.names.len(),
+        found,
+    };
+    let exact = !params.has_defaults() && !params.variadic;
+
+    let mut bound: Vec<Option<T>> = params.names.iter().map(|_| None).collect();
+    let mut positional = positional.into_iter();
+    for (param, arg) in bound.iter_mut().zip(&mut positional) {
+        *param = Some(arg);
+    }
+    let rest: Vec<T> = positional.collect();
+    if !rest.is_empty() && !params.variadic {
+        if exact {
+            return Err(arity_mismatch());
+        }
+        return Err(RuntimeError::TooManyArguments {
+            name: name.to_string(),
+            expected: params.names.len(),
+            found,
+        });
+    }
+
+    let has_named = !named.is_empty();
+    for (argument, arg) in named {
+        let Some(index) = params.names.iter().position(|p| **p == *argument) else {
+            return Err(RuntimeError::UnknownArgument {
+                name: name.to_string(),
+                argument: argument.to_string(),
+            });
+        };
+        if bound[index].is_some() {
+            return Err(RuntimeError::DuplicateArgument {
+                name: name.to_string(),
+                argument: argument.to_string(),
+            });
+        }
+        bound[index] = Some(arg);
+    }
+
+    if let Some(missing) = bound[..params.required].iter().position(Option::is_none) {
+        if exact && !has_named {
+            return Err(arity_mismatch());
+        }
+        let argument = match &*params.names[missing] {
+            "" => format!("argument {}", missing + 1),
+            param => format!("argument `{}`", param),
+        };
+        return Err(RuntimeError::MissingArgument {
+            name: name.to_string(),
+            argument,
+        });
+    }
+    Ok(Bound {
+        params: bound,
+        rest,
+    })
+}
@@ -0,0 +1,280 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Frame, Returned, RuntimeError, TraceFrame, Vm};
+use crate::value::{Builtin, Channel, Upvalue, Value};
+
+/// A task that isn't running, with the stack and frames it continues from
+pub(super) struct Task {
+    /// The order the task was spawned in, the main program is task 0
+    id: usize,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// Upvalues that referred to the task's stack slots, which are closed while it isn't running,
+    /// along with their slots
+    upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,
+}
+
+/// A task waiting in `recv` or `select` for a value to be sent to one of `channels`. Its stack
+/// still ends with the call it waits in, which is replaced by the result once a value arrives.
+pub(super) struct Blocked {
+    task: Task,
+    channels: Vec<Rc<Channel>>,
+    /// Whether the task waits in `select`, which gives the index of the channel with the value
+    select: bool,
+}
+
+impl Vm<'_> {
+    /// Runs the current task and then the ready tasks in turn until the main program has finished
+    /// and no task is ready. Tasks still waiting then are dropped.
+    pub(super) fn schedule(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            match self.execute(1) {
+                Ok(()) => {}
+                // Nothing can run once a task waits with no task ready, which is only a deadlock
+                // while the main program is one of the tasks waiting
+                Err(RuntimeError::Deadlock) if !self.main_waits() => return Ok(()),
+                Err(err) => return Err(err),
+            }
+            // Only the result of the finished task is left on the stack
+            match self.ready.pop_front() {
+                Some(task) => self.load(task),
+                None if self.main_waits() => return Err(RuntimeError::Deadlock),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn main_waits(&self) -> bool {
+        self.blocked.iter().any(|blocked| blocked.task.id == 0)
+    }
+
+    /// Starts a task that calls the function below the top `arg_count` values with them, the
+    /// last of which are passed by `names`
+    pub(super) fn spawn(&mut self, arg_count: usize, names: &[Value]) -> Result<(), RuntimeError> {
+        let callee = &self.stack[self.stack.len() - 1 - arg_count];
+        let closure = match callee {
+            Value::Function(closure) if !closure.function.generator => closure.clone(),
+            Value::Function(closure) => {
+                return Err(RuntimeError::NotSpawnable {
+                    found: format!("generator `{}`", closure.function.name),
+                })
+            }
+            Value::Builtin(builtin) => {
+                return Err(RuntimeError::NotSpawnable {
+                    found: format!("builtin `{}`", builtin.name()),
+                })
+            }
+            Value::Constructor(def, variant) => {
+                return Err(RuntimeError::NotSpawnable {
+                    found: format!("variant `{}`", def.variant_name(*variant)),
+                })
+            }
+            other => {
+                return Err(RuntimeError::NotCallable {
+                    found: other.type_name(),
+                })
+            }
+        };
+        let slots = self.arguments(&closure.function, arg_count, names)?;
+
+        // The task's frame is made of the callee and arguments, like that of a generator
+        let stack = self.stack.split_off(self.stack.len() - 1 - slots);
+        self.next_task += 1;
+        self.ready.push_back(Task {
+            id: self.next_task,
+            stack,
+            frames: vec![Frame {
+                closure,
+                ip: 0,
+                base: 1,
+                returned: Returned::Value,
+            }],
+            upvalues: vec![],
+        });
+        Ok(())
+    }
+
+    /// Calls `builtin`, one of the builtins of channels, with the top `arg_count` values. A task
+    /// that has to wait for a value is set aside and the next ready task runs.
+    pub(super) fn channel_call(
+        &mut self,
+        builtin: Builtin,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
+        let args = &self.stack[self.stack.len() - arg_count..];
+        let unsupported = |operand: &Value| RuntimeError::UnsupportedOperand {
+            op: builtin.name(),
+            operand: operand.type_name(),
+        };
+        match (builtin, args) {
+            (Builtin::Send, [Value::Channel(channel), value]) => {
+                let (channel, value) = (channel.clone(), value.clone());
+                self.stack.truncate(self.stack.len() - 3);
+                self.stack.push(Value::Unit);
+                self.send(channel, value);
+                Ok(())
+            }
+            (Builtin::Recv, [Value::Channel(channel)]) => {
+                let channel = channel.clone();
+                let value = channel.values.borrow_mut().pop_front();
+                match value {
+                    Some(value) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.stack.push(value);
+                        Ok(())
+                    }
+                    None => self.block(builtin, vec![channel], false),
+                }
+            }
+            (Builtin::Select, [Value::List(list)]) => {
+                let channels = list
+                    .borrow()
+                    .iter()
+                    .map(|value| match value {
+                        Value::Channel(channel) => Ok(channel.clone()),
+                        other => Err(unsupported(other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Waiting on no channel would only ever end in a deadlock
+                if channels.is_empty() {
+                    return Err(RuntimeError::EmptyList { op: "select" });
+                }
+                // The first channel in the list with a value wins
+                let ready = channels.iter().enumerate().find_map(|(index, channel)| {
+                    let value = channel.values.borrow_mut().pop_front()?;
+                    Some(Value::Tuple(vec![Value::Int(index as i64), value].into()))
+                });
+                match ready {
+                    Some(result) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.stack.push(result);
+                        Ok(())
+                    }
+                    None => self.block(builtin, channels, true),
+                }
+            }
+            (_, [first, ..]) => Err(unsupported(first)),
+            (_, []) => unreachable!("channel builtins take at least one argument"),
+        }
+    }
+
+    /// Hands `value` to the task that has waited the longest for `channel`, or queues it when no
+    /// task is waiting
+    fn send(&mut self, channel: Rc<Channel>, value: Value) {
+        let waiting = self.blocked.iter().position(|blocked| {
+            blocked
+                .channels
+                .iter()
+                .any(|waited| Rc::ptr_eq(waited, &channel))
+        });
+        let Some(index) = waiting else {
+            channel.values.borrow_mut().push_back(value);
+            return;
+        };
+
+        let Blocked {
+            mut task,
+            channels,
+            select,
+        } = self.blocked.remove(index);
+        let result = if select {
+            // This unwrap is safe because the task waits for the channel
+            let index = channels
+                .iter()
+                .position(|waited| Rc::ptr_eq(waited, &channel))
+                .unwrap();
+            Value::Tuple(vec![Value::Int(index as i64), value].into())
+        } else {
+            value
+        };
+        // The call takes the callee and its one argument off the stack
+        task.stack.truncate(task.stack.len() - 2);
+        task.stack.push(result);
+        self.ready.push_back(task);
+    }
+
+    /// Sets the current task aside until a value is sent to one of `channels` and runs the next
+    /// ready task
+    fn block(
+        &mut self,
+        builtin: Builtin,
+        channels: Vec<Rc<Channel>>,
+        select: bool,
+    ) -> Result<(), RuntimeError> {
+        // The native stack of nested code can't be set aside
+        if self.nested > 0 {
+            return Err(RuntimeError::NestedWait { op: builtin.name() });
+        }
+        let task = self.save();
+        self.blocked.push(Blocked {
+            task,
+            channels,
+            select,
+        });
+        match self.ready.pop_front() {
+            Some(task) => {
+                self.load(task);
+                Ok(())
+            }
+            None => Err(RuntimeError::Deadlock),
+        }
+    }
+
+    /// Takes the current task off the VM, closing the upvalues that refer to its stack
+    fn save(&mut self) -> Task {
+        let mut upvalues = vec![];
+        for upvalue in self.open_upvalues.drain(..) {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => unreachable!("closed upvalue in open list"),
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            upvalues.push((upvalue, slot));
+        }
+        Task {
+            id: self.task,
+            stack: std::mem::take(&mut self.stack),
+            frames: std::mem::take(&mut self.frames),
+            upvalues,
+        }
+    }
+
+    /// Makes `task` the current task, replacing the stack of a finished one
+    fn load(&mut self, task: Task) {
+        self.task = task.id;
+        self.stack = task.stack;
+        self.frames = task.frames;
+        for (upvalue, slot) in task.upvalues {
+            // Other tasks may have changed the value while it was closed
+            match std::mem::replace(&mut *upvalue.borrow_mut(), Upvalue::Open(slot)) {
+                Upvalue::Closed(value) => self.stack[slot] = value,
+                Upvalue::Open(_) => unreachable!("open upvalue of a task that isn't running"),
+            }
+            self.open_upvalues.push(upvalue);
+        }
+    }
+
+    /// The frames of the tasks left waiting by a deadlock, in the order the tasks were spawned
+    /// and innermost first. Since the error has no position of its own, the script is only left
+    /// out when it isn't what waits.
+    pub(super) fn deadlock_trace(&mut self) -> Vec<TraceFrame> {
+        self.blocked.sort_by_key(|blocked| blocked.task.id);
+        self.blocked
+            .iter()
+            .flat_map(|blocked| {
+                let frames = &blocked.task.frames;
+                let skip = (blocked.task.id == 0 && frames.len() > 1) as usize;
+                super::trace(blocked.task.id, &frames[skip..])
+            })
+            .collect()
+    }
+
+    /// Drops every task, once the program has ended
+    pub(super) fn clear_tasks(&mut self) {
+        self.task = 0;
+        self.next_task = 0;
+        self.ready.clear();
+        self.blocked.clear();
+    }
+}
@@ -1,8 +1,8 @@
+//! Simple tests for all examples in lang examples to make sure that everything compiles
+
 use std::path::{Path, PathBuf};
 use yapl::{compile, CompilerError};
 
-/// Simple tests for all examples in lang examples to make sure that everything compiles
-
 fn load_example(name: &str) -> PathBuf {
     Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("lang_examples")
@@ -24,3 +24,108 @@ fn factorial() -> Result<(), CompilerError> {
 fn scratch_pad() -> Result<(), CompilerError> {
     compile(load_example("scratch_pad.ypl").to_str().unwrap())
 }
+
+#[test]
+fn chars() -> Result<(), CompilerError> {
+    compile(load_example("chars.ypl").to_str().unwrap())
+}
+
+#[test]
+fn closures() -> Result<(), CompilerError> {
+    compile(load_example("closures.ypl").to_str().unwrap())
+}
+
+#[test]
+fn lists() -> Result<(), CompilerError> {
+    compile(load_example("lists.ypl").to_str().unwrap())
+}
+
+#[test]
+fn maps() -> Result<(), CompilerError> {
+    compile(load_example("maps.ypl").to_str().unwrap())
+}
+
+#[test]
+fn structs() -> Result<(), CompilerError> {
+    compile(load_example("structs.ypl").to_str().unwrap())
+}
+
+#[test]
+fn enums() -> Result<(), CompilerError> {
+    compile(load_example("enums.ypl").to_str().unwrap())
+}
+
+#[test]
+fn control_flow() -> Result<(), CompilerError> {
+    compile(load_example("control_flow.ypl").to_str().unwrap())
+}
+
+#[test]
+fn tuples() -> Result<(), CompilerError> {
+    compile(load_example("tuples.ypl").to_str().unwrap())
+}
+
+#[test]
+fn optionals() -> Result<(), CompilerError> {
+    compile(load_example("optionals.ypl").to_str().unwrap())
+}
+
+#[test]
+fn results() -> Result<(), CompilerError> {
+    compile(load_example("results.ypl").to_str().unwrap())
+}
+
+#[test]
+fn generics() -> Result<(), CompilerError> {
+    compile(load_example("generics.ypl").to_str().unwrap())
+}
+
+#[test]
+fn traits() -> Result<(), CompilerError> {
+    compile(load_example("traits.ypl").to_str().unwrap())
+}
+
+#[test]
+fn big_ints() -> Result<(), CompilerError> {
+    compile(load_example("big_ints.ypl").to_str().unwrap())
+}
+
+#[test]
+fn constants() -> Result<(), CompilerError> {
+    compile(load_example("constants.ypl").to_str().unwrap())
+}
+
+#[test]
+fn generators() -> Result<(), CompilerError> {
+    compile(load_example("generators.ypl").to_str().unwrap())
+}
+
+#[test]
+fn tasks() -> Result<(), CompilerError> {
+    compile(load_example("tasks.ypl").to_str().unwrap())
+}
+
+#[test]
+fn decimals() -> Result<(), CompilerError> {
+    compile(load_example("decimals.ypl").to_str().unwrap())
+}
+
+#[test]
+fn operators() -> Result<(), CompilerError> {
+    compile(load_example("operators.ypl").to_str().unwrap())
+}
+
+#[test]
+fn arguments() -> Result<(), CompilerError> {
+    compile(load_example("arguments.ypl").to_str().unwrap())
+}
+
+#[test]
+fn persistent() -> Result<(), CompilerError> {
+    compile(load_example("persistent.ypl").to_str().unwrap())
+}
+
+#[test]
+fn modules() -> Result<(), CompilerError> {
+    compile(load_example("modules/shapes.ypl").to_str().unwrap())
+}
@@ -0,0 +1,2152 @@
+//! Tests that run programs and check their output
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use yapl::modules::ModuleLoader;
+use yapl::token::Position;
+use yapl::{read_source, run_file, run_source, CompilerError};
+
+fn run(source: &str) -> Result<String, CompilerError> {
+    let mut out = Vec::new();
+    run_source(source, &mut out)?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+fn run_example(name: &str) -> Result<String, CompilerError> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("lang_examples")
+        .join(name);
+    run(&std::fs::read_to_string(path).unwrap())
+}
+
+fn modules_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("lang_examples")
+        .join("modules")
+}
+
+/// Runs the module at `path` within the modules example, searching `lib` for imports
+fn run_module(path: &str) -> Result<String, CompilerError> {
+    let dir = modules_dir();
+    let mut out = Vec::new();
+    run_file(&dir.join(path), vec![dir.join("lib"), dir], &mut out)?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn factorial() -> Result<(), CompilerError> {
+    assert_eq!(run_example("factorial.ypl")?, "5040\n");
+    Ok(())
+}
+
+#[test]
+fn chars() -> Result<(), CompilerError> {
+    assert_eq!(run_example("chars.ypl")?, "☺\n7\né\ntrue\n'quoted'\n");
+    Ok(())
+}
+
+#[test]
+fn closures() -> Result<(), CompilerError> {
+    assert_eq!(run_example("closures.ypl")?, "3\n10\n610\n");
+    Ok(())
+}
+
+#[test]
+fn lists() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("lists.ypl")?,
+        "[4, 10, 2, 5]\n4\n5\n[10, 2]\n[4, 10]\n[8, 20, 4]\nel\n[[1, 2], [0, 4]]\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn maps() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("maps.ypl")?,
+        "{\"name\": \"yapl\", \"version\": 1, \"debug\": false}\nyapl\n\
+         [\"name\", \"version\", \"debug\"]\n[\"yapl\", 1, false]\n1\nfalse\nname\ndebug\n\
+         {'h': 1, 'e': 1, 'l': 2, 'o': 1}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn structs() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("structs.ypl")?,
+        "5.0\nPoint { x: 6.0, y: 8.0 }\nPoint { x: 0.0, y: 2.0 }\n10\nequal\n3.0\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn enums() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("enums.ypl")?,
+        "3.0\n6.0\n0.0\nShape.Rect(2.0, 3.0)\nzero\nnegative\nmany\nbelow the x axis\n5\nsquare\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn tuples() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("tuples.ypl")?,
+        "3\n1\n(3, 2)\n5\n2\n25\nada\n3\nbob\n5\n1\n[2, 3]\n10\n5\n6\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn optionals() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("optionals.ypl")?,
+        "2\nnone\n0\n2\nnone\n3\n-1\nnothing\nsomething\n2\n10\ntrue\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn results() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("results.ypl")?,
+        "Result.Ok(27)\nResult.Err(\"not a digit\")\nnumber\nnot a digit\nboth parsed\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn generics() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("generics.ypl")?,
+        "3\na\n[10, 20, 30]\n(\"x\", \"y\")\n3\n3\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn traits() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("traits.ypl")?,
+        "[square]\n[circle]\n[label hi]\nsquare\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn control_flow() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("control_flow.ypl")?,
+        "-1\n0\n1\nA\nB\nC\nF\n128\n12\n22\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn char_literal_errors() {
+    for source in ["val c = ''", "val c = 'ab'", "val c = '\\q'"] {
+        assert!(
+            matches!(run(source), Err(CompilerError::ParseError { .. })),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn string_index_out_of_bounds() {
+    let err = run("val s = \"héllo\"\nprint(s[5])").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "encountered an error while running `runtime error - index 5 is out of bounds for length 5 at 2:7`\n  in <module main> at 2:7"
+    );
+}
+
+#[test]
+fn assign_to_val() {
+    for source in [
+        "val x = 1\nx = 2",
+        "val x = 1\nx += 2",
+        "fun f(a) { a *= 2 }",
+        "fun f() { val x = 1\n val g = || x = 2 }",
+    ] {
+        assert!(
+            matches!(run(source), Err(CompilerError::CodegenError { .. })),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn name_errors_have_positions() {
+    let cases = [
+        ("print(1)\nprint(y)", "undefined variable `y` at 2:6"),
+        ("val x = 1\nx = 2", "cannot assign to val `x` at 2:0"),
+        ("val x = 1\nval x = 2", "`x` is already defined at 2:4"),
+        ("val x: Thing = 1", "undefined type `Thing` at 1:7"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn compound_assignment() -> Result<(), CompilerError> {
+    let source = r#"
+        var x = 10
+        x += 5
+        print(x)
+        x -= 3
+        print(x)
+        x *= 2
+        print(x)
+        x /= 5
+        print(x)
+        x %= 3
+        print(x)
+        var s = "a"
+        s += 'b'
+        print(s += "c")
+    "#;
+    assert_eq!(run(source)?, "15\n12\n24\n4\n1\nabc\n");
+    Ok(())
+}
+
+#[test]
+fn operator_precedence() -> Result<(), CompilerError> {
+    let source = r#"
+        print(2 ** 3 ** 2)
+        print(-2 ** 2)
+        print(2 ** -1.0)
+        print(7 % 3 + 1)
+        print(-7 % 3)
+        print(1 + 2 << 1)
+        print(6 & 3 == 2)
+        print(1 | 2 ^ 3 & 5)
+        print(~5)
+        print(-16 >> 2)
+    "#;
+    assert_eq!(run(source)?, "512\n-4\n0.5\n2\n-1\n6\ntrue\n3\n-6\n-4\n");
+    Ok(())
+}
+
+#[test]
+fn arithmetic_errors() {
+    let cases = [
+        ("print(1 / 0)", "division by zero"),
+        ("print(1 % 0)", "division by zero"),
+        ("print(2 ** -1)", "negative exponent -1 for Int power"),
+        ("print(2d ** -1)", "negative exponent -1 for Decimal power"),
+        (
+            "var s = \"a\"\ns *= 2",
+            "cannot apply `*` to String and Int at 2:2",
+        ),
+        ("val l = [1]\nl[0] /= 0", "division by zero at 2:5"),
+        ("print(1 << -1)", "negative shift amount -1"),
+        (
+            "print(1 >> -(2 ** 64))",
+            "negative shift amount -18446744073709551616",
+        ),
+        ("print(1.0 & 1)", "cannot apply `&` to Float and Int"),
+        ("print(1.0 & 2 ** 64)", "cannot apply `&` to Float and Int"),
+        ("print(2 ** 64 / 0)", "division by zero"),
+        (
+            "print(3 ** -(2 ** 64))",
+            "negative exponent -18446744073709551616",
+        ),
+        (
+            "print(3 ** (2 ** 32))",
+            "exponent 4294967296 is too large for Int power",
+        ),
+        (
+            "print(1 << 2 ** 64)",
+            "shift amount 18446744073709551616 is too large",
+        ),
+        (
+            "print([1][2 ** 64])",
+            "index 18446744073709551616 is out of bounds for length 1",
+        ),
+        (
+            "print([1][-(2 ** 64)..])",
+            "slice -18446744073709551616..1 is out of bounds for length 1",
+        ),
+        (
+            "take([1], -(2 ** 64))",
+            "cannot take a negative number of values",
+        ),
+        ("print(~true)", "cannot apply `~` to Bool"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn unicode_source() -> Result<(), CompilerError> {
+    // Composed and decomposed spellings of the same name are one variable
+    let source = "\u{FEFF}var caf\u{E9} = 1\r\ncafe\u{301} += 1\r\nprint(caf\u{E9})\r\n";
+    assert_eq!(run(source)?, "2\n");
+    Ok(())
+}
+
+#[test]
+fn lints_are_returned_with_the_module() {
+    let graph = ModuleLoader::new(vec![])
+        .load_source("val \u{430}pple = 1")
+        .unwrap();
+    let lints: Vec<String> = graph.modules[0]
+        .lints
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        lints,
+        ["warning - identifier `\u{430}pple` mixes multiple scripts at 1:4"]
+    );
+}
+
+#[test]
+fn invalid_utf8() {
+    let path = std::env::temp_dir().join("yapl_invalid_utf8.ypl");
+    std::fs::write(&path, b"val x = \"ab\xFFcd\"").unwrap();
+
+    let err = read_source(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "source is not valid UTF-8 at byte offset 11 in {}",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn imported_files_are_named_in_read_errors() {
+    let dir = std::env::temp_dir().join("yapl_imported_read_errors");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("bad.ypl"), b"val x = \"ab\xFFcd\"").unwrap();
+    std::fs::write(dir.join("main.ypl"), "import bad").unwrap();
+
+    let err = run_file(&dir.join("main.ypl"), vec![], &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "source is not valid UTF-8 at byte offset 11 in {}",
+            dir.join("bad.ypl").display()
+        )
+    );
+}
+
+#[test]
+fn captured_vars_are_shared() -> Result<(), CompilerError> {
+    let source = "
+        fun main() {
+            var x = 1
+            val get = || x
+            val set = fun(v) { x = v }
+            set(2)
+            print(get())
+            x = 3
+            print(get())
+        }
+    ";
+    assert_eq!(run(source)?, "2\n3\n");
+    Ok(())
+}
+
+#[test]
+fn loop_variables_are_captured_per_iteration() -> Result<(), CompilerError> {
+    let source = "
+        var first = || ' '
+        var last = || ' '
+        var i = 0
+        for c in \"abc\" {
+            val f = || c
+            if i == 0 {
+                first = f
+            }
+            last = f
+            i += 1
+            if c == 'c' {
+                break
+            }
+        }
+        print(first())
+        print(last())
+    ";
+    assert_eq!(run(source)?, "a\nc\n");
+    Ok(())
+}
+
+#[test]
+fn lists_are_shared() -> Result<(), CompilerError> {
+    let source = "
+        val xs = ['a']
+        val ys = xs
+        push(ys, 'b')
+        print(xs)
+        print(xs == ['a', 'b'])
+        print(xs[0..0] == [])
+    ";
+    assert_eq!(run(source)?, "['a', 'b']\ntrue\ntrue\n");
+    Ok(())
+}
+
+#[test]
+fn lists_holding_themselves() -> Result<(), CompilerError> {
+    let source = "
+        val l = [1]
+        push(l, l)
+        print(l)
+        print(l == l)
+        val m = [1]
+        push(m, m)
+        print(l == m)
+        print([l, l])
+        print(l == [1, [1]])
+    ";
+    assert_eq!(
+        run(source)?,
+        "[1, [...]]\ntrue\ntrue\n[[1, [...]], [1, [...]]]\nfalse\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn list_errors() {
+    let cases = [
+        (
+            "val xs = [1, 2]\nprint(xs[2])",
+            "index 2 is out of bounds for length 2 at 2:8",
+        ),
+        (
+            "val xs = [1, 2]\nxs[-1] = 0",
+            "index -1 is out of bounds for length 2 at 2:2",
+        ),
+        (
+            "print([1, 2][1..3])",
+            "slice 1..3 is out of bounds for length 2 at 1:12",
+        ),
+        ("print([1][true])", "List cannot be indexed by Bool"),
+        (
+            "val s = \"ab\"\ns[0] = 'c'",
+            "String does not support indexed assignment",
+        ),
+        ("pop([])", "cannot pop from an empty list at 1:3"),
+        ("push(1, 2)", "cannot apply `push` to Int"),
+        ("len([], [])", "`len` expects 1 arguments, found 2"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn invalid_assignment_target() {
+    let err = run("val xs = [1]\nlen(xs) = 2").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "encountered an error during parsing `parse error - invalid assignment target at 2:8`"
+    );
+}
+
+#[test]
+fn map_equality_ignores_order() -> Result<(), CompilerError> {
+    let source = "
+        print({1: 'a', 2: 'b'} == {2: 'b', 1: 'a'})
+        print({1: 'a'} == {1: 'b'})
+        print({} == {})
+    ";
+    assert_eq!(run(source)?, "true\nfalse\ntrue\n");
+    Ok(())
+}
+
+#[test]
+fn map_errors() {
+    let cases = [
+        (
+            "val m = {\"a\": 1}\nprint(m[\"b\"])",
+            "key \"b\" is not in the map at 2:7",
+        ),
+        ("remove({}, 'x')", "key 'x' is not in the map"),
+        (
+            "val m = {1.5: 1}",
+            "Float cannot be used as a map key at 1:8",
+        ),
+        (
+            "val m = {}\nm[[]] = 1",
+            "List cannot be used as a map key at 2:1",
+        ),
+        ("keys([])", "cannot apply `keys` to List"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn struct_errors() {
+    let point = "struct Point { x: Float, y: Float }\nimpl Point { fun len(self) { self.x } }\n";
+    let cases = [
+        (
+            "val p = Point { x: 1.0 }",
+            "`Point` is missing field `y` at 3:8",
+        ),
+        (
+            "val p = Point { x: 1.0, y: 2.0, z: 3.0 }",
+            "struct `Point` has no field `z`",
+        ),
+        (
+            "val p = Point { x: 1.0, x: 2.0, y: 1.0 }",
+            "`x` is already defined",
+        ),
+        (
+            "val p = Pointt { x: 1.0, y: 2.0 }",
+            "undefined struct `Pointt` at 3:8",
+        ),
+        (
+            "val p = Point { x: 1.0, y: 2.0 }\nprint(p.xx)",
+            "struct `Point` has no field `xx` at 4:8",
+        ),
+        (
+            "val p = Point { x: 1.0, y: 2.0 }\nprint(p.length())",
+            "struct `Point` has no method `length`",
+        ),
+        ("fun f(p) { p.z }", "no struct has a field `z` at 3:13"),
+        (
+            "val p = Point { x: 1.0, y: 2.0 }\np.x = 3.0",
+            "cannot assign to val `p` at 4:0",
+        ),
+        (
+            "fun f() { struct S { a: Int } }",
+            "`struct` is only allowed at the top level",
+        ),
+        ("fun f(p) { p.x }\nf(1)", "Int has no field `x`"),
+    ];
+    for (source, message) in cases {
+        let err = run(&format!("{}{}", point, source))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn structs_and_maps_holding_themselves() -> Result<(), CompilerError> {
+    let source = "
+        struct N { n: N? }
+        var a = N { n: none }
+        a.n = a
+        print(a)
+        print(a == a)
+        var b = N { n: none }
+        b.n = b
+        print(a == b)
+        val m = {\"a\": 1}
+        m[\"self\"] = m
+        print(m)
+        print(m == m)
+    ";
+    assert_eq!(
+        run(source)?,
+        "N { n: N { ... } }\ntrue\ntrue\n{\"a\": 1, \"self\": {...}}\ntrue\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn struct_literals_in_conditions() -> Result<(), CompilerError> {
+    let source = "
+        struct S { a: Int }
+        val s = S { a: 1 }
+        if s.a == 1 {
+            print(s)
+        }
+        for c in \"x\" {
+            print(c)
+        }
+    ";
+    assert_eq!(run(source)?, "S { a: 1 }\nx\n");
+    Ok(())
+}
+
+#[test]
+fn match_errors() {
+    let shape = "enum Shape { Circle(Float), Rect(Float, Float), Empty }\n";
+    let cases = [
+        (
+            "fun f(s) {\n  match s {\n    Shape.Circle(r) => r\n    Shape.Empty => 0.0\n  }\n}",
+            "non-exhaustive match at 3:2, `Shape.Rect(_, _)` is not covered",
+        ),
+        (
+            "fun f(s) {\n  match s {\n    _ => 1\n    Shape.Empty => 0\n  }\n}",
+            "unreachable match arm at 5:4",
+        ),
+        (
+            "fun f(s) {\n  match s {\n    Shape.Circle(r) if r > 1.0 => r\n    Shape.Circle(_) => 0.0\n    Shape.Circle(r) => r\n    _ => 0.0\n  }\n}",
+            "unreachable match arm at 6:4",
+        ),
+        (
+            "fun f(t) { match t { (true, _) => 1, (_, false) => 2 } }",
+            "`(false, true)` is not covered",
+        ),
+        (
+            "fun f(n) { match n { 1 => 1, 2 => 2 } }",
+            "`_` is not covered",
+        ),
+        (
+            "fun f(s) { match s { Shape.Circle => 1, _ => 2 } }",
+            "`Shape.Circle` holds 1 values, pattern has 0 at 2:21",
+        ),
+        (
+            "fun f(s) { match s { Shape.Square(x) => 1, _ => 2 } }",
+            "enum `Shape` has no variant `Square`",
+        ),
+        (
+            "fun f(s) { match s { Shape.Rect(x, x) => 1, _ => 2 } }",
+            "`x` is bound twice in the same pattern",
+        ),
+        ("print(Shape.Square)", "enum `Shape` has no variant `Square`"),
+        ("print(Shape.Circle(1.0, 2.0))", "`Shape.Circle` expects 1 arguments, found 2"),
+        (
+            "fun f() { enum E { A } }",
+            "`enum` is only allowed at the top level",
+        ),
+        ("struct Shape { a: Int }", "`Shape` is already defined"),
+    ];
+    for (source, message) in cases {
+        let err = run(&format!("{}{}", shape, source))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn exhaustive_matches() -> Result<(), CompilerError> {
+    let source = "
+        enum Light { Red, Yellow, Green }
+        fun next(light) {
+            match light {
+                Light.Red => Light.Green
+                Light.Green => Light.Yellow
+                Light.Yellow => Light.Red
+            }
+        }
+        fun both(a, b) {
+            match (a, b) {
+                (true, true) => \"both\"
+                _ => \"not both\"
+            }
+        }
+        print(next(Light.Red))
+        print(both(true, false))
+        print(match (1, (2, 3)) { (a, (b, c)) => a + b + c })
+        print(match \"hi\" { \"hi\" => 1, _ => 2 })
+    ";
+    assert_eq!(run(source)?, "Light.Green\nnot both\n6\n1\n");
+    Ok(())
+}
+
+#[test]
+fn block_expressions() -> Result<(), CompilerError> {
+    let source = "
+        fun main() {
+            val empty = {}
+            val map = { \"a\": 1 }
+            val block = { 1 + 2 }
+            print(len(empty))
+            print(map[\"a\"] + block)
+
+            // An if without else is unit when the condition is false
+            print(if false { 1 })
+
+            // Breaking out of a loop pops the temporaries of the expression it is in
+            var i = 0
+            val found = loop {
+                i += 1
+                val doubled = i * 2
+                print(100 + if doubled > 4 { break doubled } else { 0 })
+            }
+            print(found)
+
+            for x in [1, 2, 3] {
+                print(x * { if x == 2 { continue } else { 10 } })
+            }
+        }
+    ";
+    assert_eq!(run(source)?, "0\n4\n()\n100\n100\n6\n10\n30\n");
+    Ok(())
+}
+
+#[test]
+fn break_errors() {
+    let cases = [
+        (
+            "for x in [1] { break x }",
+            "only `break` out of a `loop` can have a value",
+        ),
+        ("break", "`break` outside of a loop"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn destructuring() -> Result<(), CompilerError> {
+    let source = "\
+var (a, [b, ..rest]) = (\"x\", [1, 2, 3])
+a = \"y\"
+print(a)
+print(b + rest[1])
+val add = |(x, y), _| x + y
+print(add((3, 4), 0))
+val ((c, _), [d, e]) = ((1, 2), [3, 4])
+print(c + d + e)
+val [..] = []
+print((1,).0)
+";
+    assert_eq!(run(source)?, "y\n4\n7\n8\n1\n");
+    Ok(())
+}
+
+#[test]
+fn list_and_struct_matches() -> Result<(), CompilerError> {
+    let source = "\
+struct Point { x: Int, y: Int }
+fun size(list) {
+  match list {
+    [] => \"empty\",
+    [_] => \"one\",
+    [_, _, ..] => \"many\",
+  }
+}
+fun axis(p) {
+  match p {
+    Point { x: 0, y: 0 } => \"origin\",
+    Point { x: 0, .. } => \"y axis\",
+    Point { y: 0, .. } => \"x axis\",
+    Point { .. } => \"neither\",
+  }
+}
+print(size([]))
+print(size([1]))
+print(size([1, 2, 3]))
+print(axis(Point { x: 0, y: 4 }))
+print(axis(Point { x: 2, y: 0 }))
+print(axis(Point { x: 2, y: 2 }))
+";
+    assert_eq!(run(source)?, "empty\none\nmany\ny axis\nx axis\nneither\n");
+    Ok(())
+}
+
+#[test]
+fn destructuring_errors() {
+    let point = "struct Point { x: Int, y: Int }\n";
+    let cases = [
+        (
+            "val [a] = [1, 2]",
+            "value does not match the pattern at 2:4",
+        ),
+        (
+            "fun f(p) {\n  val (a, b) = p\n}\nf((1, 2, 3))",
+            "value does not match the pattern at 3:6",
+        ),
+        ("print((1, 2).2)", "Tuple has no element 2 at 2:12"),
+        (
+            "val (a, a) = (1, 2)",
+            "`a` is bound twice in the same pattern",
+        ),
+        (
+            "val Point { x } = Point { x: 1, y: 2 }",
+            "`Point` is missing field `y` at 2:4",
+        ),
+        (
+            "val Point { z, .. } = Point { x: 1, y: 2 }",
+            "struct `Point` has no field `z`",
+        ),
+        (
+            "fun f(l) { match l { [] => 0, [a] => a } }",
+            "`[_, _, ..]` is not covered",
+        ),
+        (
+            "fun f(l) { match l { [..] => 0, [a] => a } }",
+            "unreachable match arm at 2:32",
+        ),
+        (
+            "fun f(p) { match p { Point { x: 0, .. } => 0 } }",
+            "`Point { x: _, y: _ }` is not covered",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(&format!("{}{}", point, source))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn modules() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_module("main.ypl")?,
+        "16\n3.14159\n6.0\n3.14159\na, b, c\nhello modules\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn module_errors() {
+    let cases = [
+        (
+            "errors/cycle_a.ypl",
+            "import cycle `cycle_a -> cycle_b -> cycle_a`",
+        ),
+        (
+            "errors/missing.ypl",
+            "cannot find module `missing.module` imported at ",
+        ),
+        (
+            "errors/private.ypl",
+            "`checked` is private to module `math`",
+        ),
+        ("errors/unknown.ypl", "module `math` has no item `cube`"),
+        ("errors/runtime.ypl", "math.ypl:8:18"),
+        ("errors/shadowed.ypl", "`format` is already defined at 3:4"),
+    ];
+    for (path, message) in cases {
+        let err = run_module(path).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", path, err);
+    }
+
+    let cases = [
+        (
+            "fun f() {\n  pub val x = 1\n}",
+            "`pub` is only allowed at the top level",
+        ),
+        (
+            "fun f() {\n  import math\n}",
+            "`import` is only allowed at the top level",
+        ),
+        ("pub print(1)", "Print"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn optional_errors() {
+    let decls = "struct P { a: P?, n: Int }\nfun find() -> Int? { none }\n";
+    let cases = [
+        ("var x\nprint(x + 1)", "`x` at 4:6 may be none"),
+        (
+            "print(find() * 2)",
+            "the result of `find` at 3:10 may be none",
+        ),
+        ("fun f(p) { p.a + 1 }", "field `a` at 3:13 may be none"),
+        (
+            "fun f() { if find() { 1 } }",
+            "the result of `find` at 3:17 may be none",
+        ),
+        (
+            "fun f() { for x in find() {} }",
+            "the result of `find` at 3:23 may be none",
+        ),
+        (
+            "fun f() { val (a, b) = find() }",
+            "the result of `find` at 3:27 may be none",
+        ),
+        ("var x: Int? = 2\nx += 1", "`x` at 4:0 may be none"),
+        ("fun f(p) { p.a?.n + 1 }", "`?.n` at 3:16 may be none"),
+        (
+            "fun f() { none }",
+            "the result of `f` is not optional and can't be none at 3:10",
+        ),
+        (
+            "fun f(x) { x }\nf(none)",
+            "argument 1 of `f` is not optional and can't be none at 4:2",
+        ),
+        (
+            "var x = 1\nx = none",
+            "`x` is not optional and can't be none at 4:4",
+        ),
+        (
+            "struct Q { b: Int }\nval q = Q { b: none }",
+            "field `b` is not optional and can't be none at 4:15",
+        ),
+        (
+            "val x = find()",
+            "top level `x` may be none, declare it with an optional type like `x: T?` at 3:4",
+        ),
+        (
+            "var x = none",
+            "top level `x` may be none, declare it with an optional type like `x: T?` at 3:4",
+        ),
+        (
+            "fun f() { val x = find()\nx }",
+            "the result of `f` is not optional and can't be none at 4:0",
+        ),
+        (
+            "fun f() { val y: Int = find() }",
+            "`y` is not optional and can't be none at 3:27",
+        ),
+        (
+            "print(len(none))",
+            "argument 1 of `len` is not optional and can't be none at 3:10",
+        ),
+        (
+            "print([none, 1][0] + 1)",
+            "the element at the index at 3:15 may be none",
+        ),
+        (
+            "print((|| none)() + 1)",
+            "the result of `<lambda>` at 3:15 may be none",
+        ),
+        (
+            "val f = || none\nprint(f() + 1)",
+            "the result of `f` at 4:7 may be none",
+        ),
+        (
+            "print((find(), 1).0 + 1)",
+            "element 0 of the tuple at 3:17 may be none",
+        ),
+        (
+            "print({\"a\": none}[\"a\"] + 1)",
+            "the element at the index at 3:17 may be none",
+        ),
+        (
+            "fun first<T>(xs: List<T>) -> T { xs[0] }\nprint(first([none, 1]) + 1)",
+            "the result of `first` at 4:11 may be none",
+        ),
+        (
+            "for x in [1, none] { print(x + 1) }",
+            "`x` at 3:27 may be none",
+        ),
+        (
+            "for x: Int in [1, none] {}",
+            "`x` is not optional and can't be none at 3:4",
+        ),
+        (
+            "val xs: List<Int> = [none, 1]",
+            "expected List<Int> for `xs`, found List<Int?>",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(&format!("{}{}", decls, source))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn optional_values() -> Result<(), CompilerError> {
+    let source = "enum Cell { Empty, Full(Int?) }
+fun unwrap(cell) {
+    match cell {
+        Cell.Full(v) => v ?? -1,
+        Cell.Empty => 0,
+    }
+}
+val pick = |flag| if flag { 1 } else { none }
+val pair = if val n = pick(true) { (n, n) } else { (0, 0) }
+var count: Int? = none
+count = 2
+print(unwrap(Cell.Full(none)))
+print(unwrap(Cell.Full(3)))
+print(pick(false) ?? pick(true) ?? 7)
+print(pair)
+print(count)
+match count {
+    none => { print(\"none\") },
+    c => { print(c * 2) },
+}";
+    assert_eq!(run(source)?, "-1\n3\n1\n(1, 1)\n2\n4\n");
+    Ok(())
+}
+
+#[test]
+fn result_errors() {
+    let cases = [
+        ("val x = ok(1)?", "`?` outside of a function"),
+        ("fun f(r: Int?) { r? }", "`r` at 1:17 may be none"),
+        ("fun f() { 1? }\nf()", "`?` expects a Result, found Int"),
+        (
+            "fun f() { ok(1, 2) }\nf()",
+            "`Result.Ok` expects 1 arguments, found 2",
+        ),
+        ("panic(\"giving up\")", "panic: giving up at 1:5"),
+        (
+            "panic(none)",
+            "argument 1 of `panic` is not optional and can't be none at 1:6",
+        ),
+        ("ok = 1", "cannot assign to val `ok`"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn generic_errors() {
+    let cases = [
+        (
+            "fun pair<T>(a: T, b: T) { (a, b) }\npair(1, \"x\")",
+            "type parameter `T` of `pair` can't be both Int and String at 2:4",
+        ),
+        (
+            "fun first<T>(xs: List<T>) -> T { xs[0] }\nfirst(1)",
+            "expected List<T> for argument 1 of `first`, found Int at 2:5",
+        ),
+        ("val x: Thing = 1", "undefined type `Thing`"),
+        (
+            "val x: List<Int, Int> = []",
+            "`List` takes 1 type arguments, found 2",
+        ),
+        (
+            "val x: Int = \"s\"",
+            "expected Int for `x`, found String at 1:4",
+        ),
+        (
+            "struct Pair<T> { a: T, b: T }\nPair { a: 1, b: true }",
+            "type parameter `T` of `Pair` can't be both Int and Bool at 2:0",
+        ),
+        (
+            "fun inc(n: Int) { n + 1 }\nfun f<T>(x: T) { inc(x) }",
+            "expected Int for argument 1 of `inc`, found T at 2:20",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn trait_errors() {
+    let show = "trait Show { fun show(self) -> String }\nstruct P {}\n";
+    let cases = [
+        (
+            "impl Show for P {}",
+            "`P` is missing method `show` of trait `Show` at 3:5",
+        ),
+        (
+            "impl Show for P { fun show(self) { \"a\" } }
+impl Show for P { fun show(self) { \"b\" } }",
+            "trait `Show` is already implemented for `P` at 4:5",
+        ),
+        (
+            "impl Show for P { fun show(self) { \"a\" }\nfun size(self) { 1 } }",
+            "`size` is not a method of trait `Show` at 4:4",
+        ),
+        (
+            "impl Show for P { fun show() { \"a\" } }",
+            "`show` of `P` takes 0 parameters, trait `Show` declares 1 at 3:22",
+        ),
+        (
+            "fun f<T: Show>(x: T) { x.show() }\nf(1)",
+            "`Int` does not implement trait `Show` at 4:1",
+        ),
+        (
+            "fun f<T: Show>(x: T) { x.size() }",
+            "`T` has no method `size`",
+        ),
+        (
+            "fun f(x: dyn Show) { x.size() }",
+            "`dyn Show` has no method `size`",
+        ),
+        (
+            "val x: dyn Show = P {}",
+            "expected dyn Show for `x`, found P",
+        ),
+        ("fun f<T: Eq>(x: T) { x }", "undefined trait `Eq`"),
+        ("impl Eq for P {}", "undefined trait `Eq`"),
+        ("struct Show {}", "`Show` is already defined"),
+        (
+            "struct Boxed<T: Show> { item: T }\nBoxed { item: 1 }",
+            "`Int` does not implement trait `Show` at 4:0",
+        ),
+        (
+            "impl Show for P { fun show(self) { \"a\" } }\nstruct Q {}
+val xs: List<dyn Show> = [P {}, Q {}]",
+            "expected List<dyn Show> for `xs`, found List<P or Q>",
+        ),
+        (
+            "impl Show for P { fun show(self) { \"a\" } }
+fun all<T: Show>(xs: List<T>) {}\nall([P {}, 1])",
+            "`P or Int` does not implement trait `Show` at 5:3",
+        ),
+        // Methods are found by name, so a struct can't have two of the same name
+        (
+            "trait Debug { fun show(self) -> String }
+impl Show for P { fun show(self) { \"a\" } }
+impl Debug for P { fun show(self) { \"b\" } }",
+            "`show` is already defined at 5:23",
+        ),
+        (
+            "impl P { fun show(self) { \"own\" } }
+impl Show for P { fun show(self) { \"a\" } }",
+            "`show` is already defined at 4:22",
+        ),
+    ];
+    for (source, message) in cases {
+        let source = format!("{}{}", show, source);
+        let err = run(&source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn trait_bounds_carry_over() -> Result<(), CompilerError> {
+    let source = "trait Show { fun show(self) -> String }
+struct P { n: String }
+impl Show for P { fun show(self) -> String { self.n } }
+struct Labeled<T: Show> { item: T }
+impl Labeled {
+    fun label(self) -> String { \"[\" + self.item.show() + \"]\" }
+}
+fun erase<T: Show>(x: T) -> String {
+    val shown: dyn Show = x
+    shown.show()
+}
+print(Labeled { item: P { n: \"x\" } }.label())
+print(erase(P { n: \"y\" }))";
+    assert_eq!(run(source)?, "[x]\ny\n");
+    Ok(())
+}
+
+#[test]
+fn big_ints() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("big_ints.ypl")?,
+        "2432902008176640000\n51090942171709440000\n354224848179261915075\ntrue\n100\n\
+         9223372036854775808\ntrue\n-9223372036854775809\n255\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn ints_promote_on_overflow() -> Result<(), CompilerError> {
+    let source = "val max = 9223372036854775807
+val min = -max - 1
+print(min)
+print(min - 1)
+print(-min)
+print(min / -1)
+print(min % -1)
+print(max * max)
+print(max * max / max == max)
+print(3 ** 40)
+print((2 ** 64) ** 2)
+print(1 << 63)
+print(-1 << 63)
+print((1 << 62) << 1 == 2 ** 63)
+print(2 ** 70 >> 60)
+print(1 << 100 == 2 ** 100)
+print(-5 >> 64)
+print(2 ** 70 >> 100)
+print(~(2 ** 64))
+print(2 ** 64 | 1)
+print((2 ** 64 + 6) & 7)
+print(-(2 ** 64) ^ -1)
+print(2 ** 64 > max)
+print(-(2 ** 64) < min)
+print(2 ** 64 == 2.0 ** 64)
+print(2 ** 64 + 0.5)
+print(99999999999999999999 == 99999999999999999999)
+val counts = {2 ** 64: 1}
+counts[18446744073709551616] += 1
+print(counts)
+print(match 18446744073709551616 { 18446744073709551616 => \"big\", _ => \"other\" })
+const BIG = 2 ** 100
+print(BIG)
+for x in take(iter([1, 2]), 2 ** 64) { print(x) }";
+    assert_eq!(
+        run(source)?,
+        "-9223372036854775808\n-9223372036854775809\n9223372036854775808\n\
+         9223372036854775808\n0\n85070591730234615847396907784232501249\ntrue\n\
+         12157665459056928801\n340282366920938463463374607431768211456\n\
+         9223372036854775808\n-9223372036854775808\ntrue\n1024\ntrue\n-1\n0\n\
+         -18446744073709551617\n18446744073709551617\n6\n18446744073709551615\ntrue\ntrue\ntrue\n\
+         1.8446744073709552e19\ntrue\n{18446744073709551616: 2}\nbig\n\
+         1267650600228229401496703205376\n1\n2\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn decimals() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("decimals.ypl")?,
+        "13.25\n(4.41, 0.02)\ntrue\nfalse\n0.3333333333333333\n2.68\n2.66\n-3\ntrue\n1/4\n\
+         0.3333\n19.875\n0.1\n-7\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn exact_numbers_promote() -> Result<(), CompilerError> {
+    let source = "print(1.50d + 1)
+print(2 ** 64 * 0.5d)
+print(10.00d / 4)
+print(-7.5d % 2)
+print(1.5d ** 2)
+print(-1.5d)
+print(rational(1, 2) + 1)
+print(rational(1, 2) + 0.25d)
+print(rational(6, -4))
+print(rational(2, 3) ** -2)
+print(-rational(1, 3) < 0)
+print(1.0d == 1.00d)
+print(1 == 1.0d)
+print(rational(1, 4) == 0.25d)
+print(0.5d == 0.5)
+print(int(2 ** 64 + 0.9d))
+print(int(rational(-7, 2)))
+print(int(-2.5))
+print(float(rational(1, 4)))
+print(decimal(7))
+print(decimal(rational(1, 8)))
+print(decimal(0.0000001))
+print(div(1, 8, 2, Rounding.HalfEven))
+print(div(2.5d, rational(1, 3), 3, Rounding.Ceiling))
+print(round(7, 2, Rounding.Up))
+print(round(-0.125d, 2, Rounding.HalfDown))
+print(match 1.50d { 1.5d => \"one and a half\", _ => \"other\" })
+const TAX = 0.08d * 100
+print(TAX)
+val price: Decimal = 9.99d
+print(price * 3)";
+    assert_eq!(
+        run(source)?,
+        "2.50\n9223372036854775808.0\n2.50\n-1.5\n2.25\n-1.5\n3/2\n3/4\n-3/2\n9/4\n\
+         true\ntrue\ntrue\ntrue\nfalse\n18446744073709551616\n-3\n-2\n0.25\n7\n0.125\n\
+         0.0000001\n0.12\n7.500\n7.00\n-0.12\none and a half\n8.00\n29.97\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn exact_number_errors() {
+    let cases = [
+        ("print(1.5d + 1.0)", "cannot apply `+` to Decimal and Float"),
+        (
+            "print(1.0 * rational(1, 2))",
+            "cannot apply `*` to Float and Rational",
+        ),
+        (
+            "print(2d ** 0.5d)",
+            "cannot apply `**` to Decimal and Decimal",
+        ),
+        ("print(1.5d < 2.0)", "cannot apply `<` to Decimal and Float"),
+        ("val x: Decimal = 1", "expected Decimal for `x`, found Int"),
+        (
+            "print(round(1d, 2, 0))",
+            "expected Rounding for argument 3 of `round`",
+        ),
+        (
+            "val f = fun() { 1.5 }\nprint(1d - f())",
+            "cannot apply `-` to Decimal and Float",
+        ),
+        ("print(1d / 0)", "division by zero"),
+        ("print(1 % 0.00d)", "division by zero"),
+        ("print(rational(1, 0))", "division by zero"),
+        ("print(rational(0, 1) ** -1)", "division by zero"),
+        ("print(div(1, 0, 2, Rounding.Up))", "division by zero"),
+        ("print(1.5d ** -1)", "negative exponent -1"),
+        (
+            "print(round(1d, -1, Rounding.Up))",
+            "scale -1 is outside of 0..=1000",
+        ),
+        (
+            "print(round(1d, 1001, Rounding.Up))",
+            "scale 1001 is outside of 0..=1000",
+        ),
+        (
+            "print(round(1.5, 0, Rounding.Up))",
+            "cannot apply `round` to Float",
+        ),
+        (
+            "print(rational(0.5, 1))",
+            "cannot apply `rational` to Float",
+        ),
+        ("print(int(0.0 / 0.0))", "NaN cannot be converted to Int"),
+        (
+            "print(decimal(1.0 / 0.0))",
+            "inf cannot be converted to Decimal",
+        ),
+        ("print(int(\"1\"))", "cannot apply `int` to String"),
+        ("val m = {1.5d: 1}", "Decimal cannot be used as a map key"),
+        ("const A = 1d / 0", "evaluating constant `A` failed"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn rounding_can_be_shadowed() -> Result<(), CompilerError> {
+    let source = "enum Rounding { Nearest }
+print(Rounding.Nearest)";
+    assert_eq!(run(source)?, "Rounding.Nearest\n");
+    Ok(())
+}
+
+#[test]
+fn persistent_collections() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("persistent.ypl")?,
+        "vector([3, 5, 8])\nvector([3, 5, 8, 13, 21])\n13\nvector([5, 8])\nvector([0, 5, 8])\n\
+         true\n5\n29\n2\n2\nfalse\n2\n3\nvector([3, 5, 8, 1])\nvector([3, 5, 8, 2])\n\
+         vector([3, 5, 8])\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn persistent_updates_leave_versions_unchanged() -> Result<(), CompilerError> {
+    let source = "var v = vector([])
+var d = dict({})
+var versions = []
+var i = 0
+loop {
+    if i == 100 { break }
+    push(versions, v)
+    v = append(v, i)
+    d = insert(d, i, i * i)
+    i += 1
+}
+print(len(versions[40]))
+print(versions[99][98])
+print(len(set(v, 50, none)))
+print(v[50])
+val old = d
+d = without(insert(d, 7, 0), 8)
+print((old[7], old[8], d[7], contains(d, 8)))
+print((len(old), len(d), len(keys(old)), len(values(d))))
+print(dict({\"a\": [1]}) == dict({\"a\": [1]}))
+print((vector([1, 2]) == vector([1, 2]), vector([1]) == [1]))
+print(contains(vector([\"x\"]), \"x\"))
+for c in vector(['a', 'b']) { print(c) }";
+    assert_eq!(
+        run(source)?,
+        "40\n98\n100\n50\n(49, 64, 0, false)\n(100, 99, 100, 99)\ntrue\n(true, false)\ntrue\na\nb\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn persistent_collection_errors() {
+    let cases = [
+        (
+            "val v = vector([1])\nv[0] = 2",
+            "Vector does not support indexed assignment",
+        ),
+        (
+            "print(vector([1])[1])",
+            "index 1 is out of bounds for length 1",
+        ),
+        (
+            "print(set(vector([1]), -1, 0))",
+            "index -1 is out of bounds",
+        ),
+        (
+            "print(dict({\"a\": 1})[\"b\"])",
+            "key \"b\" is not in the map",
+        ),
+        (
+            "print(insert(dict({}), 1.5, 1))",
+            "Float cannot be used as a map key",
+        ),
+        (
+            "print(append(vector([1]), \"a\"))",
+            "type parameter `T` of `append` can't be both Int and String",
+        ),
+        (
+            "val d: Dict<String, Int> = dict({1: 2})",
+            "expected Dict<String, Int> for `d`, found Dict<Int, Int>",
+        ),
+        (
+            "print(append([1], 2))",
+            "expected Vector<T> for argument 1 of `append`",
+        ),
+        (
+            "val v: Vector<Int, Int> = vector([])",
+            "takes 1 type argument",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn operators() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("operators.ypl")?,
+        "Vec2 { x: 1.5, y: 1.0 }\nVec2 { x: 0.5, y: 3.0 }\nVec2 { x: 2.0, y: 4.0 }\n\
+         Vec2 { x: -1.0, y: -2.0 }\n2.0\ntrue\n300\ntrue\ntrue\ntrue\ntrue\n\
+         Vec2 { x: 2.5, y: 3.0 }\n250\n6\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn operators_resolve_when_run() -> Result<(), CompilerError> {
+    let source = "struct Id { n: Int }
+impl Id {
+    fun eq(self, other: Id) -> Bool { self.n % 10 == other.n % 10 }
+    fun cmp(self, other: Id) -> Int { (self.n - other.n) * 2 ** 64 }
+    fun index(self, i) -> Int? { if i == 0 { self.n } else { none } }
+    fun rem(self, m) { Id { n: self.n % m } }
+}
+fun check(a, b, c) {
+    print((a == b, a != b, a != c))
+    print((a < c, c >= b, b > a))
+    print((c[0], c[1], (b % 4).n))
+}
+val (one, eleven) = (Id { n: 1 }, Id { n: 11 })
+check(one, eleven, Id { n: 2 })
+print([one] == [Id { n: 1 }])
+print((one == eleven, one < eleven))
+val maybe: Id? = one
+print((maybe == none, one != none))";
+    assert_eq!(
+        run(source)?,
+        "(true, false, true)\n(true, false, true)\n(2, none, 3)\ntrue\n(true, true)\n\
+         (false, true)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn operator_methods_can_wait_for_values() -> Result<(), CompilerError> {
+    let source = "struct Next { c: Chan<Int> }
+impl Next {
+    fun eq(self, other) { recv(self.c) == other }
+    fun cmp(self, other) { recv(self.c) - other }
+}
+val c: Chan<Int> = chan()
+spawn (fun() { for i in [1, 2, 3, 4] { send(c, i) } })()
+fun check(next) { (next == 1, next != 1, next < 4, next > 4) }
+print(check(Next { c: c }))";
+    assert_eq!(run(source)?, "(true, true, true, false)\n");
+    Ok(())
+}
+
+#[test]
+fn operator_errors() {
+    let point = "struct P { x: Int }
+impl P {
+    fun add(self, other: P) -> P { P { x: self.x + other.x } }
+    fun cmp(self, other: P) -> String { \"less\" }
+    fun index(self, i: Int) -> Int? { none }
+}
+val p = P { x: 1 }
+";
+    let cases = [
+        (
+            "print(p * p)",
+            "cannot apply `*` to P, which has no method `mul`",
+        ),
+        (
+            "print(-p)",
+            "cannot apply `-` to P, which has no method `neg`",
+        ),
+        (
+            "print(p + 1)",
+            "expected P for the operand of `+`, found Int at 8:8",
+        ),
+        ("print(1 + p)", "cannot apply `+` to Int and P"),
+        ("print(p ** 2)", "cannot apply `**` to P and Int"),
+        (
+            "print(p < p)",
+            "expected Int for the result of `cmp`, found String at 8:8",
+        ),
+        (
+            "print(p[\"a\"])",
+            "expected Int for the operand of `[]`, found String at 8:7",
+        ),
+        ("print(p[0] + 1)", "the result of `index` at 8:7 may be none"),
+        (
+            "struct Q { x: Int }\nimpl Q { fun neg(self, o) { self } }\nprint(-Q { x: 1 })",
+            "`neg` expects 2 arguments, found 1 at 10:6",
+        ),
+        (
+            "fun f(a) { a / a }\nprint(f(p))",
+            "runtime error - cannot apply `/` to P, which has no method `div`",
+        ),
+        (
+            "fun f(a) { a >= a }\nprint(f(p))",
+            "runtime error - `cmp` must return an Int, found String",
+        ),
+        (
+            "struct Q { x: Int }\nimpl Q { fun eq(self, o) { 1 } }\nprint(Q { x: 1 } == Q { x: 2 })",
+            "runtime error - `eq` must return a Bool, found Int at 10:17",
+        ),
+        (
+            "struct Q { x: Int }\nimpl Q { fun eq(self, o) { 1 } }\nprint(Q { x: 1 } != Q { x: 2 })",
+            "runtime error - `eq` must return a Bool, found Int at 10:17",
+        ),
+    ];
+    for (source, message) in cases {
+        let source = format!("{}{}", point, source);
+        let err = run(&source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn arguments() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("arguments.ypl")?,
+        "hi, Ada!\nhello, Ada!\nhey, Grace!\nhi, Linus?\n1\n10\n(5, 15)\n(5, 7)\n\
+         (\"app [info] started\", [])\n(\"app [warn] disk almost full\", [\"disk\", \"io\"])\n\
+         hi, Barbara.\n3\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn arguments_are_matched_when_run() -> Result<(), CompilerError> {
+    let source = "fun count(from = 1, ...more) {
+    var n = from
+    loop {
+        if n > from + len(more) { break }
+        yield n
+        n += 1
+    }
+}
+fun adder(step = 1) { fun(x, by = step) { x + by } }
+fun all(values) {
+    val list = []
+    for value in values { push(list, value) }
+    list
+}
+const fun scaled(x, factor = 2) { x * factor }
+const TEN = scaled(5)
+const NINE = scaled(factor: 3, x: 3)
+val add = adder(10)
+print((add(1), add(1, by: 2), all(map([1, 2], add))))
+print((all(count()), all(count(5, \"a\", \"b\"))))
+val results: Chan<Int> = chan()
+spawn (fun(c, ...vs) { send(c, len(vs)) })(results, 1, 2, 3)
+spawn (fun(c, v = 7) { send(c, v) })(c: results)
+print((recv(results), recv(results)))
+print((fun([x, y], z = x + y) { (x, y, z) })([1, 2]))
+print((TEN, NINE))";
+    assert_eq!(
+        run(source)?,
+        "(11, 3, [11, 12])\n([1], [5, 6, 7])\n(3, 7)\n(1, 2, 3)\n(10, 9)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn argument_errors() {
+    let greet = "fun greet(name, greeting = \"hi\") { greeting + name }
+val dynamic = greet
+";
+    let cases = [
+        (
+            "greet(\"a\", tone: 1)",
+            "`greet` has no parameter `tone` at 3:11",
+        ),
+        ("dynamic(\"a\", tone: 1)", "`greet` has no parameter `tone`"),
+        (
+            "greet(\"a\", greeting: \"yo\", greeting: \"hey\")",
+            "argument `greeting` of `greet` is passed more than once at 3:27",
+        ),
+        (
+            "greet(\"a\", name: \"b\")",
+            "argument `name` of `greet` is passed more than once at 3:11",
+        ),
+        (
+            "dynamic(\"a\", name: \"b\")",
+            "argument `name` of `greet` is passed more than once",
+        ),
+        (
+            "greet(greeting: \"yo\")",
+            "call of `greet` is missing argument `name`",
+        ),
+        ("greet()", "call of `greet` is missing argument `name`"),
+        (
+            "greet(\"a\", \"b\", \"c\")",
+            "`greet` expects at most 2 arguments, found 3",
+        ),
+        ("len([1], of: 2)", "`len` has no parameter `of`"),
+        ("val l = len\nl([1], of: 2)", "`len` has no parameter `of`"),
+        (
+            "fun f(...rest) { rest }\nf(rest: 1)",
+            "`f` has no parameter `rest`",
+        ),
+        (
+            "fun f(a = 1, b) { a }",
+            "parameter `b` of `f` needs a default value, as one before it has one",
+        ),
+        (
+            "fun f(a: Int = \"one\") { a }",
+            "expected Int for parameter `a`, found String at 3:6",
+        ),
+        (
+            "fun f(a, b: Int = 1) { a }\nf(1, b: \"two\")",
+            "expected Int for argument `b` of `f`, found String at 4:1",
+        ),
+        (
+            "fun f(...rest: Int) { rest }\nf(1, \"two\")",
+            "expected Int for argument 2 of `f`, found String at 4:1",
+        ),
+        (
+            "fun f(a: Int) { a }\nf(a: none)",
+            "argument `a` of `f` is not optional and can't be none",
+        ),
+        ("fun s(a, a) { a }", "`a` is already defined at 3:9"),
+        (
+            "fun s([a, b], ...b) { b }",
+            "`b` is already defined at 3:17",
+        ),
+        ("val s = fun(x, x) { x }", "`x` is already defined at 3:15"),
+        ("fun f(a = a) { a }", "undefined variable `a` at 3:10"),
+        (
+            "fun f(a, b = c, c = 1) { b }",
+            "undefined variable `c` at 3:13",
+        ),
+        ("greet(name: \"a\", \"b\")", "unexpected token"),
+        ("fun f(...rest, a) { a }", "unexpected token"),
+    ];
+    for (source, message) in cases {
+        let source = format!("{}{}", greet, source);
+        let err = run(&source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn arity_errors_of_known_functions_are_reported_before_running() {
+    let greet = "fun greet(name, greeting = \"hi\") { greeting + name }
+enum Shape { Circle(Float) }
+struct P { x: Int }
+impl P { fun get(self) -> Int { self.x } }
+print(\"running\")
+";
+    let cases = [
+        (
+            "greet()",
+            "call of `greet` is missing argument `name` at 6:5",
+        ),
+        (
+            "greet(\"a\", \"b\", \"c\")",
+            "`greet` expects at most 2 arguments, found 3 at 6:5",
+        ),
+        (
+            "greet(greeting: \"yo\")",
+            "call of `greet` is missing argument `name` at 6:5",
+        ),
+        ("len([], [])", "`len` expects 1 arguments, found 2 at 6:3"),
+        (
+            "Shape.Circle()",
+            "`Shape.Circle` expects 1 arguments, found 0 at 6:12",
+        ),
+        (
+            "fun f() { ok(1, 2) }",
+            "`Result.Ok` expects 1 arguments, found 2 at 6:12",
+        ),
+        (
+            "P { x: 1 }.get(2)",
+            "`get` expects 1 arguments, found 2 at 6:14",
+        ),
+    ];
+    for (source, message) in cases {
+        let source = format!("{}{}", greet, source);
+        let err = run(&source).unwrap_err();
+        assert!(
+            matches!(err, CompilerError::CodegenError { .. }),
+            "{}: {}",
+            source,
+            err
+        );
+        assert!(err.to_string().contains(message), "{}: {}", source, err);
+    }
+
+    // A function only known when the call runs is checked then
+    let source = format!("{}val dynamic = greet\ndynamic()", greet);
+    let err = run(&source).unwrap_err();
+    assert!(matches!(err, CompilerError::RuntimeError { .. }), "{}", err);
+    assert!(err
+        .to_string()
+        .contains("call of `greet` is missing argument `name`"));
+}
+
+#[test]
+fn constants() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("constants.ypl")?,
+        "102400\nhello, yapl\n120\n720\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn constant_errors() {
+    let cases = [
+        ("const A = 1 / 0", "evaluating constant `A` failed"),
+        (
+            "const A = [1]",
+            "list can't be used in a constant expression",
+        ),
+        (
+            "var x = 1\nconst A = x",
+            "can't be used in a constant expression",
+        ),
+        ("const A = B\nconst B = A", "constant `A` depends on itself"),
+        (
+            "const A: String = 1",
+            "expected String for `A`, found Int at 1:6",
+        ),
+        (
+            "fun f() { 1 }\nconst A = f()",
+            "can't be used in a constant expression",
+        ),
+        ("const A = 1\nA = 2", "cannot assign to constant `A`"),
+        ("const A = 1\nconst A = 2", "`A` is already defined"),
+        ("const A = 1 / 0", "evaluating constant `A` failed"),
+        (
+            "const fun f(n) { f(n + 1) }\nconst A = f(0)",
+            "stack overflow",
+        ),
+        (
+            "fun f() { const A = 1 }",
+            "`const` is only allowed at the top level",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn generators() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("generators.ypl")?,
+        "1\n9\n25\n5\n(2, 'a')\n(1, 'b')\nlazy\nvalues\ndone\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn generators_run_lazily() -> Result<(), CompilerError> {
+    let source = "fun noisy() {
+    var i = 0
+    loop {
+        print(\"step\")
+        yield i
+        i += 1
+    }
+}
+for x in take(map(noisy(), |x| x * 10), 2) { print(x) }
+val it = noisy()
+print(\"created\")
+print(it.next())";
+    assert_eq!(run(source)?, "step\n0\nstep\n10\ncreated\nstep\n0\n");
+    Ok(())
+}
+
+#[test]
+fn generators_keep_their_frames() -> Result<(), CompilerError> {
+    let source = "enum Tree { Leaf, Node(Tree, Int, Tree) }
+fun walk(tree) {
+    match tree {
+        Tree.Node(left, value, right) => {
+            for x in walk(left) { yield x }
+            yield value
+            for x in walk(right) { yield x }
+        },
+        Tree.Leaf => {}
+    }
+}
+val leaf = Tree.Leaf
+print(walk(Tree.Node(Tree.Node(leaf, 1, leaf), 2, Tree.Node(leaf, 3, leaf))).next())
+for x in walk(Tree.Node(Tree.Node(leaf, 1, leaf), 2, Tree.Node(leaf, 3, leaf))) { print(x) }
+
+fun counter() {
+    var seen = 0
+    val add = fun() { seen += 1 }
+    yield add
+    print(seen)
+    yield add
+}
+val g = counter()
+val add = g.next() ?? fun() {}
+add()
+add()
+g.next()
+print(g.next())
+
+struct Range { start: Int, end: Int }
+impl Range {
+    fun evens(self) -> Iterator<Int> {
+        var x = self.start
+        loop {
+            if x >= self.end { break }
+            if x % 2 == 0 { yield x }
+            x += 1
+        }
+    }
+}
+for x in (Range { start: 1, end: 6 }).evens() { print(x) }";
+    assert_eq!(run(source)?, "1\n1\n2\n3\n2\nnone\n2\n4\n");
+    Ok(())
+}
+
+#[test]
+fn generator_errors() {
+    let cases = [
+        ("yield 1", "yield outside of a function"),
+        (
+            "fun g() { yield 1\nreturn 2 }",
+            "`return` can't return a value from generator `g`",
+        ),
+        (
+            "fun g() { yield 1\nok(1)? }",
+            "`?` can't return a value from generator `g`",
+        ),
+        ("fun g() { yield none }", "`none` at 1:16 may be none"),
+        (
+            "fun g() -> Int { yield 1 }",
+            "expected Int for the result of generator `g`, found Iterator<_> at 1:4",
+        ),
+        (
+            "fun g() { yield 1 }\ng().size()",
+            "`Iterator<_>` has no method `size`",
+        ),
+        (
+            "var it = iter([])\nfun g() { yield 1\nit.next() }\nit = g()\nfor x in it {}",
+            "generator `g` is stepped while it is running",
+        ),
+        (
+            "fun deep(n) { if n > 0 { for x in deep(n - 1) { yield x } } else { yield 0 } }
+for x in deep(100) {}",
+            "stack overflow",
+        ),
+        (
+            "take([1], -1)",
+            "cannot take a negative number of values, found -1",
+        ),
+        (
+            "for x in filter([1], |x| x) {}",
+            "expected Bool condition, found Int",
+        ),
+        ("iter([1]).next(1)", "`next` expects 0 arguments, found 1"),
+        ("for x in 5 {}", "Int is not iterable"),
+        (
+            "const fun g() { yield 1 }\nconst A = g()",
+            "`yield` can't be used",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn nested_type_arguments() -> Result<(), CompilerError> {
+    let source = "val xs: List<List<Int>> = [[1], [2, 3]]
+fun id<T>(x: T) -> T { x }
+val n: Int = id(3)
+print(xs)
+print(n)";
+    assert_eq!(run(source)?, "[[1], [2, 3]]\n3\n");
+    Ok(())
+}
+
+#[test]
+fn results_can_be_shadowed() -> Result<(), CompilerError> {
+    let source = "enum Result { Ok, Fail }
+fun ok(n) { n + 1 }
+print(ok(1))
+print(Result.Fail)
+fun f() {
+    val err = |e| e * 2
+    err(3)
+}
+print(f())";
+    assert_eq!(run(source)?, "2\nResult.Fail\n6\n");
+    Ok(())
+}
+
+#[test]
+fn tasks() -> Result<(), CompilerError> {
+    assert_eq!(
+        run_example("tasks.ypl")?,
+        "(\"first\", 1)\n(\"first\", 4)\n(\"first\", 9)\n(\"second\", 16)\n6\n60\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn tasks_take_turns() -> Result<(), CompilerError> {
+    let source = "fun echo(name, input, output) {
+    loop {
+        val value = recv(input)
+        print(name)
+        send(output, value + 1)
+    }
+}
+fun main() {
+    val a = chan()
+    val b = chan()
+    val c = chan()
+    spawn echo(\"a\", a, b)
+    spawn echo(\"b\", b, c)
+    print(\"spawned\")
+    send(a, 1)
+    print(\"sent\")
+    print(recv(c))
+    send(a, 10)
+    send(a, 20)
+    print(recv(c))
+    print(recv(c))
+    spawn echo(\"c\", c, a)
+    send(a, 0)
+    print(select([b, c]))
+}";
+    assert_eq!(
+        run(source)?,
+        "spawned\nsent\na\nb\n3\na\na\nb\nb\n12\n22\na\nb\n(1, 2)\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn deadlock() {
+    let source = "fun wait(c) {
+    recv(c)
+}
+fun pick(c) {
+    select([chan(), c])
+}
+fun main() {
+    val c = chan()
+    spawn wait(c)
+    spawn pick(c)
+    spawn wait(chan())
+    send(c, 1)
+    wait(chan())
+}";
+    let Err(CompilerError::RuntimeError { err, trace }) = run(source) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(
+        err.to_string(),
+        "runtime error - deadlock, every task is waiting for a channel"
+    );
+
+    let frames: Vec<_> = trace
+        .iter()
+        .map(|frame| (frame.task, frame.function.as_str(), frame.position))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (0, "wait", Some(Position { line: 2, char: 8 })),
+            (0, "main", Some(Position { line: 13, char: 8 })),
+            (2, "pick", Some(Position { line: 5, char: 10 })),
+            (3, "wait", Some(Position { line: 2, char: 8 })),
+        ]
+    );
+
+    let err = run(source).unwrap_err().to_string();
+    assert!(
+        err.ends_with("\n  task 0\n    in wait at 2:8\n    in main at 13:8\n  task 2\n    in pick at 5:10\n  task 3\n    in wait at 2:8"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn task_errors() {
+    let cases = [
+        (
+            "spawn 1",
+            "`spawn` needs a call of a function, found an expression that isn't a call",
+        ),
+        (
+            "struct S {}\nimpl S { fun f(self) {} }\nspawn (S {}).f()",
+            "`spawn` needs a call of a function, found a call of method `f`",
+        ),
+        (
+            "fun g() { yield 1 }\nspawn g()",
+            "generator `g` can't be spawned as a task",
+        ),
+        ("spawn len([])", "builtin `len` can't be spawned as a task"),
+        ("val f = 1\nspawn f()", "Int is not callable"),
+        ("fun f(a) {}\nspawn f()", "`f` expects 1 arguments, found 0"),
+        (
+            "val c: Chan<Int> = chan()\nsend(c, \"one\")",
+            "type parameter `T` of `send` can't be both Int and String",
+        ),
+        (
+            "val c: Chan<Int> = chan()\nval s: String = recv(c)",
+            "expected String for `s`, found Int",
+        ),
+        (
+            "send([], 1)",
+            "expected Chan<T> for argument 1 of `send`, found List<_>",
+        ),
+        (
+            "fun f(c) { send(c, 1) }\nf([])",
+            "cannot apply `send` to List",
+        ),
+        (
+            "fun pick(other) { select([chan(), other]) }\npick(1)",
+            "cannot apply `select` to Int",
+        ),
+        ("select([])", "cannot select from an empty list"),
+        (
+            "fun g(c) { yield recv(c) }\nfor x in g(chan()) {}",
+            "`recv` can't wait for a channel in a generator",
+        ),
+        (
+            "val c: Chan<Int> = chan()\nfor x in map([1], |x| recv(c)) {}",
+            "`recv` can't wait for a channel in a generator, in the `next` method of an iterator \
+             or in a function given to `map` or `filter`",
+        ),
+        (
+            "fun f() { panic(\"oops\") }\nspawn f()",
+            "panic: oops at 1:15`\n  task 1\n    in f at 1:15",
+        ),
+        (
+            "const fun f() { spawn f() }\nconst A = f()",
+            "`spawn` can't be used",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn stack_trace() {
+    let source = "fun inner(n) {
+    if n == 0 {
+        panic(\"done\")
+    }
+    inner(n - 1)
+}
+fun main() { inner(1) }";
+    let Err(CompilerError::RuntimeError { err, trace }) = run(source) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(err.to_string(), "runtime error - panic: done at 3:13");
+
+    let frames: Vec<_> = trace
+        .iter()
+        .map(|frame| (frame.function.as_str(), frame.position))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            ("inner", Some(Position { line: 3, char: 13 })),
+            ("inner", Some(Position { line: 5, char: 9 })),
+            ("main", Some(Position { line: 7, char: 18 })),
+        ]
+    );
+
+    let source = "fun ratio(a, b) {
+    a / b
+}
+fun main() { ratio(1, 0) }";
+    let Err(CompilerError::RuntimeError { err, trace }) = run(source) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(err.to_string(), "runtime error - division by zero at 2:6");
+    let frames: Vec<_> = trace
+        .iter()
+        .map(|frame| (frame.function.as_str(), frame.position))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            ("ratio", Some(Position { line: 2, char: 6 })),
+            ("main", Some(Position { line: 4, char: 18 })),
+        ]
+    );
+
+    // A runaway recursion is one frame standing for every call
+    let source = "fun f(n) { f(n + 1) }\nf(0)";
+    let Err(CompilerError::RuntimeError { trace, .. }) = run(source) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(trace.len(), 2);
+    assert_eq!(trace[0].function, "f");
+    assert!(trace[0].calls > 1000, "{}", trace[0].calls);
+    assert!(
+        trace[0]
+            .to_string()
+            .ends_with(&format!("(repeated {} times)", trace[0].calls)),
+        "{}",
+        trace[0]
+    );
+    assert_eq!(trace[1].calls, 1);
+}
+
+#[test]
+fn uncaught_errors_exit_with_failure() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("lang_examples")
+        .join("results.ypl");
+    let status = Command::new(env!("CARGO_BIN_EXE_yapl"))
+        .args(["run", path.to_str().unwrap()])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let dir = std::env::temp_dir().join("yapl_uncaught_error");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.ypl");
+    std::fs::write(&path, "fun main() {\n    panic(\"oops\")\n}\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_yapl"))
+        .args(["run", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("panic: oops"), "{}", stderr);
+    assert!(stderr.contains("\n  in main at "), "{}", stderr);
+
+    // An `err` propagated out of `main` is not handled by anything either
+    std::fs::write(
+        &path,
+        "fun open() { err(\"no such file\") }\nfun main() {\n    val f = open()?\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_yapl"))
+        .args(["run", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("`main` returned an error: no such file"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("\n  in main at "), "{}", stderr);
+}